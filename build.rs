@@ -0,0 +1,17 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc();
+}
+
+/// Generates the gRPC service from `proto/`, with a Rust protobuf compiler
+/// so building doesn't need `protoc`.
+#[cfg(feature = "grpc")]
+fn grpc() {
+    println!("cargo:rerun-if-changed=proto");
+    let descriptors =
+        protox::compile(["proto/yt_dlp_web.proto"], ["proto"]).expect("invalid proto file");
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)
+        .expect("failed to generate gRPC service");
+}
@@ -0,0 +1,121 @@
+use std::{io, path::Path};
+
+use tokio::process::Command;
+use tracing::{debug, instrument};
+
+use crate::{
+    jobs::JobHandle,
+    storage::sanitize_filename,
+    video::{DownloadedVideo, VideoInfo},
+};
+
+/// Longest track title kept in file names, in characters.
+const MAX_TRACK_NAME_CHARS: usize = 100;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AlbumError {
+    #[error("video has no chapters to split into tracks")]
+    NoChapters,
+    #[error("failed to create tracks directory")]
+    Dir(#[source] io::Error),
+    #[error("failed to run ffmpeg")]
+    Command(#[source] io::Error),
+    #[error("ffmpeg exited with no status code")]
+    ExitNoCode,
+    #[error("ffmpeg exited with status code {0}")]
+    ExitErrorCode(i32),
+}
+
+/// Splits a long music upload into one audio track per chapter, named
+/// `NN - <chapter title>.m4a` and tagged with the track number, title,
+/// album and artist so music libraries file them correctly.
+///
+/// The audio is copied, not re-encoded, so cuts land on the nearest audio
+/// frame, a few milliseconds at most.
+#[instrument(skip(video, job))]
+pub async fn split(
+    video: &DownloadedVideo,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<Vec<DownloadedVideo>, AlbumError> {
+    let info = &video.info;
+    let chapters = info.chapters.as_deref().unwrap_or_default();
+    if chapters.is_empty() {
+        return Err(AlbumError::NoChapters);
+    }
+    let dir = dir.join("tracks");
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(AlbumError::Dir)?;
+
+    let album = info.title.as_deref().unwrap_or(video.filename.as_str());
+    let artist = info.artist.as_deref().or(info.uploader.as_deref());
+    let total = chapters.len();
+    job.log_output(&format!("Splitting into {} tracks", total));
+
+    let mut tracks = Vec::with_capacity(total);
+    for (i, chapter) in chapters.iter().enumerate() {
+        let number = i + 1;
+        let title = chapter
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Track {}", number));
+        let name: String = sanitize_filename(&title)
+            .chars()
+            .take(MAX_TRACK_NAME_CHARS)
+            .collect();
+        let filename = format!("{:02} - {}.m4a", number, name);
+        let path = dir.join(format!("{:02}.m4a", number));
+
+        let mut command = Command::new("ffmpeg");
+        command
+            .arg("-hide_banner")
+            .arg("-nostdin")
+            .arg("-y")
+            .arg("-ss")
+            .arg(chapter.start_time.to_string())
+            .arg("-to")
+            .arg(chapter.end_time.to_string())
+            .arg("-i")
+            .arg(&video.path)
+            .args(["-map", "0:a:0", "-c:a", "copy", "-map_metadata", "-1"])
+            .arg("-metadata")
+            .arg(format!("title={}", title))
+            .arg("-metadata")
+            .arg(format!("track={}/{}", number, total))
+            .arg("-metadata")
+            .arg(format!("album={}", album));
+        if let Some(artist) = artist {
+            command
+                .arg("-metadata")
+                .arg(format!("artist={}", artist))
+                .arg("-metadata")
+                .arg(format!("album_artist={}", artist));
+        }
+        let cmd = command
+            .arg(&path)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(AlbumError::Command)?;
+
+        debug!("ffmpeg status: {}", cmd.status);
+        match cmd.status.code() {
+            Some(0) => {}
+            Some(code) => {
+                job.log_output(&String::from_utf8_lossy(&cmd.stderr));
+                return Err(AlbumError::ExitErrorCode(code));
+            }
+            None => return Err(AlbumError::ExitNoCode),
+        }
+
+        tracks.push(DownloadedVideo {
+            path,
+            filename,
+            info: VideoInfo::default(),
+            sidecars: Vec::new(),
+        });
+    }
+
+    Ok(tracks)
+}
@@ -0,0 +1,2513 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use futures_util::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::io::ReaderStream;
+use tracing::{Level, debug, error, info, instrument, warn};
+
+use async_graphql::http::GraphiQLSource;
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{
+    Json, Router,
+    body::Body,
+    extract::{self, DefaultBodyLimit, Query, RawQuery, State},
+    http::{HeaderMap, Request, Response, StatusCode, header},
+    response::{
+        Html, IntoResponse, Redirect,
+        sse::{Event, KeepAlive, Sse},
+    },
+    routing::{delete, get, post, put},
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+};
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    bookmarks::Bookmark,
+    bundle, cache,
+    checksum::{self, Verification},
+    clip::{self, ClipFormat, FrameFormat, Section},
+    cluster::{self, Failed, Finished, Role},
+    download::{self, DownloadError, JobDir, JobStream},
+    export::{self, ExportRecord, HistoryImport, Skipped},
+    graphql::{Admin, Caller},
+    hooks,
+    import::{self, Accepted, ImportSummary, Rejected},
+    info::{self, MediaInfo},
+    jobs::{self, Job, JobHandle, JobMode, JobOptions, JobStatus, LogEvent},
+    playlist::{self, ChannelTab, MatchFilter, Playlist},
+    push::{PushError, PushSubscription},
+    qr,
+    queue::Pause,
+    quick, server_log,
+    share::{self, Signature},
+    simulate::{self, Simulation},
+    stats::Stats,
+    storage::{self, Stored, attachment_disposition},
+    subtitles::SubFormat,
+    tee::{self, Tee},
+    transcode::{FrameRate, Hdr, VideoCodec, Widescreen},
+    trash::{self, TrashError},
+    upstream,
+    usage::{self, StorageUsage},
+    video::MetadataOverride,
+};
+
+/// How long `respond=json` waits for a download by default, and at most.
+const DEFAULT_SYNC_WAIT_SECS: u64 = 120;
+const MAX_SYNC_WAIT_SECS: u64 = 600;
+
+/// How long the link returned with `respond=json` works.
+const SYNC_LINK_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Largest history export accepted by `POST /api/import/history`.
+const MAX_HISTORY_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+
+/// Header a client sets to make retrying `POST /api/jobs` safe, and the one
+/// marking a response as a replay of an earlier request.
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Header carrying a kept file's checksum, as `sha256=<hex>`.
+const X_CHECKSUM: &str = "x-checksum";
+
+/// Longest accepted `Idempotency-Key`, enough for any UUID or hash.
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// The HTTP API under `/api`, plus the health check, share links and the web
+/// UI from `static/`.
+pub fn app(state: &AppState) -> Router {
+    let api = Router::new()
+        .route("/download", get(download_video))
+        .route("/clip", post(create_clip))
+        .route("/frame", get(get_frame))
+        .route("/thumbnail/download", get(get_thumbnail))
+        .route("/jobs", get(list_jobs).post(submit_job))
+        .route("/jobs/{id}", get(get_job))
+        .route("/jobs/{id}/file", get(get_job_file))
+        .route("/jobs/{id}/token", post(create_download_token))
+        .route("/jobs/{id}/qr.png", get(get_job_qr))
+        .route("/jobs/{id}/comments", get(get_job_comments))
+        .route("/jobs/{id}/log", get(get_job_log))
+        .route("/jobs/{id}/log/stream", get(stream_job_log))
+        .route("/jobs/{id}/debug", get(get_job_debug))
+        .route("/jobs/{id}/pause", post(pause_job))
+        .route("/jobs/{id}/resume", post(resume_job))
+        .route("/queue/reorder", post(reorder_queue))
+        .route("/library/trash", get(list_trash))
+        .route("/library/verify", post(verify_library))
+        .route("/library/{id}", delete(delete_library_item))
+        .route("/library/{id}/restore", post(restore_library_item))
+        .route("/library/{id}/verify", post(verify_library_item))
+        .route("/library/{id}/play", get(play_library_item))
+        .route("/library/{id}/share", post(share_library_item))
+        .route(
+            "/library/{id}/pin",
+            put(pin_library_item).delete(unpin_library_item),
+        )
+        .route("/library/{id}/stream.m3u8", get(get_hls_playlist))
+        .route("/library/{id}/playlist.m3u8", get(get_library_playlist))
+        .route("/library/{id}/entries/{name}", get(get_library_entry))
+        .route("/library/{id}/{segment}", get(get_hls_segment))
+        .route("/bookmarks", get(list_bookmarks).post(add_bookmark))
+        .route("/bookmarks/download", post(download_bookmarks))
+        .route("/bookmarks/{id}", delete(delete_bookmark))
+        .route("/export", get(export_history))
+        .route("/import", post(import_urls))
+        .route(
+            "/import/history",
+            post(import_history).layer(DefaultBodyLimit::max(MAX_HISTORY_IMPORT_BYTES)),
+        )
+        .route("/quick", get(quick_download))
+        .route("/info", get(get_info))
+        .route("/simulate", get(simulate_download))
+        .route("/playlist", get(get_playlist))
+        .route("/search", get(search_jobs))
+        .route("/storage", get(get_storage))
+        .route("/graphql", get(graphiql).post(run_graphql))
+        .route("/admin/stats", get(get_stats))
+        .route("/admin/cache/clear", post(clear_cache))
+        .route("/admin/logs/stream", get(stream_server_log))
+        .route("/worker/claim", post(claim_job))
+        .route("/worker/jobs/{id}/heartbeat", post(renew_lease))
+        .route(
+            "/worker/jobs/{id}/files/{name}",
+            put(upload_job_file).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/worker/jobs/{id}/finish", post(finish_claimed_job))
+        .route("/worker/jobs/{id}/fail", post(fail_claimed_job))
+        .route("/push/key", get(get_push_key))
+        .route(
+            "/push/subscriptions",
+            post(subscribe_push).delete(unsubscribe_push),
+        )
+        .with_state(state.clone())
+        .nest("/plugins", state.plugins.router());
+
+    let static_dir = ServeDir::new("static");
+    Router::new()
+        .route("/health", get(healthcheck))
+        .route("/share/{id}", get(get_shared_file))
+        .route("/once/{token}", get(get_once_file))
+        .with_state(state.clone())
+        .nest("/api", api)
+        .fallback_service(static_dir)
+}
+
+#[instrument]
+async fn healthcheck() -> &'static str {
+    "OK"
+}
+
+#[derive(Deserialize, Debug)]
+struct DownloadVideoRequest {
+    url: String,
+    /// Name of a configured destination to upload to instead of streaming.
+    dest: Option<String>,
+    /// Comma-separated labels, e.g. `music,project-x`.
+    tags: Option<String>,
+    /// Transcode profile, see [`JobOptions`].
+    profile: Option<String>,
+    /// Normalize loudness, see [`JobOptions`].
+    #[serde(default)]
+    normalize: bool,
+    /// Size or bitrate to re-encode to, see [`JobOptions`].
+    target_size: Option<String>,
+    video_bitrate: Option<String>,
+    #[serde(default)]
+    two_pass: bool,
+    /// Preferred video codec, see [`JobOptions`].
+    vcodec: Option<VideoCodec>,
+    /// Preferred frame rate, see [`JobOptions`].
+    fps: Option<FrameRate>,
+    /// HDR handling, see [`JobOptions`].
+    hdr: Option<Hdr>,
+    /// Vertical video framing, see [`JobOptions`].
+    widescreen: Option<Widescreen>,
+    /// Fetch comments, see [`JobOptions`].
+    #[serde(default)]
+    comments: bool,
+    /// Embed chapter markers, see [`JobOptions`].
+    #[serde(default)]
+    embed_chapters: bool,
+    /// Keep the chat replay, see [`JobOptions`].
+    #[serde(default)]
+    chat: bool,
+    /// Semicolon-separated `extractor:key=value` pairs, e.g.
+    /// `youtube:player_client=android,web;youtube:lang=en`.
+    extractor_args: Option<String>,
+    /// Bundle the description, see [`JobOptions`].
+    #[serde(default)]
+    description: bool,
+    /// Bundle the info JSON, see [`JobOptions`].
+    #[serde(default)]
+    info_json: bool,
+    /// Playlist entries to download, see [`JobOptions`].
+    items: Option<String>,
+    /// Download playlist entries in reverse order.
+    #[serde(default)]
+    reverse: bool,
+    /// Channel tab, see [`JobOptions`].
+    tab: Option<ChannelTab>,
+    /// Most recent entries to download, see [`JobOptions`].
+    limit: Option<u32>,
+    /// Earliest upload date, `YYYY-MM-DD`.
+    after: Option<String>,
+    /// Latest upload date, `YYYY-MM-DD`.
+    before: Option<String>,
+    /// Entry filters, see [`MatchFilter`].
+    min_duration: Option<u32>,
+    max_duration: Option<u32>,
+    min_views: Option<u64>,
+    title: Option<String>,
+    #[serde(default)]
+    skip_live: bool,
+    /// Split into chapter tracks, see [`JobOptions`].
+    #[serde(default)]
+    album: bool,
+    /// Join a playlist into an audiobook, see [`JobOptions`].
+    #[serde(default)]
+    audiobook: bool,
+    /// Comma-separated subtitle languages, e.g. `en,de`.
+    subs: Option<String>,
+    /// Subtitle format, see [`JobOptions`].
+    sub_format: Option<SubFormat>,
+    /// Subtitle language to burn in, see [`JobOptions`].
+    burn_subs: Option<String>,
+    /// Comma-separated audio track languages, or `all`.
+    audio: Option<String>,
+    /// Tag overrides, see [`MetadataOverride`].
+    meta_title: Option<String>,
+    meta_artist: Option<String>,
+    meta_album: Option<String>,
+    meta_genre: Option<String>,
+    /// File name template, see [`JobOptions::filename`].
+    filename: Option<String>,
+    /// ASCII-only file names, see [`JobOptions::restrict_filenames`].
+    restrict_filenames: Option<bool>,
+    /// Parallel fragment downloads, see [`JobOptions::fragments`].
+    fragments: Option<u32>,
+    /// Verbose yt-dlp output, see [`JobOptions::debug`].
+    #[serde(default)]
+    debug: bool,
+    /// Append yt-dlp's stderr excerpt to error responses.
+    #[serde(default)]
+    details: bool,
+    /// What to respond with once the download is done.
+    #[serde(default)]
+    respond: Respond,
+    /// Seconds to wait for the download with `respond=json`.
+    wait: Option<u64>,
+}
+
+impl DownloadVideoRequest {
+    fn options(&self) -> JobOptions {
+        JobOptions {
+            profile: self.profile.clone(),
+            normalize: self.normalize,
+            target_size: self.target_size.clone(),
+            video_bitrate: self.video_bitrate.clone(),
+            two_pass: self.two_pass,
+            vcodec: self.vcodec,
+            fps: self.fps,
+            hdr: self.hdr,
+            widescreen: self.widescreen,
+            comments: self.comments,
+            embed_chapters: self.embed_chapters,
+            chat: self.chat,
+            extractor_args: split_extractor_args(self.extractor_args.as_deref()),
+            description: self.description,
+            info_json: self.info_json,
+            items: self.items.clone(),
+            reverse: self.reverse,
+            tab: self.tab,
+            limit: self.limit,
+            after: self.after.clone(),
+            before: self.before.clone(),
+            filter: MatchFilter {
+                min_duration: self.min_duration,
+                max_duration: self.max_duration,
+                min_views: self.min_views,
+                title: self.title.clone(),
+                skip_live: self.skip_live,
+            },
+            album: self.album,
+            audiobook: self.audiobook,
+            subs: split_list(self.subs.as_deref()),
+            sub_format: self.sub_format,
+            burn_subs: self.burn_subs.clone(),
+            audio: split_list(self.audio.as_deref()),
+            metadata: MetadataOverride {
+                title: self.meta_title.clone(),
+                artist: self.meta_artist.clone(),
+                album: self.meta_album.clone(),
+                genre: self.meta_genre.clone(),
+            },
+            filename: self.filename.clone(),
+            restrict_filenames: self.restrict_filenames,
+            fragments: self.fragments,
+            debug: self.debug,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Respond {
+    /// The file itself, streamed as it downloads.
+    #[default]
+    File,
+    /// JSON with a short-lived link to the file, for clients like iOS
+    /// Shortcuts that handle a quick JSON reply better than a long download.
+    Json,
+}
+
+/// Splits a comma-separated query parameter, skipping empty items.
+fn split_list(value: Option<&str>) -> Vec<String> {
+    value
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Splits `extractor:key=value` pairs separated by semicolons. A pair
+/// without a value is kept with an empty one, for validation to reject.
+fn split_extractor_args(value: Option<&str>) -> BTreeMap<String, String> {
+    value
+        .unwrap_or_default()
+        .split(';')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (key.trim().to_string(), value.trim().to_string()),
+            None => (pair.to_string(), String::new()),
+        })
+        .collect()
+}
+
+#[instrument(skip(state, headers))]
+async fn download_video(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    RawQuery(query): RawQuery,
+    Query(payload): Query<DownloadVideoRequest>,
+) -> Response<Body> {
+    let tags = payload.tags.as_deref().unwrap_or_default().split(',');
+    let tags = match jobs::normalize_tags(tags) {
+        Ok(tags) => tags,
+        Err(tag) => return DownloadError::InvalidTag(tag).into_response(),
+    };
+    let user = match check_quota(&state, &headers) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Download rejected: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let options = payload.options();
+    if let Err(e) = check_debug(&state, &headers, &options) {
+        return e.into_response();
+    }
+    if payload.respond == Respond::Json {
+        return wait_for_download(&state, &headers, &payload, user, &tags, options).await;
+    }
+    let job = state.jobs.create(
+        &payload.url,
+        payload.dest.as_deref(),
+        user.as_deref(),
+        &tags,
+        options,
+        JobMode::Stream,
+    );
+    let job_id = job.id();
+
+    let mut response = match run_download(&state, &job, &payload).await {
+        Ok(response) => {
+            // A teed download is still running; its stream finishes the job.
+            if response.extensions().get::<tee::Following>().is_none() {
+                job.complete();
+            }
+            response
+        }
+        Err(e) => {
+            error!("Download failed: {:?}", e);
+            if let Some(response) = proxy_upstream(&state, &headers, &job, &e, query).await {
+                job.complete();
+                response
+            } else {
+                job.fail(&e);
+                let stderr_tail = match state.jobs.get(job_id) {
+                    Some(job) if payload.details => job.stderr_tail,
+                    _ => Vec::new(),
+                };
+                error_response(e, &stderr_tail).await
+            }
+        }
+    };
+    response
+        .headers_mut()
+        .insert("x-job-id", job_id.to_string().parse().unwrap());
+    response
+}
+
+/// Retries a download yt-dlp failed on here through the instance at
+/// `UPSTREAM_URL`, streaming its response back. `None` when there is none,
+/// the failure would happen there too, or it failed as well.
+async fn proxy_upstream(
+    state: &AppState,
+    headers: &HeaderMap,
+    job: &JobHandle,
+    error: &DownloadError,
+    query: Option<String>,
+) -> Option<Response<Body>> {
+    let upstream = state.upstream.as_ref()?;
+    if !error.may_work_elsewhere() || headers.contains_key(upstream::PROXIED_HEADER) {
+        return None;
+    }
+    match upstream
+        .download(&query.unwrap_or_default(), job.clone())
+        .await
+    {
+        Ok(response) => {
+            job.log_output(&format!(
+                "Downloaded through upstream instance after local failure: {}",
+                error
+            ));
+            Some(response)
+        }
+        Err(e) => {
+            error!("Upstream download failed: {:?}", e);
+            None
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DownloadResult {
+    id: Uuid,
+    status: JobStatus,
+    filename: Option<String>,
+    /// Link to the finished file; short-lived for files kept on this server.
+    url: Option<String>,
+    /// Unix timestamp in seconds after which `url` stops working, if it does.
+    expires_at: Option<u64>,
+    error: Option<String>,
+}
+
+/// Queues the download and waits for it, up to `wait` seconds, then
+/// responds with a link to the file instead of the file itself. A download
+/// that takes longer answers `202` with its job id, to poll
+/// `GET /api/jobs/{id}`.
+async fn wait_for_download(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &DownloadVideoRequest,
+    user: Option<String>,
+    tags: &[String],
+    options: JobOptions,
+) -> Response<Body> {
+    let wait = payload.wait.unwrap_or(DEFAULT_SYNC_WAIT_SECS);
+    if wait == 0 || wait > MAX_SYNC_WAIT_SECS {
+        let message = format!("wait must be between 1 and {}", MAX_SYNC_WAIT_SECS);
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+    let checked = download::resolve_storage(state, payload.dest.as_deref())
+        .and_then(|_| download::resolve_profile(state, &options))
+        .and_then(|_| download::check_options(&payload.url, &options));
+    if let Err(e) = checked {
+        return e.into_response();
+    }
+
+    // Subscribed before queueing so the job can't finish unseen.
+    let mut finished = state.jobs.subscribe_finished();
+    let job = state.jobs.create(
+        &payload.url,
+        payload.dest.as_deref(),
+        user.as_deref(),
+        tags,
+        options,
+        JobMode::Queued,
+    );
+    let id = job.id();
+    state.queue.push(job);
+
+    let done = tokio::time::timeout(Duration::from_secs(wait), async {
+        loop {
+            match finished.recv().await {
+                Ok(job) if job.id == id => return Some(job),
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+    .await;
+    // A lagged receiver may have missed it, so the job itself has the say.
+    let job = match done {
+        Ok(Some(job)) => job,
+        _ => match state.jobs.get(id) {
+            Some(job) => job,
+            None => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+    };
+
+    let mut result = DownloadResult {
+        id,
+        status: job.status,
+        filename: job.filename.clone(),
+        url: None,
+        expires_at: None,
+        error: job.error.clone(),
+    };
+    let status = match job.status {
+        JobStatus::Completed => {
+            match &job.location {
+                Some(location)
+                    if location.starts_with("https://") || location.starts_with("http://") =>
+                {
+                    result.url = Some(location.clone());
+                }
+                _ if job.output.is_some() => {
+                    let signature = state.shares.sign(id, SYNC_LINK_TTL);
+                    let origin = request_origin(headers).unwrap_or_default();
+                    result.url = Some(format!("{}{}", origin, signature.path(id)));
+                    result.expires_at = Some(signature.expires);
+                }
+                _ => {}
+            }
+            StatusCode::OK
+        }
+        JobStatus::Failed => StatusCode::INTERNAL_SERVER_ERROR,
+        JobStatus::Queued | JobStatus::Running | JobStatus::Paused => StatusCode::ACCEPTED,
+    };
+    let mut response = (status, Json(result)).into_response();
+    response
+        .headers_mut()
+        .insert("x-job-id", id.to_string().parse().unwrap());
+    response
+}
+
+#[derive(Deserialize, Debug)]
+struct ClipRequest {
+    /// Video to cut the clip from; only the needed section is downloaded.
+    url: Option<String>,
+    /// Job whose kept file to cut the clip from, instead of `url`.
+    id: Option<Uuid>,
+    /// Timestamps like `90`, `1:30` or `0:01:30.5`.
+    start: String,
+    end: String,
+    format: ClipFormat,
+}
+
+#[instrument(skip(state, headers))]
+async fn create_clip(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<ClipRequest>,
+) -> Response<Body> {
+    let section = match Section::parse(&payload.start, &payload.end) {
+        Ok(section) => section,
+        Err(e) => return DownloadError::from(e).into_response(),
+    };
+    let url = match (&payload.url, payload.id) {
+        (Some(url), None) => url.clone(),
+        (None, Some(id)) => match state.jobs.get(id) {
+            Some(job) => job.url,
+            None => return DownloadError::NotInLibrary.into_response(),
+        },
+        _ => return (StatusCode::BAD_REQUEST, "Give either url or id").into_response(),
+    };
+    let user = match check_quota(&state, &headers) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Clip rejected: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let job = state.jobs.create(
+        &url,
+        None,
+        user.as_deref(),
+        &[],
+        JobOptions::default(),
+        JobMode::Stream,
+    );
+    let job_id = job.id();
+
+    let mut response = match run_clip(&state, &job, &payload, &url, section).await {
+        Ok(response) => {
+            job.complete();
+            response
+        }
+        Err(e) => {
+            error!("Clip failed: {:?}", e);
+            job.fail(&e);
+            e.into_response()
+        }
+    };
+    response
+        .headers_mut()
+        .insert("x-job-id", job_id.to_string().parse().unwrap());
+    response
+}
+
+async fn run_clip(
+    state: &AppState,
+    job: &JobHandle,
+    payload: &ClipRequest,
+    url: &str,
+    section: Section,
+) -> Result<Response<Body>, DownloadError> {
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let clip = match payload.id {
+        Some(id) => {
+            let input = library_file(state, id)
+                .await
+                .map_err(|_| DownloadError::NotInLibrary)?;
+            clip::convert(&input, Some(section), payload.format, job_dir.path(), job).await?
+        }
+        None => {
+            let url = hooks::rewrite_url(&state.config.hooks.url_rules, url)
+                .map_err(DownloadError::UrlRejected)?;
+            let input =
+                clip::fetch_section(&url, section, &state.config, job_dir.path(), job).await?;
+            clip::convert(&input, None, payload.format, job_dir.path(), job).await?
+        }
+    };
+
+    let filename = format!("clip.{}", payload.format.ext());
+    let mut headers = attachment_headers(&filename);
+    headers.insert(
+        header::CONTENT_TYPE,
+        payload.format.content_type().parse().unwrap(),
+    );
+    let stream = JobStream::open(&clip, job_dir, job).await?;
+
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+struct FrameRequest {
+    url: String,
+    /// Timestamp like `90` or `1:30`.
+    t: String,
+    #[serde(default)]
+    format: FrameFormat,
+}
+
+#[instrument(skip(state, headers))]
+async fn get_frame(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(payload): Query<FrameRequest>,
+) -> Response<Body> {
+    let at = match clip::parse_timestamp(&payload.t) {
+        Ok(at) => at,
+        Err(e) => return DownloadError::from(e).into_response(),
+    };
+    let user = match check_quota(&state, &headers) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Frame rejected: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let job = state.jobs.create(
+        &payload.url,
+        None,
+        user.as_deref(),
+        &[],
+        JobOptions::default(),
+        JobMode::Stream,
+    );
+    let job_id = job.id();
+
+    let mut response = match run_frame(&state, &job, &payload, at).await {
+        Ok(response) => {
+            job.complete();
+            response
+        }
+        Err(e) => {
+            error!("Frame extraction failed: {:?}", e);
+            job.fail(&e);
+            e.into_response()
+        }
+    };
+    response
+        .headers_mut()
+        .insert("x-job-id", job_id.to_string().parse().unwrap());
+    response
+}
+
+async fn run_frame(
+    state: &AppState,
+    job: &JobHandle,
+    payload: &FrameRequest,
+    at: f64,
+) -> Result<Response<Body>, DownloadError> {
+    let url = hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url)
+        .map_err(DownloadError::UrlRejected)?;
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let section =
+        clip::fetch_section(&url, Section::frame(at), &state.config, job_dir.path(), job).await?;
+    let frame = clip::extract_frame(&section, payload.format, job_dir.path(), job).await?;
+
+    let stream = JobStream::open(&frame, job_dir, job).await?;
+    Ok((
+        [(header::CONTENT_TYPE, payload.format.content_type())],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize, Debug)]
+struct ThumbnailRequest {
+    url: String,
+    #[serde(default)]
+    format: FrameFormat,
+}
+
+#[instrument(skip(state, headers))]
+async fn get_thumbnail(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(payload): Query<ThumbnailRequest>,
+) -> Response<Body> {
+    let user = match check_quota(&state, &headers) {
+        Ok(user) => user,
+        Err(e) => {
+            error!("Thumbnail rejected: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let job = state.jobs.create(
+        &payload.url,
+        None,
+        user.as_deref(),
+        &[],
+        JobOptions::default(),
+        JobMode::Stream,
+    );
+    let job_id = job.id();
+
+    let mut response = match run_thumbnail(&state, &job, &payload).await {
+        Ok(response) => {
+            job.complete();
+            response
+        }
+        Err(e) => {
+            error!("Thumbnail download failed: {:?}", e);
+            job.fail(&e);
+            e.into_response()
+        }
+    };
+    response
+        .headers_mut()
+        .insert("x-job-id", job_id.to_string().parse().unwrap());
+    response
+}
+
+async fn run_thumbnail(
+    state: &AppState,
+    job: &JobHandle,
+    payload: &ThumbnailRequest,
+) -> Result<Response<Body>, DownloadError> {
+    let url = hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url)
+        .map_err(DownloadError::UrlRejected)?;
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let thumbnail =
+        clip::fetch_thumbnail(&url, payload.format, &state.config, job_dir.path(), job).await?;
+
+    let stream = JobStream::open(&thumbnail, job_dir, job).await?;
+    Ok((
+        [(header::CONTENT_TYPE, payload.format.content_type())],
+        Body::from_stream(stream),
+    )
+        .into_response())
+}
+
+/// Identifies the caller from the proxy headers and refuses them once they
+/// have used up their role's quota.
+fn check_quota(state: &AppState, headers: &HeaderMap) -> Result<Option<String>, DownloadError> {
+    let Some(caller) = state.config.quotas.caller(headers) else {
+        return Ok(None);
+    };
+    state.config.quotas.check(&state.db, &caller)?;
+
+    Ok(Some(caller.user))
+}
+
+/// Refuses debug mode to callers without an admin role.
+fn check_debug(
+    state: &AppState,
+    headers: &HeaderMap,
+    options: &JobOptions,
+) -> Result<(), DownloadError> {
+    if options.debug && !state.config.quotas.is_admin(headers) {
+        return Err(DownloadError::DebugForbidden);
+    }
+    Ok(())
+}
+
+/// Error response for `e`, followed by the yt-dlp output excerpt if any.
+async fn error_response(e: DownloadError, stderr_tail: &[String]) -> Response<Body> {
+    let response = e.into_response();
+    if stderr_tail.is_empty() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let message = axum::body::to_bytes(body, usize::MAX)
+        .await
+        .unwrap_or_default();
+    let body = format!(
+        "{}\n\nyt-dlp output:\n{}\n",
+        String::from_utf8_lossy(&message),
+        stderr_tail.join("\n")
+    );
+    Response::from_parts(parts, Body::from(body))
+}
+
+async fn run_download(
+    state: &AppState,
+    job: &JobHandle,
+    payload: &DownloadVideoRequest,
+) -> Result<Response<Body>, DownloadError> {
+    let storage = download::resolve_storage(state, payload.dest.as_deref())?;
+    let (video, job_dir) =
+        if storage.is_none() && tee::eligible(state, &job.options(), &payload.url) {
+            match tee::start(state, job, &payload.url).await? {
+                Tee::Following { stream, filename } => {
+                    let mut response =
+                        (attachment_headers(&filename), Body::from_stream(stream)).into_response();
+                    response.extensions_mut().insert(tee::Following);
+                    return Ok(response);
+                }
+                Tee::Finished(video, job_dir) => (*video, job_dir),
+            }
+        } else {
+            let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+            let video = download::fetch(state, job, &payload.url, &job_dir).await?;
+            (video, job_dir)
+        };
+
+    if let Some(storage) = storage {
+        return Ok(match storage::store(storage.as_ref(), &video, job).await? {
+            Stored::Presigned(url) => Redirect::to(&url).into_response(),
+            Stored::Pushed(location) => (
+                StatusCode::CREATED,
+                [(header::LOCATION, location)],
+                format!("Saved {}", video.filename),
+            )
+                .into_response(),
+            Stored::Filed(path) => {
+                let location = path.display().to_string();
+                job.set_result(&video.filename, &location, Some(path));
+                (
+                    StatusCode::CREATED,
+                    [(header::LOCATION, location)],
+                    format!("Saved {}", video.filename),
+                )
+                    .into_response()
+            }
+        });
+    }
+
+    let (path, filename) = download::deliverable(&video, job).await?;
+    let stream = JobStream::open(&path, job_dir, job).await?;
+    let headers = attachment_headers(&filename);
+    debug!("{:?}", headers);
+
+    Ok((headers, Body::from_stream(stream)).into_response())
+}
+
+/// Headers sending `filename` as a download, typed from its extension.
+fn attachment_headers(filename: &str) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        attachment_disposition(filename).parse().unwrap(),
+    );
+    let content_type = mime_guess::from_path(filename).first_or_octet_stream();
+    headers.insert(header::CONTENT_TYPE, content_type.as_ref().parse().unwrap());
+    headers
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SubmitJobRequest {
+    pub url: String,
+    /// Name of a configured destination; the file is kept on this server for
+    /// `GET /api/jobs/{id}/file` when there is none.
+    pub dest: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Queue the job even if the same video is already being downloaded.
+    #[serde(default)]
+    pub force: bool,
+    #[serde(flatten)]
+    pub options: JobOptions,
+}
+
+/// Queues a download, or responds `200` with an identical job that's
+/// queued, running or recently finished instead, unless `force` is set.
+///
+/// A retried request with the same `Idempotency-Key` gets the job the first
+/// one created, marked with `Idempotent-Replayed: true`.
+#[instrument(skip(state, headers))]
+async fn submit_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<SubmitJobRequest>,
+) -> Result<Response<Body>, Response<Body>> {
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(value) => match value.to_str() {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => Some(key),
+            _ => {
+                return Err((StatusCode::BAD_REQUEST, "Invalid Idempotency-Key").into_response());
+            }
+        },
+        None => None,
+    };
+    let user = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    let _guard = match idempotency_key {
+        Some(key) => {
+            let Some(guard) = state.jobs.begin_idempotent(user.as_deref(), key) else {
+                return Err((
+                    StatusCode::CONFLICT,
+                    "A request with this Idempotency-Key is still being handled",
+                )
+                    .into_response());
+            };
+            let window = Duration::from_secs(state.config.idempotency_window_hours * 60 * 60);
+            if let Some(job) = state.jobs.find_idempotent(user.as_deref(), key, window) {
+                if job.url != payload.url
+                    || job.dest != payload.dest
+                    || job.options != payload.options
+                {
+                    return Err((
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "Idempotency-Key was used for a different request",
+                    )
+                        .into_response());
+                }
+                info!("Replaying job {} for Idempotency-Key {:?}", job.id, key);
+                return Ok((
+                    StatusCode::ACCEPTED,
+                    [(IDEMPOTENT_REPLAYED, "true")],
+                    Json(job),
+                )
+                    .into_response());
+            }
+            Some(guard)
+        }
+        None => None,
+    };
+
+    // A request that would be turned away shouldn't cost a yt-dlp lookup,
+    // nor learn about other users' downloads.
+    let (user, tags) =
+        check_request(&state, &headers, &payload).map_err(IntoResponse::into_response)?;
+    let key = lookup_video_key(&state, &payload.url).await;
+    if let Some(key) = &key
+        && !payload.force
+        && let Some(job) = state
+            .jobs
+            .find_duplicate(key, payload.dest.as_deref(), &payload.options)
+    {
+        info!("{} is already downloaded by job {}", payload.url, job.id);
+        return Ok((StatusCode::OK, Json(job)).into_response());
+    }
+    let id = queue_job(&state, payload, user, tags);
+    let job = state.jobs.handle(id);
+    if let Some(key) = &key {
+        job.set_video_key(key);
+    }
+    if let Some(key) = idempotency_key {
+        job.set_idempotency_key(key);
+    }
+    match state.jobs.get(id) {
+        Some(job) => Ok((StatusCode::ACCEPTED, Json(job)).into_response()),
+        None => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+/// `EXTRACTOR-ID` of the video at `url`, after rewrite rules. A failed
+/// lookup only means duplicates aren't caught; the download reports the
+/// actual problem.
+async fn lookup_video_key(state: &AppState, url: &str) -> Option<String> {
+    let url = hooks::rewrite_url(&state.config.hooks.url_rules, url).ok()?;
+    info::video_key(&url, &state.config)
+        .await
+        .inspect_err(|e| warn!("Failed to look up video key for {}: {:?}", url, e))
+        .ok()
+}
+
+/// Checks a job request and queues it, charging the caller's quota.
+pub fn enqueue(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: SubmitJobRequest,
+) -> Result<Uuid, DownloadError> {
+    let (user, tags) = check_request(state, headers, &payload)?;
+    Ok(queue_job(state, payload, user, tags))
+}
+
+/// Validates a job request and checks the caller's quota, returning the
+/// caller and the job's tags.
+fn check_request(
+    state: &AppState,
+    headers: &HeaderMap,
+    payload: &SubmitJobRequest,
+) -> Result<(Option<String>, Vec<String>), DownloadError> {
+    download::resolve_storage(state, payload.dest.as_deref())?;
+    download::resolve_profile(state, &payload.options)?;
+    download::check_options(&payload.url, &payload.options)?;
+    check_debug(state, headers, &payload.options)?;
+    let tags = jobs::normalize_tags(payload.tags.iter().map(String::as_str))
+        .map_err(DownloadError::InvalidTag)?;
+    let user = check_quota(state, headers).inspect_err(|e| error!("Job rejected: {:?}", e))?;
+    Ok((user, tags))
+}
+
+fn queue_job(
+    state: &AppState,
+    payload: SubmitJobRequest,
+    user: Option<String>,
+    tags: Vec<String>,
+) -> Uuid {
+    let job = state.jobs.create(
+        &payload.url,
+        payload.dest.as_deref(),
+        user.as_deref(),
+        &tags,
+        payload.options,
+        JobMode::Queued,
+    );
+    let id = job.id();
+    state.queue.push(job);
+    id
+}
+
+#[derive(Deserialize, Debug)]
+struct ListJobsRequest {
+    /// Search the whole history for jobs with this tag instead of listing
+    /// recent jobs.
+    tag: Option<String>,
+}
+
+#[instrument(skip(state))]
+async fn list_jobs(
+    State(state): State<AppState>,
+    Query(payload): Query<ListJobsRequest>,
+) -> Json<Vec<Job>> {
+    match payload.tag {
+        Some(tag) => Json(state.jobs.list_tagged(tag.trim())),
+        None => Json(state.jobs.list()),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BookmarkRequest {
+    url: String,
+}
+
+/// Saves a URL for later, with its title and thumbnail.
+#[instrument(skip(state, headers))]
+async fn add_bookmark(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<BookmarkRequest>,
+) -> Result<(StatusCode, Json<Bookmark>), Response<Body>> {
+    let url =
+        hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url).map_err(|reason| {
+            info!("Rejected URL {}: {}", payload.url, reason);
+            DownloadError::UrlRejected(reason).into_response()
+        })?;
+    let info = info::probe(&url, &state.config).await.map_err(|e| {
+        error!("Bookmark lookup failed: {:?}", e);
+        e.into_response()
+    })?;
+
+    let user = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    let bookmark = Bookmark::new(&payload.url, info, user);
+    state.db.save_bookmark(&bookmark).map_err(|e| {
+        error!("Failed to save bookmark: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+    Ok((StatusCode::CREATED, Json(bookmark)))
+}
+
+#[instrument(skip(state, headers))]
+async fn list_bookmarks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<Bookmark>>, StatusCode> {
+    let user = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    state.db.bookmarks(user.as_deref()).map(Json).map_err(|e| {
+        error!("Failed to load bookmarks: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[instrument(skip(state, headers))]
+async fn delete_bookmark(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<Uuid>,
+) -> StatusCode {
+    let user = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    match state.db.delete_bookmark(id, user.as_deref()) {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            error!("Failed to delete bookmark: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Settings for the jobs created from bookmarks, as for `POST /api/jobs`.
+#[derive(Deserialize, Debug, Default)]
+struct DownloadBookmarksRequest {
+    dest: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+/// Queues a job for each of the caller's bookmarks, oldest first, removing
+/// the bookmarks as they are queued. Stops early when the caller runs out of
+/// quota, leaving the rest saved.
+#[instrument(skip(state, headers))]
+async fn download_bookmarks(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    payload: Option<Json<DownloadBookmarksRequest>>,
+) -> Result<(StatusCode, Json<Vec<Job>>), Response<Body>> {
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    if let Err(e) = download::resolve_storage(&state, payload.dest.as_deref()) {
+        return Err(e.into_response());
+    }
+    if let Err(e) = download::resolve_profile(&state, &payload.options) {
+        return Err(e.into_response());
+    }
+    if let Err(e) = check_debug(&state, &headers, &payload.options) {
+        return Err(e.into_response());
+    }
+    let tags = jobs::normalize_tags(payload.tags.iter().map(String::as_str))
+        .map_err(|tag| DownloadError::InvalidTag(tag).into_response())?;
+    let caller = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    let bookmarks = state.db.bookmarks(caller.as_deref()).map_err(|e| {
+        error!("Failed to load bookmarks: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })?;
+
+    let mut queued = Vec::new();
+    for bookmark in bookmarks {
+        if let Err(e) = download::check_options(&bookmark.url, &payload.options) {
+            return Err(e.into_response());
+        }
+        let user = match check_quota(&state, &headers) {
+            Ok(user) => user,
+            Err(e) if queued.is_empty() => {
+                error!("Bookmark download rejected: {:?}", e);
+                return Err(e.into_response());
+            }
+            Err(e) => {
+                info!("Stopped queueing bookmarks: {:?}", e);
+                break;
+            }
+        };
+        let job = state.jobs.create(
+            &bookmark.url,
+            payload.dest.as_deref(),
+            user.as_deref(),
+            &tags,
+            payload.options.clone(),
+            JobMode::Queued,
+        );
+        let id = job.id();
+        state.queue.push(job);
+        if let Err(e) = state.db.delete_bookmark(bookmark.id, caller.as_deref()) {
+            error!("Failed to delete queued bookmark: {:?}", e);
+        }
+        queued.extend(state.jobs.get(id));
+    }
+
+    Ok((StatusCode::ACCEPTED, Json(queued)))
+}
+
+#[derive(Deserialize, Debug)]
+struct ImportRequest {
+    /// Destination for every imported job, as for `POST /api/jobs`.
+    dest: Option<String>,
+    /// Comma-separated labels for every imported job.
+    tags: Option<String>,
+}
+
+/// Queues a job for each URL in an uploaded text, CSV or OPML list,
+/// reporting which lines were accepted and why the others weren't.
+#[instrument(skip(state, headers, body))]
+async fn import_urls(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(payload): Query<ImportRequest>,
+    body: String,
+) -> Result<Json<ImportSummary>, Response<Body>> {
+    if let Err(e) = download::resolve_storage(&state, payload.dest.as_deref()) {
+        return Err(e.into_response());
+    }
+    let tags = jobs::normalize_tags(payload.tags.as_deref().unwrap_or("").split(','))
+        .map_err(|tag| DownloadError::InvalidTag(tag).into_response())?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok());
+    let entries = import::parse(&body, import::Format::detect(content_type, &body));
+    if entries.len() > import::MAX_ENTRIES {
+        let message = format!("At most {} URLs per import", import::MAX_ENTRIES);
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, message).into_response());
+    }
+
+    let mut summary = ImportSummary::default();
+    for entry in entries {
+        let checked = import::check_url(&entry.url).and_then(|()| {
+            hooks::rewrite_url(&state.config.hooks.url_rules, &entry.url)
+                .map_err(|reason| format!("URL rejected: {}", reason))?;
+            check_quota(&state, &headers).map_err(|e| e.to_string())
+        });
+        let user = match checked {
+            Ok(user) => user,
+            Err(reason) => {
+                summary.rejected.push(Rejected {
+                    line: entry.line,
+                    url: entry.url,
+                    reason,
+                });
+                continue;
+            }
+        };
+        let job = state.jobs.create(
+            &entry.url,
+            payload.dest.as_deref(),
+            user.as_deref(),
+            &tags,
+            JobOptions::default(),
+            JobMode::Queued,
+        );
+        summary.accepted.push(Accepted {
+            line: entry.line,
+            url: entry.url,
+            job_id: job.id(),
+        });
+        state.queue.push(job);
+    }
+    info!(
+        "Imported {} URLs, rejected {}",
+        summary.accepted.len(),
+        summary.rejected.len()
+    );
+
+    Ok(Json(summary))
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportRequest {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+/// Dumps the whole history, for backups or moving to another instance.
+#[instrument(skip(state))]
+async fn export_history(
+    State(state): State<AppState>,
+    Query(payload): Query<ExportRequest>,
+) -> Result<Response<Body>, StatusCode> {
+    let jobs = state.jobs.all().map_err(|e| {
+        error!("Failed to load history: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let (body, filename, content_type) = match payload.format {
+        ExportFormat::Json => {
+            let records: Vec<ExportRecord> = jobs.iter().map(ExportRecord::from).collect();
+            let body = serde_json::to_vec(&records).map_err(|e| {
+                error!("Failed to serialize history: {:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+            (body, "yt-dlp-web-history.json", "application/json")
+        }
+        ExportFormat::Csv => (
+            export::to_csv(&jobs).into_bytes(),
+            "yt-dlp-web-history.csv",
+            "text/csv; charset=utf-8",
+        ),
+    };
+    let mut headers = attachment_headers(filename);
+    headers.insert(header::CONTENT_TYPE, content_type.parse().unwrap());
+    Ok((headers, body).into_response())
+}
+
+/// Adds the finished jobs of a JSON export to the history. Jobs that are
+/// already known or weren't finished are skipped.
+#[instrument(skip(state, records))]
+async fn import_history(
+    State(state): State<AppState>,
+    Json(records): Json<Vec<ExportRecord>>,
+) -> Json<HistoryImport> {
+    let mut summary = HistoryImport::default();
+    for record in records {
+        let id = record.id;
+        let reason = match record.into_job() {
+            Ok(job) if state.jobs.import(&job) => {
+                summary.imported += 1;
+                continue;
+            }
+            Ok(_) => "already exists",
+            Err(reason) => reason,
+        };
+        summary.skipped.push(Skipped { id, reason });
+    }
+    info!(
+        "Imported {} jobs into the history, skipped {}",
+        summary.imported,
+        summary.skipped.len()
+    );
+
+    Json(summary)
+}
+
+#[derive(Deserialize)]
+struct QuickRequest {
+    token: String,
+    url: String,
+    /// Respond with an empty `204` instead of the job id.
+    #[serde(default)]
+    quiet: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct QuickQueued {
+    id: Uuid,
+}
+
+/// Queues a download with default settings from a single GET, for
+/// bookmarklets, iOS Shortcuts and share sheets. The token in the query
+/// string stands in for the proxy's login.
+#[instrument(skip(state, payload))]
+async fn quick_download(
+    State(state): State<AppState>,
+    Query(payload): Query<QuickRequest>,
+) -> Result<Response<Body>, Response<Body>> {
+    let Some(caller) = quick::caller(
+        &state.config.quick_tokens,
+        &state.config.quotas,
+        &payload.token,
+    ) else {
+        return Err(StatusCode::UNAUTHORIZED.into_response());
+    };
+    hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url).map_err(|reason| {
+        info!("Rejected URL {}: {}", payload.url, reason);
+        DownloadError::UrlRejected(reason).into_response()
+    })?;
+    state.config.quotas.check(&state.db, &caller).map_err(|e| {
+        error!("Quick download rejected: {:?}", e);
+        DownloadError::from(e).into_response()
+    })?;
+
+    let job = state.jobs.create(
+        &payload.url,
+        None,
+        Some(&caller.user),
+        &[],
+        JobOptions::default(),
+        JobMode::Queued,
+    );
+    let id = job.id();
+    state.queue.push(job);
+    info!("Queued {} for {} via quick link", id, caller.user);
+
+    // Bookmarklets run on the page's origin and may want to read the reply.
+    let cors = [(header::ACCESS_CONTROL_ALLOW_ORIGIN, "*")];
+    if payload.quiet {
+        return Ok((StatusCode::NO_CONTENT, cors).into_response());
+    }
+    Ok((StatusCode::ACCEPTED, cors, Json(QuickQueued { id })).into_response())
+}
+
+#[derive(Deserialize, Debug)]
+struct InfoRequest {
+    url: String,
+}
+
+#[instrument(skip(state))]
+async fn get_info(
+    State(state): State<AppState>,
+    Query(payload): Query<InfoRequest>,
+) -> Result<Json<MediaInfo>, DownloadError> {
+    let url =
+        hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url).map_err(|reason| {
+            info!("Rejected URL {}: {}", payload.url, reason);
+            DownloadError::UrlRejected(reason)
+        })?;
+    let info = info::probe(&url, &state.config).await.inspect_err(|e| {
+        error!("Info lookup failed: {:?}", e);
+    })?;
+    Ok(Json(info))
+}
+
+/// Works out what `GET /api/download` with the same query would run and
+/// deliver, without downloading.
+#[instrument(skip(state))]
+async fn simulate_download(
+    State(state): State<AppState>,
+    Query(payload): Query<DownloadVideoRequest>,
+) -> Result<Json<Simulation>, DownloadError> {
+    let streamed = payload.respond != Respond::Json;
+    let simulation = simulate::simulate(
+        &state,
+        &payload.url,
+        &payload.options(),
+        payload.dest.as_deref(),
+        streamed,
+    )
+    .await
+    .inspect_err(|e| error!("Simulation failed: {:?}", e))?;
+    Ok(Json(simulation))
+}
+
+#[derive(Deserialize, Debug)]
+struct PlaylistRequest {
+    url: String,
+    /// Channel tab to list, for YouTube channel URLs.
+    tab: Option<ChannelTab>,
+}
+
+#[instrument(skip(state))]
+async fn get_playlist(
+    State(state): State<AppState>,
+    Query(payload): Query<PlaylistRequest>,
+) -> Result<Json<Playlist>, DownloadError> {
+    let url =
+        hooks::rewrite_url(&state.config.hooks.url_rules, &payload.url).map_err(|reason| {
+            info!("Rejected URL {}: {}", payload.url, reason);
+            DownloadError::UrlRejected(reason)
+        })?;
+    let url = match payload.tab {
+        Some(tab) => playlist::channel_tab_url(&url, tab).ok_or(DownloadError::NotAChannel)?,
+        None => url,
+    };
+    let playlist = playlist::preview(&url, &state.config)
+        .await
+        .inspect_err(|e| {
+            error!("Playlist preview failed: {:?}", e);
+        })?;
+    Ok(Json(playlist))
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchRequest {
+    q: String,
+}
+
+#[instrument(skip(state))]
+async fn search_jobs(
+    State(state): State<AppState>,
+    Query(payload): Query<SearchRequest>,
+) -> Json<Vec<Job>> {
+    Json(state.jobs.search(&payload.q))
+}
+
+#[instrument(skip(state))]
+async fn get_job(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    state.jobs.get(id).map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Holds a queued job, or stops a running one so it can be resumed later.
+/// A running job is paused once its worker has stopped, so the response is
+/// `202` with the job still running.
+#[instrument(skip(state))]
+async fn pause_job(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, &'static str)> {
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, "No such job"))?;
+    match job.status {
+        JobStatus::Queued | JobStatus::Running => {}
+        JobStatus::Paused => return Ok((StatusCode::OK, Json(job))),
+        JobStatus::Completed | JobStatus::Failed => {
+            return Err((StatusCode::CONFLICT, "Job has already finished"));
+        }
+    }
+    let status = match state.queue.pause(id) {
+        Pause::Held(handle) => {
+            handle.pause();
+            StatusCode::OK
+        }
+        Pause::Stopping => StatusCode::ACCEPTED,
+        Pause::NotHere => {
+            return Err((StatusCode::CONFLICT, "Job isn't run by this server's queue"));
+        }
+    };
+    info!("Pausing job {}", id);
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, "No such job"))?;
+    Ok((status, Json(job)))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReorderRequest {
+    /// Queued jobs to run next, in this order.
+    #[serde(default)]
+    pub ids: Vec<Uuid>,
+    /// A single job to run next, as with `ids: [top]`.
+    pub top: Option<Uuid>,
+}
+
+/// Bumps queued jobs ahead of the rest of the queue. Responds with the
+/// waiting jobs in their new order.
+#[instrument(skip(state))]
+async fn reorder_queue(
+    State(state): State<AppState>,
+    Json(payload): Json<ReorderRequest>,
+) -> Result<Json<Vec<Job>>, (StatusCode, String)> {
+    let ids = match (payload.top, payload.ids.is_empty()) {
+        (Some(top), true) => vec![top],
+        (None, false) => payload.ids,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "Give either ids or top".to_string(),
+            ));
+        }
+    };
+    state.queue.reorder(&ids).map_err(|id| {
+        (
+            StatusCode::CONFLICT,
+            format!("Job {} isn't waiting in the queue", id),
+        )
+    })?;
+    info!("Moved {} jobs to the front of the queue", ids.len());
+    let jobs = state
+        .queue
+        .ids()
+        .into_iter()
+        .filter_map(|id| state.jobs.get(id))
+        .collect();
+    Ok(Json(jobs))
+}
+
+/// Puts a paused job back in the queue. Partial files it left are picked up
+/// with yt-dlp's `--continue`.
+#[instrument(skip(state))]
+async fn resume_job(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<(StatusCode, Json<Job>), (StatusCode, &'static str)> {
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, "No such job"))?;
+    if job.status != JobStatus::Paused {
+        return Err((StatusCode::CONFLICT, "Job isn't paused"));
+    }
+    let handle = state.jobs.handle(id);
+    handle.requeue();
+    state.queue.push(handle);
+    info!("Resuming job {}", id);
+    let job = state
+        .jobs
+        .get(id)
+        .ok_or((StatusCode::NOT_FOUND, "No such job"))?;
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+#[instrument(skip(state))]
+async fn get_job_file(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Response<Body>, StatusCode> {
+    send_kept_file(&state, id).await
+}
+
+/// Sends a finished job's kept file as an attachment.
+async fn send_kept_file(state: &AppState, id: Uuid) -> Result<Response<Body>, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let output = job
+        .output
+        .filter(|_| job.trashed_at.is_none())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let file = File::open(&output).await.map_err(|e| {
+        error!("Error when opening {:?}: {:?}", output, e);
+        StatusCode::NOT_FOUND
+    })?;
+
+    state.jobs.touch(id);
+    let filename = job.filename.unwrap_or_else(|| "video.mp4".to_string());
+    let mut headers = attachment_headers(&filename);
+    insert_checksum(&mut headers, job.sha256.as_deref());
+    let body = Body::from_stream(JobStream::new(file, state.jobs.handle(id), None));
+    Ok((headers, body).into_response())
+}
+
+fn insert_checksum(headers: &mut HeaderMap, sha256: Option<&str>) {
+    if let Some(Ok(value)) = sha256.map(|sha256| format!("sha256={}", sha256).parse()) {
+        headers.insert(X_CHECKSUM, value);
+    }
+}
+
+/// Path of a finished job's file kept on this server, either for download
+/// or in a library destination.
+async fn library_file(state: &AppState, id: Uuid) -> Result<PathBuf, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let output = job
+        .output
+        .filter(|_| job.trashed_at.is_none())
+        .ok_or(StatusCode::NOT_FOUND)?;
+    match tokio::fs::try_exists(&output).await {
+        Ok(true) => {
+            state.jobs.touch(id);
+            Ok(output)
+        }
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct ShareRequest {
+    /// How long the link works, up to `SHARE_MAX_TTL_HOURS`; defaults to
+    /// `SHARE_TTL_HOURS`.
+    hours: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct ShareLink {
+    /// Path of the signed link, outside `/api`.
+    url: String,
+    /// Unix timestamp in seconds.
+    expires_at: u64,
+}
+
+/// Signs a link to a kept file that works without access to the API.
+#[instrument(skip(state))]
+async fn share_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+    payload: Option<Json<ShareRequest>>,
+) -> Result<Json<ShareLink>, StatusCode> {
+    let payload = payload.map(|Json(payload)| payload).unwrap_or_default();
+    let hours = payload.hours.unwrap_or(state.config.share_ttl_hours);
+    if hours == 0 || hours > state.config.share_max_ttl_hours {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    library_file(&state, id).await?;
+
+    let signature = state.shares.sign(id, Duration::from_secs(hours * 60 * 60));
+    Ok(Json(ShareLink {
+        url: signature.path(id),
+        expires_at: signature.expires,
+    }))
+}
+
+/// Sends a kept file to anyone holding a valid share link.
+#[instrument(skip(state, signature))]
+async fn get_shared_file(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+    Query(signature): Query<Signature>,
+) -> Result<Response<Body>, StatusCode> {
+    if !state.shares.verify(id, &signature) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    send_kept_file(&state, id).await
+}
+
+#[derive(Serialize, Debug)]
+struct DownloadToken {
+    /// Path of the single-use link, outside `/api`.
+    url: String,
+    /// Unix timestamp in seconds after which an unused link stops working.
+    expires_at: u64,
+}
+
+/// Creates a link to a kept file that works for one download.
+#[instrument(skip(state))]
+async fn create_download_token(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<DownloadToken>, StatusCode> {
+    library_file(&state, id).await?;
+
+    let token = share::new_token();
+    let now = share::unix_now();
+    let expires_at = now + state.config.share_ttl_hours * 60 * 60;
+    state
+        .db
+        .save_download_token(&token, id, expires_at, now)
+        .map_err(|e| {
+            error!("Failed to save download token: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(Json(DownloadToken {
+        url: format!("/once/{}", token),
+        expires_at,
+    }))
+}
+
+/// Sends a kept file for a one-time token, which is used up once the file
+/// is found. Concurrent requests can't both claim it.
+#[instrument(skip(state, token))]
+async fn get_once_file(
+    State(state): State<AppState>,
+    extract::Path(token): extract::Path<String>,
+) -> Result<Response<Body>, StatusCode> {
+    let now = share::unix_now();
+    let taken = state.db.take_download_token(&token, now).map_err(|e| {
+        error!("Failed to look up download token: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let Some((id, expires_at)) = taken else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let result = send_kept_file(&state, id).await;
+    if result.is_err() {
+        // Nothing was sent, so the link still has its download.
+        if let Err(e) = state.db.save_download_token(&token, id, expires_at, now) {
+            error!("Failed to restore download token: {:?}", e);
+        }
+    }
+    result
+}
+
+/// QR code of a link to a job's file, for pulling a download onto a phone.
+/// Files kept on this server get a share link, so the phone doesn't need
+/// access to the API.
+#[instrument(skip(state, headers))]
+async fn get_job_qr(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let url = match job.location {
+        Some(location) if location.starts_with("https://") || location.starts_with("http://") => {
+            location
+        }
+        _ => {
+            library_file(&state, id).await?;
+            let signature = state.shares.sign(
+                id,
+                Duration::from_secs(state.config.share_ttl_hours * 60 * 60),
+            );
+            let origin = request_origin(&headers).ok_or(StatusCode::BAD_REQUEST)?;
+            format!("{}{}", origin, signature.path(id))
+        }
+    };
+
+    let png = qr::png(&url).map_err(|e| {
+        error!("Failed to render QR code: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, "image/png"),
+            // The link inside expires.
+            (header::CACHE_CONTROL, "no-store"),
+        ],
+        png,
+    )
+        .into_response())
+}
+
+/// Scheme and host the client reached the server at, preferring the
+/// headers set by a reverse proxy.
+fn request_origin(headers: &HeaderMap) -> Option<String> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    };
+    let host = header("x-forwarded-host").or_else(|| header("host"))?;
+    let scheme = match header("x-forwarded-proto") {
+        Some("https") => "https",
+        _ => "http",
+    };
+    Some(format!("{}://{}", scheme, host))
+}
+
+/// Exempts a kept file from retention cleanup.
+#[instrument(skip(state))]
+async fn pin_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    state
+        .jobs
+        .set_pinned(id, true)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[instrument(skip(state))]
+async fn unpin_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    state
+        .jobs
+        .set_pinned(id, false)
+        .map(Json)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Moves a kept file to the trash, from where it can be restored until
+/// `TRASH_RETENTION_DAYS` have passed.
+#[instrument(skip(state))]
+async fn delete_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    trash::trash(&state, &job)
+        .await
+        .map(Json)
+        .map_err(trash_error_status)
+}
+
+#[instrument(skip(state))]
+async fn restore_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Job>, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    trash::restore(&state, &job)
+        .await
+        .map(Json)
+        .map_err(trash_error_status)
+}
+
+fn trash_error_status(e: TrashError) -> StatusCode {
+    match e {
+        TrashError::NotKept | TrashError::NotTrashed => StatusCode::NOT_FOUND,
+        TrashError::Occupied(_) => StatusCode::CONFLICT,
+        TrashError::Io(e) => {
+            error!("Failed to move files through the trash: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Re-hashes a kept file to catch bit rot or a truncated transfer.
+#[instrument(skip(state))]
+async fn verify_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Json<Verification>, StatusCode> {
+    let job = state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    match checksum::verify(&state.jobs, &job).await {
+        Ok(Some(verification)) => Ok(Json(verification)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Failed to verify kept file of job {}: {:?}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Re-hashes every kept file, one after another.
+#[instrument(skip(state))]
+async fn verify_library(State(state): State<AppState>) -> Json<Vec<Verification>> {
+    let mut verified = Vec::new();
+    for job in state.jobs.kept() {
+        match checksum::verify(&state.jobs, &job).await {
+            Ok(Some(verification)) => verified.push(verification),
+            Ok(None) => {}
+            Err(e) => error!("Failed to verify kept file of job {}: {:?}", job.id, e),
+        }
+    }
+    Json(verified)
+}
+
+/// Jobs whose kept file is in the trash, most recently deleted first.
+#[instrument(skip(state))]
+async fn list_trash(State(state): State<AppState>) -> Json<Vec<Job>> {
+    Json(state.jobs.trashed())
+}
+
+/// Serves a kept file for an embedded `<video>` player: typed from its
+/// extension, shown inline, with range requests for seeking.
+#[instrument(skip(state, request))]
+async fn play_library_item(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+    request: Request<Body>,
+) -> Result<Response<Body>, StatusCode> {
+    let path = library_file(&state, id).await?;
+    let Ok(mut response) = ServeFile::new(&path).oneshot(request).await;
+    let sha256 = state.jobs.get(id).and_then(|job| job.sha256);
+    insert_checksum(response.headers_mut(), sha256.as_deref());
+    if let Some(filename) = path.file_name() {
+        let disposition = format!(
+            "inline; filename={}",
+            urlencoding::encode(&filename.to_string_lossy())
+        );
+        if let Ok(value) = disposition.parse() {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_DISPOSITION, value);
+        }
+    }
+
+    Ok(response.map(Body::new))
+}
+
+/// The playlist of a kept playlist download, pointing at its videos inside
+/// the ZIP through [`get_library_entry`].
+#[instrument(skip(state))]
+async fn get_library_playlist(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Response<Body>, StatusCode> {
+    let path = library_file(&state, id).await?;
+    if path.extension().is_none_or(|ext| ext != "zip") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let playlist = bundle::read_playlist(&path)
+        .await
+        .map_err(|e| {
+            error!("Failed to read playlist of job {}: {:?}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    // Entries are relative to this playlist's URL.
+    let playlist: String = playlist
+        .lines()
+        .map(|line| {
+            if line.starts_with('#') || line.is_empty() {
+                format!("{}\n", line)
+            } else {
+                format!("entries/{}\n", urlencoding::encode(line))
+            }
+        })
+        .collect();
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/vnd.apple.mpegurl")],
+        playlist,
+    )
+        .into_response())
+}
+
+/// One video from a kept playlist download, read straight out of the ZIP.
+#[instrument(skip(state))]
+async fn get_library_entry(
+    State(state): State<AppState>,
+    extract::Path((id, name)): extract::Path<(Uuid, String)>,
+) -> Result<Response<Body>, StatusCode> {
+    let path = library_file(&state, id).await?;
+    if path.extension().is_none_or(|ext| ext != "zip") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let (start, len) = bundle::stored_range(&path, &name)
+        .await
+        .map_err(|e| {
+            error!("Failed to read playlist of job {}: {:?}", id, e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let mut file = File::open(&path).await.map_err(|_| StatusCode::NOT_FOUND)?;
+    file.seek(std::io::SeekFrom::Start(start))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let content_type = mime_guess::from_path(&name).first_or_octet_stream();
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_LENGTH, len.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename={}", urlencoding::encode(&name)),
+            ),
+        ],
+        Body::from_stream(ReaderStream::new(file.take(len))),
+    )
+        .into_response())
+}
+
+#[instrument(skip(state))]
+async fn get_hls_playlist(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Response<Body>, StatusCode> {
+    let input = library_file(&state, id).await?;
+    let playlist = state.hls.playlist(id, &input).await.map_err(|e| {
+        error!("Error when streaming job {}: {:?}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+            // Players poll the playlist while it is still being written.
+            (header::CACHE_CONTROL, "no-cache"),
+        ],
+        playlist,
+    )
+        .into_response())
+}
+
+#[instrument(skip(state))]
+async fn get_hls_segment(
+    State(state): State<AppState>,
+    extract::Path((id, segment)): extract::Path<(Uuid, String)>,
+) -> Result<Response<Body>, StatusCode> {
+    let path = state
+        .hls
+        .segment(id, &segment)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let file = File::open(&path).await.map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "video/mp2t")],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+#[instrument(skip(state))]
+async fn get_job_comments(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Response<Body>, StatusCode> {
+    state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    let file = File::open(download::comments_path(&state, id))
+        .await
+        .map_err(|_| StatusCode::NOT_FOUND)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/json")],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+#[instrument(skip(state))]
+async fn get_job_log(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<String, StatusCode> {
+    state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    state.jobs.logs().read(id).map_err(|e| {
+        error!("Error when reading log for job {}: {:?}", id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+/// yt-dlp's verbose output for a job run with `debug`, for admin roles only.
+#[instrument(skip(state, headers))]
+async fn get_job_debug(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<String, StatusCode> {
+    if !state.config.quotas.is_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    match state.jobs.logs().read_debug(id) {
+        Ok(Some(log)) => Ok(log),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            error!("Error when reading debug log for job {}: {:?}", id, e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ServerLogQuery {
+    /// Most verbose level to show, `info` by default.
+    level: Option<String>,
+}
+
+/// Server-sent events with the server's log from now on, at `level` and
+/// more severe ones. For admin roles only.
+#[instrument(skip(state, headers))]
+async fn stream_server_log(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<ServerLogQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Response<Body>> {
+    if !state.config.quotas.is_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN.into_response());
+    }
+    let level = match query.level.as_deref() {
+        Some(level) => level.parse::<Level>().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid level {:?}", level),
+            )
+                .into_response()
+        })?,
+        None => Level::INFO,
+    };
+
+    let receiver = server_log::subscribe();
+    let live = futures_util::stream::unfold(receiver, move |mut receiver| async move {
+        let event = loop {
+            match receiver.recv().await {
+                Ok(record) if record.level > level => continue,
+                Ok(record) => break Event::default().data(record.line),
+                Err(RecvError::Lagged(skipped)) => {
+                    break Event::default().event("lagged").data(skipped.to_string());
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        };
+        Some((Ok(event), receiver))
+    });
+
+    Ok(Sse::new(live).keep_alive(KeepAlive::default()))
+}
+
+/// Server-sent events with the job's log so far, then new lines as they are
+/// written, ending with a `finished` event carrying the job status.
+#[instrument(skip(state))]
+async fn stream_job_log(
+    State(state): State<AppState>,
+    extract::Path(id): extract::Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    state.jobs.get(id).ok_or(StatusCode::NOT_FOUND)?;
+    // Subscribe before reading the backlog so no line falls in between.
+    let receiver = state.jobs.logs().subscribe(id);
+    let backlog = state.jobs.logs().read(id).unwrap_or_default();
+    let status = state.jobs.get(id).map(|job| job.status);
+    if let Some(status @ (JobStatus::Completed | JobStatus::Failed)) = status {
+        state.jobs.logs().finish(id, status);
+    }
+
+    let backlog = futures_util::stream::iter(
+        backlog
+            .lines()
+            .map(|line| Ok(Event::default().data(line)))
+            .collect::<Vec<_>>(),
+    );
+    let live = futures_util::stream::unfold(Some(receiver), |receiver| async move {
+        let mut receiver = receiver?;
+        let (event, receiver) = match receiver.recv().await {
+            Ok(LogEvent::Line(line)) => (Event::default().data(line), Some(receiver)),
+            Ok(LogEvent::Finished(status)) => (
+                Event::default().event("finished").data(status.as_str()),
+                None,
+            ),
+            Err(RecvError::Lagged(skipped)) => (
+                Event::default().event("lagged").data(skipped.to_string()),
+                Some(receiver),
+            ),
+            Err(RecvError::Closed) => return None,
+        };
+        Some((Ok(event), receiver))
+    });
+
+    Ok(Sse::new(backlog.chain(live)).keep_alive(KeepAlive::default()))
+}
+
+/// Days of history covered by `GET /api/admin/stats` unless `days` is given.
+const DEFAULT_STATS_DAYS: u64 = 30;
+
+#[derive(Deserialize, Debug)]
+struct StatsRequest {
+    days: Option<u64>,
+}
+
+/// Usage summary, for admin roles only.
+#[instrument(skip(state, headers))]
+async fn get_stats(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(payload): Query<StatsRequest>,
+) -> Result<Json<Stats>, StatusCode> {
+    if !state.config.quotas.is_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let days = payload.days.unwrap_or(DEFAULT_STATS_DAYS);
+    load_stats(&state, days).await.map(Json)
+}
+
+/// Usage over the last `days` days, for `GET /api/admin/stats` and GraphQL.
+pub async fn load_stats(state: &AppState, days: u64) -> Result<Stats, StatusCode> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let since = now.saturating_sub(days.saturating_mul(24 * 60 * 60));
+
+    let mut stats = state.db.stats(since).map_err(|e| {
+        error!("Failed to compute stats: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    stats.ytdlp_cache_bytes = usage::size(&state.config.ytdlp_cache_dir)
+        .await
+        .map_err(|e| {
+            error!("Failed to measure yt-dlp cache: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    Ok(stats)
+}
+
+async fn run_graphql(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let user = state
+        .config
+        .quotas
+        .caller(&headers)
+        .map(|caller| caller.user);
+    let admin = Admin(state.config.quotas.is_admin(&headers));
+    let request = request
+        .into_inner()
+        .data(state.clone())
+        .data(Caller(user))
+        .data(admin);
+    state.graphql.execute(request).await.into()
+}
+
+/// GraphiQL, for trying out queries against `/api/graphql` in the browser.
+async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/api/graphql").finish())
+}
+
+#[instrument(skip(state))]
+async fn get_storage(State(state): State<AppState>) -> Result<Json<StorageUsage>, StatusCode> {
+    usage::collect(&state.config).await.map(Json).map_err(|e| {
+        error!("Failed to measure storage: {:?}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Serialize, Debug)]
+struct CacheCleared {
+    bytes_freed: u64,
+}
+
+/// Empties yt-dlp's cache. Stale signature data there is a common reason
+/// for every YouTube download failing at once. For admin roles only.
+#[instrument(skip(state, headers))]
+async fn clear_cache(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Json<CacheCleared>, StatusCode> {
+    if !state.config.quotas.is_admin(&headers) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let dir = &state.config.ytdlp_cache_dir;
+    let bytes_freed = cache::clear(dir).await.map_err(|e| {
+        error!("Failed to clear yt-dlp cache {:?}: {:?}", dir, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    info!("Cleared yt-dlp cache, freeing {} bytes", bytes_freed);
+    Ok(Json(CacheCleared { bytes_freed }))
+}
+
+#[instrument(skip(state))]
+async fn get_push_key(State(state): State<AppState>) -> String {
+    state.push.public_key()
+}
+
+#[instrument(skip(state))]
+async fn subscribe_push(
+    State(state): State<AppState>,
+    Json(subscription): Json<PushSubscription>,
+) -> StatusCode {
+    match state.push.subscribe(&subscription) {
+        Ok(()) => StatusCode::CREATED,
+        Err(PushError::InvalidSubscription) => StatusCode::UNPROCESSABLE_ENTITY,
+        Err(e) => {
+            error!("Failed to save push subscription: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct UnsubscribePushRequest {
+    endpoint: String,
+}
+
+#[instrument(skip(state))]
+async fn unsubscribe_push(
+    State(state): State<AppState>,
+    Json(payload): Json<UnsubscribePushRequest>,
+) -> StatusCode {
+    match state.push.unsubscribe(&payload.endpoint) {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            error!("Failed to remove push subscription: {:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Checks a worker's bearer token against `WORKER_TOKEN`. Only coordinators
+/// hand out jobs.
+fn check_worker(state: &AppState, headers: &HeaderMap) -> Result<(), StatusCode> {
+    if state.config.role != Role::Coordinator {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (token, &state.config.worker_token) {
+        (Some(token), Some(expected))
+            if quick::constant_time_eq(token.as_bytes(), expected.as_bytes()) =>
+        {
+            Ok(())
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Hands the next queued job to a worker, waiting a while for one before
+/// answering `204`.
+#[instrument(skip(state, headers))]
+async fn claim_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<Response<Body>, StatusCode> {
+    check_worker(&state, &headers)?;
+    let Ok(job) = tokio::time::timeout(cluster::CLAIM_WAIT, state.queue.next()).await else {
+        return Ok(StatusCode::NO_CONTENT.into_response());
+    };
+    state.leases.claim(job.id());
+    job.start();
+    info!("Handing job {} to a worker", job.id());
+    let job = state
+        .jobs
+        .get(job.id())
+        .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+    Ok(Json(job).into_response())
+}
+
+#[instrument(skip(state, headers))]
+async fn renew_lease(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<Uuid>,
+) -> StatusCode {
+    if let Err(status) = check_worker(&state, &headers) {
+        return status;
+    }
+    if state.leases.renew(id) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CONFLICT
+    }
+}
+
+/// Receives one of a claimed job's files from its worker.
+#[instrument(skip(state, headers, body))]
+async fn upload_job_file(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path((id, name)): extract::Path<(Uuid, String)>,
+    body: Body,
+) -> Result<StatusCode, StatusCode> {
+    check_worker(&state, &headers)?;
+    if !cluster::valid_file_name(&name) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !state.leases.renew(id) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let dir = cluster::uploads_root(&state).join(id.to_string());
+    let write = async {
+        tokio::fs::create_dir_all(&dir).await?;
+        let mut file = File::create(dir.join(&name)).await?;
+        let mut stream = body.into_data_stream();
+        while let Some(chunk) = stream.next().await {
+            file.write_all(&chunk.map_err(std::io::Error::other)?)
+                .await?;
+        }
+        file.flush().await
+    };
+    write.await.map_err(|e| {
+        error!("Failed to receive {} for job {}: {:?}", name, id, e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Takes a claimed job back from its worker once the files are uploaded, and
+/// delivers them in the background.
+#[instrument(skip(state, headers, finished))]
+async fn finish_claimed_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<Uuid>,
+    Json(finished): Json<Finished>,
+) -> Result<StatusCode, StatusCode> {
+    check_worker(&state, &headers)?;
+    let names = std::iter::once(&finished.file).chain(&finished.sidecars);
+    if !names.into_iter().all(|name| cluster::valid_file_name(name)) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    if !state.leases.release(id) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let job = state.jobs.handle(id);
+    job.log_output(&finished.log.join("\n"));
+    let job_dir = JobDir::create(&cluster::uploads_root(&state), id)
+        .await
+        .map_err(|e| {
+            error!("Failed to open uploads of job {}: {:?}", id, e);
+            job.fail(&e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tokio::spawn(async move {
+        match cluster::deliver(&state, &job, &job_dir, finished).await {
+            Ok(()) => job.complete(),
+            Err(e) => {
+                error!("Delivering job {} failed: {:?}", id, e);
+                job.fail(&e);
+            }
+        }
+    });
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[instrument(skip(state, headers, failed))]
+async fn fail_claimed_job(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<Uuid>,
+    Json(failed): Json<Failed>,
+) -> Result<StatusCode, StatusCode> {
+    check_worker(&state, &headers)?;
+    if !state.leases.release(id) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let job = state.jobs.handle(id);
+    job.log_output(&failed.log.join("\n"));
+    job.set_stderr_tail(failed.stderr_tail);
+    job.fail_with(&failed.error, failed.failure);
+    let uploads = cluster::uploads_root(&state).join(id.to_string());
+    let _ = tokio::fs::remove_dir_all(uploads).await;
+    Ok(StatusCode::NO_CONTENT)
+}
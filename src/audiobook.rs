@@ -0,0 +1,140 @@
+use std::{
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::process::Command;
+use tracing::{debug, instrument};
+
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(thiserror::Error, Debug)]
+pub enum AudiobookError {
+    #[error("failed to run {0}")]
+    Command(&'static str, #[source] io::Error),
+    #[error("{0} exited with no status code")]
+    ExitNoCode(&'static str),
+    #[error("{0} exited with status code {1}")]
+    ExitErrorCode(&'static str, i32),
+    #[error("failed to read duration of {0:?}")]
+    Duration(PathBuf),
+    #[error("failed to write ffmpeg input files")]
+    Write(#[source] io::Error),
+}
+
+/// Concatenates playlist entries, in order, into a single `.m4b` with a
+/// chapter at the start of each entry, named after the entry's title.
+///
+/// Entries are re-encoded to AAC, since they may come in different formats.
+#[instrument(skip(entries, job))]
+pub async fn assemble(
+    entries: &[DownloadedVideo],
+    title: &str,
+    author: Option<&str>,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, AudiobookError> {
+    job.log_output(&format!(
+        "Assembling audiobook from {} entries",
+        entries.len()
+    ));
+
+    let mut list = String::new();
+    let mut metadata = String::from(";FFMETADATA1\n");
+    let _ = writeln!(metadata, "title={}", escape_metadata(title));
+    let _ = writeln!(metadata, "album={}", escape_metadata(title));
+    if let Some(author) = author {
+        let _ = writeln!(metadata, "artist={}", escape_metadata(author));
+    }
+    let _ = writeln!(metadata, "genre=Audiobook");
+
+    let mut start_ms = 0;
+    for entry in entries {
+        let end_ms = start_ms + (duration(&entry.path).await? * 1000.0).round() as u64;
+        let chapter = entry.info.title.as_deref().unwrap_or(&entry.filename);
+        let _ = writeln!(list, "file '{}'", escape_concat(&entry.path));
+        let _ = write!(
+            metadata,
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            start_ms,
+            end_ms,
+            escape_metadata(chapter)
+        );
+        start_ms = end_ms;
+    }
+
+    let list_path = dir.join("audiobook-files.txt");
+    let metadata_path = dir.join("audiobook-metadata.txt");
+    tokio::fs::write(&list_path, list)
+        .await
+        .map_err(AudiobookError::Write)?;
+    tokio::fs::write(&metadata_path, metadata)
+        .await
+        .map_err(AudiobookError::Write)?;
+
+    let output = dir.join("audiobook.m4b");
+    let cmd = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        .arg("-i")
+        .arg(&metadata_path)
+        .args(["-map", "0:a", "-map_metadata", "1", "-map_chapters", "1"])
+        .args(["-c:a", "aac", "-b:a", "128k", "-movflags", "+faststart"])
+        .args(["-f", "ipod"])
+        .arg(&output)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| AudiobookError::Command("ffmpeg", e))?;
+
+    debug!("ffmpeg status: {}", cmd.status);
+    job.log_output(&String::from_utf8_lossy(&cmd.stderr));
+    match cmd.status.code() {
+        Some(0) => Ok(output),
+        Some(code) => Err(AudiobookError::ExitErrorCode("ffmpeg", code)),
+        None => Err(AudiobookError::ExitNoCode("ffmpeg")),
+    }
+}
+
+/// Length of a media file in seconds, as reported by ffprobe.
+async fn duration(path: &Path) -> Result<f64, AudiobookError> {
+    let cmd = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format=duration"])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| AudiobookError::Command("ffprobe", e))?;
+    match cmd.status.code() {
+        Some(0) => {}
+        Some(code) => return Err(AudiobookError::ExitErrorCode("ffprobe", code)),
+        None => return Err(AudiobookError::ExitNoCode("ffprobe")),
+    }
+
+    String::from_utf8_lossy(&cmd.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| AudiobookError::Duration(path.to_path_buf()))
+}
+
+/// Quotes a path for ffmpeg's concat demuxer.
+fn escape_concat(path: &Path) -> String {
+    path.display().to_string().replace('\'', "'\\''")
+}
+
+/// Escapes a value for ffmpeg's metadata file format.
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,86 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tracing::debug;
+
+/// Server-wide cap on yt-dlp's download rate.
+///
+/// yt-dlp can only be throttled per process with `--limit-rate`, fixed when it
+/// starts, so the budget is handed out in shares: each child reserves a fair
+/// part of what is left and returns it when it exits. Children wait while too
+/// little is free, which keeps the total under the cap.
+#[derive(Debug)]
+pub struct Bandwidth {
+    /// Total budget in KiB/s, or `None` for no limit.
+    limit_kb: Option<u64>,
+    /// Number of downloads the budget is normally shared between.
+    slots: u64,
+    allocated: Mutex<Allocation>,
+    released: Notify,
+}
+
+#[derive(Debug, Default)]
+struct Allocation {
+    active: u64,
+    kb: u64,
+}
+
+/// A reserved part of the budget, given back when dropped.
+#[derive(Debug)]
+pub struct RateShare {
+    bandwidth: Arc<Bandwidth>,
+    kb: u64,
+}
+
+impl Bandwidth {
+    pub fn new(limit_kb: Option<u64>, slots: usize) -> Self {
+        Self {
+            limit_kb,
+            slots: (slots as u64).max(1),
+            allocated: Mutex::new(Allocation::default()),
+            released: Notify::new(),
+        }
+    }
+
+    /// Reserves a share for one yt-dlp run, waiting if the budget is used up.
+    /// Returns `None` when no limit is configured.
+    pub async fn acquire(self: &Arc<Self>) -> Option<RateShare> {
+        let limit = self.limit_kb?;
+        loop {
+            let released = self.released.notified();
+            {
+                let mut allocated = self.allocated.lock().unwrap();
+                let fair = (limit / (allocated.active + 1).max(self.slots)).max(1);
+                let share = fair.min(limit - allocated.kb);
+                // Don't start a download on scraps; wait for a fairer share.
+                if share > 0 && share >= fair / 2 {
+                    allocated.active += 1;
+                    allocated.kb += share;
+                    debug!("Reserved {} KiB/s of {} KiB/s", share, limit);
+                    return Some(RateShare {
+                        bandwidth: self.clone(),
+                        kb: share,
+                    });
+                }
+            }
+            released.await;
+        }
+    }
+}
+
+impl RateShare {
+    /// This download's share of the rate, in KiB/s.
+    pub fn kb(&self) -> u64 {
+        self.kb
+    }
+}
+
+impl Drop for RateShare {
+    fn drop(&mut self) {
+        let mut allocated = self.bandwidth.allocated.lock().unwrap();
+        allocated.active -= 1;
+        allocated.kb -= self.kb;
+        drop(allocated);
+        self.bandwidth.released.notify_waiters();
+    }
+}
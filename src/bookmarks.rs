@@ -0,0 +1,38 @@
+use std::time::SystemTime;
+
+use async_graphql::SimpleObject;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::info::MediaInfo;
+
+/// A URL saved to download later, e.g. collected during the day and
+/// downloaded in one go at night.
+#[derive(Debug, Clone, Serialize, SimpleObject)]
+pub struct Bookmark {
+    pub id: Uuid,
+    pub url: String,
+    pub title: Option<String>,
+    /// URL of the video's thumbnail image.
+    pub thumbnail: Option<String>,
+    /// User who saved it, as reported by the authenticating proxy.
+    pub user: Option<String>,
+    /// Unix timestamp in seconds.
+    pub created_at: u64,
+}
+
+impl Bookmark {
+    pub fn new(url: &str, info: MediaInfo, user: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            url: url.to_string(),
+            title: info.title,
+            thumbnail: info.thumbnail,
+            user,
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
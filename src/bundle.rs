@@ -0,0 +1,151 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read},
+    path::{Path, PathBuf},
+};
+
+use tracing::{debug, instrument};
+use zip::{CompressionMethod, ZipArchive, ZipWriter, result::ZipError, write::SimpleFileOptions};
+
+use crate::video::DownloadedVideo;
+
+/// Packs the video and its sidecars into a ZIP in the job directory, named
+/// after the video so the files stay together when extracted. Returns the
+/// archive's path and file name.
+///
+/// The video is stored as-is, since it is already compressed; only the
+/// text sidecars are deflated.
+#[instrument(skip(video))]
+pub async fn zip(video: &DownloadedVideo) -> io::Result<(PathBuf, String)> {
+    let output = video.path.with_extension("bundle.zip");
+    write_zip(files(video), &output).await?;
+    Ok((output, format!("{}.zip", stem(video))))
+}
+
+/// Name of the playlist listing a playlist ZIP's videos in order.
+pub const PLAYLIST_M3U8: &str = "playlist.m3u8";
+
+/// Packs several videos and their sidecars into one ZIP at `output`, with a
+/// [`PLAYLIST_M3U8`] so players keep them in order.
+#[instrument(skip(videos))]
+pub async fn zip_entries(videos: &[DownloadedVideo], output: &Path) -> io::Result<()> {
+    let playlist = output.with_file_name(PLAYLIST_M3U8);
+    tokio::fs::write(&playlist, m3u8(videos)).await?;
+    let mut files: Vec<_> = videos.iter().flat_map(files).collect();
+    files.push((
+        playlist,
+        PLAYLIST_M3U8.to_string(),
+        CompressionMethod::Deflated,
+    ));
+    write_zip(files, output).await
+}
+
+/// An extended M3U playlist of `videos` by their names in the archive.
+fn m3u8(videos: &[DownloadedVideo]) -> String {
+    let mut playlist = String::from("#EXTM3U\n");
+    for video in videos {
+        let duration = video.info.duration.map_or(-1, |d| d.round() as i64);
+        let title = video.info.title.as_deref().unwrap_or(&video.filename);
+        // Line breaks would end the entry early.
+        let title = title.replace(['\r', '\n'], " ");
+        playlist.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            duration, title, video.filename
+        ));
+    }
+    playlist
+}
+
+/// The [`PLAYLIST_M3U8`] in the ZIP at `path`, if it has one.
+pub async fn read_playlist(path: &Path) -> io::Result<Option<String>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut zip = ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+        let mut file = match zip.by_name(PLAYLIST_M3U8) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        let mut playlist = String::new();
+        file.read_to_string(&mut playlist)?;
+        Ok(Some(playlist))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Offset and length of the stored (uncompressed) file `name` in the ZIP at
+/// `path`, so it can be read straight from the archive.
+pub async fn stored_range(path: &Path, name: &str) -> io::Result<Option<(u64, u64)>> {
+    let (path, name) = (path.to_path_buf(), name.to_string());
+    tokio::task::spawn_blocking(move || {
+        let mut zip = ZipArchive::new(File::open(path)?).map_err(io::Error::other)?;
+        let file = match zip.by_name(&name) {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(io::Error::other(e)),
+        };
+        if file.compression() != CompressionMethod::Stored {
+            return Ok(None);
+        }
+        Ok(file.data_start().map(|start| (start, file.size())))
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// A video's files and their names in the archive, the video first.
+fn files(video: &DownloadedVideo) -> Vec<(PathBuf, String, CompressionMethod)> {
+    let stem = stem(video);
+    let mut files = vec![(
+        video.path.clone(),
+        video.filename.clone(),
+        CompressionMethod::Stored,
+    )];
+    for sidecar in &video.sidecars {
+        files.push((
+            video.path.with_extension(sidecar),
+            format!("{}.{}", stem, sidecar),
+            CompressionMethod::Deflated,
+        ));
+    }
+    files
+}
+
+/// The video's file name without its extension.
+fn stem(video: &DownloadedVideo) -> String {
+    Path::new(&video.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "video".to_string())
+}
+
+async fn write_zip(
+    files: Vec<(PathBuf, String, CompressionMethod)>,
+    output: &Path,
+) -> io::Result<()> {
+    let path = output.to_path_buf();
+    tokio::task::spawn_blocking(move || write_zip_blocking(&files, &path))
+        .await
+        .map_err(io::Error::other)??;
+    debug!("Bundled {:?}", output);
+    Ok(())
+}
+
+fn write_zip_blocking(
+    files: &[(PathBuf, String, CompressionMethod)],
+    output: &Path,
+) -> io::Result<()> {
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(output)?));
+    for (path, name, method) in files {
+        let mut file = File::open(path)?;
+        let options = SimpleFileOptions::default()
+            .compression_method(*method)
+            .large_file(file.metadata()?.len() > u32::MAX as u64);
+        zip.start_file(name.as_str(), options)
+            .map_err(io::Error::other)?;
+        io::copy(&mut file, &mut zip)?;
+    }
+    zip.finish().map_err(io::Error::other)?;
+    Ok(())
+}
@@ -0,0 +1,47 @@
+use std::{io, path::Path};
+
+use tokio::process::Command;
+
+use crate::{config::Config, sites, usage};
+
+/// A `yt-dlp` command for `url` using the server's cache directory, so the
+/// signature and extractor data it caches can be measured and cleared, the
+/// admin's config file if there is one, and the site's cookies and extra
+/// arguments.
+pub fn ytdlp(config: &Config, url: &str) -> Command {
+    let mut command = Command::new("yt-dlp");
+    command.arg("--cache-dir").arg(&config.ytdlp_cache_dir);
+    if let Some(location) = &config.ytdlp_config {
+        command.arg("--config-location").arg(location);
+    }
+    if let Some(site) = sites::for_url(&config.sites, url) {
+        command.args(site.args());
+    }
+    command
+}
+
+/// Deletes everything in `dir`, keeping the directory itself. Returns the
+/// number of bytes freed.
+pub async fn clear(dir: &Path) -> io::Result<u64> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let freed = usage::dir_size(&dir)?;
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                std::fs::remove_dir_all(&path)?;
+            } else {
+                std::fs::remove_file(&path)?;
+            }
+        }
+        Ok(freed)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
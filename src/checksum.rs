@@ -0,0 +1,79 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::jobs::{Job, Jobs};
+
+/// Outcome of re-hashing a kept file.
+#[derive(Debug, Serialize)]
+pub struct Verification {
+    pub id: Uuid,
+    /// Checksum recorded when the job finished, if it was.
+    pub expected: Option<String>,
+    pub sha256: String,
+    /// Whether the file still matches, or `None` if there was nothing to
+    /// compare against and `sha256` has been recorded instead.
+    pub intact: Option<bool>,
+}
+
+/// Lowercase hex SHA-256 of the file at `path`, read on a blocking thread.
+pub async fn sha256(path: &Path) -> io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let read = file.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buf[..read]);
+        }
+        let mut hex = String::with_capacity(64);
+        for byte in hasher.finalize() {
+            let _ = write!(hex, "{:02x}", byte);
+        }
+        Ok(hex)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Re-hashes a job's kept file and compares it with the checksum recorded
+/// when the job finished, recording one if there is none. `None` if the job
+/// has no kept file.
+pub async fn verify(jobs: &Jobs, job: &Job) -> io::Result<Option<Verification>> {
+    let Some(output) = job.output.as_ref().filter(|_| job.trashed_at.is_none()) else {
+        return Ok(None);
+    };
+    let sha256 = match sha256(output).await {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        result => result?,
+    };
+    let intact = match &job.sha256 {
+        Some(expected) => Some(*expected == sha256),
+        None => {
+            jobs.record_sha256(job.id, &sha256);
+            None
+        }
+    };
+    if intact == Some(false) {
+        warn!("Kept file of job {} no longer matches its checksum", job.id);
+    }
+
+    Ok(Some(Verification {
+        id: job.id,
+        expected: job.sha256.clone(),
+        sha256,
+        intact,
+    }))
+}
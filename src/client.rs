@@ -0,0 +1,320 @@
+use std::{
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+use futures_util::StreamExt;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::json;
+use tokio::{fs::File, io::AsyncWriteExt};
+use url::Url;
+
+use yt_dlp_web::jobs::{Job, JobStatus};
+
+const USAGE: &str = "\
+Usage: yt-dlp-web client add <url> [options]
+
+Queues a download on a yt-dlp-web server, shows its progress and saves the
+file in the current directory.
+
+Options:
+  --server <url>           Server to use (default: $YTDLP_WEB_SERVER or http://localhost:3000)
+  --authorization <value>  Authorization header to send (default: $YTDLP_WEB_AUTHORIZATION)
+  --dest <name>            Upload to a configured destination instead of saving the file
+  --tag <tag>              Tag the job; can be repeated
+  -o, --output <dir>       Directory to save the file in";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("{0}\n\n{USAGE}")]
+    Usage(String),
+    #[error("invalid server URL: {0}")]
+    InvalidServer(#[from] url::ParseError),
+    #[error("HTTP request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server responded with status code {0}: {1}")]
+    Status(StatusCode, String),
+    #[error("failed to save file: {0}")]
+    Save(#[from] std::io::Error),
+    #[error("download failed: {0}")]
+    Failed(String),
+}
+
+struct Options {
+    server: Url,
+    authorization: Option<String>,
+    url: String,
+    dest: Option<String>,
+    tags: Vec<String>,
+    output: PathBuf,
+}
+
+/// Runs `yt-dlp-web client ...` with the arguments after `client`, then
+/// exits.
+pub async fn main(args: impl Iterator<Item = String>) -> ! {
+    match run(args).await {
+        Ok(()) => std::process::exit(0),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run(mut args: impl Iterator<Item = String>) -> Result<(), ClientError> {
+    match args.next().as_deref() {
+        Some("add") => add(parse(args)?).await,
+        Some("-h" | "--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(command) => Err(ClientError::Usage(format!("unknown command {:?}", command))),
+        None => Err(ClientError::Usage("missing command".to_string())),
+    }
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, ClientError> {
+    let mut server = std::env::var("YTDLP_WEB_SERVER").ok();
+    let mut authorization = std::env::var("YTDLP_WEB_AUTHORIZATION").ok();
+    let mut url = None;
+    let mut dest = None;
+    let mut tags = Vec::new();
+    let mut output = PathBuf::from(".");
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| ClientError::Usage(format!("{} needs a value", arg)))
+        };
+        match arg.as_str() {
+            "--server" => server = Some(value()?),
+            "--authorization" => authorization = Some(value()?),
+            "--dest" => dest = Some(value()?),
+            "--tag" => tags.push(value()?),
+            "-o" | "--output" => output = value()?.into(),
+            _ if arg.starts_with('-') => {
+                return Err(ClientError::Usage(format!("unknown option {}", arg)));
+            }
+            _ if url.is_none() => url = Some(arg),
+            _ => return Err(ClientError::Usage(format!("unexpected argument {}", arg))),
+        }
+    }
+
+    let mut server = server.unwrap_or_else(|| "http://localhost:3000".to_string());
+    if !server.ends_with('/') {
+        server.push('/');
+    }
+    Ok(Options {
+        server: Url::parse(&server)?,
+        authorization,
+        url: url.ok_or_else(|| ClientError::Usage("missing URL".to_string()))?,
+        dest,
+        tags,
+        output,
+    })
+}
+
+/// Submits the job, follows its log until it finishes and fetches the file.
+async fn add(options: Options) -> Result<(), ClientError> {
+    let client = Client::new();
+    let api = |method: reqwest::Method, path: &str| {
+        let url = options.server.join(path).expect("path is valid");
+        let request = client.request(method, url);
+        match &options.authorization {
+            Some(authorization) => request.header(reqwest::header::AUTHORIZATION, authorization),
+            None => request,
+        }
+    };
+
+    let job: Job = send(api(reqwest::Method::POST, "api/jobs").json(&json!({
+        "url": options.url,
+        "dest": options.dest,
+        "tags": options.tags,
+    })))
+    .await?
+    .json()
+    .await?;
+    eprintln!("Queued job {}", job.id);
+
+    follow_log(api(
+        reqwest::Method::GET,
+        &format!("api/jobs/{}/log/stream", job.id),
+    ))
+    .await?;
+
+    let job: Job = send(api(reqwest::Method::GET, &format!("api/jobs/{}", job.id)))
+        .await?
+        .json()
+        .await?;
+    match job.status {
+        JobStatus::Completed => {}
+        JobStatus::Failed => {
+            // yt-dlp's errors were already printed with the log.
+            let error = job.error.unwrap_or_else(|| "unknown error".to_string());
+            return Err(ClientError::Failed(error));
+        }
+        status => {
+            return Err(ClientError::Failed(format!(
+                "job is still {}",
+                status.as_str()
+            )));
+        }
+    }
+
+    let location = job.location.clone().unwrap_or_default();
+    if !location.starts_with("/api/") {
+        // Pushed to a destination, which is where the file stays.
+        println!("{}", location);
+        return Ok(());
+    }
+    let path = save(
+        api(reqwest::Method::GET, &location[1..]),
+        &job,
+        &options.output,
+    )
+    .await?;
+    println!("{}", path.display());
+    Ok(())
+}
+
+/// Sends `request`, turning error statuses into errors with the response
+/// body.
+async fn send(request: RequestBuilder) -> Result<reqwest::Response, ClientError> {
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(ClientError::Status(status, body.trim().to_string()));
+    }
+    Ok(response)
+}
+
+/// Prints the server-sent events of `GET /api/jobs/{id}/log/stream` until
+/// the `finished` event. yt-dlp's progress lines overwrite each other on a
+/// terminal.
+async fn follow_log(request: RequestBuilder) -> Result<(), ClientError> {
+    let mut progress = Progress::new();
+    let mut body = send(request).await?.bytes_stream();
+    let mut buffer = Vec::new();
+    let mut event = None;
+    while let Some(chunk) = body.next().await {
+        buffer.extend_from_slice(&chunk?);
+        while let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\r', '\n']);
+            if let Some(name) = line.strip_prefix("event:") {
+                event = Some(name.trim().to_string());
+            } else if let Some(data) = line.strip_prefix("data:") {
+                let data = data.strip_prefix(' ').unwrap_or(data);
+                match event.as_deref() {
+                    Some("finished") => {
+                        progress.done();
+                        return Ok(());
+                    }
+                    Some("lagged") => progress.line(&format!("({} lines skipped)", data)),
+                    _ => progress.line(data),
+                }
+            } else if line.is_empty() {
+                event = None;
+            }
+        }
+    }
+    progress.done();
+    Ok(())
+}
+
+/// Downloads the job's file into `dir`, through a `.part` file so an
+/// interrupted download doesn't look finished.
+async fn save(request: RequestBuilder, job: &Job, dir: &Path) -> Result<PathBuf, ClientError> {
+    let filename = job
+        .filename
+        .as_deref()
+        .and_then(|filename| Path::new(filename).file_name())
+        .unwrap_or("video.mp4".as_ref());
+    let path = dir.join(filename);
+    let mut part = path.clone().into_os_string();
+    part.push(".part");
+
+    let response = send(request).await?;
+    let total = response.content_length();
+    let mut file = File::create(&part).await?;
+    let mut progress = Progress::new();
+    let mut bytes = 0;
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        bytes += chunk.len() as u64;
+        progress.bytes(bytes, total);
+    }
+    file.flush().await?;
+    progress.done();
+    tokio::fs::rename(&part, &path).await?;
+    Ok(path)
+}
+
+/// Progress output on stderr, redrawn in place on a terminal.
+struct Progress {
+    terminal: bool,
+    /// Whether the cursor is at the end of a line that will be redrawn.
+    pending: bool,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Self {
+            terminal: std::io::stderr().is_terminal(),
+            pending: false,
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let progress = line.starts_with("[download]") && line.contains('%');
+        self.print(line, progress);
+    }
+
+    fn bytes(&mut self, bytes: u64, total: Option<u64>) {
+        if !self.terminal {
+            return;
+        }
+        let line = match total {
+            Some(total) if total > 0 => format!(
+                "Saving {:.1} / {:.1} MiB ({}%)",
+                mib(bytes),
+                mib(total),
+                bytes * 100 / total
+            ),
+            _ => format!("Saving {:.1} MiB", mib(bytes)),
+        };
+        self.print(&line, true);
+    }
+
+    fn print(&mut self, line: &str, redraw: bool) {
+        let mut stderr = std::io::stderr().lock();
+        let redraw = redraw && self.terminal;
+        if self.pending {
+            // Keep the last progress line when regular output follows it.
+            let _ = write!(stderr, "{}", if redraw { "\r\x1b[K" } else { "\n" });
+        }
+        if redraw {
+            let _ = write!(stderr, "{}", line);
+            let _ = stderr.flush();
+            self.pending = true;
+        } else {
+            let _ = writeln!(stderr, "{}", line);
+            self.pending = false;
+        }
+    }
+
+    /// Ends a line left to be redrawn.
+    fn done(&mut self) {
+        if self.pending {
+            eprintln!();
+            self.pending = false;
+        }
+    }
+}
+
+fn mib(bytes: u64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
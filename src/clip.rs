@@ -0,0 +1,330 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, instrument};
+
+use crate::{
+    cache,
+    config::Config,
+    download,
+    jobs::JobHandle,
+    sites::{self, SiteConfig},
+};
+
+/// Longest clip that can be extracted, in seconds.
+const MAX_CLIP_SECS: f64 = 60.0;
+
+/// Format sort for sections, capped at 1080p.
+const CLIP_FORMAT_SORT: &str = "res:1080,ext:mp4:m4a";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClipError {
+    #[error("invalid timestamp {0:?}")]
+    InvalidTimestamp(String),
+    #[error("clip must end after it starts")]
+    EmptyRange,
+    #[error("clip is longer than {MAX_CLIP_SECS} seconds")]
+    TooLong,
+    #[error("failed to run {0}")]
+    Command(&'static str, #[source] io::Error),
+    #[error("{0} exited with no status code")]
+    ExitNoCode(&'static str),
+    #[error("{0} exited with status code {1}")]
+    ExitErrorCode(&'static str, i32),
+    #[error("video has no thumbnail")]
+    NoThumbnail,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    Gif,
+    Webp,
+    Mp4,
+}
+
+impl ClipFormat {
+    pub fn ext(self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "gif",
+            ClipFormat::Webp => "webp",
+            ClipFormat::Mp4 => "mp4",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ClipFormat::Gif => "image/gif",
+            ClipFormat::Webp => "image/webp",
+            ClipFormat::Mp4 => "video/mp4",
+        }
+    }
+
+    /// ffmpeg output options. Animations are scaled down and get a palette
+    /// generated from the clip itself, which keeps GIFs small and clean.
+    fn ffmpeg_args(self) -> &'static [&'static str] {
+        match self {
+            ClipFormat::Gif => &[
+                "-vf",
+                "fps=15,scale=480:-1:flags=lanczos,split[a][b];[a]palettegen[p];[b][p]paletteuse",
+                "-loop",
+                "0",
+            ],
+            ClipFormat::Webp => &[
+                "-vf",
+                "fps=15,scale=480:-1:flags=lanczos",
+                "-c:v",
+                "libwebp",
+                "-q:v",
+                "70",
+                "-loop",
+                "0",
+                "-an",
+            ],
+            ClipFormat::Mp4 => &[
+                "-c:v",
+                "libx264",
+                "-crf",
+                "23",
+                "-pix_fmt",
+                "yuv420p",
+                "-c:a",
+                "aac",
+                "-movflags",
+                "+faststart",
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameFormat {
+    #[default]
+    #[serde(alias = "jpeg")]
+    Jpg,
+    Png,
+}
+
+impl FrameFormat {
+    pub fn ext(self) -> &'static str {
+        match self {
+            FrameFormat::Jpg => "jpg",
+            FrameFormat::Png => "png",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            FrameFormat::Jpg => "image/jpeg",
+            FrameFormat::Png => "image/png",
+        }
+    }
+}
+
+/// A time range within a video, in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct Section {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Section {
+    pub fn parse(start: &str, end: &str) -> Result<Self, ClipError> {
+        let section = Self {
+            start: parse_timestamp(start)?,
+            end: parse_timestamp(end)?,
+        };
+        if section.end <= section.start {
+            return Err(ClipError::EmptyRange);
+        }
+        if section.duration() > MAX_CLIP_SECS {
+            return Err(ClipError::TooLong);
+        }
+        Ok(section)
+    }
+
+    /// The second of video starting at `at`, enough to grab a frame from.
+    pub fn frame(at: f64) -> Self {
+        Self {
+            start: at,
+            end: at + 1.0,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+}
+
+/// Parses `90`, `1:30` or `0:01:30.5` into seconds.
+pub fn parse_timestamp(value: &str) -> Result<f64, ClipError> {
+    let invalid = || ClipError::InvalidTimestamp(value.to_string());
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+
+    let mut seconds = 0.0;
+    for part in parts {
+        let part: f64 = part.parse().map_err(|_| invalid())?;
+        if !part.is_finite() || part < 0.0 {
+            return Err(invalid());
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Ok(seconds)
+}
+
+/// Downloads only `section` of `url` into `dir`, cut at the exact times.
+#[instrument(skip(config, job))]
+pub async fn fetch_section(
+    url: &str,
+    section: Section,
+    config: &Config,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, ClipError> {
+    let path = dir.join("section.mp4");
+    let format_sort = match sites::for_url(&config.sites, url) {
+        Some(SiteConfig {
+            format_sort: Some(sort),
+            ..
+        }) => sort.as_str(),
+        _ => CLIP_FORMAT_SORT,
+    };
+    let mut command = cache::ytdlp(config, url);
+    if let Some(format) = download::capped_format(config) {
+        command.arg("-f").arg(format);
+    }
+    let cmd = command
+        .arg("-S")
+        .arg(format_sort)
+        .arg("--download-sections")
+        .arg(format!("*{}-{}", section.start, section.end))
+        .arg("--force-keyframes-at-cuts")
+        .arg("--recode")
+        .arg("mp4")
+        .arg("-o")
+        .arg(&path)
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| ClipError::Command("yt-dlp", e))?;
+    run_status("yt-dlp", cmd, job)?;
+
+    Ok(path)
+}
+
+/// Converts `input` into a clip in `dir`. `cut` seeks within the input
+/// first, for files that weren't downloaded as a section.
+#[instrument(skip(job))]
+pub async fn convert(
+    input: &Path,
+    cut: Option<Section>,
+    format: ClipFormat,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, ClipError> {
+    let output = dir.join(format!("clip.{}", format.ext()));
+    let mut command = Command::new("ffmpeg");
+    command.arg("-hide_banner").arg("-nostdin").arg("-y");
+    if let Some(cut) = cut {
+        command
+            .arg("-ss")
+            .arg(cut.start.to_string())
+            .arg("-t")
+            .arg(cut.duration().to_string());
+    }
+    let cmd = command
+        .arg("-i")
+        .arg(input)
+        .args(format.ffmpeg_args())
+        .arg(&output)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| ClipError::Command("ffmpeg", e))?;
+    run_status("ffmpeg", cmd, job)?;
+
+    Ok(output)
+}
+
+/// Grabs the first frame of `input` as an image in `dir`.
+#[instrument(skip(job))]
+pub async fn extract_frame(
+    input: &Path,
+    format: FrameFormat,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, ClipError> {
+    let output = dir.join(format!("frame.{}", format.ext()));
+    let cmd = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(input)
+        .args(["-frames:v", "1", "-q:v", "2"])
+        .arg(&output)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| ClipError::Command("ffmpeg", e))?;
+    run_status("ffmpeg", cmd, job)?;
+
+    Ok(output)
+}
+
+/// Downloads the best thumbnail of `url` into `dir` as `format`, without
+/// the video.
+#[instrument(skip(config, job))]
+pub async fn fetch_thumbnail(
+    url: &str,
+    format: FrameFormat,
+    config: &Config,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, ClipError> {
+    let cmd = cache::ytdlp(config, url)
+        .args(["--skip-download", "--no-playlist", "--write-thumbnail"])
+        .arg("--convert-thumbnails")
+        .arg(format.ext())
+        .arg("-o")
+        .arg(dir.join("thumbnail.%(ext)s"))
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(|e| ClipError::Command("yt-dlp", e))?;
+    run_status("yt-dlp", cmd, job)?;
+
+    // yt-dlp only warns when there's nothing to write.
+    let path = dir.join(format!("thumbnail.{}", format.ext()));
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Err(ClipError::NoThumbnail);
+    }
+    Ok(path)
+}
+
+/// Logs a finished command's output and turns a failed exit into an error.
+fn run_status(
+    program: &'static str,
+    output: std::process::Output,
+    job: &JobHandle,
+) -> Result<(), ClipError> {
+    debug!("{} status: {}", program, output.status);
+    job.log_output(&String::from_utf8_lossy(&output.stdout));
+    job.log_output(&String::from_utf8_lossy(&output.stderr));
+
+    match output.status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(ClipError::ExitErrorCode(program, code)),
+        None => Err(ClipError::ExitNoCode(program)),
+    }
+}
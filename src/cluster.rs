@@ -0,0 +1,414 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use reqwest::{Body, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, instrument, warn};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    AppState, checksum,
+    download::{self, DownloadError, JobDir},
+    jobs::{Failure, FailureKind, Job, JobHandle},
+    queue,
+    video::{DownloadedVideo, VideoInfo},
+};
+
+/// How long a claim request waits for a job before answering `204`.
+pub const CLAIM_WAIT: Duration = Duration::from_secs(25);
+
+/// How often workers report that they're still on a job, and how long the
+/// coordinator waits without a report before handing the job to another.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+const LEASE: Duration = Duration::from_secs(120);
+
+/// Pause before a worker asks again after the coordinator couldn't be
+/// reached.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Which part of a cluster this instance plays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Role {
+    /// Serves the API and runs queued jobs itself.
+    #[default]
+    Standalone,
+    /// Serves the API and hands queued jobs to workers.
+    Coordinator,
+    /// Runs jobs claimed from a coordinator, without serving the API.
+    Worker,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Standalone => "standalone",
+            Role::Coordinator => "coordinator",
+            Role::Worker => "worker",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "standalone" => Ok(Role::Standalone),
+            "coordinator" => Ok(Role::Coordinator),
+            "worker" => Ok(Role::Worker),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClusterError {
+    #[error("HTTP request failed")]
+    Http(#[from] reqwest::Error),
+    #[error("coordinator responded with status code {0}")]
+    Status(u16),
+    #[error("failed to read downloaded file")]
+    Read(#[source] io::Error),
+    #[error(transparent)]
+    Download(#[from] DownloadError),
+}
+
+/// Reported by a worker once a job's files are uploaded.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Finished {
+    /// File name presented to the user.
+    pub filename: String,
+    /// Name of the uploaded video file.
+    pub file: String,
+    /// Sidecars uploaded next to it, see [`DownloadedVideo`].
+    pub sidecars: Vec<String>,
+    /// The worker's log for the job.
+    pub log: Vec<String>,
+}
+
+/// Reported by a worker when a job failed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Failed {
+    pub error: String,
+    /// Missing from workers older than the coordinator.
+    #[serde(default)]
+    pub failure: Option<Failure>,
+    pub stderr_tail: Vec<String>,
+    pub log: Vec<String>,
+}
+
+/// Jobs handed out to workers, with when each worker was last heard from.
+#[derive(Debug, Default)]
+pub struct Leases {
+    claimed: Mutex<HashMap<Uuid, Instant>>,
+}
+
+impl Leases {
+    pub fn claim(&self, id: Uuid) {
+        self.claimed.lock().unwrap().insert(id, Instant::now());
+    }
+
+    /// Extends the lease on `id`. Returns `false` if it has none, e.g.
+    /// because it expired and the job went to another worker.
+    pub fn renew(&self, id: Uuid) -> bool {
+        match self.claimed.lock().unwrap().get_mut(&id) {
+            Some(heard) => {
+                *heard = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ends the lease on `id`. Returns `false` if it had none.
+    pub fn release(&self, id: Uuid) -> bool {
+        self.claimed.lock().unwrap().remove(&id).is_some()
+    }
+
+    fn take_expired(&self) -> Vec<Uuid> {
+        let mut claimed = self.claimed.lock().unwrap();
+        let expired: Vec<Uuid> = claimed
+            .iter()
+            .filter(|(_, heard)| heard.elapsed() > LEASE)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &expired {
+            claimed.remove(id);
+        }
+        expired
+    }
+}
+
+/// Spawns the task putting jobs back in the queue when their worker stops
+/// sending heartbeats.
+pub fn spawn_lease_sweeper(state: &AppState) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            interval.tick().await;
+            for id in state.leases.take_expired() {
+                warn!("Worker on job {} stopped responding; re-queueing", id);
+                let job = state.jobs.handle(id);
+                job.log_output("Worker stopped responding; re-queueing");
+                job.requeue();
+                let _ = tokio::fs::remove_dir_all(uploads_root(&state).join(id.to_string())).await;
+                state.queue.push(job);
+            }
+        }
+    });
+}
+
+/// Where a coordinator collects the files workers upload, one directory per
+/// job. Kept apart from its own job directories in case a worker shares the
+/// temp directory.
+pub fn uploads_root(state: &AppState) -> PathBuf {
+    state.config.tmp_dir.join("uploads")
+}
+
+/// Whether a worker-supplied file name or sidecar extension stays inside
+/// the job's directory.
+pub fn valid_file_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\'])
+}
+
+/// Delivers the files a worker uploaded for a finished job, on the
+/// coordinator, as if it had downloaded them itself.
+pub async fn deliver(
+    state: &AppState,
+    job: &JobHandle,
+    job_dir: &JobDir,
+    finished: Finished,
+) -> Result<(), DownloadError> {
+    let path = job_dir.path().join(&finished.file);
+    let bytes = tokio::fs::metadata(&path)
+        .await
+        .map_err(|_| DownloadError::MissingOutput)?
+        .len();
+    let video = DownloadedVideo {
+        info: VideoInfo::read_for(&path).await,
+        path,
+        filename: finished.filename,
+        sidecars: finished.sidecars,
+    };
+    job.set_metadata(&video.info);
+    job.set_bytes(bytes);
+    match checksum::sha256(&video.path).await {
+        Ok(sha256) => job.set_sha256(sha256),
+        Err(e) => warn!("Failed to hash {:?}: {:?}", video.path, e),
+    }
+    if video.sidecars.iter().any(|s| s == "comments.json") {
+        let kept = download::comments_path(state, job.id());
+        if let Some(parent) = kept.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(DownloadError::Comments)?;
+        }
+        tokio::fs::copy(video.path.with_extension("comments.json"), &kept)
+            .await
+            .map_err(DownloadError::Comments)?;
+    }
+    queue::deliver(state, job, &video).await
+}
+
+/// Client a worker uses to claim jobs from its coordinator and report back.
+pub struct Coordinator {
+    client: Client,
+    base: Url,
+    token: String,
+}
+
+impl Coordinator {
+    pub fn new(base: Url, token: String) -> Result<Self, ClusterError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            base,
+            token,
+        })
+    }
+
+    /// The next queued job, or `None` if there was none for a while.
+    async fn claim(&self) -> Result<Option<Job>, ClusterError> {
+        let response = self
+            .client
+            .post(self.url("api/worker/claim"))
+            .bearer_auth(&self.token)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::NO_CONTENT => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            status => Err(ClusterError::Status(status.as_u16())),
+        }
+    }
+
+    async fn heartbeat(&self, id: Uuid) -> Result<(), ClusterError> {
+        let request = self
+            .client
+            .post(self.url(&format!("api/worker/jobs/{}/heartbeat", id)));
+        self.send(request).await
+    }
+
+    async fn upload(&self, id: Uuid, path: &Path) -> Result<String, ClusterError> {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| ClusterError::Read(io::ErrorKind::InvalidInput.into()))?
+            .to_string();
+        let file = File::open(path).await.map_err(ClusterError::Read)?;
+        let request = self
+            .client
+            .put(self.url(&format!(
+                "api/worker/jobs/{}/files/{}",
+                id,
+                urlencoding::encode(&name)
+            )))
+            .body(Body::wrap_stream(ReaderStream::new(file)));
+        self.send(request).await?;
+        Ok(name)
+    }
+
+    async fn finish(&self, id: Uuid, finished: &Finished) -> Result<(), ClusterError> {
+        let request = self
+            .client
+            .post(self.url(&format!("api/worker/jobs/{}/finish", id)))
+            .json(finished);
+        self.send(request).await
+    }
+
+    async fn fail(&self, id: Uuid, failed: &Failed) -> Result<(), ClusterError> {
+        let request = self
+            .client
+            .post(self.url(&format!("api/worker/jobs/{}/fail", id)))
+            .json(failed);
+        self.send(request).await
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<(), ClusterError> {
+        let response = request.bearer_auth(&self.token).send().await?;
+        if !response.status().is_success() {
+            return Err(ClusterError::Status(response.status().as_u16()));
+        }
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> Url {
+        self.base.join(path).expect("path is valid")
+    }
+}
+
+/// Spawns `count` loops claiming jobs from the coordinator, downloading them
+/// here and uploading the results back.
+pub fn spawn_workers(state: &AppState, coordinator: Arc<Coordinator>, count: usize) {
+    for _ in 0..count {
+        let state = state.clone();
+        let coordinator = coordinator.clone();
+        tokio::spawn(async move {
+            loop {
+                let job = match coordinator.claim().await {
+                    Ok(Some(job)) => state.jobs.adopt(job),
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Failed to claim a job: {:?}", e);
+                        tokio::time::sleep(RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                work(&state, &coordinator, &job).await;
+            }
+        });
+    }
+}
+
+/// Runs a claimed job, sending heartbeats until it's done.
+async fn work(state: &AppState, coordinator: &Arc<Coordinator>, job: &JobHandle) {
+    let id = job.id();
+    let heartbeat = {
+        let coordinator = coordinator.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = coordinator.heartbeat(id).await {
+                    warn!("Heartbeat for job {} failed: {:?}", id, e);
+                }
+            }
+        })
+    };
+    let result = run(state, coordinator, job).await;
+    heartbeat.abort();
+
+    let log = state.jobs.get(id).map(|job| job.log).unwrap_or_default();
+    let reported = match result {
+        Ok(mut finished) => {
+            job.complete();
+            finished.log = log;
+            coordinator.finish(id, &finished).await
+        }
+        Err(e) => {
+            error!("Job {} failed: {:?}", id, e);
+            match &e {
+                ClusterError::Download(e) => job.fail(e),
+                // Couldn't hand the file over; another worker may manage.
+                e => job.fail_with(
+                    &e.to_string(),
+                    Some(Failure::new(FailureKind::Storage, true)),
+                ),
+            }
+            let snapshot = state.jobs.get(id);
+            let failed = Failed {
+                error: e.to_string(),
+                failure: snapshot.as_ref().and_then(|job| job.failure.clone()),
+                stderr_tail: snapshot.map(|job| job.stderr_tail).unwrap_or_default(),
+                log,
+            };
+            coordinator.fail(id, &failed).await
+        }
+    };
+    if let Err(e) = reported {
+        // The lease runs out and another worker tries again.
+        error!("Failed to report job {} to the coordinator: {:?}", id, e);
+    }
+}
+
+#[instrument(skip(state, coordinator, job), fields(job = %job.id()))]
+async fn run(
+    state: &AppState,
+    coordinator: &Coordinator,
+    job: &JobHandle,
+) -> Result<Finished, ClusterError> {
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let video = download::fetch(state, job, &job.url(), &job_dir).await?;
+
+    job.log_output("Uploading to the coordinator");
+    let file = coordinator.upload(job.id(), &video.path).await?;
+    let info_json = video.path.with_extension("info.json");
+    let has_info_json = video.sidecars.iter().any(|s| s == "info.json");
+    if !has_info_json && tokio::fs::try_exists(&info_json).await.unwrap_or(false) {
+        // Read by the coordinator for the video's metadata.
+        coordinator.upload(job.id(), &info_json).await?;
+    }
+    for sidecar in &video.sidecars {
+        coordinator
+            .upload(job.id(), &video.path.with_extension(sidecar))
+            .await?;
+    }
+    info!("Uploaded {}", video.filename);
+
+    Ok(Finished {
+        filename: video.filename,
+        file,
+        sidecars: video.sidecars,
+        log: Vec::new(),
+    })
+}
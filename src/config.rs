@@ -0,0 +1,457 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tempfile::env;
+use tracing::{info, warn};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    cluster::Role,
+    email::EmailConfig,
+    hooks::HooksConfig,
+    hwaccel::HwAccel,
+    quick::QuickToken,
+    quotas::QuotasConfig,
+    sites::SiteConfig,
+    storage::{DestinationConfig, S3Config},
+    telegram::TelegramConfig,
+    transcode::{self, TranscodeProfile},
+};
+
+/// Default threshold below which the startup check warns about free space.
+const DEFAULT_TMP_MIN_FREE_MB: u64 = 1024;
+const DEFAULT_MAX_CONCURRENT_JOBS: usize = 2;
+const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+const DEFAULT_CONCURRENT_FRAGMENTS: u32 = 4;
+const DEFAULT_YTDLP_RETRIES: u32 = 10;
+const DEFAULT_YTDLP_FRAGMENT_RETRIES: u32 = 10;
+const DEFAULT_YTDLP_RETRY_SLEEP: &str = "http:exp=1:30,fragment:exp=1:30";
+/// Most fragments of an HLS/DASH download fetched at once, for the server
+/// default and per request.
+pub const MAX_CONCURRENT_FRAGMENTS: u32 = 16;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 2000;
+const DEFAULT_THROTTLE_MIN_SPEED_KB: u64 = 100;
+const DEFAULT_THROTTLE_GRACE_SECS: u64 = 30;
+const DEFAULT_THROTTLE_FALLBACK_CLIENTS: &str = "tv,ios,mweb";
+const DEFAULT_HWACCEL_DEVICE: &str = "/dev/dri/renderD128";
+const DEFAULT_JOB_LOG_MAX_KB: u64 = 1024;
+const DEFAULT_IDEMPOTENCY_WINDOW_HOURS: u64 = 24;
+const DEFAULT_RETENTION_INTERVAL_MINS: u64 = 60;
+const DEFAULT_TRASH_RETENTION_DAYS: u64 = 7;
+const DEFAULT_SHARE_TTL_HOURS: u64 = 24;
+const DEFAULT_SHARE_MAX_TTL_HOURS: u64 = 30 * 24;
+const DEFAULT_VAPID_SUBJECT: &str = "mailto:yt-dlp-web@localhost";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read config file {0:?}")]
+    Read(PathBuf, #[source] io::Error),
+    #[error("failed to parse config file {0:?}")]
+    Parse(PathBuf, #[source] toml::de::Error),
+    #[error("invalid settings for site {0}: {1}")]
+    InvalidSite(String, &'static str),
+    #[error("yt-dlp config file {0:?} does not exist")]
+    MissingYtdlpConfig(PathBuf),
+    #[error("invalid {0} {1:?}")]
+    InvalidUrl(&'static str, String),
+    #[error("invalid ROLE {0:?}")]
+    InvalidRole(String),
+    #[error("{0} is required for ROLE={1}")]
+    MissingClusterSetting(&'static str, &'static str),
+    #[error("invalid YTDLP_RETRY_SLEEP entry {0:?}")]
+    InvalidRetrySleep(String),
+    #[error("invalid HWACCEL {0:?}")]
+    InvalidHwAccel(String),
+    #[error("invalid MAX_RESOLUTION {0:?}, expected a height in pixels")]
+    InvalidMaxResolution(String),
+}
+
+/// Settings read from the optional TOML file pointed to by `CONFIG_FILE`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct FileConfig {
+    destinations: HashMap<String, DestinationConfig>,
+    email: Option<EmailConfig>,
+    hooks: HooksConfig,
+    quotas: QuotasConfig,
+    profiles: HashMap<String, TranscodeProfile>,
+    quick_tokens: HashMap<String, QuickToken>,
+    sites: HashMap<String, SiteConfig>,
+    telegram: Option<TelegramConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub port: u16,
+    /// Root directory under which per-job scratch directories are created.
+    pub tmp_dir: PathBuf,
+    pub tmp_min_free_mb: u64,
+    /// Persistent directory for the job database and kept downloads.
+    pub data_dir: PathBuf,
+    /// Number of queued jobs downloaded in parallel.
+    pub max_concurrent_jobs: usize,
+    /// Combined download rate of all yt-dlp processes, in KiB/s.
+    pub max_download_rate_kb: Option<u64>,
+    /// Tallest video format yt-dlp may pick, whatever the request asks for.
+    pub max_resolution: Option<u32>,
+    /// yt-dlp's `--cache-dir`, holding signature and extractor data.
+    pub ytdlp_cache_dir: PathBuf,
+    /// A yt-dlp config file passed with `--config-location`, for options the
+    /// server doesn't expose.
+    pub ytdlp_config: Option<PathBuf>,
+    /// yt-dlp's own `--retries`, for each HTTP request.
+    pub ytdlp_retries: u32,
+    /// yt-dlp's `--fragment-retries`, for each HLS/DASH fragment.
+    pub ytdlp_fragment_retries: u32,
+    /// yt-dlp `--retry-sleep` values, like `fragment:exp=1:30`.
+    pub ytdlp_retry_sleep: Vec<String>,
+    /// Fragments of an HLS/DASH download fetched in parallel (yt-dlp's `-N`).
+    pub concurrent_fragments: u32,
+    /// Stream downloads while yt-dlp's ffmpeg is still writing them.
+    pub tee_streaming: bool,
+    /// Default for jobs' `restrict_filenames` option.
+    pub restrict_filenames: bool,
+    /// Extra attempts made when yt-dlp fails with a transient error.
+    pub download_retries: u32,
+    /// Delay before the first retry; doubled on each further attempt.
+    pub retry_base_delay_ms: u64,
+    /// Speed below which a YouTube download counts as throttled; 0 turns
+    /// the check off.
+    pub throttle_min_speed_kb: u64,
+    /// How long a download may stay under that speed before it's restarted
+    /// with another player client.
+    pub throttle_grace_secs: u64,
+    /// YouTube player clients tried in turn when a download is throttled.
+    pub throttle_fallback_clients: Vec<String>,
+    /// GPU API re-encodes try first.
+    pub hwaccel: Option<HwAccel>,
+    /// Render node opened for VA-API.
+    pub hwaccel_device: PathBuf,
+    /// Size at which a job's log file is rotated.
+    pub job_log_max_kb: u64,
+    /// How long an `Idempotency-Key` keeps answering with the job it
+    /// created.
+    pub idempotency_window_hours: u64,
+    /// Kept files older than this are deleted, unless pinned.
+    pub library_max_age_days: Option<u64>,
+    /// Least recently served kept files are deleted while the total is over
+    /// this, unless pinned.
+    pub library_max_size_mb: Option<u64>,
+    /// How often kept files are checked against the limits above.
+    pub retention_interval_mins: u64,
+    /// How long deleted kept files stay restorable in the trash; 0 deletes
+    /// them right away.
+    pub trash_retention_days: u64,
+    /// Base64url VAPID private key for Web Push; generated when unset.
+    pub vapid_private_key: Option<String>,
+    /// Contact URL sent to push services with each notification.
+    pub vapid_subject: String,
+    /// Key share links are signed with; generated when unset.
+    pub share_secret: Option<String>,
+    /// How long share links work by default.
+    pub share_ttl_hours: u64,
+    /// Longest validity a client can ask for.
+    pub share_max_ttl_hours: u64,
+    /// Whether queued jobs run here, on workers, or are claimed from a
+    /// coordinator.
+    pub role: Role,
+    /// Coordinator a worker claims jobs from.
+    pub coordinator_url: Option<Url>,
+    /// Shared secret between a coordinator and its workers.
+    pub worker_token: Option<String>,
+    /// Port the gRPC API listens on; not served when unset.
+    #[cfg(feature = "grpc")]
+    pub grpc_port: Option<u16>,
+    /// Directory shared with other instances for locks that keep them from
+    /// downloading the same video at once.
+    pub lock_dir: Option<PathBuf>,
+    /// Another instance streaming downloads are proxied to when yt-dlp fails
+    /// here.
+    pub upstream_url: Option<Url>,
+    /// `Authorization` header sent to the upstream instance.
+    pub upstream_authorization: Option<String>,
+    /// Upload finished downloads to S3 instead of streaming them directly.
+    pub s3: Option<S3Config>,
+    /// Named upload destinations clients can pick with `dest=`.
+    pub destinations: HashMap<String, DestinationConfig>,
+    pub hooks: HooksConfig,
+    /// Emails about finished queued jobs.
+    pub email: Option<EmailConfig>,
+    pub quotas: QuotasConfig,
+    /// Transcode profiles clients can pick with `profile=`.
+    pub profiles: HashMap<String, TranscodeProfile>,
+    /// Tokens accepted by `GET /api/quick`, and who they submit jobs as.
+    pub quick_tokens: HashMap<String, QuickToken>,
+    /// yt-dlp settings by domain.
+    pub sites: HashMap<String, SiteConfig>,
+    /// Bot taking downloads over Telegram.
+    pub telegram: Option<TelegramConfig>,
+}
+
+impl Config {
+    pub fn load() -> Result<Self, ConfigError> {
+        let file = match std::env::var_os("CONFIG_FILE") {
+            Some(path) => read_file(PathBuf::from(path))?,
+            None => FileConfig::default(),
+        };
+
+        for (domain, site) in &file.sites {
+            site.validate()
+                .map_err(|reason| ConfigError::InvalidSite(domain.clone(), reason))?;
+        }
+
+        let mut profiles = transcode::builtin_profiles();
+        profiles.extend(file.profiles);
+
+        let tmp_dir = std::env::var_os("TMP_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(env::temp_dir);
+
+        let role = match std::env::var("ROLE") {
+            Ok(value) => value.parse().map_err(|_| ConfigError::InvalidRole(value))?,
+            Err(_) => Role::default(),
+        };
+        let coordinator_url = instance_url_from_env("COORDINATOR_URL")?;
+        let hwaccel = match std::env::var("HWACCEL") {
+            Ok(value) if value.is_empty() => None,
+            Ok(value) => Some(
+                value
+                    .parse()
+                    .map_err(|_| ConfigError::InvalidHwAccel(value))?,
+            ),
+            Err(_) => None,
+        };
+        let max_resolution = match std::env::var("MAX_RESOLUTION") {
+            Ok(value) if value.is_empty() => None,
+            Ok(value) => Some(
+                value
+                    .parse::<u32>()
+                    .map_err(|_| ConfigError::InvalidMaxResolution(value))?,
+            ),
+            Err(_) => None,
+        };
+        let worker_token = std::env::var("WORKER_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+        if role != Role::Standalone && worker_token.is_none() {
+            return Err(ConfigError::MissingClusterSetting(
+                "WORKER_TOKEN",
+                role.as_str(),
+            ));
+        }
+        if role == Role::Worker && coordinator_url.is_none() {
+            return Err(ConfigError::MissingClusterSetting(
+                "COORDINATOR_URL",
+                role.as_str(),
+            ));
+        }
+
+        let data_dir = std::env::var_os("DATA_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("data"));
+
+        Ok(Self {
+            port: env_parse("PORT").unwrap_or(3000),
+            tmp_dir: tmp_dir.join("ytdlp-web"),
+            tmp_min_free_mb: env_parse("TMP_MIN_FREE_MB").unwrap_or(DEFAULT_TMP_MIN_FREE_MB),
+            ytdlp_cache_dir: std::env::var_os("YTDLP_CACHE_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| data_dir.join("ytdlp-cache")),
+            ytdlp_config: ytdlp_config_from_env()?,
+            data_dir,
+            max_concurrent_jobs: env_parse("MAX_CONCURRENT_JOBS")
+                .unwrap_or(DEFAULT_MAX_CONCURRENT_JOBS)
+                .max(1),
+            max_download_rate_kb: env_parse("MAX_DOWNLOAD_RATE_KB"),
+            max_resolution: max_resolution.filter(|height| *height > 0),
+            ytdlp_retries: env_parse("YTDLP_RETRIES").unwrap_or(DEFAULT_YTDLP_RETRIES),
+            ytdlp_fragment_retries: env_parse("YTDLP_FRAGMENT_RETRIES")
+                .unwrap_or(DEFAULT_YTDLP_FRAGMENT_RETRIES),
+            ytdlp_retry_sleep: retry_sleep_from_env()?,
+            concurrent_fragments: env_parse("CONCURRENT_FRAGMENTS")
+                .unwrap_or(DEFAULT_CONCURRENT_FRAGMENTS)
+                .clamp(1, MAX_CONCURRENT_FRAGMENTS),
+            tee_streaming: env_parse("TEE_STREAMING").unwrap_or(true),
+            restrict_filenames: env_parse("RESTRICT_FILENAMES").unwrap_or(false),
+            download_retries: env_parse("DOWNLOAD_RETRIES").unwrap_or(DEFAULT_DOWNLOAD_RETRIES),
+            retry_base_delay_ms: env_parse("RETRY_BASE_DELAY_MS")
+                .unwrap_or(DEFAULT_RETRY_BASE_DELAY_MS),
+            throttle_min_speed_kb: env_parse("THROTTLE_MIN_SPEED_KB")
+                .unwrap_or(DEFAULT_THROTTLE_MIN_SPEED_KB),
+            throttle_grace_secs: env_parse("THROTTLE_GRACE_SECS")
+                .unwrap_or(DEFAULT_THROTTLE_GRACE_SECS),
+            throttle_fallback_clients: std::env::var("THROTTLE_FALLBACK_CLIENTS")
+                .unwrap_or_else(|_| DEFAULT_THROTTLE_FALLBACK_CLIENTS.to_string())
+                .split(',')
+                .map(str::trim)
+                .filter(|client| !client.is_empty())
+                .map(str::to_string)
+                .collect(),
+            hwaccel,
+            hwaccel_device: std::env::var_os("HWACCEL_DEVICE")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_HWACCEL_DEVICE)),
+            job_log_max_kb: env_parse("JOB_LOG_MAX_KB").unwrap_or(DEFAULT_JOB_LOG_MAX_KB),
+            idempotency_window_hours: env_parse("IDEMPOTENCY_WINDOW_HOURS")
+                .unwrap_or(DEFAULT_IDEMPOTENCY_WINDOW_HOURS),
+            library_max_age_days: env_parse("LIBRARY_MAX_AGE_DAYS"),
+            library_max_size_mb: env_parse("LIBRARY_MAX_SIZE_MB"),
+            retention_interval_mins: env_parse("RETENTION_INTERVAL_MINS")
+                .unwrap_or(DEFAULT_RETENTION_INTERVAL_MINS)
+                .max(1),
+            trash_retention_days: env_parse("TRASH_RETENTION_DAYS")
+                .unwrap_or(DEFAULT_TRASH_RETENTION_DAYS),
+            vapid_private_key: std::env::var("VAPID_PRIVATE_KEY").ok(),
+            vapid_subject: std::env::var("VAPID_SUBJECT")
+                .unwrap_or_else(|_| DEFAULT_VAPID_SUBJECT.to_string()),
+            share_secret: std::env::var("SHARE_SECRET").ok(),
+            share_ttl_hours: env_parse("SHARE_TTL_HOURS").unwrap_or(DEFAULT_SHARE_TTL_HOURS),
+            share_max_ttl_hours: env_parse("SHARE_MAX_TTL_HOURS")
+                .unwrap_or(DEFAULT_SHARE_MAX_TTL_HOURS),
+            role,
+            coordinator_url,
+            worker_token,
+            #[cfg(feature = "grpc")]
+            grpc_port: env_parse("GRPC_PORT"),
+            lock_dir: std::env::var_os("LOCK_DIR").map(PathBuf::from),
+            upstream_url: instance_url_from_env("UPSTREAM_URL")?,
+            upstream_authorization: std::env::var("UPSTREAM_AUTHORIZATION").ok(),
+            s3: s3_from_env(),
+            destinations: file.destinations,
+            hooks: file.hooks,
+            email: file.email,
+            quotas: file.quotas,
+            profiles,
+            quick_tokens: file.quick_tokens,
+            sites: file.sites,
+            telegram: file.telegram,
+        })
+    }
+
+    /// Ensures the temp directory exists and is writable, and warns when it is
+    /// low on space. Containers often mount a tiny tmpfs on `/tmp`, which only
+    /// shows up as failed downloads otherwise.
+    pub fn check_tmp_dir(&self) -> io::Result<()> {
+        std::fs::create_dir_all(&self.tmp_dir)?;
+        check_writable(&self.tmp_dir)?;
+
+        let available_mb = fs4::available_space(&self.tmp_dir)? / (1024 * 1024);
+        if available_mb < self.tmp_min_free_mb {
+            warn!(
+                "Temp directory {:?} has only {} MiB free (recommended at least {} MiB); set TMP_DIR to a larger volume",
+                self.tmp_dir, available_mb, self.tmp_min_free_mb
+            );
+        } else {
+            info!(
+                "Temp directory {:?} has {} MiB free",
+                self.tmp_dir, available_mb
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// The yt-dlp config file from `YTDLP_CONFIG`. Checked here since a missing
+/// one would fail every download.
+fn ytdlp_config_from_env() -> Result<Option<PathBuf>, ConfigError> {
+    let Some(path) = std::env::var_os("YTDLP_CONFIG").map(PathBuf::from) else {
+        return Ok(None);
+    };
+    if !path.is_file() {
+        return Err(ConfigError::MissingYtdlpConfig(path));
+    }
+    Ok(Some(path))
+}
+
+/// The URL of another instance from `key`, with a trailing slash so API
+/// paths resolve under any path prefix it is served from.
+fn instance_url_from_env(key: &'static str) -> Result<Option<Url>, ConfigError> {
+    let Ok(value) = std::env::var(key) else {
+        return Ok(None);
+    };
+    let mut url = match Url::parse(&value) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url,
+        _ => return Err(ConfigError::InvalidUrl(key, value)),
+    };
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    Ok(Some(url))
+}
+
+/// Comma-separated `--retry-sleep` values from `YTDLP_RETRY_SLEEP`, checked
+/// here since a bad one would fail every download. Empty disables sleeping.
+fn retry_sleep_from_env() -> Result<Vec<String>, ConfigError> {
+    let value = std::env::var("YTDLP_RETRY_SLEEP")
+        .unwrap_or_else(|_| DEFAULT_YTDLP_RETRY_SLEEP.to_string());
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            if valid_retry_sleep(entry) {
+                Ok(entry.to_string())
+            } else {
+                Err(ConfigError::InvalidRetrySleep(entry.to_string()))
+            }
+        })
+        .collect()
+}
+
+/// `[TYPE:]EXPR`, where EXPR is a number of seconds, `linear=START[:END[:STEP]]`
+/// or `exp=START[:END[:BASE]]`.
+fn valid_retry_sleep(entry: &str) -> bool {
+    let expr = match entry.split_once(':') {
+        Some(("http" | "fragment" | "file_access" | "extractor", expr)) => expr,
+        _ => entry,
+    };
+    let numbers = match expr.split_once('=') {
+        Some(("linear" | "exp", numbers)) => numbers.split(':').collect::<Vec<_>>(),
+        Some(_) => return false,
+        None => vec![expr],
+    };
+    numbers.len() <= 3
+        && numbers
+            .iter()
+            .all(|n| n.parse::<f64>().is_ok_and(|n| n >= 0.0))
+}
+
+fn read_file(path: PathBuf) -> Result<FileConfig, ConfigError> {
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(ConfigError::Read(path, e)),
+    };
+    toml::from_str(&contents).map_err(|e| ConfigError::Parse(path, e))
+}
+
+fn s3_from_env() -> Option<S3Config> {
+    let bucket = std::env::var("S3_BUCKET").ok()?;
+
+    Some(S3Config {
+        bucket,
+        region: std::env::var("S3_REGION").unwrap_or_else(|_| S3Config::default_region()),
+        endpoint: std::env::var("S3_ENDPOINT").ok(),
+        access_key_id: std::env::var("S3_ACCESS_KEY_ID").ok(),
+        secret_access_key: std::env::var("S3_SECRET_ACCESS_KEY").ok(),
+        path_style: env_parse("S3_PATH_STYLE").unwrap_or(false),
+        presign_expiry_secs: env_parse("S3_PRESIGN_EXPIRY_SECS")
+            .unwrap_or_else(S3Config::default_presign_expiry_secs),
+    })
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    std::env::var(key).ok().and_then(|v| v.parse().ok())
+}
+
+fn check_writable(dir: &Path) -> io::Result<()> {
+    let probe = dir.join(format!(".write-test-{}", Uuid::new_v4()));
+    let result = std::fs::File::create(&probe).and_then(|mut f| f.write_all(b"ok"));
+    let _ = std::fs::remove_file(&probe);
+    result
+}
@@ -0,0 +1,615 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    bookmarks::Bookmark,
+    jobs::{Job, JobMode, JobStage, JobStatus, StageChange},
+    push::{PushKeys, PushSubscription},
+    quotas::{Period, Usage},
+    stats::{DayStats, Stats, StatsTotals},
+};
+
+/// Schema migrations, applied in order and tracked with `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE jobs (
+        id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        dest TEXT,
+        mode TEXT NOT NULL,
+        status TEXT NOT NULL,
+        error TEXT,
+        filename TEXT,
+        location TEXT,
+        output TEXT,
+        log TEXT NOT NULL DEFAULT '',
+        created_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+    CREATE INDEX jobs_status ON jobs (status);",
+    "ALTER TABLE jobs ADD COLUMN stderr_tail TEXT NOT NULL DEFAULT '';",
+    "CREATE TABLE push_subscriptions (
+        endpoint TEXT PRIMARY KEY,
+        p256dh TEXT NOT NULL,
+        auth TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );",
+    "ALTER TABLE jobs ADD COLUMN bytes INTEGER;
+    ALTER TABLE jobs ADD COLUMN served_bytes INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX jobs_created_at ON jobs (created_at);",
+    "ALTER TABLE jobs ADD COLUMN user TEXT;
+    CREATE INDEX jobs_user ON jobs (user, created_at);",
+    "ALTER TABLE jobs ADD COLUMN tags TEXT NOT NULL DEFAULT '';",
+    "ALTER TABLE jobs ADD COLUMN title TEXT;
+    ALTER TABLE jobs ADD COLUMN uploader TEXT;
+    CREATE VIRTUAL TABLE job_search USING fts5 (
+        job_id UNINDEXED, url, title, description, uploader, tags,
+        tokenize = 'trigram'
+    );
+    INSERT INTO job_search (job_id, url, tags) SELECT id, url, tags FROM jobs;",
+    "ALTER TABLE jobs ADD COLUMN options TEXT NOT NULL DEFAULT '{}';",
+    "ALTER TABLE jobs ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE jobs ADD COLUMN accessed_at INTEGER;",
+    "CREATE TABLE download_tokens (
+        token TEXT PRIMARY KEY,
+        job_id TEXT NOT NULL,
+        expires_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE bookmarks (
+        id TEXT PRIMARY KEY,
+        url TEXT NOT NULL,
+        title TEXT,
+        thumbnail TEXT,
+        user TEXT,
+        created_at INTEGER NOT NULL
+    );",
+    "ALTER TABLE jobs ADD COLUMN stages TEXT NOT NULL DEFAULT '[]';",
+    "ALTER TABLE jobs ADD COLUMN video_key TEXT;",
+    "ALTER TABLE jobs ADD COLUMN idempotency_key TEXT;
+    CREATE INDEX jobs_idempotency_key ON jobs (idempotency_key);",
+    "ALTER TABLE jobs ADD COLUMN failure TEXT;",
+    "ALTER TABLE jobs ADD COLUMN trashed_at INTEGER;",
+    "ALTER TABLE jobs ADD COLUMN sha256 TEXT;",
+];
+
+const JOB_COLUMNS: &str = "id, url, dest, mode, status, error, filename, location, output, log, created_at, finished_at, stderr_tail, bytes, served_bytes, user, tags, title, uploader, options, pinned, accessed_at, stages, video_key, idempotency_key, failure, trashed_at, sha256";
+
+#[derive(thiserror::Error, Debug)]
+pub enum DbError {
+    #[error("failed to create data directory {0:?}")]
+    DataDir(PathBuf, #[source] std::io::Error),
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+}
+
+/// SQLite database holding everything that has to survive a restart.
+#[derive(Debug)]
+pub struct Db {
+    conn: Mutex<Connection>,
+}
+
+impl Db {
+    /// Opens (creating if needed) `yt-dlp-web.db` in `data_dir` and brings the
+    /// schema up to date.
+    pub fn open(data_dir: &Path) -> Result<Self, DbError> {
+        std::fs::create_dir_all(data_dir)
+            .map_err(|e| DbError::DataDir(data_dir.to_path_buf(), e))?;
+        let mut conn = Connection::open(data_dir.join("yt-dlp-web.db"))?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn save_job(&self, job: &Job) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            &format!(
+                "INSERT OR REPLACE INTO jobs ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28)",
+                JOB_COLUMNS
+            ),
+            params![
+                job.id.to_string(),
+                job.url,
+                job.dest,
+                job.mode.as_str(),
+                job.status.as_str(),
+                job.error,
+                job.filename,
+                job.location,
+                job.output.as_ref().map(|p| p.to_string_lossy().into_owned()),
+                job.log.join("\n"),
+                job.created_at as i64,
+                job.finished_at.map(|t| t as i64),
+                job.stderr_tail.join("\n"),
+                job.bytes.map(|b| b as i64),
+                job.served_bytes as i64,
+                job.user,
+                job.tags.join("\n"),
+                job.title,
+                job.uploader,
+                serde_json::to_string(&job.options).unwrap_or_default(),
+                job.pinned,
+                job.accessed_at.map(|t| t as i64),
+                serde_json::to_string(&job.stages).unwrap_or_default(),
+                job.video_key,
+                job.idempotency_key,
+                job.failure
+                    .as_ref()
+                    .map(|failure| serde_json::to_string(failure).unwrap_or_default()),
+                job.trashed_at.map(|t| t as i64),
+                job.sha256,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_job(&self, id: Uuid) -> Result<Option<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let job = conn
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS),
+                [id.to_string()],
+                job_from_row,
+            )
+            .optional()?;
+
+        Ok(job)
+    }
+
+    /// The newest job `user` created with `Idempotency-Key: key` since
+    /// `since`, a Unix timestamp in seconds.
+    pub fn job_by_idempotency_key(
+        &self,
+        user: Option<&str>,
+        key: &str,
+        since: u64,
+    ) -> Result<Option<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let job = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM jobs
+                    WHERE idempotency_key = ?1 AND user IS ?2 AND created_at >= ?3
+                    ORDER BY created_at DESC LIMIT 1",
+                    JOB_COLUMNS
+                ),
+                params![key, user, since as i64],
+                job_from_row,
+            )
+            .optional()?;
+
+        Ok(job)
+    }
+
+    /// Every job, oldest first.
+    pub fn all_jobs(&self) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs ORDER BY created_at",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Finished jobs whose file is kept on this server, least recently
+    /// served first.
+    pub fn kept_jobs(&self) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs
+            WHERE output IS NOT NULL AND finished_at IS NOT NULL AND trashed_at IS NULL
+            ORDER BY COALESCE(accessed_at, finished_at)",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Jobs whose kept file is in the trash, most recently deleted first.
+    pub fn trashed_jobs(&self) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE trashed_at IS NOT NULL ORDER BY trashed_at DESC",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Jobs that were queued or running when the server last stopped, oldest first.
+    pub fn unfinished_jobs(&self) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE finished_at IS NULL ORDER BY created_at",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map([], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    /// Replaces the search index entry for `job`; `description` is only kept
+    /// in the index.
+    pub fn index_job(&self, job: &Job, description: Option<&str>) -> Result<(), DbError> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM job_search WHERE job_id = ?1",
+            [job.id.to_string()],
+        )?;
+        tx.execute(
+            "INSERT INTO job_search (job_id, url, title, description, uploader, tags)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                job.id.to_string(),
+                job.url,
+                job.title,
+                description,
+                job.uploader,
+                job.tags.join("\n"),
+            ],
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// The best `limit` matches for an FTS5 `query`, most relevant first.
+    pub fn search_jobs(&self, query: &str, limit: usize) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let columns = JOB_COLUMNS
+            .split(", ")
+            .map(|column| format!("jobs.{}", column))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM job_search JOIN jobs ON jobs.id = job_search.job_id
+            WHERE job_search MATCH ?1 ORDER BY rank LIMIT ?2",
+            columns
+        ))?;
+        let jobs = stmt
+            .query_map(params![query, limit as i64], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    /// The newest `limit` jobs labelled `tag`.
+    pub fn jobs_with_tag(&self, tag: &str, limit: usize) -> Result<Vec<Job>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        // Tags are stored newline-separated.
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM jobs WHERE instr(char(10) || tags || char(10), char(10) || ?1 || char(10)) > 0
+            ORDER BY created_at DESC LIMIT ?2",
+            JOB_COLUMNS
+        ))?;
+        let jobs = stmt
+            .query_map(params![tag, limit as i64], job_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(jobs)
+    }
+
+    pub fn add_served_bytes(&self, id: Uuid, bytes: u64) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET served_bytes = served_bytes + ?2 WHERE id = ?1",
+            params![id.to_string(), bytes as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// Usage totals for jobs created since `since`, a Unix timestamp.
+    pub fn stats(&self, since: u64) -> Result<Stats, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let since = since as i64;
+
+        let totals = conn.query_row(
+            "SELECT
+                COUNT(*),
+                COUNT(*) FILTER (WHERE status = 'completed'),
+                COUNT(*) FILTER (WHERE status = 'failed'),
+                COALESCE(SUM(bytes), 0),
+                COALESCE(SUM(served_bytes), 0),
+                AVG(finished_at - created_at) FILTER (WHERE status = 'completed')
+            FROM jobs WHERE created_at >= ?1",
+            [since],
+            |row| {
+                Ok(StatsTotals {
+                    jobs: row.get::<_, i64>(0)? as u64,
+                    completed: row.get::<_, i64>(1)? as u64,
+                    failed: row.get::<_, i64>(2)? as u64,
+                    bytes_downloaded: row.get::<_, i64>(3)? as u64,
+                    bytes_served: row.get::<_, i64>(4)? as u64,
+                    average_duration_secs: row.get(5)?,
+                })
+            },
+        )?;
+
+        let mut stmt = conn.prepare(
+            "SELECT
+                date(created_at, 'unixepoch') AS day,
+                COUNT(*),
+                COUNT(*) FILTER (WHERE status = 'completed'),
+                COUNT(*) FILTER (WHERE status = 'failed'),
+                COALESCE(SUM(bytes), 0)
+            FROM jobs WHERE created_at >= ?1
+            GROUP BY day ORDER BY day",
+        )?;
+        let per_day = stmt
+            .query_map([since], |row| {
+                Ok(DayStats {
+                    date: row.get(0)?,
+                    jobs: row.get::<_, i64>(1)? as u64,
+                    completed: row.get::<_, i64>(2)? as u64,
+                    failed: row.get::<_, i64>(3)? as u64,
+                    bytes_downloaded: row.get::<_, i64>(4)? as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut stmt = conn.prepare("SELECT url FROM jobs WHERE created_at >= ?1")?;
+        let urls = stmt
+            .query_map([since], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Stats::new(totals, per_day, &urls))
+    }
+
+    /// Jobs submitted by `user` in the current `period`, and their size.
+    pub fn user_usage(&self, user: &str, period: Period) -> Result<Usage, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let usage = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(bytes), 0) FROM jobs
+            WHERE user = ?1 AND created_at >= unixepoch('now', ?2)",
+            params![user, period.sqlite_start()],
+            |row| {
+                Ok(Usage {
+                    jobs: row.get::<_, i64>(0)? as u64,
+                    bytes: row.get::<_, i64>(1)? as u64,
+                })
+            },
+        )?;
+
+        Ok(usage)
+    }
+
+    pub fn save_push_subscription(
+        &self,
+        subscription: &PushSubscription,
+        created_at: u64,
+    ) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR REPLACE INTO push_subscriptions (endpoint, p256dh, auth, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                subscription.endpoint,
+                subscription.keys.p256dh,
+                subscription.keys.auth,
+                created_at as i64,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn delete_push_subscription(&self, endpoint: &str) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM push_subscriptions WHERE endpoint = ?1",
+            [endpoint],
+        )?;
+
+        Ok(())
+    }
+
+    /// Stores a one-time download token, dropping expired ones on the way.
+    pub fn save_download_token(
+        &self,
+        token: &str,
+        job_id: Uuid,
+        expires_at: u64,
+        now: u64,
+    ) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM download_tokens WHERE expires_at <= ?1",
+            [now as i64],
+        )?;
+        conn.execute(
+            "INSERT INTO download_tokens (token, job_id, expires_at) VALUES (?1, ?2, ?3)",
+            params![token, job_id.to_string(), expires_at as i64],
+        )?;
+
+        Ok(())
+    }
+
+    /// Deletes an unexpired download token, returning its job and expiry so
+    /// it can be put back if the download fails.
+    pub fn take_download_token(
+        &self,
+        token: &str,
+        now: u64,
+    ) -> Result<Option<(Uuid, u64)>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let taken = conn
+            .query_row(
+                "DELETE FROM download_tokens WHERE token = ?1 AND expires_at > ?2
+                RETURNING job_id, expires_at",
+                params![token, now as i64],
+                |row| {
+                    let job_id: String = row.get(0)?;
+                    let expires_at: i64 = row.get(1)?;
+                    Ok((
+                        Uuid::parse_str(&job_id).unwrap_or_default(),
+                        expires_at as u64,
+                    ))
+                },
+            )
+            .optional()?;
+
+        Ok(taken)
+    }
+
+    pub fn save_bookmark(&self, bookmark: &Bookmark) -> Result<(), DbError> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO bookmarks (id, url, title, thumbnail, user, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                bookmark.id.to_string(),
+                bookmark.url,
+                bookmark.title,
+                bookmark.thumbnail,
+                bookmark.user,
+                bookmark.created_at as i64,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// `user`'s bookmarks, or those saved without a user when `None`, oldest
+    /// first.
+    pub fn bookmarks(&self, user: Option<&str>) -> Result<Vec<Bookmark>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, url, title, thumbnail, user, created_at FROM bookmarks
+            WHERE user IS ?1 ORDER BY created_at, rowid",
+        )?;
+        let bookmarks = stmt
+            .query_map([user], |row| {
+                let id: String = row.get(0)?;
+                let created_at: i64 = row.get(5)?;
+                Ok(Bookmark {
+                    id: Uuid::parse_str(&id).unwrap_or_default(),
+                    url: row.get(1)?,
+                    title: row.get(2)?,
+                    thumbnail: row.get(3)?,
+                    user: row.get(4)?,
+                    created_at: created_at as u64,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(bookmarks)
+    }
+
+    /// Deletes one of `user`'s bookmarks. Returns whether it existed.
+    pub fn delete_bookmark(&self, id: Uuid, user: Option<&str>) -> Result<bool, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let deleted = conn.execute(
+            "DELETE FROM bookmarks WHERE id = ?1 AND user IS ?2",
+            params![id.to_string(), user],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    pub fn push_subscriptions(&self) -> Result<Vec<PushSubscription>, DbError> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT endpoint, p256dh, auth FROM push_subscriptions")?;
+        let subscriptions = stmt
+            .query_map([], |row| {
+                Ok(PushSubscription {
+                    endpoint: row.get(0)?,
+                    keys: PushKeys {
+                        p256dh: row.get(1)?,
+                        auth: row.get(2)?,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(subscriptions)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), DbError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        debug!("Applying database migration {}", i + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (i + 1) as i64)?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+    let id: String = row.get("id")?;
+    let mode: String = row.get("mode")?;
+    let status: String = row.get("status")?;
+    let log: String = row.get("log")?;
+    let stderr_tail: String = row.get("stderr_tail")?;
+    let tags: String = row.get("tags")?;
+    let options: String = row.get("options")?;
+    let stages: Vec<StageChange> =
+        serde_json::from_str(&row.get::<_, String>("stages")?).unwrap_or_default();
+    let status = JobStatus::parse(&status).unwrap_or(JobStatus::Failed);
+
+    Ok(Job {
+        id: Uuid::parse_str(&id).unwrap_or_default(),
+        url: row.get("url")?,
+        dest: row.get("dest")?,
+        mode: JobMode::parse(&mode).unwrap_or(JobMode::Stream),
+        status,
+        stage: stages
+            .last()
+            .map_or(JobStage::from(status), |change| change.stage),
+        stages,
+        video_key: row.get("video_key")?,
+        idempotency_key: row.get("idempotency_key")?,
+        error: row.get("error")?,
+        failure: row
+            .get::<_, Option<String>>("failure")?
+            .and_then(|failure| serde_json::from_str(&failure).ok()),
+        exit_code: None,
+        filename: row.get("filename")?,
+        location: row.get("location")?,
+        output: row.get::<_, Option<String>>("output")?.map(PathBuf::from),
+        created_at: row.get::<_, i64>("created_at")? as u64,
+        finished_at: row.get::<_, Option<i64>>("finished_at")?.map(|t| t as u64),
+        bytes: row.get::<_, Option<i64>>("bytes")?.map(|b| b as u64),
+        sha256: row.get("sha256")?,
+        served_bytes: row.get::<_, i64>("served_bytes")? as u64,
+        user: row.get("user")?,
+        tags: tags.lines().map(String::from).collect(),
+        options: serde_json::from_str(&options).unwrap_or_default(),
+        title: row.get("title")?,
+        uploader: row.get("uploader")?,
+        pinned: row.get("pinned")?,
+        accessed_at: row.get::<_, Option<i64>>("accessed_at")?.map(|t| t as u64),
+        trashed_at: row.get::<_, Option<i64>>("trashed_at")?.map(|t| t as u64),
+        progress: None,
+        post_processing: None,
+        overall_percent: None,
+        upload: None,
+        log: log.lines().map(String::from).collect(),
+        stderr_tail: stderr_tail.lines().map(String::from).collect(),
+    })
+}
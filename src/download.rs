@@ -0,0 +1,1874 @@
+use std::{
+    collections::BTreeMap,
+    io::{self},
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Stdio,
+    string::FromUtf8Error,
+    sync::{Arc, LazyLock},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+};
+use bytes::Bytes;
+use futures_core::Stream;
+use regex::Regex;
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+    sync::Notify,
+};
+use tokio_util::io::ReaderStream;
+use tracing::{debug, error, info, instrument, warn};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    album::{self, AlbumError},
+    audiobook::{self, AudiobookError},
+    bandwidth::RateShare,
+    bundle, cache, checksum,
+    clip::ClipError,
+    config::{Config, MAX_CONCURRENT_FRAGMENTS},
+    hooks::{self, HookError},
+    hwaccel::HwEncoders,
+    jobs::{FailureKind, JobHandle, JobOptions, JobStage},
+    playlist,
+    plugins::{Plugin, PluginError},
+    progress::{self, DownloadProgress},
+    quotas::QuotaError,
+    scan::{self, ScanError},
+    sites, sniff,
+    storage::{self, Storage, StorageError, sanitize_filename},
+    subtitles,
+    transcode::{self, FrameRate, Hdr, RateTarget, TranscodeError, TranscodeProfile, VideoCodec},
+    video::{DownloadedVideo, VideoInfo},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DownloadError {
+    #[error("URL rejected: {0}")]
+    UrlRejected(String),
+    #[error("invalid tag {0:?}")]
+    InvalidTag(String),
+    #[error("unknown transcode profile {0}")]
+    UnknownProfile(String),
+    #[error("unknown destination {0}")]
+    UnknownDestination(String),
+    #[error("failed to create job directory")]
+    JobDir(#[source] io::Error),
+    #[error("failed to run title command")]
+    TitleCommand(#[source] io::Error),
+    #[error("failed to run video command")]
+    VideoCommand(#[source] io::Error),
+    #[error("video download command exited with no status code")]
+    VideoExitNoCode,
+    #[error("video download command exited with status code {0}")]
+    VideoExitErrorCode(i32),
+    #[error("video download failed with a transient error: {0}")]
+    VideoTransient(&'static str),
+    #[error("video download was throttled to {0} KB/s")]
+    Throttled(u64),
+    #[error("yt-dlp post-processing failed with status code {0}")]
+    VideoPostprocessing(i32),
+    #[error("video is private")]
+    VideoPrivate,
+    #[error("video is unavailable")]
+    VideoUnavailable,
+    #[error("video is age-restricted and requires signing in")]
+    AgeRestricted,
+    #[error("video is not available in this server's region")]
+    GeoBlocked,
+    #[error("URL is not supported by yt-dlp")]
+    UnsupportedUrl,
+    #[error("title download command exited with no status code")]
+    TitleExitNoCode,
+    #[error("title download command exited with status code {0}")]
+    TitleExitErrorCode(i32),
+    #[error("failed to keep downloaded file")]
+    Keep(#[source] io::Error),
+    #[error("failed to save comments")]
+    Comments(#[source] io::Error),
+    #[error("invalid playlist items {0:?}")]
+    InvalidItems(String),
+    #[error("fragments must be between 1 and {MAX_CONCURRENT_FRAGMENTS}")]
+    InvalidFragments,
+    #[error("playlist limit must be at least 1")]
+    InvalidLimit,
+    #[error("album mode does not work on playlists")]
+    AlbumPlaylist,
+    #[error(transparent)]
+    Album(#[from] AlbumError),
+    #[error("audiobook assembly failed")]
+    Audiobook(#[from] AudiobookError),
+    #[error("invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("invalid date {0:?}")]
+    InvalidDate(String),
+    #[error("channel tabs need a YouTube channel URL")]
+    NotAChannel,
+    #[error("no playlist entries were downloaded")]
+    EmptyPlaylist,
+    #[error("failed to read downloaded playlist entries")]
+    PlaylistFiles(#[source] io::Error),
+    #[error("invalid language {0:?}")]
+    InvalidLanguage(String),
+    #[error("invalid extractor args: {0}")]
+    InvalidExtractorArgs(String),
+    #[error("invalid file name: {0}")]
+    InvalidFilename(&'static str),
+    #[error("metadata override is too long")]
+    InvalidMetadata,
+    #[error("downloaded file not found")]
+    MissingOutput,
+    #[error("no {0} subtitles to burn in")]
+    NoSubtitles(String),
+    #[error("invalid encoding target: {0}")]
+    InvalidRateTarget(String),
+    #[error("failed to parse video info")]
+    InfoJson(#[source] serde_json::Error),
+    #[error("failed to parse playlist")]
+    PlaylistJson(#[source] serde_json::Error),
+    #[error("failed to bundle sidecars")]
+    Bundle(#[source] io::Error),
+    #[error("failed to open temp file")]
+    TempFileOpen(#[source] io::Error),
+    #[error("UTF-8 conversion failed")]
+    FromUtf8(#[source] FromUtf8Error),
+    #[error(transparent)]
+    Plugin(#[from] PluginError),
+    #[error("transcoding failed")]
+    Transcode(#[from] TranscodeError),
+    #[error("post-download hook failed")]
+    Hook(#[from] HookError),
+    #[error(transparent)]
+    Scan(#[from] ScanError),
+    #[error("failed to store video")]
+    Storage(#[from] StorageError),
+    #[error(transparent)]
+    Quota(#[from] QuotaError),
+    #[error("clip extraction failed")]
+    Clip(#[from] ClipError),
+    #[error("library item not found")]
+    NotInLibrary,
+    #[error("failed to lock video")]
+    Lock(#[source] io::Error),
+    #[error("debug mode is limited to admin roles")]
+    DebugForbidden,
+}
+
+impl DownloadError {
+    /// Classification reported with a failed job.
+    pub fn failure_kind(&self) -> FailureKind {
+        match self {
+            DownloadError::VideoPrivate => FailureKind::Private,
+            DownloadError::Scan(ScanError::Flagged(_)) => FailureKind::Quarantined,
+            DownloadError::VideoUnavailable | DownloadError::EmptyPlaylist => {
+                FailureKind::Unavailable
+            }
+            DownloadError::AgeRestricted => FailureKind::AgeRestricted,
+            DownloadError::GeoBlocked => FailureKind::GeoBlocked,
+            DownloadError::UnsupportedUrl => FailureKind::UnsupportedUrl,
+            DownloadError::VideoTransient(_) | DownloadError::Throttled(_) => {
+                FailureKind::Transient
+            }
+            DownloadError::VideoExitNoCode
+            | DownloadError::VideoExitErrorCode(_)
+            | DownloadError::TitleExitNoCode
+            | DownloadError::TitleExitErrorCode(_) => FailureKind::Ytdlp,
+            DownloadError::UrlRejected(_)
+            | DownloadError::InvalidTag(_)
+            | DownloadError::UnknownProfile(_)
+            | DownloadError::UnknownDestination(_)
+            | DownloadError::InvalidItems(_)
+            | DownloadError::InvalidFragments
+            | DownloadError::InvalidRateTarget(_)
+            | DownloadError::InvalidLimit
+            | DownloadError::AlbumPlaylist
+            | DownloadError::InvalidFilter(_)
+            | DownloadError::InvalidDate(_)
+            | DownloadError::NotAChannel
+            | DownloadError::InvalidLanguage(_)
+            | DownloadError::InvalidExtractorArgs(_)
+            | DownloadError::InvalidFilename(_)
+            | DownloadError::InvalidMetadata
+            | DownloadError::Quota(_)
+            | DownloadError::NotInLibrary
+            | DownloadError::DebugForbidden => FailureKind::Rejected,
+            DownloadError::Album(_)
+            | DownloadError::Audiobook(_)
+            | DownloadError::NoSubtitles(_)
+            | DownloadError::Bundle(_)
+            | DownloadError::Plugin(_)
+            | DownloadError::Transcode(_)
+            | DownloadError::Hook(_)
+            | DownloadError::Scan(_)
+            | DownloadError::Clip(_)
+            | DownloadError::VideoPostprocessing(_) => FailureKind::PostProcessing,
+            DownloadError::Keep(_) | DownloadError::Storage(_) => FailureKind::Storage,
+            DownloadError::JobDir(_)
+            | DownloadError::TitleCommand(_)
+            | DownloadError::VideoCommand(_)
+            | DownloadError::Comments(_)
+            | DownloadError::MissingOutput
+            | DownloadError::InfoJson(_)
+            | DownloadError::PlaylistJson(_)
+            | DownloadError::PlaylistFiles(_)
+            | DownloadError::TempFileOpen(_)
+            | DownloadError::FromUtf8(_)
+            | DownloadError::Lock(_) => FailureKind::Internal,
+        }
+    }
+
+    /// Whether yt-dlp failed in a way another server might not, like a
+    /// blocked IP, a region lock or a sign-in it has cookies for.
+    pub fn may_work_elsewhere(&self) -> bool {
+        matches!(
+            self,
+            DownloadError::TitleExitNoCode
+                | DownloadError::TitleExitErrorCode(_)
+                | DownloadError::VideoExitNoCode
+                | DownloadError::VideoExitErrorCode(_)
+                | DownloadError::VideoTransient(_)
+                | DownloadError::Throttled(_)
+                | DownloadError::AgeRestricted
+                | DownloadError::GeoBlocked
+        )
+    }
+}
+
+impl IntoResponse for DownloadError {
+    fn into_response(self) -> Response<Body> {
+        match self {
+            DownloadError::UrlRejected(reason) => {
+                (StatusCode::FORBIDDEN, format!("URL rejected: {}", reason)).into_response()
+            }
+            DownloadError::InvalidTag(tag) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid tag {:?}", tag)).into_response()
+            }
+            DownloadError::UnknownDestination(_) => {
+                (StatusCode::BAD_REQUEST, "Unknown destination").into_response()
+            }
+            DownloadError::InvalidItems(items) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid playlist items {:?}", items),
+            )
+                .into_response(),
+            DownloadError::InvalidLimit => {
+                (StatusCode::BAD_REQUEST, "Limit must be at least 1").into_response()
+            }
+            DownloadError::InvalidFragments => (
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Fragments must be between 1 and {}",
+                    MAX_CONCURRENT_FRAGMENTS
+                ),
+            )
+                .into_response(),
+            DownloadError::InvalidFilter(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid filter: {}", reason),
+            )
+                .into_response(),
+            DownloadError::AlbumPlaylist => (
+                StatusCode::BAD_REQUEST,
+                "Album mode does not work on playlists",
+            )
+                .into_response(),
+            DownloadError::Album(AlbumError::NoChapters) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Video has no chapters to split into tracks",
+            )
+                .into_response(),
+            DownloadError::Album(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Error splitting album").into_response()
+            }
+            DownloadError::Audiobook(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error assembling audiobook",
+            )
+                .into_response(),
+            DownloadError::InvalidLanguage(lang) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid language {:?}", lang),
+            )
+                .into_response(),
+            DownloadError::InvalidExtractorArgs(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid extractor args: {}", reason),
+            )
+                .into_response(),
+            DownloadError::InvalidFilename(reason) => {
+                (StatusCode::BAD_REQUEST, format!("File name {}", reason)).into_response()
+            }
+            DownloadError::InvalidMetadata => (
+                StatusCode::BAD_REQUEST,
+                format!("Metadata values are limited to {} bytes", MAX_METADATA_LEN),
+            )
+                .into_response(),
+            DownloadError::NoSubtitles(lang) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("No {} subtitles to burn in", lang),
+            )
+                .into_response(),
+            DownloadError::InvalidRateTarget(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid encoding target: {}", reason),
+            )
+                .into_response(),
+            DownloadError::Transcode(
+                e @ (TranscodeError::UnknownDuration | TranscodeError::TargetTooSmall(..)),
+            ) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Can't re-encode: {}", e),
+            )
+                .into_response(),
+            DownloadError::InvalidDate(date) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid date {:?}, expected YYYY-MM-DD", date),
+            )
+                .into_response(),
+            DownloadError::NotAChannel => (
+                StatusCode::BAD_REQUEST,
+                "Channel tabs need a YouTube channel URL",
+            )
+                .into_response(),
+            DownloadError::EmptyPlaylist => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "No playlist entries matched",
+            )
+                .into_response(),
+            DownloadError::UnknownProfile(_) => {
+                (StatusCode::BAD_REQUEST, "Unknown transcode profile").into_response()
+            }
+            DownloadError::VideoPrivate => {
+                (StatusCode::FORBIDDEN, "Video is private").into_response()
+            }
+            DownloadError::AgeRestricted => (
+                StatusCode::FORBIDDEN,
+                "Video is age-restricted and requires signing in",
+            )
+                .into_response(),
+            DownloadError::VideoUnavailable => {
+                (StatusCode::NOT_FOUND, "Video is unavailable").into_response()
+            }
+            DownloadError::GeoBlocked => (
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                "Video is not available in this server's region",
+            )
+                .into_response(),
+            DownloadError::UnsupportedUrl => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "Unsupported URL").into_response()
+            }
+            DownloadError::JobDir(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error preparing download",
+            )
+                .into_response(),
+            DownloadError::Plugin(_) | DownloadError::Transcode(_) | DownloadError::Hook(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error post-processing video",
+            )
+                .into_response(),
+            DownloadError::Storage(_) => {
+                (StatusCode::BAD_GATEWAY, "Error storing video").into_response()
+            }
+            DownloadError::Clip(
+                e @ (ClipError::InvalidTimestamp(_) | ClipError::EmptyRange | ClipError::TooLong),
+            ) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+            DownloadError::Clip(ClipError::NoThumbnail) => {
+                (StatusCode::NOT_FOUND, "Video has no thumbnail").into_response()
+            }
+            DownloadError::Clip(_) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "Error extracting clip").into_response()
+            }
+            DownloadError::DebugForbidden => (
+                StatusCode::FORBIDDEN,
+                "Debug mode is limited to admin roles",
+            )
+                .into_response(),
+            DownloadError::NotInLibrary => {
+                (StatusCode::NOT_FOUND, "Library item not found").into_response()
+            }
+            DownloadError::Quota(QuotaError::Exceeded(reason)) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("Quota exceeded: {}", reason),
+            )
+                .into_response(),
+            _ => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error downloading video stream",
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// Looks up the storage a job delivers to: the named destination if one was
+/// requested, otherwise the default storage, if any.
+pub fn resolve_storage(
+    state: &AppState,
+    dest: Option<&str>,
+) -> Result<Option<Arc<dyn Storage>>, DownloadError> {
+    match dest {
+        Some(name) => match state.destinations.get(name) {
+            Some(storage) => Ok(Some(storage.clone())),
+            None => Err(DownloadError::UnknownDestination(name.to_string())),
+        },
+        None => Ok(state.storage.clone()),
+    }
+}
+
+/// Checks that the requested transcode profile exists.
+pub fn resolve_profile<'a>(
+    state: &'a AppState,
+    options: &JobOptions,
+) -> Result<Option<&'a TranscodeProfile>, DownloadError> {
+    match &options.profile {
+        Some(name) => match state.config.profiles.get(name) {
+            Some(profile) => Ok(Some(profile)),
+            None => Err(DownloadError::UnknownProfile(name.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// The name a download is delivered under: the requested file name, if any,
+/// normalized and restricted as the job asks.
+pub fn delivered_filename(
+    state: &AppState,
+    options: &JobOptions,
+    video: &DownloadedVideo,
+) -> String {
+    let filename = match &options.filename {
+        Some(template) => storage::render_filename(template, video),
+        None => video.filename.clone(),
+    };
+    storage::normalize_filename(&filename, restrict_filenames(state, options))
+}
+
+/// Whether the job's file names are kept ASCII-safe, falling back to the
+/// server default.
+pub fn restrict_filenames(state: &AppState, options: &JobOptions) -> bool {
+    options
+        .restrict_filenames
+        .unwrap_or(state.config.restrict_filenames)
+}
+
+/// Checks options that can't be validated while deserializing: playlist
+/// items must look like `--playlist-items`, a limit must be positive, a tab
+/// needs a channel URL, languages must be plain codes and a file name must
+/// stay a single path segment.
+pub fn check_options(url: &str, options: &JobOptions) -> Result<(), DownloadError> {
+    if let Some(items) = &options.items {
+        let valid = !items.is_empty()
+            && items.len() <= MAX_PLAYLIST_ITEMS_LEN
+            && items
+                .bytes()
+                .all(|b| b.is_ascii_digit() || matches!(b, b',' | b'-' | b':'));
+        if !valid {
+            return Err(DownloadError::InvalidItems(items.clone()));
+        }
+    }
+    if options.limit == Some(0) {
+        return Err(DownloadError::InvalidLimit);
+    }
+    if options
+        .fragments
+        .is_some_and(|n| !(1..=MAX_CONCURRENT_FRAGMENTS).contains(&n))
+    {
+        return Err(DownloadError::InvalidFragments);
+    }
+    for date in [&options.after, &options.before].into_iter().flatten() {
+        if ytdlp_date(date).is_none() {
+            return Err(DownloadError::InvalidDate(date.clone()));
+        }
+    }
+    subtitles::validate_langs(&options.subs).map_err(DownloadError::InvalidLanguage)?;
+    subtitles::validate_langs(&options.audio).map_err(DownloadError::InvalidLanguage)?;
+    if let Some(lang) = &options.burn_subs {
+        subtitles::validate_langs(std::slice::from_ref(lang))
+            .map_err(DownloadError::InvalidLanguage)?;
+    }
+    if options
+        .metadata
+        .tags()
+        .iter()
+        .any(|(_, value)| value.len() > MAX_METADATA_LEN)
+    {
+        return Err(DownloadError::InvalidMetadata);
+    }
+    RateTarget::from_options(options).map_err(DownloadError::InvalidRateTarget)?;
+    check_extractor_args(&options.extractor_args).map_err(DownloadError::InvalidExtractorArgs)?;
+    if let Some(template) = &options.filename {
+        storage::check_filename_template(template).map_err(DownloadError::InvalidFilename)?;
+    }
+    if options.album && options.is_playlist() {
+        return Err(DownloadError::AlbumPlaylist);
+    }
+    options
+        .filter
+        .validate()
+        .map_err(DownloadError::InvalidFilter)?;
+    if let Some(tab) = options.tab
+        && playlist::channel_tab_url(url, tab).is_none()
+    {
+        return Err(DownloadError::NotAChannel);
+    }
+    Ok(())
+}
+
+/// Checks extractor arguments against [`EXTRACTOR_ARGS`]. Values are
+/// limited to characters that can't end the argument or start another.
+fn check_extractor_args(args: &BTreeMap<String, String>) -> Result<(), String> {
+    for (key, value) in args {
+        if !EXTRACTOR_ARGS.contains(&key.as_str()) {
+            return Err(format!("{:?} is not allowed", key));
+        }
+        let valid = !value.is_empty()
+            && value.len() <= MAX_EXTRACTOR_ARG_LEN
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b',' | b'-' | b'_' | b'.'));
+        if !valid {
+            return Err(format!("invalid value for {:?}", key));
+        }
+    }
+    Ok(())
+}
+
+/// One `--extractor-args` per extractor, with its keys joined as yt-dlp
+/// expects, e.g. `youtube:lang=en;player_client=android,web`.
+fn extractor_args(args: &BTreeMap<String, String>) -> Vec<String> {
+    let mut by_extractor: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (key, value) in args {
+        if let Some((extractor, key)) = key.split_once(':') {
+            by_extractor
+                .entry(extractor)
+                .or_default()
+                .push(format!("{}={}", key, value));
+        }
+    }
+    by_extractor
+        .into_iter()
+        .flat_map(|(extractor, pairs)| {
+            [
+                "--extractor-args".to_string(),
+                format!("{}:{}", extractor, pairs.join(";")),
+            ]
+        })
+        .collect()
+}
+
+/// Converts `YYYY-MM-DD` or `YYYYMMDD` into yt-dlp's `YYYYMMDD`.
+fn ytdlp_date(date: &str) -> Option<String> {
+    let digits: String = date.chars().filter(|&c| c != '-').collect();
+    let valid_shape = match date.len() {
+        8 => true,
+        10 => date.as_bytes()[4] == b'-' && date.as_bytes()[7] == b'-',
+        _ => false,
+    };
+    if !valid_shape || digits.len() != 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let month: u32 = digits[4..6].parse().ok()?;
+    let day: u32 = digits[6..8].parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(digits)
+}
+
+/// Downloads `url` into the job directory and runs plugins and the
+/// post-download hook on the result.
+pub async fn fetch(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    job_dir: &JobDir,
+) -> Result<DownloadedVideo, DownloadError> {
+    fetch_with(state, job, url, job_dir, false).await
+}
+
+/// Like [`fetch`], but has yt-dlp's ffmpeg write fragmented MP4, which is
+/// written front to back and so can be sent while it grows.
+pub async fn fetch_fragmented(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    job_dir: &JobDir,
+) -> Result<DownloadedVideo, DownloadError> {
+    fetch_with(state, job, url, job_dir, true).await
+}
+
+#[instrument(skip(state, job, job_dir))]
+async fn fetch_with(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    job_dir: &JobDir,
+    fragmented: bool,
+) -> Result<DownloadedVideo, DownloadError> {
+    let url = hooks::rewrite_url(&state.config.hooks.url_rules, url).map_err(|reason| {
+        info!("Rejected URL {}: {}", url, reason);
+        DownloadError::UrlRejected(reason)
+    })?;
+    if url != job.url() {
+        job.log_output(&format!("Rewrote URL to {}", url));
+    }
+    let options = job.options();
+    let profile = resolve_profile(state, &options)?;
+    check_options(&url, &options)?;
+    let url = match options
+        .tab
+        .and_then(|tab| playlist::channel_tab_url(&url, tab))
+    {
+        Some(tab_url) => {
+            job.log_output(&format!("Downloading from channel tab {}", tab_url));
+            tab_url
+        }
+        None => url,
+    };
+    let url = url.as_str();
+    let plugins = state.plugins.for_url(url);
+    if job_dir.resumed() {
+        job.log_output("Resuming interrupted download");
+    }
+    let extra_args = ytdlp_args(
+        state,
+        &plugins,
+        &options,
+        url,
+        job_dir.resumed(),
+        fragmented,
+    );
+
+    let mut video = if options.is_playlist() {
+        fetch_playlist(state, job, url, extra_args, &plugins, profile, job_dir).await?
+    } else {
+        let mut video = fetch_video(state, job, url, &extra_args, job_dir).await?;
+        post_process(&mut video, &plugins, profile, state.hwaccel.as_deref(), job).await?;
+        if !options.metadata.is_empty() {
+            job.set_metadata(&video.info);
+        }
+        if options.album {
+            split_album(&video, job_dir, job).await?
+        } else {
+            video
+        }
+    };
+    video.filename = delivered_filename(state, &options, &video);
+
+    if let Some(command) = &state.config.hooks.post_download {
+        hooks::run_post_download(command, &video, job).await?;
+    }
+    if let Some(scanner) = &state.config.hooks.scan {
+        scan::scan(scanner, &state.config.data_dir, &video.path, job).await?;
+    }
+
+    // Measured last, since plugins and hooks may rewrite the file.
+    match tokio::fs::metadata(&video.path).await {
+        Ok(metadata) => job.set_bytes(metadata.len()),
+        Err(e) => warn!("Failed to read size of {:?}: {:?}", video.path, e),
+    }
+    match checksum::sha256(&video.path).await {
+        Ok(sha256) => job.set_sha256(sha256),
+        Err(e) => warn!("Failed to hash {:?}: {:?}", video.path, e),
+    }
+
+    Ok(video)
+}
+
+/// Arguments a download of `url` passes to yt-dlp besides the output and
+/// format settings every download gets.
+pub fn ytdlp_args(
+    state: &AppState,
+    plugins: &[Arc<dyn Plugin>],
+    options: &JobOptions,
+    url: &str,
+    resume: bool,
+    fragmented: bool,
+) -> Vec<String> {
+    let mut extra_args: Vec<String> = match Url::parse(url) {
+        Ok(parsed) => plugins.iter().flat_map(|p| p.ytdlp_args(&parsed)).collect(),
+        Err(_) => Vec::new(),
+    };
+    let config = &state.config;
+    extra_args.push("--retries".to_string());
+    extra_args.push(config.ytdlp_retries.to_string());
+    extra_args.push("--fragment-retries".to_string());
+    extra_args.push(config.ytdlp_fragment_retries.to_string());
+    for sleep in &config.ytdlp_retry_sleep {
+        extra_args.push("--retry-sleep".to_string());
+        extra_args.push(sleep.clone());
+    }
+    extra_args.push("--concurrent-fragments".to_string());
+    extra_args.push(
+        options
+            .fragments
+            .unwrap_or(config.concurrent_fragments)
+            .to_string(),
+    );
+    if resume {
+        extra_args.push("--continue".to_string());
+    }
+    if options.debug {
+        extra_args.push("-v".to_string());
+    }
+    if restrict_filenames(state, options) {
+        extra_args.push("--restrict-filenames".to_string());
+    }
+    if fragmented {
+        extra_args.push("--postprocessor-args".to_string());
+        extra_args.push(format!("ffmpeg_o:{}", FRAGMENTED_MOVFLAGS));
+    }
+    if options.comments {
+        extra_args.push("--write-comments".to_string());
+    }
+    if options.description {
+        extra_args.push("--write-description".to_string());
+    }
+    if options.embed_chapters {
+        // Written with the streams copied, after any recode.
+        extra_args.push("--embed-chapters".to_string());
+    }
+    extra_args.extend(extractor_args(&options.extractor_args));
+    let sort: Vec<&str> = options
+        .hdr
+        .and_then(Hdr::format_sort)
+        .into_iter()
+        .chain(options.vcodec.map(VideoCodec::format_sort))
+        .chain(options.fps.and_then(FrameRate::format_sort))
+        .collect();
+    if !sort.is_empty() {
+        // yt-dlp puts later sort fields first, so these outrank the site's.
+        extra_args.push("-S".to_string());
+        extra_args.push(sort.join(","));
+    }
+    if options.audio.is_empty() {
+        if let Some(format) = capped_format(config) {
+            extra_args.push("-f".to_string());
+            extra_args.push(format);
+        }
+        if options.hdr == Some(Hdr::Preserve) {
+            for arg in ["--merge-output-format", "mkv", "--remux-video", "mkv"] {
+                extra_args.push(arg.to_string());
+            }
+        } else {
+            // Recoding to MP4 means H.264, so keep other codecs by remuxing.
+            match options.vcodec {
+                None | Some(VideoCodec::H264) => {
+                    extra_args.push("--recode".to_string());
+                    extra_args.push("mp4".to_string());
+                    if let Some(hw) = state.hwaccel.as_deref() {
+                        extra_args.extend(hw_recode_args(hw, fragmented));
+                    }
+                }
+                Some(_) => {
+                    extra_args.push("--remux-video".to_string());
+                    extra_args.push("mp4".to_string());
+                }
+            }
+        }
+    } else {
+        extra_args.extend(audio_track_args(&options.audio, &height_filter(config)));
+    }
+    let mut sub_langs = options.subs.clone();
+    if let Some(lang) = &options.burn_subs
+        && !sub_langs.contains(lang)
+    {
+        sub_langs.push(lang.clone());
+    }
+    if !sub_langs.is_empty() {
+        extra_args.extend(subtitles::ytdlp_args(&sub_langs, options.sub_format));
+    }
+    extra_args
+}
+
+/// yt-dlp options running its `--recode` to H.264 on the GPU, if it
+/// encodes that. These replace the fragmenting options for that step, so
+/// they are repeated.
+fn hw_recode_args(hw: &HwEncoders, fragmented: bool) -> Vec<String> {
+    if !hw.supports(VideoCodec::H264) {
+        return Vec::new();
+    }
+    let mut args = Vec::new();
+    let input = hw.input_args();
+    if !input.is_empty() {
+        args.push("--postprocessor-args".to_string());
+        args.push(format!("VideoConvertor+ffmpeg_i1:{}", input.join(" ")));
+    }
+    let mut output: Vec<&str> = Vec::new();
+    if let Some(upload) = hw.upload_filter() {
+        output.extend(["-vf", upload]);
+    }
+    output.extend(hw.encoder_args(VideoCodec::H264));
+    output.extend(hw.quality_args(VideoCodec::H264));
+    if fragmented {
+        output.push(FRAGMENTED_MOVFLAGS);
+    }
+    args.push("--postprocessor-args".to_string());
+    args.push(format!("VideoConvertor+ffmpeg_o:{}", output.join(" ")));
+    args
+}
+
+/// yt-dlp format filter keeping videos within `MAX_RESOLUTION`, or nothing
+/// without one. Formats that don't report a height, like audio, pass.
+pub fn height_filter(config: &Config) -> String {
+    config
+        .max_resolution
+        .map(|height| format!("[height<=?{}]", height))
+        .unwrap_or_default()
+}
+
+/// yt-dlp's default format selection kept within `MAX_RESOLUTION`, if set.
+pub fn capped_format(config: &Config) -> Option<String> {
+    let height = height_filter(config);
+    config
+        .max_resolution
+        .map(|_| format!("bv*{}+ba/b{}", height, height))
+}
+
+/// yt-dlp output template in the job directory for a download with
+/// `options`.
+pub fn output_template(options: &JobOptions) -> &'static str {
+    if options.is_playlist() {
+        PLAYLIST_OUTPUT
+    } else if !options.audio.is_empty() {
+        MULTI_AUDIO_OUTPUT
+    } else if options.hdr == Some(Hdr::Preserve) {
+        MKV_VIDEO_OUTPUT
+    } else {
+        VIDEO_OUTPUT
+    }
+}
+
+/// Downloads a single video and collects the sidecars the job asked for.
+async fn fetch_video(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    extra_args: &[String],
+    job_dir: &JobDir,
+) -> Result<DownloadedVideo, DownloadError> {
+    let multi_audio = !job.options().audio.is_empty();
+    let output = output_template(&job.options());
+    let (video_title, video_file) = tokio::join!(
+        get_video_title(
+            url,
+            restrict_filenames(state, &job.options()),
+            &state.config
+        ),
+        get_video_file_with_retries(state, url, extra_args, output, job_dir.path(), job)
+    );
+
+    let mut filename = match video_title {
+        Ok(title) => title,
+        Err(e) => {
+            error!("Failed to get title, defaulting: {:?}", e);
+            "video".to_string()
+        }
+    };
+    let mut video_path = video_file?;
+    if multi_audio {
+        video_path = merged_output(job_dir.path())
+            .await
+            .ok_or(DownloadError::MissingOutput)?;
+    }
+    // The title lookup assumes MP4.
+    if let Some(ext) = video_path.extension() {
+        filename = Path::new(&filename)
+            .with_extension(ext)
+            .to_string_lossy()
+            .into_owned();
+    }
+
+    let mut video = DownloadedVideo {
+        info: VideoInfo::read_for(&video_path).await,
+        path: video_path,
+        filename,
+        sidecars: Vec::new(),
+    };
+    job.set_metadata(&video.info);
+    if job.options().comments {
+        keep_comments(state, &mut video, job).await?;
+    }
+    collect_sidecars(&mut video, job).await;
+
+    Ok(video)
+}
+
+/// Downloads the selected entries of a playlist, post-processes each one and
+/// packs them into a ZIP, which is then handled like a single download.
+async fn fetch_playlist(
+    state: &AppState,
+    job: &JobHandle,
+    url: &str,
+    mut extra_args: Vec<String>,
+    plugins: &[Arc<dyn Plugin>],
+    profile: Option<&TranscodeProfile>,
+    job_dir: &JobDir,
+) -> Result<DownloadedVideo, DownloadError> {
+    let options = job.options();
+    let dir = job_dir.path();
+    let list = dir.join(PLAYLIST_LIST);
+    if let Some(filter) = options.filter.to_ytdlp() {
+        job.log_output(&format!("Filtering entries with {}", filter));
+    }
+    extra_args.extend(playlist_args(&state.config, &options, &list));
+    // A resumed run appends to the list again.
+    let _ = tokio::fs::remove_file(&list).await;
+
+    get_video_file_with_retries(state, url, &extra_args, PLAYLIST_OUTPUT, dir, job).await?;
+
+    let paths = tokio::fs::read_to_string(&list)
+        .await
+        .map_err(DownloadError::PlaylistFiles)?;
+    let mut entries = Vec::new();
+    for path in paths.lines().filter(|line| !line.is_empty()) {
+        let path = PathBuf::from(path);
+        let filename = path
+            .file_name()
+            .map(|name| storage::normalize_filename(&name.to_string_lossy(), false))
+            .unwrap_or_else(|| "video.mp4".to_string());
+        let mut entry = DownloadedVideo {
+            info: VideoInfo::read_for(&path).await,
+            path,
+            filename,
+            sidecars: Vec::new(),
+        };
+        if options.comments
+            && !entry
+                .split_comments()
+                .await
+                .map_err(DownloadError::Comments)?
+        {
+            job.log_output(&format!("No comments available for {}", entry.filename));
+        }
+        collect_sidecars(&mut entry, job).await;
+        post_process(&mut entry, plugins, profile, state.hwaccel.as_deref(), job).await?;
+        entries.push(entry);
+    }
+    if entries.is_empty() {
+        return Err(DownloadError::EmptyPlaylist);
+    }
+    job.log_output(&format!("Downloaded {} playlist entries", entries.len()));
+
+    let title = entries[0].info.playlist_title.clone();
+    let info = VideoInfo {
+        title: title.clone(),
+        uploader: entries[0].info.uploader.clone(),
+        ..VideoInfo::default()
+    };
+    job.set_metadata(&info);
+    let name = sanitize_filename(title.as_deref().unwrap_or("playlist"));
+    if options.audiobook {
+        let author = entries[0].info.uploader.as_deref();
+        let path = audiobook::assemble(
+            &entries,
+            title.as_deref().unwrap_or("Audiobook"),
+            author,
+            dir,
+            job,
+        )
+        .await?;
+        return Ok(DownloadedVideo {
+            path,
+            filename: format!("{}.m4b", name),
+            info,
+            sidecars: Vec::new(),
+        });
+    }
+
+    let filename = format!("{}.zip", name);
+    let path = dir.join("playlist.zip");
+    bundle::zip_entries(&entries, &path)
+        .await
+        .map_err(DownloadError::Bundle)?;
+
+    Ok(DownloadedVideo {
+        path,
+        filename,
+        info,
+        sidecars: Vec::new(),
+    })
+}
+
+/// Arguments selecting the playlist entries to download, which yt-dlp lists
+/// in `list` as it finishes them.
+pub fn playlist_args(config: &Config, options: &JobOptions, list: &Path) -> Vec<String> {
+    let mut args = vec![
+        "--yes-playlist".to_string(),
+        "--no-write-playlist-metafiles".to_string(),
+    ];
+    if let Some(items) = &options.items {
+        args.push("--playlist-items".to_string());
+        args.push(items.clone());
+    }
+    if options.reverse {
+        args.push("--playlist-reverse".to_string());
+    }
+    if options.audiobook {
+        args.push("-f".to_string());
+        args.push(format!("bestaudio/best{}", height_filter(config)));
+    }
+    if let Some(limit) = options.limit {
+        args.push("--playlist-end".to_string());
+        args.push(limit.to_string());
+    }
+    for (flag, date) in [
+        ("--dateafter", &options.after),
+        ("--datebefore", &options.before),
+    ] {
+        if let Some(date) = date.as_deref().and_then(ytdlp_date) {
+            args.push(flag.to_string());
+            args.push(date);
+        }
+    }
+    if let Some(filter) = options.filter.to_ytdlp() {
+        args.push("--match-filters".to_string());
+        args.push(filter);
+    }
+    // Only the final paths are reliable; yt-dlp renames files as it
+    // post-processes them.
+    args.push("--print-to-file".to_string());
+    args.push("after_move:filepath".to_string());
+    args.push(list.display().to_string());
+    args
+}
+
+/// Splits the video into chapter tracks and packs them into a ZIP named
+/// after the album.
+async fn split_album(
+    video: &DownloadedVideo,
+    job_dir: &JobDir,
+    job: &JobHandle,
+) -> Result<DownloadedVideo, DownloadError> {
+    let tracks = album::split(video, job_dir.path(), job).await?;
+    let path = job_dir.path().join("album.zip");
+    bundle::zip_entries(&tracks, &path)
+        .await
+        .map_err(DownloadError::Bundle)?;
+    let stem = Path::new(&video.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "album".to_string());
+
+    Ok(DownloadedVideo {
+        path,
+        filename: format!("{}.zip", stem),
+        info: video.info.clone(),
+        sidecars: Vec::new(),
+    })
+}
+
+/// yt-dlp options keeping several audio tracks: every track for `all`,
+/// otherwise those in the listed languages. The tracks go into an MP4 when
+/// their codecs allow it and an MKV otherwise. Falls back to a single track
+/// if none match. `height` is the [`height_filter`] for the video.
+fn audio_track_args(langs: &[String], height: &str) -> Vec<String> {
+    let tracks = if langs.iter().any(|lang| lang == "all") {
+        "mergeall[vcodec=none]".to_string()
+    } else {
+        format!("mergeall[vcodec=none][language~='^({})']", langs.join("|"))
+    };
+    [
+        "--audio-multistreams",
+        "-f",
+        &format!("bv*{h}+{}/bv*{h}+ba/b{h}", tracks, h = height),
+        "--merge-output-format",
+        "mp4/mkv",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+/// The file yt-dlp merged into for [`MULTI_AUDIO_OUTPUT`].
+async fn merged_output(dir: &Path) -> Option<PathBuf> {
+    for ext in ["mp4", "mkv"] {
+        let path = dir.join(format!("video.{}", ext));
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Adds the description, info JSON and subtitles to the video's sidecars, if
+/// the job asked for them and yt-dlp wrote them.
+async fn collect_sidecars(video: &mut DownloadedVideo, job: &JobHandle) {
+    let options = job.options();
+    for (wanted, sidecar) in [
+        (options.description, "description"),
+        (options.info_json, "info.json"),
+    ] {
+        if !wanted {
+            continue;
+        }
+        let path = video.path.with_extension(sidecar);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            video.sidecars.push(sidecar.to_string());
+        } else {
+            job.log_output(&format!("No {} available for {}", sidecar, video.filename));
+        }
+    }
+    if !options.subs.is_empty() {
+        let subs = subtitles::find(&video.path, &options.subs).await;
+        if subs.is_empty() {
+            job.log_output(&format!("No subtitles available for {}", video.filename));
+        }
+        video.sidecars.extend(subs);
+    }
+}
+
+/// Runs plugins, subtitle burning, the transcode profile, loudness
+/// normalization and metadata overrides on a downloaded video, then makes
+/// sure its extension matches what it turned out to be. Re-encodes run on
+/// `hw` when it encodes the codec they need.
+async fn post_process(
+    video: &mut DownloadedVideo,
+    plugins: &[Arc<dyn Plugin>],
+    profile: Option<&TranscodeProfile>,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), DownloadError> {
+    job.reset_post_processing();
+    for plugin in plugins {
+        plugin.post_process(video, job).await?;
+    }
+    // Before burning in subtitles, which would be tone mapped too.
+    if job.options().hdr == Some(Hdr::Tonemap) {
+        transcode::tonemap(video, hw, job).await?;
+    }
+    // Also before subtitles, so cropping can't cut them off.
+    if let Some(mode) = job.options().widescreen {
+        transcode::widescreen(video, mode, hw, job).await?;
+    }
+    if let Some(lang) = &job.options().burn_subs {
+        let Some(sidecar) = subtitles::find(&video.path, std::slice::from_ref(lang))
+            .await
+            .into_iter()
+            .next()
+        else {
+            return Err(DownloadError::NoSubtitles(lang.clone()));
+        };
+        let subtitles = video.path.with_extension(sidecar);
+        transcode::burn_subtitles(video, &subtitles, hw, job).await?;
+    }
+    if let Some(profile) = profile {
+        transcode::transcode(profile, video, hw, job).await?;
+    }
+    let options = job.options();
+    if options.normalize {
+        transcode::normalize(video, job).await?;
+    }
+    // Last of the re-encodes, so nothing changes the size afterwards.
+    if let Ok(Some(target)) = RateTarget::from_options(&options) {
+        transcode::encode_to_target(video, target, options.two_pass, hw, job).await?;
+    }
+    if !options.metadata.is_empty() {
+        transcode::tag(video, &options.metadata, job).await?;
+        options.metadata.apply(&mut video.info);
+    }
+    correct_extension(video, job).await;
+    Ok(())
+}
+
+/// Renames a video whose extension doesn't fit the container it is really
+/// in, like a WebM left as `.mp4` by a recode that didn't happen, so it is
+/// stored and served as what it is. Files of unknown types are left alone.
+async fn correct_extension(video: &mut DownloadedVideo, job: &JobHandle) {
+    let container = match sniff::sniff_file(&video.path).await {
+        Ok(Some(container)) => container,
+        Ok(None) => return,
+        Err(e) => {
+            warn!("Failed to sniff {:?}: {:?}", video.path, e);
+            return;
+        }
+    };
+    let ext = video
+        .path
+        .extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    if container.fits(&ext) {
+        return;
+    }
+
+    let path = video.path.with_extension(container.ext());
+    if let Err(e) = tokio::fs::rename(&video.path, &path).await {
+        warn!("Failed to rename {:?}: {:?}", video.path, e);
+        return;
+    }
+    job.log_output(&format!(
+        "Output is {:?}, not .{}; renamed to .{}",
+        container,
+        ext,
+        container.ext()
+    ));
+    video.path = path;
+    video.filename = Path::new(&video.filename)
+        .with_extension(container.ext())
+        .to_string_lossy()
+        .into_owned();
+}
+
+/// The file handed to a client that streams or fetches the download: a ZIP
+/// with the sidecars if the job asked for them, otherwise the video itself.
+/// Returns its path and file name.
+pub async fn deliverable(
+    video: &DownloadedVideo,
+    job: &JobHandle,
+) -> Result<(PathBuf, String), DownloadError> {
+    if !job.options().bundle() {
+        return Ok((video.path.clone(), video.filename.clone()));
+    }
+    job.log_output("Bundling sidecars into a ZIP");
+    bundle::zip(video).await.map_err(DownloadError::Bundle)
+}
+
+/// Where a job's comments are kept for `/api/jobs/{id}/comments`.
+pub fn comments_path(state: &AppState, job_id: Uuid) -> PathBuf {
+    state
+        .config
+        .data_dir
+        .join("comments")
+        .join(format!("{}.json", job_id))
+}
+
+/// Splits the comments out of the info JSON into a sidecar, which
+/// destinations keep next to the video, and keeps a copy on this server.
+async fn keep_comments(
+    state: &AppState,
+    video: &mut DownloadedVideo,
+    job: &JobHandle,
+) -> Result<(), DownloadError> {
+    if !video
+        .split_comments()
+        .await
+        .map_err(DownloadError::Comments)?
+    {
+        job.log_output("No comments available for this video");
+        return Ok(());
+    }
+
+    let path = comments_path(state, job.id());
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(DownloadError::Comments)?;
+    }
+    tokio::fs::copy(video.path.with_extension("comments.json"), &path)
+        .await
+        .map_err(DownloadError::Comments)?;
+    Ok(())
+}
+
+/// Scratch directory owned by a single download job.
+///
+/// yt-dlp writes fragments, thumbnails and info files next to its output, so
+/// every job gets its own directory. It is removed when dropped, which covers
+/// completion, failure, and the client going away mid-download. A restart
+/// skips the drop, and pausing [keeps](Self::keep) the directory, leaving
+/// partial files for a resumed job to pick up.
+#[derive(Debug)]
+pub struct JobDir {
+    path: PathBuf,
+    resumed: bool,
+    kept: bool,
+}
+
+impl JobDir {
+    pub async fn create(root: &Path, job_id: Uuid) -> Result<Self, DownloadError> {
+        let path = root.join(job_id.to_string());
+        let resumed = tokio::fs::try_exists(&path).await.unwrap_or(false);
+        tokio::fs::create_dir_all(&path)
+            .await
+            .map_err(DownloadError::JobDir)?;
+        debug!("Job directory: {:?}", path);
+
+        Ok(Self {
+            path,
+            resumed,
+            kept: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the directory was left behind by an interrupted run of the job.
+    pub fn resumed(&self) -> bool {
+        self.resumed
+    }
+
+    /// Leaves the directory behind for the next run of the job.
+    pub fn keep(mut self) {
+        self.kept = true;
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        if self.kept {
+            debug!("Keeping job directory {:?}", self.path);
+            return;
+        }
+        match std::fs::remove_dir_all(&self.path) {
+            Ok(()) => debug!("Removed job directory {:?}", self.path),
+            Err(e) => warn!("Failed to remove job directory {:?}: {:?}", self.path, e),
+        }
+    }
+}
+
+/// File stream for a job's download. Counts the bytes sent for the job's
+/// statistics and keeps its job directory, if any, alive until the body is
+/// dropped.
+pub struct JobStream {
+    // Declared before `_dir` so the file is closed before the directory is removed.
+    inner: Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>,
+    job: JobHandle,
+    sent: u64,
+    _dir: Option<JobDir>,
+}
+
+impl JobStream {
+    pub async fn open(path: &Path, dir: JobDir, job: &JobHandle) -> Result<Self, DownloadError> {
+        let tempfile = File::open(path)
+            .await
+            .map_err(DownloadError::TempFileOpen)?;
+
+        Ok(Self::new(tempfile, job.clone(), Some(dir)))
+    }
+
+    pub fn new(file: File, job: JobHandle, dir: Option<JobDir>) -> Self {
+        Self::from_stream(ReaderStream::new(file), job, dir)
+    }
+
+    pub fn from_stream(
+        stream: impl Stream<Item = io::Result<Bytes>> + Send + 'static,
+        job: JobHandle,
+        dir: Option<JobDir>,
+    ) -> Self {
+        Self {
+            inner: Box::pin(stream),
+            job,
+            sent: 0,
+            _dir: dir,
+        }
+    }
+}
+
+impl Stream for JobStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.as_mut().poll_next(cx);
+        if let Poll::Ready(Some(Ok(chunk))) = &poll {
+            self.sent += chunk.len() as u64;
+        }
+        poll
+    }
+}
+
+impl Drop for JobStream {
+    fn drop(&mut self) {
+        // Also counts partial transfers cut off by the client.
+        self.job.add_served_bytes(self.sent);
+    }
+}
+
+/// The file name yt-dlp gives a download of `url`.
+#[instrument(skip(config))]
+pub async fn get_video_title(
+    url: &str,
+    restrict: bool,
+    config: &Config,
+) -> Result<String, DownloadError> {
+    let cmd = cache::ytdlp(config, url)
+        .arg("-S")
+        .arg(sites::format_sort(config, url))
+        .arg("--recode")
+        .arg("mp4")
+        .args(restrict.then_some("--restrict-filenames"))
+        .arg("--print")
+        .arg("filename")
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(DownloadError::TitleCommand)?;
+
+    debug!("Command status: {}", cmd.status);
+    let code: Result<i32, DownloadError> = match cmd.status.code() {
+        Some(code) => match code {
+            0 => Ok(0),
+            _ => Err(DownloadError::TitleExitErrorCode(code)),
+        },
+        None => Err(DownloadError::TitleExitNoCode),
+    };
+    code?;
+
+    let title = String::from_utf8(cmd.stdout)
+        .map(|s| String::from(s.trim()))
+        .map_err(DownloadError::FromUtf8)?;
+
+    Ok(title)
+}
+
+/// Longest accepted metadata override value.
+const MAX_METADATA_LEN: usize = 1000;
+
+/// Extractor arguments a request may set, as `extractor:key`. Limited to
+/// ones that only change how a video is fetched, not where from or what
+/// else runs.
+pub const EXTRACTOR_ARGS: &[&str] = &[
+    "youtube:player_client",
+    "youtube:player_skip",
+    "youtube:lang",
+    "youtube:skip",
+    "youtube:formats",
+    "youtube:comment_sort",
+    "youtube:max_comments",
+    "youtubetab:skip",
+    "youtubetab:approximate_date",
+];
+
+/// Longest accepted extractor argument value.
+const MAX_EXTRACTOR_ARG_LEN: usize = 200;
+
+/// Longest accepted `items` selection.
+const MAX_PLAYLIST_ITEMS_LEN: usize = 200;
+
+/// ffmpeg options for yt-dlp's post-processors making them write fragmented
+/// MP4. These replace the `+faststart` yt-dlp asks for, which rewrites the
+/// whole file once it is complete.
+const FRAGMENTED_MOVFLAGS: &str = "-movflags +frag_keyframe+empty_moov+default_base_moof";
+
+/// yt-dlp output template for single videos.
+const VIDEO_OUTPUT: &str = "video.mp4";
+
+/// yt-dlp output template for single videos kept in MKV, for HDR or when
+/// recoding to MP4 failed.
+const MKV_VIDEO_OUTPUT: &str = "video.mkv";
+
+/// yt-dlp output template when merging several audio tracks, which may
+/// end up in either container.
+const MULTI_AUDIO_OUTPUT: &str = "video.%(ext)s";
+
+/// yt-dlp output template for playlist entries, numbered in playlist order.
+const PLAYLIST_OUTPUT: &str = "%(playlist_index)03d - %(title).100B [%(id)s].%(ext)s";
+
+/// File yt-dlp lists the paths of downloaded playlist entries in.
+pub const PLAYLIST_LIST: &str = "playlist-entries.txt";
+
+/// Longest wait between two attempts, however many retries are configured.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Runs the download, retrying transient failures with exponential backoff
+/// and jitter. Partial files are kept between attempts, so yt-dlp picks up
+/// where it left off.
+async fn get_video_file_with_retries(
+    state: &AppState,
+    url: &str,
+    extra_args: &[String],
+    output: &str,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, DownloadError> {
+    let config = &state.config;
+    let mut attempt = 0;
+    let mut fallbacks = throttle_fallbacks(config, url).peekable();
+    let mut client = None;
+    let mut base_args = extra_args.to_vec();
+    let mut output = output;
+    loop {
+        // Reserved per attempt so retries pick up budget freed in the meantime.
+        let share = state.bandwidth.acquire().await;
+        let mut args = base_args.clone();
+        if let Some(client) = client {
+            args.push("--extractor-args".to_string());
+            args.push(format!("youtube:player_client={}", client));
+        }
+        // The last client runs unwatched, so a video that's slow everywhere
+        // still finishes.
+        let watch = fallbacks.peek().is_some();
+        let attempt_result =
+            get_video_file(url, &args, output, share.as_ref(), watch, config, dir, job).await;
+        match attempt_result {
+            Err(DownloadError::Throttled(speed)) => {
+                let Some(next) = fallbacks.next() else {
+                    return Err(DownloadError::Throttled(speed));
+                };
+                warn!("Download of {} throttled to {} KB/s", url, speed);
+                job.log_output(&format!(
+                    "Download throttled to {} KB/s, retrying with the {} player client",
+                    speed, next
+                ));
+                client = Some(next);
+            }
+            Err(DownloadError::VideoPostprocessing(code)) => {
+                let Some(remux) = remux_fallback(&base_args) else {
+                    return Err(DownloadError::VideoPostprocessing(code));
+                };
+                warn!("Recoding {} to MP4 failed, remuxing to MKV", url);
+                job.log_output("Recoding to MP4 failed, remuxing to MKV instead");
+                base_args = remux;
+                if output == VIDEO_OUTPUT {
+                    output = MKV_VIDEO_OUTPUT;
+                }
+            }
+            Err(DownloadError::VideoTransient(reason)) if attempt < config.download_retries => {
+                attempt += 1;
+                let delay = retry_delay(config.retry_base_delay_ms, attempt);
+                warn!("Transient error downloading {}: {}", url, reason);
+                job.log_output(&format!(
+                    "Transient error ({}), retrying in {:.1}s (attempt {} of {})",
+                    reason,
+                    delay.as_secs_f32(),
+                    attempt,
+                    config.download_retries
+                ));
+                tokio::time::sleep(delay).await;
+            }
+            result => {
+                if result.is_ok() {
+                    job.set_stage(JobStage::PostProcessing);
+                }
+                return result;
+            }
+        }
+    }
+}
+
+/// `args` with `--recode mp4` swapped for a remux to MKV, which takes
+/// whatever codecs were downloaded. `None` if they don't recode, or write a
+/// fragmented MP4 that's already being streamed to the client.
+fn remux_fallback(args: &[String]) -> Option<Vec<String>> {
+    let fragmented = format!("ffmpeg_o:{}", FRAGMENTED_MOVFLAGS);
+    if args.contains(&fragmented) {
+        return None;
+    }
+    let at = args
+        .windows(2)
+        .position(|pair| pair[0] == "--recode" && pair[1] == "mp4")?;
+    let mut args = args.to_vec();
+    args.splice(
+        at..at + 2,
+        ["--merge-output-format", "mkv", "--remux-video", "mkv"].map(String::from),
+    );
+    Some(args)
+}
+
+/// Player clients to fall back to when a download of `url` is throttled:
+/// none unless it's on YouTube and the check is on.
+fn throttle_fallbacks<'a>(config: &'a Config, url: &str) -> impl Iterator<Item = &'a str> {
+    let youtube = Url::parse(url).ok().is_some_and(|url| {
+        url.host_str().is_some_and(|host| {
+            host == "youtube.com" || host.ends_with(".youtube.com") || host == "youtu.be"
+        })
+    });
+    let clients: &[String] = if youtube && config.throttle_min_speed_kb > 0 {
+        &config.throttle_fallback_clients
+    } else {
+        &[]
+    };
+    clients.iter().map(String::as_str)
+}
+
+/// Exponential backoff with "equal jitter": half the delay is fixed, the other
+/// half random, so retries of jobs that failed together spread out.
+fn retry_delay(base_ms: u64, attempt: u32) -> Duration {
+    let exp_ms = base_ms
+        .saturating_mul(1 << (attempt - 1).min(20))
+        .min(MAX_RETRY_DELAY.as_millis() as u64);
+    let jitter_ms = rand::random_range(0..=exp_ms / 2);
+    Duration::from_millis(exp_ms - exp_ms / 2 + jitter_ms)
+}
+
+/// Maps a failed yt-dlp run to an error from the `ERROR:` lines it printed.
+pub fn classify_failure(stderr: &str, code: i32) -> DownloadError {
+    let errors: Vec<&str> = stderr.lines().filter(|l| l.contains("ERROR")).collect();
+    let mentions = |patterns: &[&str]| {
+        errors
+            .iter()
+            .any(|line| patterns.iter().any(|p| line.contains(p)))
+    };
+
+    if mentions(&["Private video", "This video is private"]) {
+        DownloadError::VideoPrivate
+    } else if mentions(&[
+        "Sign in to confirm your age",
+        "age-restricted",
+        "age restricted",
+    ]) {
+        DownloadError::AgeRestricted
+    } else if mentions(&[
+        "available in your country",
+        "geo restriction",
+        "geo-restricted",
+        "geo restricted",
+    ]) {
+        DownloadError::GeoBlocked
+    } else if mentions(&["Unsupported URL"]) {
+        DownloadError::UnsupportedUrl
+    } else if mentions(&[
+        "Video unavailable",
+        "This video is unavailable",
+        "This video has been removed",
+        "HTTP Error 404",
+    ]) {
+        DownloadError::VideoUnavailable
+    } else if mentions(&["Postprocessing:"]) {
+        DownloadError::VideoPostprocessing(code)
+    } else if let Some(reason) = transient_reason(&errors) {
+        DownloadError::VideoTransient(reason)
+    } else {
+        DownloadError::VideoExitErrorCode(code)
+    }
+}
+
+/// Recognizes yt-dlp failures that are likely to succeed if tried again.
+fn transient_reason(errors: &[&str]) -> Option<&'static str> {
+    const PATTERNS: &[(&str, &str)] = &[
+        ("HTTP Error 429", "rate limited"),
+        ("Too Many Requests", "rate limited"),
+        ("timed out", "timeout"),
+        ("Timeout", "timeout"),
+        ("fragment", "fragment error"),
+        ("Connection reset", "connection reset"),
+        ("HTTP Error 500", "server error"),
+        ("HTTP Error 502", "server error"),
+        ("HTTP Error 503", "server error"),
+        ("HTTP Error 504", "server error"),
+    ];
+
+    for line in errors {
+        if let Some((_, reason)) = PATTERNS.iter().find(|(p, _)| line.contains(p)) {
+            return Some(reason);
+        }
+    }
+    None
+}
+
+/// The yt-dlp command downloading `url` to `path` in the job directory
+/// `dir`, at most at `limit_kb` KiB/s.
+pub fn video_command(
+    url: &str,
+    extra_args: &[String],
+    path: &Path,
+    limit_kb: Option<u64>,
+    config: &Config,
+    dir: &Path,
+) -> Command {
+    let mut command = cache::ytdlp(config, url);
+    if let Some(kb) = limit_kb {
+        command.arg("--limit-rate").arg(format!("{}K", kb));
+    }
+    command
+        .arg("-S")
+        .arg(sites::format_sort(config, url))
+        .arg("--newline")
+        .arg("--paths")
+        .arg(dir)
+        .arg("--write-info-json")
+        .args(extra_args)
+        .arg("-o")
+        .arg(path)
+        .arg(url);
+    command
+}
+
+#[instrument(skip(config, job))]
+#[allow(clippy::too_many_arguments)]
+async fn get_video_file(
+    url: &str,
+    extra_args: &[String],
+    output: &str,
+    share: Option<&RateShare>,
+    watch_throttling: bool,
+    config: &Config,
+    dir: &Path,
+    job: &JobHandle,
+) -> Result<PathBuf, DownloadError> {
+    let path = dir.join(output);
+    debug!("Output Path: {:?}", path);
+    let verbose = job.options().debug;
+
+    let site = sites::for_url(&config.sites, url);
+    let limit_kb = [share.map(RateShare::kb), site.and_then(|s| s.rate_limit_kb)]
+        .into_iter()
+        .flatten()
+        .min();
+    let mut child = video_command(url, extra_args, &path, limit_kb, config, dir)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(DownloadError::VideoCommand)?;
+
+    // A download held back by our own rate limit isn't throttled.
+    let throttled = Notify::new();
+    let min_speed_kb = config.throttle_min_speed_kb;
+    let mut watch =
+        (watch_throttling && limit_kb.is_none_or(|kb| kb > min_speed_kb)).then(|| ThrottleWatch {
+            min_speed: min_speed_kb * 1024,
+            grace: Duration::from_secs(config.throttle_grace_secs),
+            slow_since: None,
+            tripped: None,
+            notify: &throttled,
+        });
+
+    // Log output as it arrives so running jobs can be tailed.
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let wait = async {
+        let status = tokio::select! {
+            status = child.wait() => Some(status),
+            () = throttled.notified() => None,
+        };
+        match status {
+            Some(status) => status,
+            None => {
+                child.start_kill()?;
+                child.wait().await
+            }
+        }
+    };
+    let (status, _, stderr) = tokio::join!(
+        wait,
+        forward_lines(stdout, job, verbose, watch.as_mut()),
+        forward_lines(stderr, job, verbose, None)
+    );
+    let status = status.map_err(DownloadError::VideoCommand)?;
+    let stderr = stderr.map_err(DownloadError::VideoCommand)?;
+
+    debug!("Command status: {}", status);
+    debug!("Command stderr: {}", stderr);
+
+    if let Some(speed) = watch.and_then(|watch| watch.tripped) {
+        return Err(DownloadError::Throttled(speed / 1024));
+    }
+
+    job.set_exit_code(status.code().filter(|code| *code != 0));
+    let code: Result<i32, DownloadError> = match status.code() {
+        Some(code) => match code {
+            0 => Ok(0),
+            _ => Err(classify_failure(&stderr, code)),
+        },
+        None => Err(DownloadError::VideoExitNoCode),
+    };
+    if let Err(e) = code {
+        job.set_stderr_tail(sanitize_stderr(&stderr, dir));
+        return Err(e);
+    }
+
+    Ok(path)
+}
+
+/// Watches a YouTube download for throttling, where the site serves it at a
+/// fraction of the usual speed until it's fetched through another client.
+struct ThrottleWatch<'a> {
+    /// Bytes per second.
+    min_speed: u64,
+    grace: Duration,
+    slow_since: Option<Instant>,
+    /// Speed the download was at when it had been slow for too long.
+    tripped: Option<u64>,
+    notify: &'a Notify,
+}
+
+impl ThrottleWatch<'_> {
+    fn observe(&mut self, progress: &DownloadProgress) {
+        if self.tripped.is_some() {
+            return;
+        }
+        // Nearly done is fine, however slowly.
+        let slow = progress
+            .speed
+            .filter(|speed| *speed < self.min_speed)
+            .filter(|_| progress.eta.is_none_or(|eta| eta > self.grace.as_secs()));
+        let Some(speed) = slow else {
+            self.slow_since = None;
+            return;
+        };
+        let since = *self.slow_since.get_or_insert_with(Instant::now);
+        if since.elapsed() >= self.grace {
+            self.tripped = Some(speed);
+            self.notify.notify_one();
+        }
+    }
+}
+
+/// Copies each line of `reader` into the job log, returning everything read.
+/// With `verbose`, every line also goes to the debug log, and yt-dlp's
+/// `[debug]` lines only there, so they stay out of the log everyone can read.
+/// Progress lines are fed to `throttle`, if given.
+async fn forward_lines(
+    reader: impl AsyncRead + Unpin,
+    job: &JobHandle,
+    verbose: bool,
+    mut throttle: Option<&mut ThrottleWatch<'_>>,
+) -> io::Result<String> {
+    let mut reader = BufReader::new(reader);
+    let mut output = String::new();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(output);
+        }
+        let text = String::from_utf8_lossy(&line);
+        if text.starts_with("[download]") {
+            job.set_stage(JobStage::Downloading);
+            if let Some(progress) = progress::parse(&text) {
+                if let Some(watch) = throttle.as_deref_mut() {
+                    watch.observe(&progress);
+                }
+                job.set_download_progress(progress);
+            }
+        }
+        if verbose {
+            job.log_debug(&text);
+            if text.starts_with("[debug] ") {
+                continue;
+            }
+        }
+        job.log_output(&text);
+        output.push_str(&text);
+    }
+}
+
+/// Lines of stderr kept on a failed job.
+const STDERR_TAIL_LINES: usize = 50;
+
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;]*[A-Za-z]").unwrap());
+static URL_QUERY: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(https?://[^\s?#]+)[?#]\S*").unwrap());
+
+/// Last lines of yt-dlp's stderr, made safe to show to users: colour codes are
+/// stripped, URL query strings (which carry signatures and tokens) redacted,
+/// and server paths replaced.
+fn sanitize_stderr(stderr: &str, dir: &Path) -> Vec<String> {
+    let dir = dir.to_string_lossy();
+    let lines: Vec<&str> = stderr.lines().filter(|l| !l.trim().is_empty()).collect();
+    let start = lines.len().saturating_sub(STDERR_TAIL_LINES);
+
+    lines[start..]
+        .iter()
+        .map(|line| {
+            let line = ANSI_ESCAPE.replace_all(line, "");
+            let line = URL_QUERY.replace_all(&line, "$1?<redacted>");
+            line.replace(dir.as_ref(), "<job>")
+        })
+        .collect()
+}
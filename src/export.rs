@@ -0,0 +1,178 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::jobs::{Failure, Job, JobMode, JobOptions, JobStatus};
+
+/// Columns of the CSV export, in order.
+const CSV_COLUMNS: &[&str] = &[
+    "id",
+    "url",
+    "title",
+    "uploader",
+    "status",
+    "created_at",
+    "finished_at",
+    "bytes",
+    "sha256",
+    "filename",
+    "location",
+    "tags",
+];
+
+/// A job as exported from the history, and as accepted back by the import.
+/// Server paths and logs are left out.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportRecord {
+    pub id: Uuid,
+    pub url: String,
+    pub dest: Option<String>,
+    pub user: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub options: JobOptions,
+    pub mode: JobMode,
+    pub status: JobStatus,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub failure: Option<Failure>,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub filename: Option<String>,
+    pub location: Option<String>,
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    pub bytes: Option<u64>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub served_bytes: u64,
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// Outcome of a history import.
+#[derive(Debug, Default, Serialize)]
+pub struct HistoryImport {
+    pub imported: usize,
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Skipped {
+    pub id: Uuid,
+    pub reason: &'static str,
+}
+
+impl From<&Job> for ExportRecord {
+    fn from(job: &Job) -> Self {
+        Self {
+            id: job.id,
+            url: job.url.clone(),
+            dest: job.dest.clone(),
+            user: job.user.clone(),
+            tags: job.tags.clone(),
+            options: job.options.clone(),
+            mode: job.mode,
+            status: job.status,
+            error: job.error.clone(),
+            failure: job.failure.clone(),
+            title: job.title.clone(),
+            uploader: job.uploader.clone(),
+            filename: job.filename.clone(),
+            location: job.location.clone(),
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+            bytes: job.bytes,
+            sha256: job.sha256.clone(),
+            served_bytes: job.served_bytes,
+            pinned: job.pinned,
+        }
+    }
+}
+
+impl ExportRecord {
+    /// The job to add to this server's history, or why it can't be. Files
+    /// aren't carried over, so only links to elsewhere are kept.
+    pub fn into_job(self) -> Result<Job, &'static str> {
+        if !matches!(self.status, JobStatus::Completed | JobStatus::Failed) {
+            return Err("unfinished");
+        }
+        let location = self
+            .location
+            .filter(|location| location.starts_with("https://") || location.starts_with("http://"));
+
+        Ok(Job {
+            id: self.id,
+            url: self.url,
+            dest: self.dest,
+            user: self.user,
+            tags: self.tags,
+            options: self.options,
+            mode: self.mode,
+            status: self.status,
+            stage: self.status.into(),
+            stages: Vec::new(),
+            video_key: None,
+            idempotency_key: None,
+            error: self.error,
+            failure: self.failure,
+            exit_code: None,
+            title: self.title,
+            uploader: self.uploader,
+            filename: self.filename,
+            location,
+            output: None,
+            created_at: self.created_at,
+            finished_at: self.finished_at,
+            bytes: self.bytes,
+            sha256: self.sha256,
+            served_bytes: self.served_bytes,
+            pinned: self.pinned,
+            accessed_at: None,
+            trashed_at: None,
+            progress: None,
+            post_processing: None,
+            overall_percent: None,
+            upload: None,
+            log: Vec::new(),
+            stderr_tail: Vec::new(),
+        })
+    }
+}
+
+/// The jobs as CSV with a header row, for spreadsheets. Tags are joined with
+/// commas inside their field.
+pub fn to_csv(jobs: &[Job]) -> String {
+    let mut csv = CSV_COLUMNS.join(",");
+    csv.push_str("\r\n");
+    for job in jobs {
+        let fields = [
+            job.id.to_string(),
+            job.url.clone(),
+            job.title.clone().unwrap_or_default(),
+            job.uploader.clone().unwrap_or_default(),
+            job.status.as_str().to_string(),
+            job.created_at.to_string(),
+            job.finished_at.map(|t| t.to_string()).unwrap_or_default(),
+            job.bytes.map(|b| b.to_string()).unwrap_or_default(),
+            job.sha256.clone().unwrap_or_default(),
+            job.filename.clone().unwrap_or_default(),
+            job.location.clone().unwrap_or_default(),
+            job.tags.join(","),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+/// Quotes a field if it needs it, doubling any quotes inside.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -0,0 +1,331 @@
+use std::cmp::Reverse;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, InputObject, Json, Object, OutputType, Result,
+    Schema, SimpleObject,
+};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    api::load_stats,
+    bookmarks::Bookmark,
+    jobs::{self, Job, JobOptions, StageChange},
+    stats::Stats,
+};
+
+pub type ApiSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Items per page unless `first` is given, and at most.
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Deepest and most expensive queries accepted, so one request can't tie up
+/// the server.
+const MAX_DEPTH: usize = 8;
+const MAX_COMPLEXITY: usize = 5000;
+
+/// Schema served at `/api/graphql`. Each request carries the [`AppState`],
+/// the [`Caller`] and whether they are an [`Admin`].
+pub fn schema() -> ApiSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+/// User making the request, as reported by the authenticating proxy.
+pub struct Caller(pub Option<String>);
+
+/// Whether the caller has one of the `admin_roles`.
+pub struct Admin(pub bool);
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Jobs from the whole history, newest first, or best matches first when
+    /// searching.
+    async fn jobs(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<JobFilter>,
+        #[graphql(default)] offset: usize,
+        first: Option<usize>,
+    ) -> Result<Page<JobObject>> {
+        let state = ctx.data::<AppState>()?;
+        let filter = filter.unwrap_or_default();
+        let jobs = match filter.search.as_deref() {
+            Some(query) => state.jobs.search(query),
+            None => {
+                let mut all = state.jobs.all()?;
+                all.reverse();
+                all
+            }
+        };
+        let jobs = jobs.into_iter().filter(|job| filter.matches(job));
+        Ok(Page::of(jobs.map(JobObject), offset, first))
+    }
+
+    async fn job(&self, ctx: &Context<'_>, id: Uuid) -> Result<Option<JobObject>> {
+        Ok(ctx.data::<AppState>()?.jobs.get(id).map(JobObject))
+    }
+
+    /// Files kept on this server, most recently finished first.
+    async fn library(
+        &self,
+        ctx: &Context<'_>,
+        pinned: Option<bool>,
+        #[graphql(default)] offset: usize,
+        first: Option<usize>,
+    ) -> Result<Page<JobObject>> {
+        let mut kept = ctx.data::<AppState>()?.jobs.kept();
+        kept.retain(|job| pinned.is_none_or(|pinned| job.pinned == pinned));
+        kept.sort_by_key(|job| Reverse(job.finished_at));
+        Ok(Page::of(kept.into_iter().map(JobObject), offset, first))
+    }
+
+    /// The caller's bookmarks, oldest first.
+    async fn bookmarks(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] offset: usize,
+        first: Option<usize>,
+    ) -> Result<Page<Bookmark>> {
+        let state = ctx.data::<AppState>()?;
+        let Caller(user) = ctx.data::<Caller>()?;
+        let bookmarks = state.db.bookmarks(user.as_deref())?;
+        Ok(Page::of(bookmarks.into_iter(), offset, first))
+    }
+
+    /// Usage over the last `days` days, as in `GET /api/admin/stats`. For
+    /// admin roles only.
+    async fn stats(&self, ctx: &Context<'_>, #[graphql(default = 30)] days: u64) -> Result<Stats> {
+        let Admin(admin) = ctx.data::<Admin>()?;
+        if !admin {
+            return Err("stats are limited to admin roles".into());
+        }
+        let state = ctx.data::<AppState>()?;
+        load_stats(state, days)
+            .await
+            .map_err(|_| "failed to compute stats".into())
+    }
+}
+
+/// A slice of a longer list.
+#[derive(SimpleObject)]
+#[graphql(concrete(name = "JobPage", params(JobObject)))]
+#[graphql(concrete(name = "BookmarkPage", params(Bookmark)))]
+pub struct Page<T: OutputType> {
+    /// Length of the whole list.
+    total_count: usize,
+    items: Vec<T>,
+}
+
+impl<T: OutputType> Page<T> {
+    fn of(items: impl Iterator<Item = T>, offset: usize, first: Option<usize>) -> Self {
+        let first = first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE);
+        let mut total_count = 0;
+        let mut page = Vec::new();
+        for (i, item) in items.enumerate() {
+            total_count += 1;
+            if i >= offset && page.len() < first {
+                page.push(item);
+            }
+        }
+        Self {
+            total_count,
+            items: page,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct JobFilter {
+    status: Option<JobStatus>,
+    mode: Option<JobMode>,
+    /// Jobs with this tag.
+    tag: Option<String>,
+    /// Jobs submitted by this user.
+    user: Option<String>,
+    /// Full-text search over URL, title, description, uploader and tags.
+    search: Option<String>,
+    /// Unix timestamps in seconds bounding when jobs were submitted.
+    created_after: Option<u64>,
+    created_before: Option<u64>,
+}
+
+impl JobFilter {
+    fn matches(&self, job: &Job) -> bool {
+        self.status.is_none_or(|status| job.status == status.into())
+            && self.mode.is_none_or(|mode| job.mode == mode.into())
+            && self.tag.as_ref().is_none_or(|tag| job.tags.contains(tag))
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| job.user.as_ref() == Some(user))
+            && self.created_after.is_none_or(|t| job.created_at >= t)
+            && self.created_before.is_none_or(|t| job.created_at < t)
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "jobs::JobStatus")]
+enum JobStatus {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Failed,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "jobs::JobMode")]
+enum JobMode {
+    Stream,
+    Queued,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "jobs::JobStage")]
+enum JobStage {
+    Queued,
+    Resolving,
+    Downloading,
+    PostProcessing,
+    Uploading,
+    Storing,
+    Paused,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// When a job entered a stage.
+#[derive(SimpleObject)]
+#[graphql(name = "StageChange")]
+struct StageChangeObject {
+    stage: JobStage,
+    /// Unix timestamp in milliseconds.
+    at_ms: u64,
+}
+
+impl From<&StageChange> for StageChangeObject {
+    fn from(change: &StageChange) -> Self {
+        Self {
+            stage: change.stage.into(),
+            at_ms: change.at_ms,
+        }
+    }
+}
+
+/// A download job, as in `GET /api/jobs/{id}`.
+pub struct JobObject(Job);
+
+#[Object(name = "Job")]
+impl JobObject {
+    async fn id(&self) -> Uuid {
+        self.0.id
+    }
+
+    async fn url(&self) -> &str {
+        &self.0.url
+    }
+
+    async fn dest(&self) -> Option<&str> {
+        self.0.dest.as_deref()
+    }
+
+    async fn user(&self) -> Option<&str> {
+        self.0.user.as_deref()
+    }
+
+    async fn tags(&self) -> &[String] {
+        &self.0.tags
+    }
+
+    /// Options the job was submitted with, as in `POST /api/jobs`.
+    async fn options(&self) -> Json<&JobOptions> {
+        Json(&self.0.options)
+    }
+
+    async fn mode(&self) -> JobMode {
+        self.0.mode.into()
+    }
+
+    async fn status(&self) -> JobStatus {
+        self.0.status.into()
+    }
+
+    /// Step of the pipeline the job is at.
+    async fn stage(&self) -> JobStage {
+        self.0.stage.into()
+    }
+
+    /// Every stage the job went through, oldest first.
+    async fn stages(&self) -> Vec<StageChangeObject> {
+        self.0.stages.iter().map(Into::into).collect()
+    }
+
+    async fn error(&self) -> Option<&str> {
+        self.0.error.as_deref()
+    }
+
+    async fn title(&self) -> Option<&str> {
+        self.0.title.as_deref()
+    }
+
+    async fn uploader(&self) -> Option<&str> {
+        self.0.uploader.as_deref()
+    }
+
+    async fn filename(&self) -> Option<&str> {
+        self.0.filename.as_deref()
+    }
+
+    /// Where the finished file can be fetched from.
+    async fn location(&self) -> Option<&str> {
+        self.0.location.as_deref()
+    }
+
+    /// Unix timestamps in seconds.
+    async fn created_at(&self) -> u64 {
+        self.0.created_at
+    }
+
+    async fn finished_at(&self) -> Option<u64> {
+        self.0.finished_at
+    }
+
+    async fn bytes(&self) -> Option<u64> {
+        self.0.bytes
+    }
+
+    async fn sha256(&self) -> Option<&str> {
+        self.0.sha256.as_deref()
+    }
+
+    async fn served_bytes(&self) -> u64 {
+        self.0.served_bytes
+    }
+
+    async fn pinned(&self) -> bool {
+        self.0.pinned
+    }
+
+    async fn accessed_at(&self) -> Option<u64> {
+        self.0.accessed_at
+    }
+
+    async fn trashed_at(&self) -> Option<u64> {
+        self.0.trashed_at
+    }
+    /// Latest lines of the job log.
+    async fn log(&self) -> &[String] {
+        &self.0.log
+    }
+
+    async fn stderr_tail(&self) -> &[String] {
+        &self.0.stderr_tail
+    }
+}
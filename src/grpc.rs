@@ -0,0 +1,188 @@
+use std::pin::Pin;
+
+use axum::response::IntoResponse;
+use futures_util::{Stream, StreamExt};
+use tokio::sync::broadcast::error::RecvError;
+use tonic::{Code, Request, Response, Status, transport::Server};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    api::{SubmitJobRequest, enqueue},
+    download::DownloadError,
+    jobs::{self, JobOptions, JobStatus, LogEvent},
+};
+
+mod proto {
+    tonic::include_proto!("yt_dlp_web");
+}
+
+use proto::{
+    GetJobRequest, Job, JobEvent, ListJobsRequest, ListJobsResponse,
+    job_event::Event,
+    yt_dlp_web_server::{YtDlpWeb, YtDlpWebServer},
+};
+
+/// Serves the job API over gRPC on `port` alongside the HTTP server.
+pub fn spawn(state: &AppState, port: u16) {
+    let service = YtDlpWebServer::new(Service {
+        state: state.clone(),
+    });
+    tokio::spawn(async move {
+        let addr = ([0, 0, 0, 0], port).into();
+        info!("Serving gRPC on {}", addr);
+        if let Err(e) = Server::builder().add_service(service).serve(addr).await {
+            error!("gRPC server failed: {:?}", e);
+        }
+    });
+}
+
+struct Service {
+    state: AppState,
+}
+
+#[tonic::async_trait]
+impl YtDlpWeb for Service {
+    async fn submit_job(
+        &self,
+        request: Request<proto::SubmitJobRequest>,
+    ) -> Result<Response<Job>, Status> {
+        let headers = request.metadata().clone().into_headers();
+        let request = request.into_inner();
+        let options: JobOptions = match request.options_json.as_deref() {
+            Some(json) => serde_json::from_str(json)
+                .map_err(|e| Status::invalid_argument(format!("invalid options: {}", e)))?,
+            None => JobOptions::default(),
+        };
+        let payload = SubmitJobRequest {
+            url: request.url,
+            dest: request.dest,
+            tags: request.tags,
+            force: false,
+            options,
+        };
+        let id = enqueue(&self.state, &headers, payload).map_err(status)?;
+        self.job(id)
+    }
+
+    async fn get_job(&self, request: Request<GetJobRequest>) -> Result<Response<Job>, Status> {
+        self.job(parse_id(&request.get_ref().id)?)
+    }
+
+    async fn list_jobs(
+        &self,
+        request: Request<ListJobsRequest>,
+    ) -> Result<Response<ListJobsResponse>, Status> {
+        let jobs = match &request.get_ref().tag {
+            Some(tag) => self.state.jobs.list_tagged(tag.trim()),
+            None => self.state.jobs.list(),
+        };
+        Ok(Response::new(ListJobsResponse {
+            jobs: jobs.iter().map(Job::from).collect(),
+        }))
+    }
+
+    type WatchJobStream = Pin<Box<dyn Stream<Item = Result<JobEvent, Status>> + Send>>;
+
+    async fn watch_job(
+        &self,
+        request: Request<GetJobRequest>,
+    ) -> Result<Response<Self::WatchJobStream>, Status> {
+        let id = parse_id(&request.get_ref().id)?;
+        let jobs = &self.state.jobs;
+        jobs.get(id).ok_or_else(not_found)?;
+        // Subscribe before reading the backlog so no line falls in between.
+        let receiver = jobs.logs().subscribe(id);
+        let backlog = jobs.logs().read(id).unwrap_or_default();
+        if let Some(status @ (JobStatus::Completed | JobStatus::Failed)) =
+            jobs.get(id).map(|job| job.status)
+        {
+            jobs.logs().finish(id, status);
+        }
+
+        let backlog = futures_util::stream::iter(
+            backlog
+                .lines()
+                .map(|line| Ok(event(Event::Line(line.to_string()))))
+                .collect::<Vec<_>>(),
+        );
+        let live = futures_util::stream::unfold(Some(receiver), |receiver| async move {
+            let mut receiver = receiver?;
+            let (event, receiver) = match receiver.recv().await {
+                Ok(LogEvent::Line(line)) => (Event::Line(line), Some(receiver)),
+                Ok(LogEvent::Finished(status)) => {
+                    (Event::Finished(proto_status(status).into()), None)
+                }
+                Err(RecvError::Lagged(skipped)) => (Event::Lagged(skipped), Some(receiver)),
+                Err(RecvError::Closed) => return None,
+            };
+            Some((Ok(self::event(event)), receiver))
+        });
+        Ok(Response::new(Box::pin(backlog.chain(live))))
+    }
+}
+
+impl Service {
+    fn job(&self, id: Uuid) -> Result<Response<Job>, Status> {
+        let job = self.state.jobs.get(id).ok_or_else(not_found)?;
+        Ok(Response::new(Job::from(&job)))
+    }
+}
+
+impl From<&jobs::Job> for Job {
+    fn from(job: &jobs::Job) -> Self {
+        Self {
+            id: job.id.to_string(),
+            url: job.url.clone(),
+            dest: job.dest.clone(),
+            user: job.user.clone(),
+            tags: job.tags.clone(),
+            status: proto_status(job.status).into(),
+            error: job.error.clone(),
+            title: job.title.clone(),
+            uploader: job.uploader.clone(),
+            filename: job.filename.clone(),
+            location: job.location.clone(),
+            created_at: job.created_at,
+            finished_at: job.finished_at,
+            bytes: job.bytes,
+        }
+    }
+}
+
+fn proto_status(status: JobStatus) -> proto::JobStatus {
+    match status {
+        JobStatus::Queued => proto::JobStatus::Queued,
+        JobStatus::Running => proto::JobStatus::Running,
+        JobStatus::Paused => proto::JobStatus::Paused,
+        JobStatus::Completed => proto::JobStatus::Completed,
+        JobStatus::Failed => proto::JobStatus::Failed,
+    }
+}
+
+fn event(event: Event) -> JobEvent {
+    JobEvent { event: Some(event) }
+}
+
+fn parse_id(id: &str) -> Result<Uuid, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument("invalid job id"))
+}
+
+fn not_found() -> Status {
+    Status::not_found("job not found")
+}
+
+/// The gRPC equivalent of the HTTP status `e` maps to.
+fn status(e: DownloadError) -> Status {
+    let message = e.to_string();
+    let code = match e.into_response().status().as_u16() {
+        400 | 422 => Code::InvalidArgument,
+        403 => Code::PermissionDenied,
+        404 => Code::NotFound,
+        429 => Code::ResourceExhausted,
+        _ => Code::Internal,
+    };
+    Status::new(code, message)
+}
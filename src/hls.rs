@@ -0,0 +1,156 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::process::Command;
+use tracing::{debug, error, info, instrument};
+use uuid::Uuid;
+
+const PLAYLIST: &str = "stream.m3u8";
+
+/// Length of each segment, in seconds.
+const SEGMENT_SECS: u32 = 6;
+
+/// How long a playlist request waits for ffmpeg to write the first segment.
+const PLAYLIST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(thiserror::Error, Debug)]
+pub enum HlsError {
+    #[error("failed to prepare HLS cache")]
+    Cache(#[source] io::Error),
+    #[error("segmenting failed")]
+    Failed,
+    #[error("timed out waiting for the first segment")]
+    Timeout,
+}
+
+/// Serves files kept on this server as HLS, so they can be played while the
+/// rest is still being segmented.
+///
+/// ffmpeg segments a file the first time it is requested, writing into a
+/// per-job cache directory. The playlist is served as an `EVENT` playlist
+/// while that runs, and the finished segments are reused afterwards.
+#[derive(Debug)]
+pub struct Hls {
+    cache_dir: PathBuf,
+    running: Mutex<HashSet<Uuid>>,
+}
+
+impl Hls {
+    pub fn new(cache_dir: PathBuf) -> Self {
+        Self {
+            cache_dir,
+            running: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn dir(&self, id: Uuid) -> PathBuf {
+        self.cache_dir.join(id.to_string())
+    }
+
+    /// The playlist for `input`, starting ffmpeg if it has not been segmented
+    /// yet.
+    pub async fn playlist(self: &Arc<Self>, id: Uuid, input: &Path) -> Result<String, HlsError> {
+        let path = self.dir(id).join(PLAYLIST);
+        if let Ok(playlist) = tokio::fs::read_to_string(&path).await
+            && playlist.contains("#EXT-X-ENDLIST")
+        {
+            return Ok(playlist);
+        }
+
+        self.start(id, input).await?;
+        let deadline = tokio::time::Instant::now() + PLAYLIST_TIMEOUT;
+        loop {
+            if let Ok(playlist) = tokio::fs::read_to_string(&path).await
+                && playlist.contains("#EXTINF")
+            {
+                return Ok(playlist);
+            }
+            if !self.running.lock().unwrap().contains(&id) {
+                return Err(HlsError::Failed);
+            }
+            if tokio::time::Instant::now() >= deadline {
+                return Err(HlsError::Timeout);
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    /// Deletes the cached segments of `id`, e.g. once its file is gone.
+    pub async fn remove(&self, id: Uuid) {
+        let _ = tokio::fs::remove_dir_all(self.dir(id)).await;
+    }
+
+    /// Path of a segment of `id`'s stream, if `name` is one.
+    pub fn segment(&self, id: Uuid, name: &str) -> Option<PathBuf> {
+        let number = name.strip_prefix("segment_")?.strip_suffix(".ts")?;
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(self.dir(id).join(name))
+    }
+
+    /// Spawns ffmpeg for `id` unless it is already running. Leftovers of an
+    /// unfinished run, e.g. from before a restart, are cleared first.
+    async fn start(self: &Arc<Self>, id: Uuid, input: &Path) -> Result<(), HlsError> {
+        if !self.running.lock().unwrap().insert(id) {
+            return Ok(());
+        }
+
+        let dir = self.dir(id);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        if let Err(e) = tokio::fs::create_dir_all(&dir).await {
+            self.running.lock().unwrap().remove(&id);
+            return Err(HlsError::Cache(e));
+        }
+
+        info!("Segmenting {:?} for HLS", input);
+        let hls = self.clone();
+        let input = input.to_path_buf();
+        tokio::spawn(async move {
+            let result = segment(&input, &dir).await;
+            if let Err(e) = &result {
+                error!("Failed to segment {:?}: {:?}", input, e);
+                let _ = tokio::fs::remove_dir_all(&dir).await;
+            }
+            hls.running.lock().unwrap().remove(&id);
+        });
+
+        Ok(())
+    }
+}
+
+/// Remuxes `input` into HLS segments in `dir`. Video is copied, so this is
+/// fast; audio is re-encoded to AAC, which every HLS player supports.
+#[instrument]
+async fn segment(input: &Path, dir: &Path) -> io::Result<()> {
+    let status = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-i")
+        .arg(input)
+        .args(["-c:v", "copy", "-c:a", "aac", "-b:a", "160k"])
+        .args(["-f", "hls", "-hls_playlist_type", "event"])
+        .arg("-hls_time")
+        .arg(SEGMENT_SECS.to_string())
+        .arg("-hls_segment_filename")
+        .arg(dir.join("segment_%05d.ts"))
+        .arg(dir.join(PLAYLIST))
+        .stdout(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await?;
+
+    debug!("ffmpeg status: {}", status);
+    if !status.success() {
+        return Err(io::Error::other(format!("ffmpeg exited with {}", status)));
+    }
+    Ok(())
+}
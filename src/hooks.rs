@@ -0,0 +1,154 @@
+use std::io;
+
+use regex::Regex;
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, instrument};
+
+use crate::{
+    jobs::JobHandle,
+    scan::ScanConfig,
+    video::DownloadedVideo,
+    webhooks::{MatrixRoom, SlackWebhook, Webhook},
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Command run after each successful download and before it is delivered,
+    /// e.g. `["/scripts/tag.sh", "--verbose"]`. The file path is appended as
+    /// the last argument and metadata is passed in `YTDLP_WEB_*` variables.
+    pub post_download: Option<Vec<String>>,
+    /// Scanner every download is checked with after the post-download hook.
+    pub scan: Option<ScanConfig>,
+    /// Rules applied in order to every submitted URL before it reaches yt-dlp.
+    pub url_rules: Vec<UrlRule>,
+    /// Endpoints told when jobs complete or fail.
+    pub webhooks: Vec<Webhook>,
+    /// Slack channels and Matrix rooms told the same, in a chat message.
+    pub slack: Vec<SlackWebhook>,
+    pub matrix: Vec<MatrixRoom>,
+}
+
+/// A regex rule that rewrites or rejects matching URLs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawUrlRule")]
+pub struct UrlRule {
+    pattern: Regex,
+    action: UrlAction,
+}
+
+#[derive(Debug, Clone)]
+enum UrlAction {
+    /// Replace every match; supports `$1`-style capture references.
+    Replace(String),
+    /// Refuse the URL with this message.
+    Reject(String),
+}
+
+#[derive(Deserialize)]
+struct RawUrlRule {
+    pattern: String,
+    replace: Option<String>,
+    reject: Option<String>,
+}
+
+impl TryFrom<RawUrlRule> for UrlRule {
+    type Error = String;
+
+    fn try_from(raw: RawUrlRule) -> Result<Self, Self::Error> {
+        let pattern = Regex::new(&raw.pattern).map_err(|e| e.to_string())?;
+        let action = match (raw.replace, raw.reject) {
+            (Some(replace), None) => UrlAction::Replace(replace),
+            (None, Some(reject)) => UrlAction::Reject(reject),
+            _ => {
+                return Err(format!(
+                    "URL rule {:?} needs exactly one of `replace` or `reject`",
+                    raw.pattern
+                ));
+            }
+        };
+
+        Ok(Self { pattern, action })
+    }
+}
+
+/// Applies `rules` to `url`, returning the rewritten URL or the rejection
+/// message of the first rejecting rule that matches.
+pub fn rewrite_url(rules: &[UrlRule], url: &str) -> Result<String, String> {
+    let mut url = url.to_string();
+    for rule in rules {
+        if !rule.pattern.is_match(&url) {
+            continue;
+        }
+        match &rule.action {
+            UrlAction::Replace(replace) => {
+                url = rule
+                    .pattern
+                    .replace_all(&url, replace.as_str())
+                    .into_owned();
+            }
+            UrlAction::Reject(message) => return Err(message.clone()),
+        }
+    }
+    Ok(url)
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum HookError {
+    #[error("hook command is empty")]
+    Empty,
+    #[error("failed to run hook command")]
+    Command(#[source] io::Error),
+    #[error("hook exited with no status code")]
+    ExitNoCode,
+    #[error("hook exited with status code {0}")]
+    ExitErrorCode(i32),
+}
+
+/// Runs the post-download hook, capturing its output into the job log. The
+/// hook may modify the file in place, e.g. to transcode or tag it.
+#[instrument(skip(video, job))]
+pub async fn run_post_download(
+    command: &[String],
+    video: &DownloadedVideo,
+    job: &JobHandle,
+) -> Result<(), HookError> {
+    let (program, args) = command.split_first().ok_or(HookError::Empty)?;
+    let info = &video.info;
+
+    let cmd = Command::new(program)
+        .args(args)
+        .arg(&video.path)
+        .env("YTDLP_WEB_JOB_ID", job.id().to_string())
+        .env("YTDLP_WEB_FILE", &video.path)
+        .env("YTDLP_WEB_FILENAME", &video.filename)
+        .env(
+            "YTDLP_WEB_INFO_JSON",
+            video.path.with_extension("info.json"),
+        )
+        .env("YTDLP_WEB_ID", info.id.as_deref().unwrap_or_default())
+        .env("YTDLP_WEB_TITLE", info.title.as_deref().unwrap_or_default())
+        .env(
+            "YTDLP_WEB_UPLOADER",
+            info.uploader.as_deref().unwrap_or_default(),
+        )
+        .env(
+            "YTDLP_WEB_UPLOAD_DATE",
+            info.upload_date.as_deref().unwrap_or_default(),
+        )
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(HookError::Command)?;
+
+    debug!("Hook status: {}", cmd.status);
+    job.log_output(&String::from_utf8_lossy(&cmd.stdout));
+    job.log_output(&String::from_utf8_lossy(&cmd.stderr));
+
+    match cmd.status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(HookError::ExitErrorCode(code)),
+        None => Err(HookError::ExitNoCode),
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    str::FromStr,
+};
+
+use tracing::{debug, info, warn};
+
+use crate::transcode::VideoCodec;
+
+/// Codecs tried on the GPU. VP9 and AV1 encoders are rarer and their
+/// quality scales differ between APIs, so they stay in software.
+const CODECS: [VideoCodec; 2] = [VideoCodec::H264, VideoCodec::Hevc];
+
+/// GPU encoding API re-encodes use, from `HWACCEL`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HwAccel {
+    /// VA-API, for Intel and AMD GPUs on Linux.
+    Vaapi,
+    /// NVIDIA's NVENC.
+    Nvenc,
+    /// Intel Quick Sync Video.
+    Qsv,
+}
+
+impl FromStr for HwAccel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vaapi" => Ok(Self::Vaapi),
+            "nvenc" => Ok(Self::Nvenc),
+            "qsv" => Ok(Self::Qsv),
+            _ => Err(()),
+        }
+    }
+}
+
+impl HwAccel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Vaapi => "VA-API",
+            Self::Nvenc => "NVENC",
+            Self::Qsv => "Quick Sync",
+        }
+    }
+
+    fn encoder(self, codec: VideoCodec) -> Option<&'static str> {
+        match (self, codec) {
+            (Self::Vaapi, VideoCodec::H264) => Some("h264_vaapi"),
+            (Self::Vaapi, VideoCodec::Hevc) => Some("hevc_vaapi"),
+            (Self::Nvenc, VideoCodec::H264) => Some("h264_nvenc"),
+            (Self::Nvenc, VideoCodec::Hevc) => Some("hevc_nvenc"),
+            (Self::Qsv, VideoCodec::H264) => Some("h264_qsv"),
+            (Self::Qsv, VideoCodec::Hevc) => Some("hevc_qsv"),
+            _ => None,
+        }
+    }
+}
+
+/// The GPU encoders that worked when the server started.
+#[derive(Debug, Clone)]
+pub struct HwEncoders {
+    accel: HwAccel,
+    device: PathBuf,
+    codecs: Vec<VideoCodec>,
+}
+
+impl HwEncoders {
+    /// Tries a one-frame encode with each of `accel`'s encoders, since
+    /// ffmpeg lists encoders it was built with whether or not there is a GPU
+    /// to run them. `None` if none work.
+    pub fn detect(accel: HwAccel, device: &Path) -> Option<Self> {
+        let probe = Self {
+            accel,
+            device: device.to_path_buf(),
+            codecs: Vec::new(),
+        };
+        let codecs: Vec<VideoCodec> = CODECS
+            .into_iter()
+            .filter(|codec| probe.works(*codec))
+            .collect();
+        if codecs.is_empty() {
+            warn!("No {} encoder works, re-encoding on the CPU", accel.name());
+            return None;
+        }
+        let names: Vec<&str> = codecs.iter().filter_map(|c| accel.encoder(*c)).collect();
+        info!("Re-encoding with {} ({})", accel.name(), names.join(", "));
+        Some(Self { codecs, ..probe })
+    }
+
+    fn works(&self, codec: VideoCodec) -> bool {
+        let Some(encoder) = self.accel.encoder(codec) else {
+            return false;
+        };
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-nostdin", "-v", "error"])
+            .args(self.input_args())
+            .args(["-f", "lavfi", "-i", "color=c=black:s=256x256:r=1:d=1"]);
+        if let Some(upload) = self.upload_filter() {
+            command.args(["-vf", upload]);
+        }
+        let output = command
+            .args(self.encoder_args(codec))
+            .args(["-frames:v", "1", "-f", "null", "-"])
+            .stdout(Stdio::null())
+            .output();
+        match output {
+            Ok(output) if output.status.success() => true,
+            Ok(output) => {
+                debug!(
+                    "{} probe failed: {}",
+                    encoder,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+                false
+            }
+            Err(e) => {
+                debug!("Failed to run ffmpeg to probe {}: {:?}", encoder, e);
+                false
+            }
+        }
+    }
+
+    pub fn supports(&self, codec: VideoCodec) -> bool {
+        self.codecs.contains(&codec)
+    }
+
+    /// ffmpeg options opening the GPU, placed before the input.
+    pub fn input_args(&self) -> Vec<String> {
+        match self.accel {
+            HwAccel::Vaapi => vec![
+                "-vaapi_device".to_string(),
+                self.device.to_string_lossy().into_owned(),
+            ],
+            HwAccel::Nvenc => Vec::new(),
+            HwAccel::Qsv => ["-init_hw_device", "qsv=hw", "-filter_hw_device", "hw"]
+                .map(String::from)
+                .to_vec(),
+        }
+    }
+
+    /// Filter moving decoded frames onto the GPU, for encoders that only
+    /// take frames there. Goes last in the filter chain.
+    pub fn upload_filter(&self) -> Option<&'static str> {
+        match self.accel {
+            HwAccel::Vaapi => Some("format=nv12,hwupload"),
+            HwAccel::Nvenc | HwAccel::Qsv => None,
+        }
+    }
+
+    /// ffmpeg options selecting the GPU encoder for `codec`, which must be
+    /// one it [`supports`](Self::supports).
+    pub fn encoder_args(&self, codec: VideoCodec) -> Vec<&'static str> {
+        let mut args = vec!["-c:v", self.accel.encoder(codec).unwrap_or("copy")];
+        match self.accel {
+            HwAccel::Vaapi => {}
+            HwAccel::Nvenc => args.extend(["-pix_fmt", "yuv420p"]),
+            HwAccel::Qsv => args.extend(["-pix_fmt", "nv12"]),
+        }
+        if codec == VideoCodec::Hevc {
+            // As in software, for Apple players.
+            args.extend(["-tag:v", "hvc1"]);
+        }
+        args
+    }
+
+    /// Constant quality options close to the software encoders' CRF.
+    pub fn quality_args(&self, codec: VideoCodec) -> &'static [&'static str] {
+        let hevc = codec == VideoCodec::Hevc;
+        match self.accel {
+            HwAccel::Vaapi if hevc => &["-rc_mode", "CQP", "-qp", "25"],
+            HwAccel::Vaapi => &["-rc_mode", "CQP", "-qp", "22"],
+            HwAccel::Nvenc if hevc => &["-rc", "vbr", "-cq", "26", "-b:v", "0"],
+            HwAccel::Nvenc => &["-rc", "vbr", "-cq", "23", "-b:v", "0"],
+            HwAccel::Qsv if hevc => &["-global_quality", "25"],
+            HwAccel::Qsv => &["-global_quality", "22"],
+        }
+    }
+}
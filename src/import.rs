@@ -0,0 +1,192 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Serialize;
+use url::Url;
+use uuid::Uuid;
+
+/// Most entries a single import may contain.
+pub const MAX_ENTRIES: usize = 1000;
+
+static OUTLINE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)<outline\b([^>]*)>").unwrap());
+static ATTRIBUTE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)([A-Za-z_:][\w:.-]*)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap()
+});
+
+/// Layout of an uploaded URL list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One URL per line; blank lines and `#` comments are skipped.
+    Text,
+    /// URLs in the `url` column, or the first column without a header row.
+    Csv,
+    /// A feed list, e.g. exported from a podcast or RSS app.
+    Opml,
+}
+
+impl Format {
+    /// Picks the format from the upload's content type, falling back to
+    /// sniffing for OPML since feed readers export it under many types.
+    pub fn detect(content_type: Option<&str>, body: &str) -> Self {
+        let essence = content_type
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase());
+        match essence.as_deref() {
+            Some("text/csv") => Format::Csv,
+            Some("text/x-opml" | "text/x-opml+xml" | "application/xml" | "text/xml") => {
+                Format::Opml
+            }
+            _ if body.trim_start().starts_with("<?xml") || body.contains("<opml") => Format::Opml,
+            _ => Format::Text,
+        }
+    }
+}
+
+/// A URL found in the upload, with the 1-based line it was on.
+#[derive(Debug)]
+pub struct Entry {
+    pub line: usize,
+    pub url: String,
+}
+
+/// Outcome of an import, entry by entry.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub accepted: Vec<Accepted>,
+    pub rejected: Vec<Rejected>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Accepted {
+    pub line: usize,
+    pub url: String,
+    pub job_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Rejected {
+    pub line: usize,
+    pub url: String,
+    pub reason: String,
+}
+
+/// The URLs listed in `body`.
+pub fn parse(body: &str, format: Format) -> Vec<Entry> {
+    match format {
+        Format::Text => parse_text(body),
+        Format::Csv => parse_csv(body),
+        Format::Opml => parse_opml(body),
+    }
+}
+
+/// Why `url` can't be downloaded, if it can't.
+pub fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => Ok(()),
+        Ok(_) => Err("not an http(s) URL".to_string()),
+        Err(e) => Err(format!("invalid URL: {}", e)),
+    }
+}
+
+fn parse_text(body: &str) -> Vec<Entry> {
+    body.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, url)| Entry {
+            line,
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+fn parse_csv(body: &str) -> Vec<Entry> {
+    let mut rows = body
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((first_line, first)) = rows.next() else {
+        return Vec::new();
+    };
+
+    let header = csv_fields(first);
+    let (column, header_row) = match header
+        .iter()
+        .position(|field| field.trim().eq_ignore_ascii_case("url"))
+    {
+        Some(column) => (column, true),
+        None => (0, false),
+    };
+
+    let mut entries = Vec::new();
+    let first = (!header_row).then_some((first_line, first));
+    for (i, row) in first.into_iter().chain(rows) {
+        if let Some(url) = csv_fields(row).get(column).map(|url| url.trim())
+            && !url.is_empty()
+        {
+            entries.push(Entry {
+                line: i + 1,
+                url: url.to_string(),
+            });
+        }
+    }
+    entries
+}
+
+/// Splits a CSV row, honoring double-quoted fields with `""` escapes. Quoted
+/// line breaks aren't supported, which URL lists don't need.
+fn csv_fields(row: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = row.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Each outline's site URL, or its feed URL when it has none, since yt-dlp
+/// handles channel pages better than their feeds.
+fn parse_opml(body: &str) -> Vec<Entry> {
+    OUTLINE
+        .captures_iter(body)
+        .filter_map(|outline| {
+            let attributes = outline.get(1)?;
+            let mut html_url = None;
+            let mut xml_url = None;
+            for attribute in ATTRIBUTE.captures_iter(attributes.as_str()) {
+                let value = attribute.get(2).or(attribute.get(3))?.as_str();
+                match &attribute[1] {
+                    "htmlUrl" => html_url = Some(value),
+                    "xmlUrl" => xml_url = Some(value),
+                    _ => {}
+                }
+            }
+            let url = html_url.or(xml_url).filter(|url| !url.trim().is_empty())?;
+            let line = body[..outline.get(0)?.start()].matches('\n').count() + 1;
+            Some(Entry {
+                line,
+                url: unescape_xml(url.trim()),
+            })
+        })
+        .collect()
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
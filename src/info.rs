@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+
+use crate::{
+    cache,
+    config::Config,
+    download::{self, DownloadError},
+};
+
+const KEY_TEMPLATE: &str = "%(extractor_key)s-%(id)s";
+
+/// What a video offers, looked up without downloading it.
+#[derive(Debug, Serialize)]
+pub struct MediaInfo {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    /// URL of the thumbnail image.
+    pub thumbnail: Option<String>,
+    /// Length in seconds.
+    pub duration: Option<f64>,
+    /// Languages with uploaded subtitles.
+    pub subtitles: Vec<String>,
+    /// Languages with automatic captions.
+    pub automatic_captions: Vec<String>,
+}
+
+/// The subset of `-J` output read here.
+#[derive(Debug, Deserialize)]
+struct RawInfo {
+    id: Option<String>,
+    title: Option<String>,
+    uploader: Option<String>,
+    thumbnail: Option<String>,
+    duration: Option<f64>,
+    subtitles: Option<HashMap<String, serde_json::Value>>,
+    automatic_captions: Option<HashMap<String, serde_json::Value>>,
+}
+
+/// Looks up `url` with yt-dlp's `-J`, which resolves the video but doesn't
+/// download it.
+#[instrument(skip(config))]
+pub async fn probe(url: &str, config: &Config) -> Result<MediaInfo, DownloadError> {
+    let cmd = cache::ytdlp(config, url)
+        .arg("-J")
+        .arg("--no-playlist")
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(DownloadError::VideoCommand)?;
+
+    debug!("Command status: {}", cmd.status);
+    match cmd.status.code() {
+        Some(0) => {}
+        Some(code) => {
+            return Err(download::classify_failure(
+                &String::from_utf8_lossy(&cmd.stderr),
+                code,
+            ));
+        }
+        None => return Err(DownloadError::VideoExitNoCode),
+    }
+
+    let info: RawInfo = serde_json::from_slice(&cmd.stdout).map_err(DownloadError::InfoJson)?;
+    Ok(MediaInfo {
+        id: info.id,
+        title: info.title,
+        uploader: info.uploader,
+        thumbnail: info.thumbnail,
+        duration: info.duration,
+        subtitles: languages(info.subtitles),
+        automatic_captions: languages(info.automatic_captions),
+    })
+}
+
+/// `EXTRACTOR-ID` of the video or playlist at `url`, which stays the same
+/// across the different URLs a video can be reached by.
+#[instrument(skip(config))]
+pub async fn video_key(url: &str, config: &Config) -> Result<String, DownloadError> {
+    let cmd = cache::ytdlp(config, url)
+        .arg("--flat-playlist")
+        .arg("--print")
+        .arg(KEY_TEMPLATE)
+        .arg("--print")
+        .arg(format!("playlist:{}", KEY_TEMPLATE))
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(DownloadError::VideoCommand)?;
+
+    debug!("Command status: {}", cmd.status);
+    match cmd.status.code() {
+        Some(0) => {}
+        Some(code) => {
+            return Err(download::classify_failure(
+                &String::from_utf8_lossy(&cmd.stderr),
+                code,
+            ));
+        }
+        None => return Err(DownloadError::VideoExitNoCode),
+    }
+
+    // A playlist's own line comes after its entries'.
+    let stdout = String::from_utf8(cmd.stdout).map_err(DownloadError::FromUtf8)?;
+    stdout
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .map(String::from)
+        .ok_or(DownloadError::MissingOutput)
+}
+
+fn languages(tracks: Option<HashMap<String, serde_json::Value>>) -> Vec<String> {
+    let mut langs: Vec<String> = tracks.unwrap_or_default().into_keys().collect();
+    langs.sort();
+    langs
+}
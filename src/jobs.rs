@@ -0,0 +1,1153 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    db::{Db, DbError},
+    download::DownloadError,
+    playlist::{ChannelTab, MatchFilter},
+    progress::{self, DownloadProgress, PostProcessingProgress},
+    subtitles::SubFormat,
+    transcode::{FrameRate, Hdr, VideoCodec, Widescreen},
+    video::{MetadataOverride, VideoInfo},
+};
+
+mod log;
+
+pub use log::{JobLogs, LogEvent};
+
+/// How long finished jobs stay queryable before they are pruned.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+
+/// Most jobs returned from the history by a single query.
+const MAX_HISTORY_JOBS: usize = 500;
+
+/// Longest accepted tag, in characters.
+const MAX_TAG_LEN: usize = 64;
+
+/// Most recent log lines included in the job itself; the full log is kept by
+/// [`JobLogs`].
+const MAX_LOG_LINES: usize = 500;
+
+/// How stale a job's `accessed_at` may get before serving its file again
+/// updates it.
+const ACCESS_RESOLUTION: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    /// Held out of the queue until resumed.
+    Paused,
+    Completed,
+    Failed,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Paused => "paused",
+            JobStatus::Completed => "completed",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queued" => Some(JobStatus::Queued),
+            "running" => Some(JobStatus::Running),
+            "paused" => Some(JobStatus::Paused),
+            "completed" => Some(JobStatus::Completed),
+            "failed" => Some(JobStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// Step of the pipeline a job is at, finer-grained than its [`JobStatus`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStage {
+    #[default]
+    Queued,
+    /// Picked up, working out what to download before yt-dlp starts on it.
+    Resolving,
+    Downloading,
+    /// Transcoding, splitting, plugins and hooks after yt-dlp is done.
+    PostProcessing,
+    /// Pushing the file to a remote destination.
+    Uploading,
+    /// Moving the file to where it's kept on this server.
+    Storing,
+    Paused,
+    Completed,
+    Failed,
+    /// Failed because the client went away.
+    Cancelled,
+}
+
+impl From<JobStatus> for JobStage {
+    /// The stage of a job recorded before stages were, as far as its status
+    /// tells.
+    fn from(status: JobStatus) -> Self {
+        match status {
+            JobStatus::Queued => JobStage::Queued,
+            JobStatus::Running => JobStage::Resolving,
+            JobStatus::Paused => JobStage::Paused,
+            JobStatus::Completed => JobStage::Completed,
+            JobStatus::Failed => JobStage::Failed,
+        }
+    }
+}
+
+/// What kind of error a job failed with, for automations deciding what to
+/// do about it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    Private,
+    /// Removed, missing, or a playlist with nothing in it.
+    Unavailable,
+    AgeRestricted,
+    GeoBlocked,
+    UnsupportedUrl,
+    /// Rate limits, timeouts and other errors likely to pass.
+    Transient,
+    /// yt-dlp failed for a reason it didn't spell out.
+    Ytdlp,
+    /// Turned down before downloading: bad options, URL rules or quotas.
+    Rejected,
+    /// Transcoding, splitting, plugins or hooks failed.
+    PostProcessing,
+    /// The file couldn't be uploaded or kept.
+    Storage,
+    /// The content scanner flagged the file, which was quarantined.
+    Quarantined,
+    /// The client of a streamed download went away.
+    Cancelled,
+    /// A problem on this server, like a full disk.
+    Internal,
+}
+
+/// Why a job failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub kind: FailureKind,
+    /// Whether another instance, or trying again later, might succeed.
+    pub retryable: bool,
+    /// Status yt-dlp exited with, if it was yt-dlp that failed.
+    pub exit_code: Option<i32>,
+}
+
+impl Failure {
+    /// A failure that didn't come from yt-dlp.
+    pub fn new(kind: FailureKind, retryable: bool) -> Self {
+        Self {
+            kind,
+            retryable,
+            exit_code: None,
+        }
+    }
+}
+
+/// When a job entered a stage.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct StageChange {
+    pub stage: JobStage,
+    /// Unix timestamp in milliseconds.
+    pub at_ms: u64,
+}
+
+/// How a job's result reaches the client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobMode {
+    /// Streamed back in the response to `/api/download`. Can't outlive the
+    /// request, so it is not resumed after a restart.
+    Stream,
+    /// Submitted to the background queue and delivered to a destination.
+    Queued,
+}
+
+impl JobMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobMode::Stream => "stream",
+            JobMode::Queued => "queued",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "stream" => Some(JobMode::Stream),
+            "queued" => Some(JobMode::Queued),
+            _ => None,
+        }
+    }
+}
+
+/// Per-request settings for how a job is downloaded and processed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JobOptions {
+    /// Transcode profile applied after downloading.
+    pub profile: Option<String>,
+    /// Normalize loudness with ffmpeg after transcoding.
+    pub normalize: bool,
+    /// Re-encode so the file fits this size, e.g. `200MB` or `25MiB`.
+    pub target_size: Option<String>,
+    /// Re-encode the video at this bitrate, e.g. `2M` or `800k`.
+    pub video_bitrate: Option<String>,
+    /// Encode `target_size` or `video_bitrate` in two passes, for better
+    /// quality at the same size.
+    pub two_pass: bool,
+    /// Video codec to prefer when picking formats and to re-encode to.
+    pub vcodec: Option<VideoCodec>,
+    /// Frame rate to prefer when picking formats and to cap re-encodes at.
+    pub fps: Option<FrameRate>,
+    /// Keep HDR video as HDR or tone map it to SDR.
+    pub hdr: Option<Hdr>,
+    /// Pad or crop vertical videos to 16:9.
+    pub widescreen: Option<Widescreen>,
+    /// Also fetch the video's comments, see `/api/jobs/{id}/comments`.
+    pub comments: bool,
+    /// Add the video's chapters to the file, for players' chapter lists.
+    pub embed_chapters: bool,
+    /// Also keep the chat replay, for sites whose plugin fetches one. Like
+    /// other sidecars, it comes in a ZIP with the video.
+    pub chat: bool,
+    /// yt-dlp extractor arguments by `extractor:key`, e.g.
+    /// `youtube:player_client` to `android,web`. Only
+    /// [`EXTRACTOR_ARGS`](crate::download::EXTRACTOR_ARGS) are accepted.
+    pub extractor_args: BTreeMap<String, String>,
+    /// Include the video description in the download.
+    pub description: bool,
+    /// Include yt-dlp's info JSON in the download.
+    pub info_json: bool,
+    /// Playlist entries to download, in yt-dlp's `--playlist-items` syntax,
+    /// e.g. `1-10,15,20-`.
+    pub items: Option<String>,
+    /// Download playlist entries in reverse order.
+    pub reverse: bool,
+    /// Channel tab to download from, for YouTube channel URLs.
+    pub tab: Option<ChannelTab>,
+    /// Download at most this many entries, the most recent first on channels.
+    pub limit: Option<u32>,
+    /// Only download entries uploaded on or after this date, `YYYY-MM-DD`.
+    pub after: Option<String>,
+    /// Only download entries uploaded on or before this date, `YYYY-MM-DD`.
+    pub before: Option<String>,
+    /// Conditions entries must meet to be downloaded.
+    pub filter: MatchFilter,
+    /// Split a single video into one tagged audio track per chapter.
+    pub album: bool,
+    /// Join a playlist's audio into one `.m4b` with a chapter per entry.
+    pub audiobook: bool,
+    /// Subtitle languages to include, e.g. `["en", "de"]`.
+    pub subs: Vec<String>,
+    /// Format to convert subtitles to.
+    pub sub_format: Option<SubFormat>,
+    /// Subtitle language to render into the picture.
+    pub burn_subs: Option<String>,
+    /// Audio track languages to keep, or `["all"]`. Only the default track
+    /// is kept when empty.
+    pub audio: Vec<String>,
+    /// Tags to set on the download.
+    pub metadata: MetadataOverride,
+    /// Name to deliver and store the download under, with `{title}`, `{id}`,
+    /// `{uploader}` and `{upload_date}` placeholders.
+    pub filename: Option<String>,
+    /// Keep file names to ASCII letters, digits, `-`, `_` and `.`; the
+    /// server's `RESTRICT_FILENAMES` default applies when unset.
+    pub restrict_filenames: Option<bool>,
+    /// Fragments fetched in parallel, overriding `CONCURRENT_FRAGMENTS`.
+    pub fragments: Option<u32>,
+    /// Run yt-dlp with `-v` and keep its whole output, see
+    /// `/api/jobs/{id}/debug`. Limited to admin roles.
+    pub debug: bool,
+}
+
+impl JobOptions {
+    /// Whether the job downloads several playlist entries, delivered as a
+    /// ZIP or an audiobook.
+    pub fn is_playlist(&self) -> bool {
+        self.items.is_some()
+            || self.reverse
+            || self.tab.is_some()
+            || self.limit.is_some()
+            || self.after.is_some()
+            || self.before.is_some()
+            || !self.filter.is_empty()
+            || self.audiobook
+    }
+
+    /// Whether the client gets a ZIP bundle with sidecars instead of the
+    /// bare video. Playlists and albums are ZIPs already.
+    pub fn bundle(&self) -> bool {
+        (self.description || self.info_json || self.chat || !self.subs.is_empty())
+            && !self.is_playlist()
+            && !self.album
+    }
+
+    /// ffmpeg passes run over each video after it's downloaded.
+    pub fn ffmpeg_passes(&self) -> u32 {
+        let targeted = self.target_size.is_some() || self.video_bitrate.is_some();
+        self.burn_subs.is_some() as u32
+            + self.profile.is_some() as u32
+            + 2 * self.normalize as u32
+            + targeted as u32 * (1 + self.two_pass as u32)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: Uuid,
+    pub url: String,
+    /// Destination requested by the client, if any.
+    pub dest: Option<String>,
+    /// User who submitted the job, as reported by the authenticating proxy.
+    pub user: Option<String>,
+    /// Labels given by the client to organize downloads.
+    pub tags: Vec<String>,
+    pub options: JobOptions,
+    pub mode: JobMode,
+    pub status: JobStatus,
+    /// Current step, the last of `stages`.
+    #[serde(default)]
+    pub stage: JobStage,
+    /// Every stage the job went through, oldest first.
+    #[serde(default)]
+    pub stages: Vec<StageChange>,
+    /// `EXTRACTOR-ID` of what the job downloads, to spot duplicate
+    /// submissions of the same video under a different URL.
+    #[serde(default)]
+    pub video_key: Option<String>,
+    /// `Idempotency-Key` the job was created with, answered with this job
+    /// for a while.
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
+    pub error: Option<String>,
+    /// Classification of `error`.
+    #[serde(default)]
+    pub failure: Option<Failure>,
+    /// Status yt-dlp last exited with, if it failed.
+    #[serde(skip)]
+    pub exit_code: Option<i32>,
+    /// Video metadata reported by yt-dlp, once downloaded.
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    /// Name of the downloaded file once known.
+    pub filename: Option<String>,
+    /// Where a finished queued job's file can be fetched from.
+    pub location: Option<String>,
+    /// Local copy of a finished queued job kept on this server.
+    #[serde(skip)]
+    pub output: Option<PathBuf>,
+    /// Unix timestamps in seconds.
+    pub created_at: u64,
+    pub finished_at: Option<u64>,
+    /// Size of the downloaded file.
+    pub bytes: Option<u64>,
+    /// Lowercase hex SHA-256 of the downloaded file.
+    pub sha256: Option<String>,
+    /// Bytes of the file sent to clients so far.
+    pub served_bytes: u64,
+    /// Exempts a kept file from retention cleanup.
+    pub pinned: bool,
+    /// When the kept file was last served, to within [`ACCESS_RESOLUTION`].
+    pub accessed_at: Option<u64>,
+    /// When the kept file was moved to the trash, from where it can be
+    /// restored until it's purged.
+    pub trashed_at: Option<u64>,
+    /// Progress of yt-dlp's download, while it runs and after.
+    pub progress: Option<DownloadProgress>,
+    /// Progress of the ffmpeg passes after the download, if any.
+    pub post_processing: Option<PostProcessingProgress>,
+    /// Progress through the download and the passes after it, for single
+    /// videos.
+    pub overall_percent: Option<f64>,
+    /// Progress of pushing the file to a remote destination, if any.
+    pub upload: Option<TransferProgress>,
+    /// Latest output of the external tools run for this job.
+    pub log: Vec<String>,
+    /// Sanitized tail of yt-dlp's stderr from the failed attempt, to help
+    /// users diagnose the error themselves.
+    pub stderr_tail: Vec<String>,
+}
+
+impl Job {
+    fn update_overall_percent(&mut self) {
+        self.overall_percent = if self.options.is_playlist() {
+            None
+        } else {
+            progress::overall_percent(
+                self.progress.as_ref(),
+                self.post_processing.as_ref(),
+                self.options.ffmpeg_passes(),
+            )
+        };
+    }
+
+    /// Moves the job to `stage`, unless it's already there.
+    fn enter(&mut self, stage: JobStage) {
+        if self.stage == stage && !self.stages.is_empty() {
+            return;
+        }
+        self.stage = stage;
+        self.stages.push(StageChange {
+            stage,
+            at_ms: unix_now_ms(),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TransferProgress {
+    pub bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Registry of download jobs.
+///
+/// Recent jobs are kept in memory, and every status change is written through
+/// to the database so unfinished jobs can be picked up again after a restart.
+#[derive(Debug)]
+pub struct Jobs {
+    jobs: Mutex<HashMap<Uuid, Job>>,
+    db: Arc<Db>,
+    logs: JobLogs,
+    finished: broadcast::Sender<Job>,
+    /// User and `Idempotency-Key` of the submissions being handled.
+    idempotency_keys: Mutex<HashSet<(Option<String>, String)>>,
+}
+
+impl Jobs {
+    pub fn new(db: Arc<Db>, logs: JobLogs) -> Self {
+        Self {
+            jobs: Mutex::new(HashMap::new()),
+            db,
+            logs,
+            finished: broadcast::channel(64).0,
+            idempotency_keys: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Receives every job as it completes or fails, for notifications.
+    pub fn subscribe_finished(&self) -> broadcast::Receiver<Job> {
+        self.finished.subscribe()
+    }
+
+    pub fn logs(&self) -> &JobLogs {
+        &self.logs
+    }
+
+    pub fn create(
+        self: &Arc<Self>,
+        url: &str,
+        dest: Option<&str>,
+        user: Option<&str>,
+        tags: &[String],
+        options: JobOptions,
+        mode: JobMode,
+    ) -> JobHandle {
+        let id = Uuid::new_v4();
+        let stage = match mode {
+            JobMode::Stream => JobStage::Resolving,
+            JobMode::Queued => JobStage::Queued,
+        };
+        let job = Job {
+            id,
+            url: url.to_string(),
+            dest: dest.map(String::from),
+            user: user.map(String::from),
+            tags: tags.to_vec(),
+            options,
+            mode,
+            status: match mode {
+                JobMode::Stream => JobStatus::Running,
+                JobMode::Queued => JobStatus::Queued,
+            },
+            stage,
+            stages: vec![StageChange {
+                stage,
+                at_ms: unix_now_ms(),
+            }],
+            video_key: None,
+            idempotency_key: None,
+            error: None,
+            failure: None,
+            exit_code: None,
+            title: None,
+            uploader: None,
+            filename: None,
+            location: None,
+            output: None,
+            created_at: unix_now(),
+            finished_at: None,
+            bytes: None,
+            sha256: None,
+            served_bytes: 0,
+            pinned: false,
+            accessed_at: None,
+            trashed_at: None,
+            progress: None,
+            post_processing: None,
+            overall_percent: None,
+            upload: None,
+            log: Vec::new(),
+            stderr_tail: Vec::new(),
+        };
+        self.persist(&job);
+        self.index(&job, None);
+
+        let mut jobs = self.jobs.lock().unwrap();
+        prune(&mut jobs);
+        jobs.insert(id, job);
+
+        JobHandle {
+            id,
+            jobs: self.clone(),
+        }
+    }
+
+    /// Loads the jobs left unfinished by the previous run. Queued jobs are
+    /// returned for re-enqueueing and paused ones stay paused; streamed ones
+    /// lost their client and are marked failed.
+    pub fn restore(self: &Arc<Self>) -> Vec<JobHandle> {
+        let unfinished = match self.db.unfinished_jobs() {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                warn!("Failed to load unfinished jobs: {:?}", e);
+                return Vec::new();
+            }
+        };
+
+        let mut handles = Vec::new();
+        for mut job in unfinished {
+            let handle = JobHandle {
+                id: job.id,
+                jobs: self.clone(),
+            };
+            match job.mode {
+                JobMode::Stream => {
+                    job.status = JobStatus::Failed;
+                    job.enter(JobStage::Failed);
+                    job.error = Some("interrupted by server restart".to_string());
+                    job.finished_at = Some(unix_now());
+                    self.persist(&job);
+                    self.jobs.lock().unwrap().insert(job.id, job);
+                }
+                JobMode::Queued if job.status == JobStatus::Paused => {
+                    self.jobs.lock().unwrap().insert(job.id, job);
+                }
+                JobMode::Queued => {
+                    info!("Re-enqueueing job {} for {}", job.id, job.url);
+                    job.status = JobStatus::Queued;
+                    job.enter(JobStage::Queued);
+                    self.jobs.lock().unwrap().insert(job.id, job);
+                    handles.push(handle);
+                }
+            }
+        }
+
+        handles
+    }
+
+    /// Takes over a job claimed from a coordinator, keeping its id so the
+    /// two records can be matched up.
+    pub fn adopt(self: &Arc<Self>, job: Job) -> JobHandle {
+        self.persist(&job);
+        let id = job.id;
+        self.jobs.lock().unwrap().insert(id, job);
+        JobHandle {
+            id,
+            jobs: self.clone(),
+        }
+    }
+
+    /// Handle for reporting on an existing job.
+    pub fn handle(self: &Arc<Self>, id: Uuid) -> JobHandle {
+        JobHandle {
+            id,
+            jobs: self.clone(),
+        }
+    }
+
+    /// All known jobs, newest first.
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs: Vec<Job> = self.jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by_key(|job| Reverse(job.created_at));
+        jobs
+    }
+
+    /// Jobs from the whole history matching `query` in their URL, title,
+    /// description, uploader or tags, best matches first. Each word must
+    /// appear somewhere, but may be part of a longer word.
+    pub fn search(&self, query: &str) -> Vec<Job> {
+        let query = fts_query(query);
+        if query.is_empty() {
+            return Vec::new();
+        }
+        match self.db.search_jobs(&query, MAX_HISTORY_JOBS) {
+            Ok(found) => self.refresh(found),
+            Err(e) => {
+                warn!("Failed to search jobs for {:?}: {:?}", query, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Jobs labelled `tag` from the whole history, newest first.
+    pub fn list_tagged(&self, tag: &str) -> Vec<Job> {
+        match self.db.jobs_with_tag(tag, MAX_HISTORY_JOBS) {
+            Ok(tagged) => self.refresh(tagged),
+            Err(e) => {
+                warn!("Failed to load jobs tagged {}: {:?}", tag, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Swaps jobs loaded from the database for their in-memory versions,
+    /// which are more up to date while running.
+    fn refresh(&self, mut loaded: Vec<Job>) -> Vec<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        for job in &mut loaded {
+            if let Some(current) = jobs.get(&job.id) {
+                *job = current.clone();
+            }
+        }
+        loaded
+    }
+
+    /// Looks a job up in memory, falling back to the database for jobs that
+    /// have been pruned or predate the last restart.
+    pub fn get(&self, id: Uuid) -> Option<Job> {
+        if let Some(job) = self.jobs.lock().unwrap().get(&id) {
+            return Some(job.clone());
+        }
+        match self.db.get_job(id) {
+            Ok(job) => job,
+            Err(e) => {
+                warn!("Failed to load job {}: {:?}", id, e);
+                None
+            }
+        }
+    }
+
+    /// Finished jobs whose file is kept on this server, least recently
+    /// served first.
+    pub fn kept(&self) -> Vec<Job> {
+        match self.db.kept_jobs() {
+            Ok(kept) => self.refresh(kept),
+            Err(e) => {
+                warn!("Failed to load kept jobs: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// The newest job downloading the video `key` to `dest` with the same
+    /// options that is still waiting or running, paused, or completed within
+    /// the last hour with its file still around.
+    pub fn find_duplicate(
+        &self,
+        key: &str,
+        dest: Option<&str>,
+        options: &JobOptions,
+    ) -> Option<Job> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.values()
+            .filter(|job| {
+                job.video_key.as_deref() == Some(key)
+                    && job.dest.as_deref() == dest
+                    && job.options == *options
+                    && match job.status {
+                        JobStatus::Queued | JobStatus::Running | JobStatus::Paused => true,
+                        JobStatus::Completed => job.location.is_some(),
+                        JobStatus::Failed => false,
+                    }
+            })
+            .max_by_key(|job| job.created_at)
+            .cloned()
+    }
+
+    /// Marks `user`'s submission with `key` as being handled until the guard
+    /// is dropped. Returns `None` if another request with it is still being
+    /// handled.
+    pub fn begin_idempotent(&self, user: Option<&str>, key: &str) -> Option<IdempotencyGuard<'_>> {
+        let entry = (user.map(String::from), key.to_string());
+        if !self.idempotency_keys.lock().unwrap().insert(entry.clone()) {
+            return None;
+        }
+        Some(IdempotencyGuard { jobs: self, entry })
+    }
+
+    /// The job `user` created with `key` within the last `window`.
+    pub fn find_idempotent(&self, user: Option<&str>, key: &str, window: Duration) -> Option<Job> {
+        let since = unix_now().saturating_sub(window.as_secs());
+        match self.db.job_by_idempotency_key(user, key, since) {
+            Ok(job) => job.map(|job| self.refresh(vec![job]).remove(0)),
+            Err(e) => {
+                warn!("Failed to look up idempotency key {:?}: {:?}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Every job in the history, oldest first.
+    pub fn all(&self) -> Result<Vec<Job>, DbError> {
+        self.db.all_jobs().map(|all| self.refresh(all))
+    }
+
+    /// Adds a finished job from another instance's history. Returns `false`
+    /// if a job with its id already exists.
+    pub fn import(&self, job: &Job) -> bool {
+        if self.get(job.id).is_some() {
+            return false;
+        }
+        self.persist(job);
+        self.index(job, None);
+        true
+    }
+
+    /// Pins or unpins a job's kept file. Returns the updated job, or `None`
+    /// if there's no such job.
+    pub fn set_pinned(&self, id: Uuid, pinned: bool) -> Option<Job> {
+        self.update_stored(id, |job| job.pinned = pinned)
+    }
+
+    /// Records that the job's kept file was served, at most once per
+    /// [`ACCESS_RESOLUTION`] so seeking through a video doesn't write on
+    /// every range request.
+    pub fn touch(&self, id: Uuid) {
+        let now = unix_now();
+        let fresh = |job: &Job| {
+            job.accessed_at
+                .is_some_and(|at| now.saturating_sub(at) < ACCESS_RESOLUTION.as_secs())
+        };
+        if self.get(id).is_some_and(|job| fresh(&job)) {
+            return;
+        }
+        self.update_stored(id, |job| job.accessed_at = Some(now));
+    }
+
+    /// Forgets a job's kept file once it has been deleted.
+    pub fn clear_output(&self, id: Uuid) {
+        self.update_stored(id, |job| {
+            job.output = None;
+            job.location = None;
+            job.trashed_at = None;
+        });
+    }
+
+    /// Records the checksum of a job's kept file, for jobs that finished
+    /// without one.
+    pub fn record_sha256(&self, id: Uuid, sha256: &str) -> Option<Job> {
+        self.update_stored(id, |job| job.sha256 = Some(sha256.to_string()))
+    }
+
+    /// Jobs whose kept file is in the trash, most recently deleted first.
+    pub fn trashed(&self) -> Vec<Job> {
+        match self.db.trashed_jobs() {
+            Ok(trashed) => self.refresh(trashed),
+            Err(e) => {
+                warn!("Failed to load trashed jobs: {:?}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Marks a job's kept file as moved to the trash, or back out of it.
+    /// Returns the updated job, or `None` if there's no such job.
+    pub fn set_trashed(&self, id: Uuid, trashed: bool) -> Option<Job> {
+        let trashed_at = trashed.then(unix_now);
+        self.update_stored(id, |job| job.trashed_at = trashed_at)
+    }
+
+    /// Like [`Jobs::update_persisted`], but also for jobs that are only in
+    /// the database.
+    fn update_stored(&self, id: Uuid, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match jobs.get_mut(&id) {
+            Some(job) => {
+                f(job);
+                job.clone()
+            }
+            None => {
+                let mut job = self.db.get_job(id).ok().flatten()?;
+                f(&mut job);
+                job
+            }
+        };
+        drop(jobs);
+        self.persist(&job);
+        Some(job)
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = jobs.get_mut(&id)?;
+        f(job);
+        Some(job.clone())
+    }
+
+    /// Applies `f` and writes the result through to the database.
+    fn update_persisted(&self, id: Uuid, f: impl FnOnce(&mut Job)) -> Option<Job> {
+        let job = self.update(id, f)?;
+        self.persist(&job);
+        Some(job)
+    }
+
+    fn persist(&self, job: &Job) {
+        if let Err(e) = self.db.save_job(job) {
+            warn!("Failed to persist job {}: {:?}", job.id, e);
+        }
+    }
+
+    fn index(&self, job: &Job, description: Option<&str>) {
+        if let Err(e) = self.db.index_job(job, description) {
+            warn!("Failed to index job {}: {:?}", job.id, e);
+        }
+    }
+}
+
+/// Submission being handled with an `Idempotency-Key`, see
+/// [`Jobs::begin_idempotent`].
+#[derive(Debug)]
+pub struct IdempotencyGuard<'a> {
+    jobs: &'a Jobs,
+    entry: (Option<String>, String),
+}
+
+impl Drop for IdempotencyGuard<'_> {
+    fn drop(&mut self) {
+        self.jobs
+            .idempotency_keys
+            .lock()
+            .unwrap()
+            .remove(&self.entry);
+    }
+}
+
+/// Handle used by the download pipeline to report on its job.
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: Uuid,
+    jobs: Arc<Jobs>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn url(&self) -> String {
+        self.snapshot().map(|job| job.url).unwrap_or_default()
+    }
+
+    pub fn dest(&self) -> Option<String> {
+        self.snapshot().and_then(|job| job.dest)
+    }
+
+    pub fn options(&self) -> JobOptions {
+        self.snapshot().map(|job| job.options).unwrap_or_default()
+    }
+
+    pub fn video_key(&self) -> Option<String> {
+        self.snapshot().and_then(|job| job.video_key)
+    }
+
+    /// Records the `Idempotency-Key` the job was created with.
+    pub fn set_idempotency_key(&self, key: &str) {
+        self.jobs
+            .update_persisted(self.id, |job| job.idempotency_key = Some(key.to_string()));
+    }
+
+    /// Records the `EXTRACTOR-ID` of what the job downloads.
+    pub fn set_video_key(&self, key: &str) {
+        self.jobs
+            .update_persisted(self.id, |job| job.video_key = Some(key.to_string()));
+    }
+
+    fn snapshot(&self) -> Option<Job> {
+        self.jobs.jobs.lock().unwrap().get(&self.id).cloned()
+    }
+
+    /// Marks a queued job as picked up by a worker.
+    pub fn start(&self) {
+        self.jobs.update_persisted(self.id, |job| {
+            job.status = JobStatus::Running;
+            job.enter(JobStage::Resolving);
+        });
+    }
+
+    /// Puts a job back in line after its worker went away or it was resumed.
+    pub fn requeue(&self) {
+        self.jobs.update_persisted(self.id, |job| {
+            job.status = JobStatus::Queued;
+            job.enter(JobStage::Queued);
+        });
+    }
+
+    /// Holds the job out of the queue until it's resumed.
+    pub fn pause(&self) {
+        self.jobs.update_persisted(self.id, |job| {
+            job.status = JobStatus::Paused;
+            job.enter(JobStage::Paused);
+        });
+    }
+
+    /// Records that the running job moved on to `stage`. Cheap to repeat
+    /// while it stays there, and ignored once the job has finished.
+    pub fn set_stage(&self, stage: JobStage) {
+        let moved = self
+            .jobs
+            .jobs
+            .lock()
+            .unwrap()
+            .get(&self.id)
+            .is_some_and(|job| job.stage != stage && job.finished_at.is_none());
+        if moved {
+            self.jobs.update_persisted(self.id, |job| job.enter(stage));
+        }
+    }
+
+    /// Records where the finished file ended up.
+    pub fn set_result(&self, filename: &str, location: &str, output: Option<PathBuf>) {
+        self.jobs.update_persisted(self.id, |job| {
+            job.filename = Some(filename.to_string());
+            job.location = Some(location.to_string());
+            job.output = output;
+        });
+    }
+
+    /// Appends each line of `output` to the job log.
+    pub fn log_output(&self, output: &str) {
+        let lines: Vec<&str> = output.lines().collect();
+        if lines.is_empty() {
+            return;
+        }
+        self.jobs.logs.append(self.id, &lines);
+        self.jobs.update(self.id, |job| {
+            job.log.extend(lines.iter().map(|line| line.to_string()));
+            let excess = job.log.len().saturating_sub(MAX_LOG_LINES);
+            job.log.drain(..excess);
+        });
+    }
+
+    /// Adds yt-dlp output to the job's debug log, see [`JobLogs::append_debug`].
+    pub fn log_debug(&self, output: &str) {
+        let lines: Vec<&str> = output.lines().collect();
+        if !lines.is_empty() {
+            self.jobs.logs.append_debug(self.id, &lines);
+        }
+    }
+
+    pub fn set_download_progress(&self, progress: DownloadProgress) {
+        self.jobs.update(self.id, |job| {
+            job.progress = Some(progress);
+            job.update_overall_percent();
+        });
+    }
+
+    /// Starts counting ffmpeg passes from the first again, for the next
+    /// playlist entry.
+    pub fn reset_post_processing(&self) {
+        self.jobs.update(self.id, |job| job.post_processing = None);
+    }
+
+    /// Records that the next ffmpeg pass, doing `step`, has started.
+    pub fn start_pass(&self, step: &str) {
+        self.jobs.update(self.id, |job| {
+            let pass = job.post_processing.as_ref().map_or(1, |post| post.pass + 1);
+            job.post_processing = Some(PostProcessingProgress {
+                step: step.to_string(),
+                pass,
+                passes: job.options.ffmpeg_passes().max(pass),
+                percent: None,
+                speed: None,
+                eta: None,
+            });
+            job.update_overall_percent();
+        });
+    }
+
+    pub fn set_pass_progress(&self, percent: Option<f64>, speed: Option<f64>, eta: Option<u64>) {
+        self.jobs.update(self.id, |job| {
+            if let Some(post) = &mut job.post_processing {
+                post.percent = percent;
+                post.speed = speed;
+                post.eta = eta;
+            }
+            job.update_overall_percent();
+        });
+    }
+
+    pub fn set_upload_progress(&self, bytes: u64, total_bytes: u64) {
+        self.jobs.update(self.id, |job| {
+            job.upload = Some(TransferProgress { bytes, total_bytes });
+        });
+    }
+
+    /// Records the video's metadata and makes it searchable.
+    pub fn set_metadata(&self, info: &VideoInfo) {
+        let job = self.jobs.update_persisted(self.id, |job| {
+            job.title = info.title.clone();
+            job.uploader = info.uploader.clone();
+        });
+        if let Some(job) = job {
+            self.jobs.index(&job, info.description.as_deref());
+        }
+    }
+
+    pub fn set_bytes(&self, bytes: u64) {
+        self.jobs.update(self.id, |job| {
+            job.bytes = Some(bytes);
+        });
+    }
+
+    pub fn set_sha256(&self, sha256: String) {
+        self.jobs.update(self.id, |job| {
+            job.sha256 = Some(sha256);
+        });
+    }
+
+    /// Adds to the job's served total. The database is updated directly, as
+    /// kept files are often fetched after the job has been pruned from memory.
+    pub fn add_served_bytes(&self, bytes: u64) {
+        if bytes == 0 {
+            return;
+        }
+        self.jobs.update(self.id, |job| job.served_bytes += bytes);
+        if let Err(e) = self.jobs.db.add_served_bytes(self.id, bytes) {
+            warn!("Failed to record served bytes for job {}: {:?}", self.id, e);
+        }
+    }
+
+    /// Records how yt-dlp's latest run exited, `None` if it succeeded.
+    pub fn set_exit_code(&self, code: Option<i32>) {
+        self.jobs.update(self.id, |job| job.exit_code = code);
+    }
+
+    pub fn set_stderr_tail(&self, lines: Vec<String>) {
+        self.jobs.update(self.id, |job| {
+            job.stderr_tail = lines;
+        });
+    }
+
+    pub fn complete(&self) {
+        // A retried download may have failed before succeeding.
+        self.jobs.update(self.id, |job| job.stderr_tail.clear());
+        self.finish(JobStatus::Completed, JobStage::Completed, None, None);
+    }
+
+    pub fn fail(&self, error: &DownloadError) {
+        let exit_code = self.snapshot().and_then(|job| job.exit_code);
+        let failure = Failure {
+            kind: error.failure_kind(),
+            retryable: error.may_work_elsewhere(),
+            exit_code: exit_code.filter(|_| error.failure_kind() != FailureKind::Rejected),
+        };
+        self.fail_with(&error.to_string(), Some(failure));
+    }
+
+    /// Fails the job with an error reported from elsewhere, like a worker.
+    pub fn fail_with(&self, error: &str, failure: Option<Failure>) {
+        self.finish(
+            JobStatus::Failed,
+            JobStage::Failed,
+            Some(error.to_string()),
+            failure,
+        );
+    }
+
+    /// Fails the job because its client went away.
+    pub fn cancel(&self, reason: &impl Display) {
+        self.finish(
+            JobStatus::Failed,
+            JobStage::Cancelled,
+            Some(reason.to_string()),
+            Some(Failure::new(FailureKind::Cancelled, false)),
+        );
+    }
+
+    fn finish(
+        &self,
+        status: JobStatus,
+        stage: JobStage,
+        error: Option<String>,
+        failure: Option<Failure>,
+    ) {
+        debug!("Job {} finished: {:?}", self.id, status);
+        let job = self.jobs.update_persisted(self.id, |job| {
+            job.status = status;
+            job.enter(stage);
+            job.error = error;
+            job.failure = failure;
+            job.finished_at = Some(unix_now());
+        });
+        self.jobs.logs.finish(self.id, status);
+        if let Some(job) = job {
+            // Only fails when nothing is subscribed.
+            let _ = self.jobs.finished.send(job);
+        }
+    }
+}
+
+/// Cleans up tags from a request: trimmed, without empty entries or
+/// duplicates. Errors name the first tag that can't be stored.
+pub fn normalize_tags<'a>(tags: impl IntoIterator<Item = &'a str>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags.into_iter().map(str::trim).filter(|t| !t.is_empty()) {
+        if tag.chars().count() > MAX_TAG_LEN || tag.chars().any(char::is_control) {
+            return Err(tag.to_string());
+        }
+        if !normalized.iter().any(|t| t == tag) {
+            normalized.push(tag.to_string());
+        }
+    }
+
+    Ok(normalized)
+}
+
+/// Turns free text into an FTS5 query matching every word, quoting each one
+/// so punctuation can't be read as query syntax.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn prune(jobs: &mut HashMap<Uuid, Job>) {
+    let cutoff = unix_now().saturating_sub(FINISHED_JOB_RETENTION.as_secs());
+    jobs.retain(|_, job| job.finished_at.is_none_or(|t| t > cutoff));
+}
+
+fn unix_now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use tokio::sync::broadcast;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::JobStatus;
+
+/// Lines buffered for live subscribers that fall behind.
+const SUBSCRIBER_BUFFER: usize = 256;
+
+#[derive(Debug, Clone)]
+pub enum LogEvent {
+    Line(String),
+    Finished(JobStatus),
+}
+
+/// Full per-job output of the external tools, kept on disk.
+///
+/// Each job writes to `{id}.log`; once that exceeds the size cap it is moved
+/// to `{id}.log.1`, replacing the previous one, so a job never takes more than
+/// twice the cap. Running jobs also broadcast their lines for live tailing.
+#[derive(Debug)]
+pub struct JobLogs {
+    dir: PathBuf,
+    max_bytes: u64,
+    channels: Mutex<HashMap<Uuid, broadcast::Sender<LogEvent>>>,
+}
+
+impl JobLogs {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            warn!("Failed to create job log directory {:?}: {:?}", dir, e);
+        }
+
+        Self {
+            dir,
+            max_bytes,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn append(&self, id: Uuid, lines: &[&str]) {
+        if let Err(e) = self.write(id, lines) {
+            warn!("Failed to write log for job {}: {:?}", id, e);
+        }
+
+        if let Some(sender) = self.channels.lock().unwrap().get(&id) {
+            for line in lines {
+                // Only fails when nobody is listening.
+                let _ = sender.send(LogEvent::Line(line.to_string()));
+            }
+        }
+    }
+
+    fn write(&self, id: Uuid, lines: &[&str]) -> io::Result<()> {
+        let path = self.path(id);
+        if std::fs::metadata(&path).is_ok_and(|m| m.len() >= self.max_bytes) {
+            std::fs::rename(&path, self.rotated_path(id))?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        for line in lines {
+            writeln!(file, "{}", line)?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends to `{id}.debug.log`, the verbose output of a job in debug
+    /// mode. It is never rotated, since the first lines with yt-dlp's
+    /// versions and options matter most in a bug report.
+    pub fn append_debug(&self, id: Uuid, lines: &[&str]) {
+        let write = || -> io::Result<()> {
+            let path = self.debug_path(id);
+            let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+            for line in lines {
+                writeln!(file, "{}", line)?;
+            }
+            Ok(())
+        };
+        if let Err(e) = write() {
+            warn!("Failed to write debug log for job {}: {:?}", id, e);
+        }
+    }
+
+    /// The job's debug log, `None` if it didn't run in debug mode.
+    pub fn read_debug(&self, id: Uuid) -> io::Result<Option<String>> {
+        match std::fs::read_to_string(self.debug_path(id)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The job's whole retained log, oldest line first.
+    pub fn read(&self, id: Uuid) -> io::Result<String> {
+        let rotated = match std::fs::read_to_string(self.rotated_path(id)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let current = match std::fs::read_to_string(self.path(id)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(rotated + &current)
+    }
+
+    /// Receives lines logged from now on until the job finishes.
+    pub fn subscribe(&self, id: Uuid) -> broadcast::Receiver<LogEvent> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(id)
+            .or_insert_with(|| broadcast::channel(SUBSCRIBER_BUFFER).0)
+            .subscribe()
+    }
+
+    pub fn finish(&self, id: Uuid, status: JobStatus) {
+        if let Some(sender) = self.channels.lock().unwrap().remove(&id) {
+            let _ = sender.send(LogEvent::Finished(status));
+        }
+    }
+
+    fn path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.log", id))
+    }
+
+    fn rotated_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.log.1", id))
+    }
+
+    fn debug_path(&self, id: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.debug.log", id))
+    }
+}
@@ -0,0 +1,253 @@
+//! The download and job engine behind yt-dlp-web, usable without its HTTP
+//! server: build an [`AppState`] from a [`Config`], [`start`](AppState::start)
+//! its background tasks, then queue downloads through [`AppState::jobs`] and
+//! [`AppState::queue`] or serve [`api::app`].
+
+pub mod album;
+pub mod api;
+pub mod audiobook;
+pub mod bandwidth;
+pub mod bookmarks;
+pub mod bundle;
+pub mod cache;
+pub mod checksum;
+pub mod clip;
+pub mod cluster;
+pub mod config;
+pub mod db;
+pub mod download;
+pub mod email;
+pub mod export;
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hls;
+pub mod hooks;
+pub mod hwaccel;
+pub mod import;
+pub mod info;
+pub mod jobs;
+pub mod lock;
+pub mod playlist;
+pub mod plugins;
+pub mod progress;
+pub mod push;
+pub mod qr;
+pub mod queue;
+pub mod quick;
+pub mod quotas;
+pub mod retention;
+pub mod scan;
+pub mod server_log;
+pub mod share;
+pub mod simulate;
+pub mod sites;
+pub mod sniff;
+pub mod stats;
+pub mod storage;
+pub mod subtitles;
+pub mod tee;
+pub mod telegram;
+pub mod transcode;
+pub mod trash;
+pub mod upstream;
+pub mod usage;
+pub mod video;
+pub mod webhooks;
+
+use std::{collections::HashMap, io, path::PathBuf, sync::Arc};
+
+use tracing::info;
+
+use crate::{
+    bandwidth::Bandwidth,
+    cluster::{ClusterError, Coordinator, Leases, Role},
+    config::Config,
+    db::{Db, DbError},
+    email::{EmailError, Mailer},
+    graphql::ApiSchema,
+    hls::Hls,
+    hwaccel::HwEncoders,
+    jobs::{JobLogs, Jobs},
+    lock::Locks,
+    plugins::Plugins,
+    push::{Push, PushError},
+    queue::Queue,
+    share::{ShareError, Shares},
+    storage::{S3Storage, Storage, StorageError},
+    upstream::{Upstream, UpstreamError},
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum StartupError {
+    #[error("invalid S3 configuration")]
+    S3(#[source] StorageError),
+    #[error("invalid destination {0}")]
+    Destination(String, #[source] StorageError),
+    #[error("failed to open database in {0:?}")]
+    Db(PathBuf, #[source] DbError),
+    #[error("invalid Web Push configuration")]
+    Push(#[from] PushError),
+    #[error("invalid share link configuration")]
+    Shares(#[from] ShareError),
+    #[error("invalid upstream configuration")]
+    Upstream(#[from] UpstreamError),
+    #[error("lock directory {0:?} is not usable")]
+    Locks(PathBuf, #[source] io::Error),
+    #[error("invalid coordinator configuration")]
+    Coordinator(#[from] ClusterError),
+    #[error("invalid email configuration")]
+    Email(#[from] EmailError),
+}
+
+/// Everything the server runs on, shared by the API handlers and the
+/// background tasks.
+#[derive(Clone)]
+pub struct AppState {
+    pub config: Arc<Config>,
+    pub storage: Option<Arc<dyn Storage>>,
+    pub destinations: Arc<HashMap<String, Arc<dyn Storage>>>,
+    pub db: Arc<Db>,
+    pub jobs: Arc<Jobs>,
+    pub queue: Arc<Queue>,
+    pub bandwidth: Arc<Bandwidth>,
+    pub hls: Arc<Hls>,
+    /// GPU encoders found at startup, if `HWACCEL` is set and any work.
+    pub hwaccel: Option<Arc<HwEncoders>>,
+    pub push: Arc<Push>,
+    pub mailer: Option<Arc<Mailer>>,
+    pub shares: Arc<Shares>,
+    pub upstream: Option<Arc<Upstream>>,
+    pub locks: Option<Arc<Locks>>,
+    pub leases: Arc<Leases>,
+    pub plugins: Plugins,
+    pub graphql: ApiSchema,
+}
+
+impl AppState {
+    /// Opens the database and sets up storage from `config`. Nothing runs
+    /// until [`start`](Self::start).
+    pub fn new(config: Config) -> Result<Self, StartupError> {
+        let storage: Option<Arc<dyn Storage>> = match &config.s3 {
+            Some(s3) => {
+                let storage = S3Storage::new(s3).map_err(StartupError::S3)?;
+                info!("Uploading downloads to S3 bucket {}", s3.bucket);
+                Some(Arc::new(storage))
+            }
+            None => None,
+        };
+        let mut destinations = HashMap::new();
+        for (name, destination) in &config.destinations {
+            let storage = destination
+                .build()
+                .map_err(|e| StartupError::Destination(name.clone(), e))?;
+            destinations.insert(name.clone(), storage);
+        }
+        let db =
+            Db::open(&config.data_dir).map_err(|e| StartupError::Db(config.data_dir.clone(), e))?;
+        let db = Arc::new(db);
+        let push = Push::new(
+            db.clone(),
+            config.vapid_private_key.as_deref(),
+            &config.vapid_subject,
+            &config.data_dir,
+        )?;
+        let mailer = match &config.email {
+            Some(email) => {
+                let mailer = Mailer::new(email)?;
+                info!("Emailing {} about finished jobs", email.to.join(", "));
+                Some(Arc::new(mailer))
+            }
+            None => None,
+        };
+        let shares = Shares::new(config.share_secret.as_deref(), &config.data_dir)?;
+        let upstream = match &config.upstream_url {
+            Some(url) => {
+                let upstream = Upstream::new(url.clone(), config.upstream_authorization.clone())?;
+                info!("Falling back to upstream instance {}", url);
+                Some(Arc::new(upstream))
+            }
+            None => None,
+        };
+        let locks = match &config.lock_dir {
+            Some(dir) => {
+                let locks =
+                    Locks::new(dir.clone()).map_err(|e| StartupError::Locks(dir.clone(), e))?;
+                info!("Locking downloads in {:?}", dir);
+                Some(Arc::new(locks))
+            }
+            None => None,
+        };
+        let logs = JobLogs::new(config.data_dir.join("logs"), config.job_log_max_kb * 1024);
+        let bandwidth = Arc::new(Bandwidth::new(
+            config.max_download_rate_kb,
+            config.max_concurrent_jobs,
+        ));
+        let hls = Arc::new(Hls::new(config.data_dir.join("hls")));
+        let hwaccel = config
+            .hwaccel
+            .and_then(|accel| HwEncoders::detect(accel, &config.hwaccel_device))
+            .map(Arc::new);
+        Ok(Self {
+            config: Arc::new(config),
+            storage,
+            destinations: Arc::new(destinations),
+            jobs: Arc::new(Jobs::new(db.clone(), logs)),
+            db,
+            queue: Arc::new(Queue::default()),
+            bandwidth,
+            hls,
+            hwaccel,
+            push: Arc::new(push),
+            mailer,
+            shares: Arc::new(shares),
+            upstream,
+            locks,
+            leases: Arc::new(Leases::default()),
+            plugins: Plugins::builtin(),
+            graphql: graphql::schema(),
+        })
+    }
+
+    /// Re-enqueues jobs interrupted by a restart and spawns the queue
+    /// workers, push, webhook and email notifiers, the Telegram bot and
+    /// retention cleanup. A coordinator hands its queue to workers instead of
+    /// running it.
+    pub fn start(&self) {
+        for job in self.jobs.restore() {
+            self.queue.push(job);
+        }
+        self.push.spawn_notifier(&self.jobs);
+        webhooks::spawn_notifier(&self.config.hooks, &self.jobs);
+        if let Some(mailer) = &self.mailer {
+            mailer.spawn_notifier(self);
+        }
+        if let Some(telegram) = &self.config.telegram {
+            telegram::spawn(self, telegram);
+        }
+        retention::spawn(self);
+        match self.config.role {
+            Role::Coordinator => {
+                info!("Handing queued jobs to workers");
+                cluster::spawn_lease_sweeper(self);
+            }
+            _ => self
+                .queue
+                .spawn_workers(self, self.config.max_concurrent_jobs),
+        }
+    }
+
+    /// Claims and runs jobs from the coordinator until the process is
+    /// stopped. Workers don't serve the API; the coordinator has the jobs.
+    pub async fn run_worker(&self) -> Result<(), StartupError> {
+        let config = &self.config;
+        let (Some(url), Some(token)) = (&config.coordinator_url, &config.worker_token) else {
+            unreachable!("checked by Config::load");
+        };
+        let coordinator = Arc::new(Coordinator::new(url.clone(), token.clone())?);
+        info!("Claiming jobs from coordinator {}", url);
+        cluster::spawn_workers(self, coordinator, config.max_concurrent_jobs);
+        std::future::pending::<()>().await;
+        Ok(())
+    }
+}
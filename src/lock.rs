@@ -0,0 +1,174 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{jobs::JobHandle, share::unix_now};
+
+/// How long a lock holds without being renewed, and how often its holder
+/// renews it. An instance that dies mid-download blocks the video for at
+/// most the lease.
+const LEASE: Duration = Duration::from_secs(60);
+const RENEW_INTERVAL: Duration = Duration::from_secs(20);
+
+/// How often a waiting instance checks whether a lock was released.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Lease-based lock files in a directory shared between instances, so two
+/// of them writing to the same volume never download the same video at
+/// once. Each file holds its owner and expiry; a holder that stops renewing
+/// loses the lock once the lease runs out.
+pub struct Locks {
+    dir: PathBuf,
+    owner: Arc<str>,
+}
+
+/// A held lock, renewed in the background and released when dropped.
+pub struct Lease {
+    path: PathBuf,
+    owner: Arc<str>,
+    renewer: JoinHandle<()>,
+}
+
+impl Locks {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let host = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+        Ok(Self {
+            dir,
+            owner: format!("{}/{}", host, Uuid::new_v4()).into(),
+        })
+    }
+
+    /// Takes the lock on `key`, waiting for another instance holding it to
+    /// finish or for its lease to run out.
+    pub async fn acquire(&self, key: &str, job: &JobHandle) -> io::Result<Lease> {
+        let path = self.dir.join(format!("{}.lock", sanitize(key)));
+        let mut waiting = false;
+        loop {
+            match try_create(&path, &self.owner) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+            match read(&path) {
+                Some((owner, expires)) if expires > unix_now() => {
+                    if !waiting {
+                        job.log_output(&format!(
+                            "Waiting for {} to finish downloading the same video",
+                            owner
+                        ));
+                        waiting = true;
+                    }
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+                Some(stale) => {
+                    debug!("Taking over expired lock {:?}", path);
+                    remove_if(&path, |current| current == Some(&stale))?;
+                }
+                // Just created and not written yet, or left half-written.
+                None if abandoned(&path) => {
+                    debug!("Taking over unreadable lock {:?}", path);
+                    remove_if(&path, |current| current.is_none())?;
+                }
+                None => tokio::time::sleep(Duration::from_millis(100)).await,
+            }
+        }
+
+        let renewer = tokio::spawn({
+            let path = path.clone();
+            let owner = self.owner.clone();
+            async move {
+                let mut interval = tokio::time::interval(RENEW_INTERVAL);
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = renew(&path, &owner) {
+                        warn!("Failed to renew lock {:?}: {:?}", path, e);
+                    }
+                }
+            }
+        });
+        Ok(Lease {
+            path,
+            owner: self.owner.clone(),
+            renewer,
+        })
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.renewer.abort();
+        let owner = &self.owner;
+        let _ = remove_if(&self.path, |current| {
+            current.is_some_and(|(holder, _)| holder == &**owner)
+        });
+    }
+}
+
+/// Key of a lock file, safe to use as a file name.
+fn sanitize(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn contents(owner: &str) -> String {
+    format!("{} {}", owner, unix_now() + LEASE.as_secs())
+}
+
+fn try_create(path: &Path, owner: &str) -> io::Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)?;
+    file.write_all(contents(owner).as_bytes())
+}
+
+/// The owner and expiry in a lock file, or `None` if it's gone or
+/// unreadable.
+fn read(path: &Path) -> Option<(String, u64)> {
+    let contents = std::fs::read_to_string(path).ok()?;
+    let (owner, expires) = contents.trim().rsplit_once(' ')?;
+    Some((owner.to_string(), expires.parse().ok()?))
+}
+
+/// Whether an unreadable lock file is older than a lease. A missing one
+/// counts too, so the caller simply tries again.
+fn abandoned(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .map(|modified| modified.elapsed().unwrap_or_default() > LEASE)
+        .unwrap_or(true)
+}
+
+/// Extends the lease, unless the lock was taken over in the meantime.
+fn renew(path: &Path, owner: &str) -> io::Result<()> {
+    match read(path) {
+        Some((holder, _)) if holder == owner => std::fs::write(path, contents(owner)),
+        _ => Err(io::Error::other("lock was taken over")),
+    }
+}
+
+fn remove_if(path: &Path, matches: impl FnOnce(Option<&(String, u64)>) -> bool) -> io::Result<()> {
+    if !matches(read(path).as_ref()) {
+        return Ok(());
+    }
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,239 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, instrument};
+use url::Url;
+
+use crate::{
+    cache,
+    config::Config,
+    download::{self, DownloadError},
+};
+
+/// A playlist's entries as listed by yt-dlp without resolving each video, so
+/// clients can pick what to download before anything heavy starts.
+#[derive(Debug, Serialize)]
+pub struct Playlist {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub uploader: Option<String>,
+    pub count: usize,
+    pub entries: Vec<PlaylistEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlaylistEntry {
+    /// 1-based position, as used by yt-dlp's `--playlist-items`.
+    pub index: usize,
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub url: Option<String>,
+    /// Length in seconds, if the site lists it.
+    pub duration: Option<f64>,
+}
+
+/// Longest accepted title pattern.
+const MAX_TITLE_PATTERN_LEN: usize = 200;
+
+/// Conditions playlist entries must meet to be downloaded, compiled into
+/// yt-dlp's `--match-filters`. Entries missing a field pass its condition,
+/// since many sites don't report view counts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MatchFilter {
+    /// Minimum length in seconds, e.g. 61 to skip Shorts.
+    pub min_duration: Option<u32>,
+    /// Maximum length in seconds.
+    pub max_duration: Option<u32>,
+    pub min_views: Option<u64>,
+    /// Regular expression the title must match, case-insensitively.
+    pub title: Option<String>,
+    /// Skip livestreams and their recordings.
+    pub skip_live: bool,
+}
+
+impl MatchFilter {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks the limits are consistent and the title pattern compiles.
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_duration, self.max_duration)
+            && min > max
+        {
+            return Err("min_duration is above max_duration".to_string());
+        }
+        if let Some(title) = &self.title {
+            if title.len() > MAX_TITLE_PATTERN_LEN {
+                return Err("title pattern is too long".to_string());
+            }
+            Regex::new(title).map_err(|e| format!("invalid title pattern: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The `--match-filters` expression, or `None` if nothing is filtered.
+    pub fn to_ytdlp(&self) -> Option<String> {
+        let mut conditions = Vec::new();
+        if let Some(min) = self.min_duration {
+            conditions.push(format!("duration>=?{}", min));
+        }
+        if let Some(max) = self.max_duration {
+            conditions.push(format!("duration<=?{}", max));
+        }
+        if let Some(min) = self.min_views {
+            conditions.push(format!("view_count>=?{}", min));
+        }
+        if let Some(title) = &self.title {
+            // yt-dlp splits filters on `&` and ends the value at a quote,
+            // unless they are escaped; other backslashes pass through.
+            let quoted = title.replace('&', "\\&").replace('\'', "\\'");
+            conditions.push(format!("title~='(?i){}'", quoted));
+        }
+        if self.skip_live {
+            conditions.push("!is_live & !was_live".to_string());
+        }
+        (!conditions.is_empty()).then(|| conditions.join(" & "))
+    }
+}
+
+/// Which uploads of a channel to pull.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelTab {
+    Videos,
+    Shorts,
+    Streams,
+}
+
+impl ChannelTab {
+    fn path(self) -> &'static str {
+        match self {
+            ChannelTab::Videos => "videos",
+            ChannelTab::Shorts => "shorts",
+            ChannelTab::Streams => "streams",
+        }
+    }
+}
+
+/// Tabs a YouTube channel URL may already point at, replaced when picking
+/// another.
+const CHANNEL_TABS: &[&str] = &[
+    "featured",
+    "videos",
+    "shorts",
+    "streams",
+    "live",
+    "playlists",
+    "community",
+    "podcasts",
+    "releases",
+];
+
+/// Points a YouTube channel URL (`/@handle`, `/channel/<id>`, `/c/<name>` or
+/// `/user/<name>`) at `tab`. Returns `None` for anything else.
+pub fn channel_tab_url(url: &str, tab: ChannelTab) -> Option<String> {
+    let mut url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    if !(host == "youtube.com" || host.ends_with(".youtube.com")) {
+        return None;
+    }
+
+    let mut segments: Vec<&str> = url.path_segments()?.filter(|s| !s.is_empty()).collect();
+    let channel_len = match segments.first()? {
+        handle if handle.starts_with('@') => 1,
+        &"channel" | &"c" | &"user" => 2,
+        _ => return None,
+    };
+    if segments.len() < channel_len
+        || segments.len() > channel_len + 1
+        || segments
+            .get(channel_len)
+            .is_some_and(|s| !CHANNEL_TABS.contains(s))
+    {
+        return None;
+    }
+    segments.truncate(channel_len);
+    segments.push(tab.path());
+
+    let path = format!("/{}", segments.join("/"));
+    url.set_path(&path);
+    url.set_query(None);
+    Some(url.into())
+}
+
+/// The subset of `--flat-playlist -J` output read here. A single video
+/// prints itself instead of a playlist, without `entries`.
+#[derive(Debug, Deserialize)]
+struct FlatInfo {
+    id: Option<String>,
+    title: Option<String>,
+    uploader: Option<String>,
+    url: Option<String>,
+    webpage_url: Option<String>,
+    duration: Option<f64>,
+    entries: Option<Vec<FlatInfo>>,
+}
+
+impl FlatInfo {
+    fn into_entry(self, index: usize) -> PlaylistEntry {
+        PlaylistEntry {
+            index,
+            id: self.id,
+            title: self.title,
+            url: self.url.or(self.webpage_url),
+            duration: self.duration,
+        }
+    }
+}
+
+/// Lists the entries of `url` with `--flat-playlist`, which only reads the
+/// playlist pages. A single video is returned as a playlist of one.
+#[instrument(skip(config))]
+pub async fn preview(url: &str, config: &Config) -> Result<Playlist, DownloadError> {
+    let cmd = cache::ytdlp(config, url)
+        .arg("--flat-playlist")
+        .arg("-J")
+        .arg(url)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(DownloadError::VideoCommand)?;
+
+    debug!("Command status: {}", cmd.status);
+    match cmd.status.code() {
+        Some(0) => {}
+        Some(code) => {
+            return Err(download::classify_failure(
+                &String::from_utf8_lossy(&cmd.stderr),
+                code,
+            ));
+        }
+        None => return Err(DownloadError::VideoExitNoCode),
+    }
+
+    let mut info: FlatInfo =
+        serde_json::from_slice(&cmd.stdout).map_err(DownloadError::PlaylistJson)?;
+    let entries: Vec<PlaylistEntry> = match info.entries.take() {
+        Some(entries) => entries
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| entry.into_entry(i + 1))
+            .collect(),
+        None => vec![PlaylistEntry {
+            index: 1,
+            id: info.id.clone(),
+            title: info.title.clone(),
+            url: info.webpage_url.clone().or(info.url.clone()),
+            duration: info.duration,
+        }],
+    };
+
+    Ok(Playlist {
+        id: info.id,
+        title: info.title,
+        uploader: info.uploader,
+        count: entries.len(),
+        entries,
+    })
+}
@@ -0,0 +1,103 @@
+#[cfg(feature = "plugin-soundcloud")]
+mod soundcloud;
+#[cfg(feature = "plugin-twitch")]
+mod twitch;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::Router;
+use tracing::info;
+use url::Url;
+
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(thiserror::Error, Debug)]
+#[error("plugin {plugin} failed: {message}")]
+pub struct PluginError {
+    pub plugin: &'static str,
+    pub message: String,
+}
+
+/// Site-specific behaviour, compiled in behind a `plugin-*` cargo feature.
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Domains this plugin applies to; subdomains match too.
+    fn domains(&self) -> &'static [&'static str];
+
+    /// Extra arguments passed to yt-dlp for matching downloads.
+    fn ytdlp_args(&self, _url: &Url) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Runs after yt-dlp finishes and before hooks and delivery.
+    async fn post_process(
+        &self,
+        _video: &mut DownloadedVideo,
+        _job: &JobHandle,
+    ) -> Result<(), PluginError> {
+        Ok(())
+    }
+
+    /// Extra endpoints, mounted under `/api/plugins/{name}`.
+    fn router(&self) -> Option<Router> {
+        None
+    }
+}
+
+#[derive(Clone)]
+pub struct Plugins {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl Plugins {
+    /// All plugins enabled at compile time.
+    pub fn builtin() -> Self {
+        let plugins: Vec<Arc<dyn Plugin>> = vec![
+            #[cfg(feature = "plugin-soundcloud")]
+            Arc::new(soundcloud::SoundCloud),
+            #[cfg(feature = "plugin-twitch")]
+            Arc::new(twitch::Twitch),
+        ];
+
+        for plugin in &plugins {
+            info!("Loaded plugin {}", plugin.name());
+        }
+        Self { plugins }
+    }
+
+    /// Plugins that apply to `url`.
+    pub fn for_url(&self, url: &str) -> Vec<Arc<dyn Plugin>> {
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(String::from))
+        else {
+            return Vec::new();
+        };
+
+        self.plugins
+            .iter()
+            .filter(|plugin| {
+                plugin
+                    .domains()
+                    .iter()
+                    .any(|domain| host == *domain || host.ends_with(&format!(".{}", domain)))
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Routes contributed by plugins, each nested under its name.
+    pub fn router(&self) -> Router {
+        self.plugins
+            .iter()
+            .filter_map(|plugin| {
+                plugin
+                    .router()
+                    .map(|router| (format!("/{}", plugin.name()), router))
+            })
+            .fold(Router::new(), |app, (path, router)| app.nest(&path, router))
+    }
+}
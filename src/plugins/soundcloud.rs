@@ -0,0 +1,28 @@
+use url::Url;
+
+use super::Plugin;
+
+/// SoundCloud tracks don't set `artist`, so music players show them as
+/// unknown. Copy the uploader into the artist tag and embed the cover art.
+pub struct SoundCloud;
+
+impl Plugin for SoundCloud {
+    fn name(&self) -> &'static str {
+        "soundcloud"
+    }
+
+    fn domains(&self) -> &'static [&'static str] {
+        &["soundcloud.com"]
+    }
+
+    fn ytdlp_args(&self, _url: &Url) -> Vec<String> {
+        [
+            "--parse-metadata",
+            "%(artist,uploader)s:%(meta_artist)s",
+            "--embed-metadata",
+            "--embed-thumbnail",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+}
@@ -0,0 +1,135 @@
+use std::io;
+
+use async_trait::async_trait;
+use tokio::process::Command;
+use tracing::{debug, warn};
+use url::Url;
+
+use super::{Plugin, PluginError};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+/// Fetches chat replays. yt-dlp's `rechat` subtitles stopped working when
+/// Twitch retired the API behind them.
+const CHAT_DOWNLOADER: &str = "TwitchDownloaderCLI";
+
+/// Sidecar the chat replay is kept in.
+const CHAT_SIDECAR: &str = "chat.json";
+
+/// Twitch VODs: a chapter per game played, a quality cap suited to streams
+/// hours long, and the chat replay when the job asks for it.
+pub struct Twitch;
+
+#[async_trait]
+impl Plugin for Twitch {
+    fn name(&self) -> &'static str {
+        "twitch"
+    }
+
+    fn domains(&self) -> &'static [&'static str] {
+        &["twitch.tv"]
+    }
+
+    fn ytdlp_args(&self, _url: &Url) -> Vec<String> {
+        [
+            // yt-dlp makes a chapter of each game change, named after the
+            // game.
+            "--embed-chapters",
+            // 720p30 keeps a VOD to about 1.5 GB an hour rather than 3.5 at
+            // 1080p60. Sorted before the job's own preferences, so `fps` and
+            // the server's limits still win.
+            "-S",
+            "res:720,fps:30",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+
+    async fn post_process(
+        &self,
+        video: &mut DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<(), PluginError> {
+        let chapters = video.info.chapters.as_deref().unwrap_or_default();
+        if !chapters.is_empty() {
+            let games: Vec<String> = chapters
+                .iter()
+                .map(|chapter| {
+                    format!(
+                        "{} {}",
+                        timestamp(chapter.start_time),
+                        chapter.title.as_deref().unwrap_or("Untitled")
+                    )
+                })
+                .collect();
+            job.log_output(&format!("Chapters: {}", games.join(", ")));
+        }
+        if job.options().chat {
+            download_chat(video, job).await;
+        }
+        Ok(())
+    }
+}
+
+/// Keeps the VOD's chat replay as a sidecar. The video is worth having
+/// without it, so failures are only logged.
+async fn download_chat(video: &mut DownloadedVideo, job: &JobHandle) {
+    // yt-dlp prefixes VOD ids with `v`; clips and live streams have none.
+    let Some(id) = video
+        .info
+        .id
+        .as_deref()
+        .and_then(|id| id.strip_prefix('v'))
+        .filter(|id| !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit()))
+    else {
+        job.log_output("Chat replays are only kept for VODs");
+        return;
+    };
+    let path = video.path.with_extension(CHAT_SIDECAR);
+    job.log_output("Downloading chat replay");
+    let output = Command::new(CHAT_DOWNLOADER)
+        .arg("chatdownload")
+        .arg("--id")
+        .arg(id)
+        .arg("-o")
+        .arg(&path)
+        .kill_on_drop(true)
+        .output()
+        .await;
+
+    match output {
+        Ok(output) if output.status.success() => {
+            debug!("{} status: {}", CHAT_DOWNLOADER, output.status);
+            if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+                video.sidecars.push(CHAT_SIDECAR.to_string());
+            } else {
+                job.log_output("No chat replay available for this VOD");
+            }
+        }
+        Ok(output) => {
+            warn!("{} failed: {}", CHAT_DOWNLOADER, output.status);
+            job.log_output(&String::from_utf8_lossy(&output.stderr));
+            job.log_output("Failed to download the chat replay");
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            job.log_output(&format!(
+                "{} isn't installed, skipping the chat replay",
+                CHAT_DOWNLOADER
+            ));
+        }
+        Err(e) => {
+            warn!("Failed to run {}: {:?}", CHAT_DOWNLOADER, e);
+            job.log_output("Failed to download the chat replay");
+        }
+    }
+}
+
+/// `h:mm:ss`, as Twitch shows positions in a VOD.
+fn timestamp(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
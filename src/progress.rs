@@ -0,0 +1,164 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// `[download]  43.2% of ~ 120.35MiB at 3.10MiB/s ETA 02:10`, and once done
+/// `[download] 100% of 120.35MiB in 00:00:38 at 3.12MiB/s`.
+static PERCENT_LINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^\[download\]\s+(\d+(?:\.\d+)?)% of\s+(~)?\s*(\S+)(?:\s+in\s+\S+)?(?:\s+at\s+(\S+))?(?:\s+ETA\s+(\S+))?",
+    )
+    .unwrap()
+});
+/// `[download]   12.34MiB at  2.00MiB/s (00:00:05)`, when the size isn't
+/// known up front, e.g. for live streams.
+static SIZE_LINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^\[download\]\s+(\S+) at\s+(\S+)").unwrap());
+
+/// Where yt-dlp is with the file it's downloading, from its latest
+/// `[download]` progress line. Each playlist entry starts over.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    pub percent: Option<f64>,
+    pub downloaded_bytes: Option<u64>,
+    pub total_bytes: Option<u64>,
+    /// Whether `total_bytes` is yt-dlp's estimate, as for fragmented
+    /// streams, rather than the size reported by the site.
+    pub estimated: bool,
+    /// Bytes per second.
+    pub speed: Option<u64>,
+    /// Seconds left.
+    pub eta: Option<u64>,
+}
+
+/// Where the ffmpeg passes run after the download are, from ffmpeg's
+/// `-progress` output. Each playlist entry starts over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PostProcessingProgress {
+    /// What the current pass does, e.g. `transcode`.
+    pub step: String,
+    /// Number of the current pass, from 1, and how many the job's options
+    /// call for.
+    pub pass: u32,
+    pub passes: u32,
+    pub percent: Option<f64>,
+    /// Multiple of real time ffmpeg works at.
+    pub speed: Option<f64>,
+    /// Seconds left in this pass.
+    pub eta: Option<u64>,
+}
+
+/// Progress through the download and the ffmpeg passes after it, which
+/// count equally, since a re-encode can take as long as the download.
+pub fn overall_percent(
+    download: Option<&DownloadProgress>,
+    post_processing: Option<&PostProcessingProgress>,
+    passes: u32,
+) -> Option<f64> {
+    let (done, passes) = match post_processing {
+        Some(post) => {
+            let current = post.percent.unwrap_or_default() / 100.0;
+            (post.pass as f64 + current, passes.max(post.pass))
+        }
+        None => (download?.percent? / 100.0, passes),
+    };
+    Some((done / (passes + 1) as f64 * 100.0).min(100.0))
+}
+
+/// Collects ffmpeg's `-progress` output: blocks of `key=value` lines, each
+/// ending with a `progress=` line.
+#[derive(Debug, Default)]
+pub struct FfmpegProgress {
+    /// Seconds of media processed.
+    out_time: Option<f64>,
+    pub speed: Option<f64>,
+}
+
+impl FfmpegProgress {
+    /// Takes one line, returning `true` once a block is complete.
+    pub fn line(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return false;
+        };
+        match key {
+            "out_time_us" => {
+                self.out_time = value.parse::<u64>().ok().map(|us| us as f64 / 1e6);
+            }
+            "speed" => {
+                self.speed = value
+                    .trim()
+                    .strip_suffix('x')
+                    .and_then(|speed| speed.parse().ok())
+                    .filter(|speed: &f64| *speed > 0.0);
+            }
+            "progress" => return true,
+            _ => {}
+        }
+        false
+    }
+
+    /// Percent done and seconds left for a pass over `duration` seconds of
+    /// media.
+    pub fn estimate(&self, duration: Option<f64>) -> (Option<f64>, Option<u64>) {
+        let (Some(duration), Some(out_time)) = (duration.filter(|d| *d > 0.0), self.out_time)
+        else {
+            return (None, None);
+        };
+        let percent = (out_time / duration * 100.0).clamp(0.0, 100.0);
+        let eta = self
+            .speed
+            .map(|speed| ((duration - out_time).max(0.0) / speed).round() as u64);
+        (Some(percent), eta)
+    }
+}
+
+/// Reads a `[download]` progress line printed with `--newline`; `None` for
+/// other lines.
+pub fn parse(line: &str) -> Option<DownloadProgress> {
+    if let Some(captures) = PERCENT_LINE.captures(line) {
+        let percent: f64 = captures[1].parse().ok()?;
+        let total_bytes = parse_size(&captures[3]);
+        return Some(DownloadProgress {
+            percent: Some(percent),
+            downloaded_bytes: total_bytes.map(|total| (total as f64 * percent / 100.0) as u64),
+            total_bytes,
+            estimated: captures.get(2).is_some(),
+            speed: captures
+                .get(4)
+                .and_then(|speed| parse_speed(speed.as_str())),
+            eta: captures.get(5).and_then(|eta| parse_eta(eta.as_str())),
+        });
+    }
+    let captures = SIZE_LINE.captures(line)?;
+    Some(DownloadProgress {
+        percent: None,
+        downloaded_bytes: Some(parse_size(&captures[1])?),
+        total_bytes: None,
+        estimated: false,
+        speed: parse_speed(&captures[2]),
+        eta: None,
+    })
+}
+
+/// Sizes as yt-dlp prints them, e.g. `3.10MiB`.
+fn parse_size(size: &str) -> Option<u64> {
+    let split = size.find(|c: char| c.is_ascii_alphabetic())?;
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let power = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"]
+        .iter()
+        .position(|known| *known == unit)?;
+    Some((number * 1024f64.powi(power as i32)) as u64)
+}
+
+fn parse_speed(speed: &str) -> Option<u64> {
+    parse_size(speed.strip_suffix("/s")?)
+}
+
+/// `02:10` or `1:02:10`.
+fn parse_eta(eta: &str) -> Option<u64> {
+    eta.split(':').try_fold(0, |secs, part| {
+        part.parse::<u64>().ok().map(|part| secs * 60 + part)
+    })
+}
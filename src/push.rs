@@ -0,0 +1,248 @@
+use std::{path::Path, sync::Arc, time::SystemTime};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, info, warn};
+use web_push_native::{
+    Auth, WebPushBuilder,
+    jwt_simple::algorithms::{ECDSAP256PublicKeyLike, ES256KeyPair},
+    p256::PublicKey,
+};
+
+use crate::{
+    db::{Db, DbError},
+    jobs::{Job, JobMode, JobStatus, Jobs},
+};
+
+/// File in the data directory holding the generated VAPID key when none is
+/// configured.
+const VAPID_KEY_FILE: &str = "vapid_private_key";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PushError {
+    #[error("invalid VAPID private key")]
+    InvalidVapidKey,
+    #[error("failed to read or write VAPID key file")]
+    KeyFile(#[source] std::io::Error),
+    #[error("invalid push subscription")]
+    InvalidSubscription,
+    #[error("failed to build push message: {0}")]
+    Build(String),
+    #[error("HTTP request failed")]
+    Http(#[from] reqwest::Error),
+    #[error("push service rejected message with status code {0}")]
+    Status(u16),
+    #[error(transparent)]
+    Db(#[from] DbError),
+}
+
+/// A browser's push subscription, as returned by `PushSubscription.toJSON()`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushSubscription {
+    pub endpoint: String,
+    pub keys: PushKeys,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PushKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+/// Payload read by the service worker in `static/sw.js`.
+#[derive(Debug, Serialize)]
+struct Notification {
+    title: String,
+    body: String,
+    job_id: String,
+    status: JobStatus,
+    location: Option<String>,
+}
+
+/// Sends Web Push notifications to subscribed browsers when background jobs
+/// finish, so users hear about long downloads after closing the tab.
+pub struct Push {
+    db: Arc<Db>,
+    client: Client,
+    key: ES256KeyPair,
+    subject: String,
+}
+
+impl Push {
+    /// Uses `private_key` (base64url, as printed by most VAPID generators) if
+    /// given, otherwise a key generated on first start and kept in `data_dir`
+    /// so existing subscriptions stay valid.
+    pub fn new(
+        db: Arc<Db>,
+        private_key: Option<&str>,
+        subject: &str,
+        data_dir: &Path,
+    ) -> Result<Self, PushError> {
+        let key = match private_key {
+            Some(key) => decode_key(key)?,
+            None => load_or_generate_key(&data_dir.join(VAPID_KEY_FILE))?,
+        };
+
+        Ok(Self {
+            db,
+            client: Client::builder().build()?,
+            key,
+            subject: subject.to_string(),
+        })
+    }
+
+    /// Application server key passed to `pushManager.subscribe`.
+    pub fn public_key(&self) -> String {
+        Base64UrlUnpadded::encode_string(
+            &self.key.public_key().public_key().to_bytes_uncompressed(),
+        )
+    }
+
+    pub fn subscribe(&self, subscription: &PushSubscription) -> Result<(), PushError> {
+        // Reject keys we won't be able to encrypt for up front.
+        builder(subscription)?;
+        self.db
+            .save_push_subscription(subscription, unix_now())
+            .map_err(PushError::from)
+    }
+
+    pub fn unsubscribe(&self, endpoint: &str) -> Result<(), PushError> {
+        self.db
+            .delete_push_subscription(endpoint)
+            .map_err(PushError::from)
+    }
+
+    /// Notifies every subscriber as queued jobs finish. Streamed downloads
+    /// are skipped since their tab is still open.
+    pub fn spawn_notifier(self: &Arc<Self>, jobs: &Jobs) {
+        let push = self.clone();
+        let mut finished = jobs.subscribe_finished();
+        tokio::spawn(async move {
+            loop {
+                match finished.recv().await {
+                    Ok(job) if job.mode == JobMode::Queued => push.notify(&job).await,
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Skipped push notifications for {} jobs", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    async fn notify(&self, job: &Job) {
+        let subscriptions = match self.db.push_subscriptions() {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                warn!("Failed to load push subscriptions: {:?}", e);
+                return;
+            }
+        };
+        if subscriptions.is_empty() {
+            return;
+        }
+
+        let name = job.filename.as_deref().unwrap_or(job.url.as_str());
+        let notification = Notification {
+            title: match job.status {
+                JobStatus::Completed => "Download finished".to_string(),
+                _ => "Download failed".to_string(),
+            },
+            body: match &job.error {
+                Some(error) => format!("{}: {}", name, error),
+                None => name.to_string(),
+            },
+            job_id: job.id.to_string(),
+            status: job.status,
+            location: job.location.clone(),
+        };
+        let payload = serde_json::to_vec(&notification).unwrap_or_default();
+
+        for subscription in subscriptions {
+            match self.send(&subscription, &payload).await {
+                Ok(()) => debug!("Sent push notification to {}", subscription.endpoint),
+                // The browser unsubscribed or the subscription expired.
+                Err(PushError::Status(404 | 410)) => {
+                    info!(
+                        "Removing expired push subscription {}",
+                        subscription.endpoint
+                    );
+                    if let Err(e) = self.unsubscribe(&subscription.endpoint) {
+                        warn!("Failed to remove push subscription: {:?}", e);
+                    }
+                }
+                Err(e) => warn!(
+                    "Failed to send push notification to {}: {:?}",
+                    subscription.endpoint, e
+                ),
+            }
+        }
+    }
+
+    async fn send(&self, subscription: &PushSubscription, payload: &[u8]) -> Result<(), PushError> {
+        let request = builder(subscription)?
+            .with_vapid(&self.key, &self.subject)
+            .build(payload)
+            .map_err(|e| PushError::Build(e.to_string()))?;
+        let request = reqwest::Request::try_from(request)?;
+
+        let response = self.client.execute(request).await?;
+        if !response.status().is_success() {
+            return Err(PushError::Status(response.status().as_u16()));
+        }
+
+        Ok(())
+    }
+}
+
+fn builder(subscription: &PushSubscription) -> Result<WebPushBuilder, PushError> {
+    let endpoint = subscription
+        .endpoint
+        .parse()
+        .map_err(|_| PushError::InvalidSubscription)?;
+    let p256dh = Base64UrlUnpadded::decode_vec(&subscription.keys.p256dh)
+        .map_err(|_| PushError::InvalidSubscription)?;
+    let public_key =
+        PublicKey::from_sec1_bytes(&p256dh).map_err(|_| PushError::InvalidSubscription)?;
+    let auth = Base64UrlUnpadded::decode_vec(&subscription.keys.auth)
+        .map_err(|_| PushError::InvalidSubscription)?;
+    if auth.len() != 16 {
+        return Err(PushError::InvalidSubscription);
+    }
+
+    Ok(WebPushBuilder::new(
+        endpoint,
+        public_key,
+        Auth::clone_from_slice(&auth),
+    ))
+}
+
+fn decode_key(key: &str) -> Result<ES256KeyPair, PushError> {
+    let bytes =
+        Base64UrlUnpadded::decode_vec(key.trim()).map_err(|_| PushError::InvalidVapidKey)?;
+    ES256KeyPair::from_bytes(&bytes).map_err(|_| PushError::InvalidVapidKey)
+}
+
+fn load_or_generate_key(path: &Path) -> Result<ES256KeyPair, PushError> {
+    match std::fs::read_to_string(path) {
+        Ok(key) => decode_key(&key),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Generating VAPID key in {:?}", path);
+            let key = ES256KeyPair::generate();
+            std::fs::write(path, Base64UrlUnpadded::encode_string(&key.to_bytes()))
+                .map_err(PushError::KeyFile)?;
+            Ok(key)
+        }
+        Err(e) => Err(PushError::KeyFile(e)),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
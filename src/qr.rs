@@ -0,0 +1,28 @@
+use std::io::Cursor;
+
+use image::{ImageFormat, Luma};
+use qrcode::QrCode;
+
+/// Smallest rendered size, in pixels; large enough to scan off a screen.
+const MIN_SIZE: u32 = 256;
+
+#[derive(thiserror::Error, Debug)]
+pub enum QrError {
+    #[error("failed to encode QR code")]
+    Encode(#[from] qrcode::types::QrError),
+    #[error("failed to render QR code")]
+    Render(#[from] image::ImageError),
+}
+
+/// Renders `data` as a black-on-white QR code PNG.
+pub fn png(data: &str) -> Result<Vec<u8>, QrError> {
+    let code = QrCode::new(data.as_bytes())?;
+    let image = code
+        .render::<Luma<u8>>()
+        .min_dimensions(MIN_SIZE, MIN_SIZE)
+        .build();
+
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png)?;
+    Ok(png.into_inner())
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::Notify;
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    download::{self, DownloadError, JobDir},
+    info::video_key,
+    jobs::{JobHandle, JobStage},
+    storage::{self, Stored},
+    video::DownloadedVideo,
+};
+
+/// Background download queue.
+///
+/// Jobs submitted through `POST /api/jobs` are run here by a fixed pool of
+/// workers instead of inside the request, so they survive the client going
+/// away, and are re-enqueued from the database when the server restarts.
+#[derive(Debug, Default)]
+pub struct Queue {
+    pending: Mutex<VecDeque<JobHandle>>,
+    /// Jobs this server's workers are running, each with the signal that
+    /// pauses it.
+    running: Mutex<HashMap<Uuid, Arc<Notify>>>,
+    notify: Notify,
+}
+
+/// What [`Queue::pause`] did with a job.
+#[derive(Debug)]
+pub enum Pause {
+    /// Taken out of the queue before it started.
+    Held(JobHandle),
+    /// Running here; its worker stops it and marks it paused.
+    Stopping,
+    /// Neither queued nor run by this server, e.g. streamed or claimed by a
+    /// remote worker.
+    NotHere,
+}
+
+impl Queue {
+    pub fn push(&self, job: JobHandle) {
+        self.pending.lock().unwrap().push_back(job);
+        self.notify.notify_one();
+    }
+
+    pub fn spawn_workers(&self, state: &AppState, count: usize) {
+        for _ in 0..count {
+            let state = state.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (job, pause) = state.queue.next_to_run().await;
+                    let result = run(&state, &job, &pause).await;
+                    state.queue.running.lock().unwrap().remove(&job.id());
+                    match result {
+                        Ok(Run::Finished) => job.complete(),
+                        Ok(Run::Paused) => {
+                            info!("Paused job {}", job.id());
+                            job.pause();
+                        }
+                        Err(e) => {
+                            error!("Queued job {} failed: {:?}", job.id(), e);
+                            job.fail(&e);
+                        }
+                    }
+                }
+            });
+        }
+    }
+
+    pub async fn next(&self) -> JobHandle {
+        loop {
+            if let Some(job) = self.pending.lock().unwrap().pop_front() {
+                return job;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Like [`next`](Self::next), for a worker on this server, registering
+    /// the job as running so it can be paused.
+    async fn next_to_run(&self) -> (JobHandle, Arc<Notify>) {
+        loop {
+            {
+                let mut pending = self.pending.lock().unwrap();
+                if let Some(job) = pending.pop_front() {
+                    let pause = Arc::new(Notify::new());
+                    self.running.lock().unwrap().insert(job.id(), pause.clone());
+                    return (job, pause);
+                }
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Ids of the waiting jobs, in the order they'll run.
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.pending
+            .lock()
+            .unwrap()
+            .iter()
+            .map(JobHandle::id)
+            .collect()
+    }
+
+    /// Moves the jobs in `ids` to the front of the queue in that order,
+    /// leaving the rest in line behind them. Returns the first id that isn't
+    /// waiting, without moving anything.
+    pub fn reorder(&self, ids: &[Uuid]) -> Result<(), Uuid> {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(missing) = ids
+            .iter()
+            .find(|id| !pending.iter().any(|job| job.id() == **id))
+        {
+            return Err(*missing);
+        }
+        let mut front = Vec::with_capacity(ids.len());
+        for id in ids {
+            // Repeated ids were moved the first time.
+            if let Some(index) = pending.iter().position(|job| job.id() == *id) {
+                front.extend(pending.remove(index));
+            }
+        }
+        for job in front.into_iter().rev() {
+            pending.push_front(job);
+        }
+        Ok(())
+    }
+
+    /// Takes a queued job out of line, or asks the worker running it to
+    /// stop.
+    pub fn pause(&self, id: Uuid) -> Pause {
+        let mut pending = self.pending.lock().unwrap();
+        if let Some(index) = pending.iter().position(|job| job.id() == id) {
+            let job = pending.remove(index).expect("index is in bounds");
+            return Pause::Held(job);
+        }
+        match self.running.lock().unwrap().get(&id) {
+            Some(pause) => {
+                pause.notify_one();
+                Pause::Stopping
+            }
+            None => Pause::NotHere,
+        }
+    }
+}
+
+/// How a worker's run of a job ended, short of failing.
+enum Run {
+    Finished,
+    /// Stopped by [`Queue::pause`], with partial files left for resuming.
+    Paused,
+}
+
+#[instrument(skip(state, job, pause), fields(job = %job.id()))]
+async fn run(state: &AppState, job: &JobHandle, pause: &Notify) -> Result<Run, DownloadError> {
+    job.start();
+    // Checked up front so a typo doesn't waste a download.
+    download::resolve_storage(state, job.dest().as_deref())?;
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let work = async {
+        let _lease = match &state.locks {
+            Some(locks) => {
+                let key = match job.video_key() {
+                    Some(key) => key,
+                    None => video_key(&job.url(), &state.config).await?,
+                };
+                Some(
+                    locks
+                        .acquire(&key, job)
+                        .await
+                        .map_err(DownloadError::Lock)?,
+                )
+            }
+            None => None,
+        };
+        let video = download::fetch(state, job, &job.url(), &job_dir).await?;
+        deliver(state, job, &video).await
+    };
+    // Dropping the work kills yt-dlp or ffmpeg, which leaves what they
+    // wrote so far in the job directory.
+    let paused = tokio::select! {
+        result = work => {
+            result?;
+            false
+        }
+        () = pause.notified() => true,
+    };
+    if paused {
+        job_dir.keep();
+        return Ok(Run::Paused);
+    }
+    Ok(Run::Finished)
+}
+
+/// Uploads a finished download to the job's destination, or keeps it on
+/// this server for `GET /api/jobs/{id}/file`.
+pub async fn deliver(
+    state: &AppState,
+    job: &JobHandle,
+    video: &DownloadedVideo,
+) -> Result<(), DownloadError> {
+    let storage = download::resolve_storage(state, job.dest().as_deref())?;
+    match storage {
+        Some(storage) => match storage::store(storage.as_ref(), video, job).await? {
+            Stored::Presigned(url) => job.set_result(&video.filename, &url, None),
+            Stored::Pushed(location) => job.set_result(&video.filename, &location, None),
+            Stored::Filed(path) => {
+                let location = path.display().to_string();
+                job.set_result(&video.filename, &location, Some(path));
+            }
+        },
+        None => {
+            // Nowhere to push it, so keep the file until the client fetches it.
+            job.set_stage(JobStage::Storing);
+            let dir = state
+                .config
+                .data_dir
+                .join("files")
+                .join(job.id().to_string());
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(DownloadError::Keep)?;
+            let (path, filename) = download::deliverable(video, job).await?;
+            let output = dir.join(path.file_name().unwrap_or("video.mp4".as_ref()));
+            storage::move_file(&path, &output)
+                .await
+                .map_err(DownloadError::Keep)?;
+            let location = format!("/api/jobs/{}/file", job.id());
+            job.set_result(&filename, &location, Some(output));
+        }
+    }
+    info!("Finished {}", video.filename);
+
+    Ok(())
+}
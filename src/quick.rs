@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::quotas::{Caller, QuotasConfig};
+
+/// Who a `/api/quick` token submits jobs as, for quotas and the history.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuickToken {
+    pub user: String,
+    /// Quota role; the default role when unset.
+    pub role: Option<String>,
+}
+
+/// The caller `token` stands for, if it is one of `tokens`.
+pub fn caller(
+    tokens: &HashMap<String, QuickToken>,
+    quotas: &QuotasConfig,
+    token: &str,
+) -> Option<Caller> {
+    let (_, quick) = tokens
+        .iter()
+        .find(|(known, _)| constant_time_eq(known.as_bytes(), token.as_bytes()))?;
+    Some(Caller {
+        user: quick.user.clone(),
+        role: quick
+            .role
+            .clone()
+            .unwrap_or_else(|| quotas.default_role.clone()),
+    })
+}
+
+/// Compares without returning early, so response times don't reveal how
+/// much of a token was right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+use serde::Deserialize;
+
+use crate::db::{Db, DbError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum QuotaError {
+    #[error("quota exceeded: {0}")]
+    Exceeded(String),
+    #[error("failed to load usage")]
+    Db(#[from] DbError),
+}
+
+/// Per-role download limits. There is no built-in login: users and their
+/// groups come from headers set by an authenticating reverse proxy
+/// (Authelia, authentik, oauth2-proxy, ...).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct QuotasConfig {
+    /// Header carrying the authenticated user name.
+    pub user_header: String,
+    /// Header carrying the user's comma-separated groups.
+    pub groups_header: String,
+    /// Role used when none of the user's groups has one.
+    pub default_role: String,
+    pub roles: HashMap<String, RoleQuota>,
+    /// Roles allowed to run jobs in debug mode and read their output. They
+    /// have no limits unless also listed in `roles`.
+    pub admin_roles: Vec<String>,
+}
+
+impl Default for QuotasConfig {
+    fn default() -> Self {
+        Self {
+            user_header: "Remote-User".to_string(),
+            groups_header: "Remote-Groups".to_string(),
+            default_role: "user".to_string(),
+            roles: HashMap::new(),
+            admin_roles: Vec::new(),
+        }
+    }
+}
+
+/// Limits for one role; unset limits are unlimited. Days and months are
+/// counted in UTC.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RoleQuota {
+    pub daily_jobs: Option<u64>,
+    pub daily_mb: Option<u64>,
+    pub monthly_jobs: Option<u64>,
+    pub monthly_mb: Option<u64>,
+}
+
+/// Who submitted a request, as reported by the proxy.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user: String,
+    pub role: String,
+}
+
+impl QuotasConfig {
+    /// Identifies the caller, or `None` for requests that did not come
+    /// through an authenticating proxy; those are not limited. A user in
+    /// several groups with roles gets the most generous of them.
+    pub fn caller(&self, headers: &HeaderMap) -> Option<Caller> {
+        let user = header_str(headers, &self.user_header)?.trim();
+        if user.is_empty() {
+            return None;
+        }
+        let mut role: Option<&str> = None;
+        for group in self.groups(headers).filter(|group| {
+            self.roles.contains_key(*group) || self.admin_roles.iter().any(|role| role == group)
+        }) {
+            if role.is_none_or(|role| self.allowance(group) > self.allowance(role)) {
+                role = Some(group);
+            }
+        }
+        let role = role.unwrap_or(&self.default_role);
+
+        Some(Caller {
+            user: user.to_string(),
+            role: role.to_string(),
+        })
+    }
+
+    /// Whether the request comes from a user in one of `admin_roles`, among
+    /// any of their groups rather than just the one picked for quotas.
+    pub fn is_admin(&self, headers: &HeaderMap) -> bool {
+        self.caller(headers).is_some()
+            && self
+                .groups(headers)
+                .any(|group| self.admin_roles.iter().any(|role| role == group))
+    }
+
+    /// How generous `role`'s limits are, for picking between a user's
+    /// roles: monthly limits count before daily ones and sizes before job
+    /// counts. Unset limits, and admin roles without a section, are
+    /// unlimited.
+    fn allowance(&self, role: &str) -> [u64; 4] {
+        let Some(quota) = self.roles.get(role) else {
+            return [u64::MAX; 4];
+        };
+        [
+            quota.monthly_mb,
+            quota.monthly_jobs,
+            quota.daily_mb,
+            quota.daily_jobs,
+        ]
+        .map(|limit| limit.unwrap_or(u64::MAX))
+    }
+
+    fn groups<'a>(&self, headers: &'a HeaderMap) -> impl Iterator<Item = &'a str> {
+        header_str(headers, &self.groups_header)
+            .into_iter()
+            .flat_map(|groups| groups.split(','))
+            .map(str::trim)
+    }
+
+    /// Checks the caller's usage so far against their role's limits.
+    pub fn check(&self, db: &Db, caller: &Caller) -> Result<(), QuotaError> {
+        let Some(quota) = self.roles.get(&caller.role) else {
+            return Ok(());
+        };
+
+        for (period, jobs_limit, mb_limit) in [
+            (Period::Day, quota.daily_jobs, quota.daily_mb),
+            (Period::Month, quota.monthly_jobs, quota.monthly_mb),
+        ] {
+            if jobs_limit.is_none() && mb_limit.is_none() {
+                continue;
+            }
+            let usage = db.user_usage(&caller.user, period)?;
+            if let Some(limit) = jobs_limit
+                && usage.jobs >= limit
+            {
+                return Err(QuotaError::Exceeded(format!(
+                    "{} downloads per {} allowed for role {}",
+                    limit,
+                    period.name(),
+                    caller.role
+                )));
+            }
+            if let Some(limit) = mb_limit
+                && usage.bytes >= limit * 1024 * 1024
+            {
+                return Err(QuotaError::Exceeded(format!(
+                    "{} MiB per {} allowed for role {}",
+                    limit,
+                    period.name(),
+                    caller.role
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Calendar period a limit applies to.
+#[derive(Debug, Clone, Copy)]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    fn name(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Month => "month",
+        }
+    }
+
+    /// SQLite date modifier for the start of the current period.
+    pub fn sqlite_start(self) -> &'static str {
+        match self {
+            Period::Day => "start of day",
+            Period::Month => "start of month",
+        }
+    }
+}
+
+/// Jobs and downloaded bytes of one user within a period.
+#[derive(Debug, Default)]
+pub struct Usage {
+    pub jobs: u64,
+    pub bytes: u64,
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
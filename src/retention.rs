@@ -0,0 +1,133 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{info, instrument, warn};
+
+use crate::{AppState, jobs::Job, subtitles, trash};
+
+/// Sidecars the library destination may write next to a file, besides
+/// subtitles.
+const SIDECARS: &[&str] = &[
+    "nfo",
+    "info.json",
+    "description",
+    "comments.json",
+    "chat.json",
+];
+
+/// Spawns the task deleting kept files past `LIBRARY_MAX_AGE_DAYS` or over
+/// `LIBRARY_MAX_SIZE_MB`, and purging the trash. Does nothing when neither
+/// limit is set and the trash is off.
+pub fn spawn(state: &AppState) {
+    let config = &state.config;
+    if config.library_max_age_days.is_none()
+        && config.library_max_size_mb.is_none()
+        && config.trash_retention_days == 0
+    {
+        return;
+    }
+    let state = state.clone();
+    let period = Duration::from_secs(state.config.retention_interval_mins * 60);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            sweep(&state).await;
+            trash::purge(&state).await;
+        }
+    });
+}
+
+/// Deletes unpinned kept files finished before the age limit, then the
+/// least recently served ones until the total fits the size budget. Pinned
+/// files count towards the budget but are never deleted.
+#[instrument(skip(state))]
+async fn sweep(state: &AppState) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let cutoff = state
+        .config
+        .library_max_age_days
+        .map(|days| now.saturating_sub(days * 24 * 60 * 60));
+    let budget = state.config.library_max_size_mb.map(|mb| mb * 1024 * 1024);
+    if cutoff.is_none() && budget.is_none() {
+        return;
+    }
+
+    let mut kept = Vec::new();
+    for job in state.jobs.kept() {
+        let Some(output) = &job.output else { continue };
+        match tokio::fs::metadata(output).await {
+            Ok(metadata) => kept.push((job, metadata.len())),
+            // Deleted by hand, so forget it too.
+            Err(_) => state.jobs.clear_output(job.id),
+        }
+    }
+
+    let mut total: u64 = kept.iter().map(|(_, size)| size).sum();
+    for (job, size) in kept {
+        if job.pinned {
+            continue;
+        }
+        let expired = cutoff.zip(job.finished_at).is_some_and(|(c, f)| f < c);
+        let over_budget = budget.is_some_and(|budget| total > budget);
+        if !expired && !over_budget {
+            continue;
+        }
+        info!(
+            "Deleting kept file of job {} ({})",
+            job.id,
+            if expired {
+                "expired"
+            } else {
+                "over size budget"
+            }
+        );
+        remove(state, &job).await;
+        total -= size;
+    }
+}
+
+/// Deletes a job's kept file with its sidecars and cached HLS segments.
+pub(crate) async fn remove(state: &AppState, job: &Job) {
+    let Some(output) = &job.output else { return };
+    let files_dir = state.config.data_dir.join("files").join(job.id.to_string());
+    if output.starts_with(&files_dir) {
+        if let Err(e) = tokio::fs::remove_dir_all(&files_dir).await {
+            warn!("Failed to delete {:?}: {:?}", files_dir, e);
+            return;
+        }
+    } else {
+        if let Err(e) = tokio::fs::remove_file(output).await {
+            warn!("Failed to delete {:?}: {:?}", output, e);
+            return;
+        }
+        remove_sidecars(output, &job.options.subs).await;
+    }
+    state.hls.remove(job.id).await;
+    state.jobs.clear_output(job.id);
+}
+
+async fn remove_sidecars(video: &Path, langs: &[String]) {
+    for sidecar in sidecars(video, langs) {
+        let _ = tokio::fs::remove_file(sidecar).await;
+    }
+}
+
+/// Paths the library destination may have written sidecars of `video` to,
+/// whether or not they exist.
+pub(crate) fn sidecars(video: &Path, langs: &[String]) -> Vec<PathBuf> {
+    let mut exts: Vec<String> = SIDECARS.iter().map(|s| s.to_string()).collect();
+    for lang in langs {
+        for ext in subtitles::SUBTITLE_EXTS {
+            exts.push(format!("{}.{}", lang, ext));
+        }
+    }
+    exts.into_iter()
+        .map(|ext| video.with_extension(ext))
+        .collect()
+}
@@ -0,0 +1,196 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    process::Command,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{jobs::JobHandle, trash};
+
+/// Size of the chunks a file is streamed to clamd in.
+const CHUNK: usize = 64 * 1024;
+
+/// Scanner every download is checked with before it is delivered.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "RawScanConfig")]
+pub enum ScanConfig {
+    /// A clamd socket: the path of a Unix socket, or `host:port`.
+    Clamd(String),
+    /// A command passed the file path as its last argument, exiting with 0
+    /// when the file is clean.
+    Command(Vec<String>),
+}
+
+#[derive(Deserialize)]
+struct RawScanConfig {
+    clamd: Option<String>,
+    command: Option<Vec<String>>,
+}
+
+impl TryFrom<RawScanConfig> for ScanConfig {
+    type Error = String;
+
+    fn try_from(raw: RawScanConfig) -> Result<Self, Self::Error> {
+        match (raw.clamd, raw.command) {
+            (Some(clamd), None) => Ok(Self::Clamd(clamd)),
+            (None, Some(command)) if command.is_empty() => Err("scan command is empty".to_string()),
+            (None, Some(command)) => Ok(Self::Command(command)),
+            _ => Err("content scan needs exactly one of `clamd` or `command`".to_string()),
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScanError {
+    #[error("file flagged by content scan: {0}")]
+    Flagged(String),
+    #[error("failed to scan file with clamd")]
+    Clamd(#[source] io::Error),
+    #[error("clamd could not scan the file: {0}")]
+    ClamdReply(String),
+    #[error("scan command is empty")]
+    Empty,
+    #[error("failed to run scan command")]
+    Command(#[source] io::Error),
+    #[error("scan command exited with no status code")]
+    ExitNoCode,
+    #[error("failed to quarantine flagged file")]
+    Quarantine(#[source] io::Error),
+}
+
+/// Scans a finished download. A flagged file is moved to
+/// `quarantine/{job id}` under `data_dir` for an admin to look at, and the
+/// job fails.
+#[instrument(skip(config, job))]
+pub async fn scan(
+    config: &ScanConfig,
+    data_dir: &Path,
+    path: &Path,
+    job: &JobHandle,
+) -> Result<(), ScanError> {
+    let verdict = match config {
+        ScanConfig::Clamd(address) => clamd(address, path).await?,
+        ScanConfig::Command(command) => run_command(command, path, job).await?,
+    };
+    let Some(reason) = verdict else {
+        job.log_output("Content scan found nothing");
+        return Ok(());
+    };
+
+    let dir = quarantine_dir(data_dir, job);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(ScanError::Quarantine)?;
+    let name = path.file_name().unwrap_or("download".as_ref());
+    trash::move_file(path, &dir.join(name))
+        .await
+        .map_err(ScanError::Quarantine)?;
+    warn!("Quarantined download of job {}: {}", job.id(), reason);
+    job.log_output(&format!(
+        "Content scan flagged the file ({}), quarantined",
+        reason
+    ));
+
+    Err(ScanError::Flagged(reason))
+}
+
+fn quarantine_dir(data_dir: &Path, job: &JobHandle) -> PathBuf {
+    data_dir.join("quarantine").join(job.id().to_string())
+}
+
+/// Streams the file to clamd with `INSTREAM`. Returns the signature it
+/// found, if any.
+async fn clamd(address: &str, path: &Path) -> Result<Option<String>, ScanError> {
+    let reply = if address.starts_with('/') {
+        instream_unix(address, path).await
+    } else {
+        match TcpStream::connect(address).await {
+            Ok(stream) => instream(stream, path).await,
+            Err(e) => Err(e),
+        }
+    }
+    .map_err(ScanError::Clamd)?;
+
+    // e.g. `stream: OK`, `stream: Eicar-Test-Signature FOUND`, or
+    // `INSTREAM size limit exceeded. ERROR`.
+    let reply = reply.trim_end_matches('\0').trim();
+    debug!("clamd replied {:?}", reply);
+    let verdict = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if let Some(signature) = verdict.strip_suffix(" FOUND") {
+        Ok(Some(signature.to_string()))
+    } else if verdict == "OK" {
+        Ok(None)
+    } else {
+        Err(ScanError::ClamdReply(reply.to_string()))
+    }
+}
+
+#[cfg(unix)]
+async fn instream_unix(socket: &str, path: &Path) -> io::Result<String> {
+    let stream = tokio::net::UnixStream::connect(socket).await?;
+    instream(stream, path).await
+}
+
+#[cfg(not(unix))]
+async fn instream_unix(_socket: &str, _path: &Path) -> io::Result<String> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets are not supported on this platform",
+    ))
+}
+
+async fn instream(
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    path: &Path,
+) -> io::Result<String> {
+    let mut file = File::open(path).await?;
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buf = vec![0; CHUNK];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        stream.write_all(&buf[..read]).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    Ok(String::from_utf8_lossy(&reply).into_owned())
+}
+
+/// Runs the scan command, capturing its output into the job log. Returns
+/// why it flagged the file, if it did.
+async fn run_command(
+    command: &[String],
+    path: &Path,
+    job: &JobHandle,
+) -> Result<Option<String>, ScanError> {
+    let (program, args) = command.split_first().ok_or(ScanError::Empty)?;
+    let output = Command::new(program)
+        .args(args)
+        .arg(path)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(ScanError::Command)?;
+
+    debug!("Scan command status: {}", output.status);
+    job.log_output(&String::from_utf8_lossy(&output.stdout));
+    job.log_output(&String::from_utf8_lossy(&output.stderr));
+
+    match output.status.code() {
+        Some(0) => Ok(None),
+        Some(code) => Ok(Some(format!("scanner exited with status code {}", code))),
+        None => Err(ScanError::ExitNoCode),
+    }
+}
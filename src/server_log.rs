@@ -0,0 +1,85 @@
+//! The server's own tracing output, tailed live by
+//! `GET /api/admin/logs/stream`.
+
+use std::{
+    fmt::{self, Write},
+    sync::LazyLock,
+};
+
+use tokio::sync::broadcast;
+use tracing::{
+    Event, Level, Subscriber,
+    field::{Field, Visit},
+};
+use tracing_subscriber::{
+    fmt::{
+        format::Writer,
+        time::{FormatTime, SystemTime},
+    },
+    layer::{Context, Layer},
+};
+
+/// Events buffered for live subscribers that fall behind.
+const SUBSCRIBER_BUFFER: usize = 1024;
+
+/// One event, formatted like the console output.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub level: Level,
+    pub line: String,
+}
+
+/// Tracing is process-wide, and so is its tail.
+static SENDER: LazyLock<broadcast::Sender<LogRecord>> =
+    LazyLock::new(|| broadcast::channel(SUBSCRIBER_BUFFER).0);
+
+/// Layer copying events to [`subscribe`]rs. Add it to the tracing subscriber
+/// for `/api/admin/logs/stream` to have anything to show; it does nothing
+/// while nobody is listening.
+pub fn layer() -> ServerLogLayer {
+    ServerLogLayer
+}
+
+/// Receives events logged from now on, at every level.
+pub fn subscribe() -> broadcast::Receiver<LogRecord> {
+    SENDER.subscribe()
+}
+
+pub struct ServerLogLayer;
+
+impl<S: Subscriber> Layer<S> for ServerLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if SENDER.receiver_count() == 0 {
+            return;
+        }
+        let metadata = event.metadata();
+        let mut line = String::new();
+        let _ = SystemTime.format_time(&mut Writer::new(&mut line));
+        let _ = write!(line, " {:>5} {}:", metadata.level(), metadata.target());
+        event.record(&mut LineVisitor(&mut line));
+        // Only fails when the last subscriber just left.
+        let _ = SENDER.send(LogRecord {
+            level: *metadata.level(),
+            line,
+        });
+    }
+}
+
+/// Appends the message, then the other fields as `name=value`.
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {:?}", value),
+            name => write!(self.0, " {}={:?}", name, value),
+        };
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {}", value),
+            name => write!(self.0, " {}={:?}", name, value),
+        };
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tracing::info;
+use uuid::Uuid;
+
+/// File in the data directory holding the generated signing key when none is
+/// configured.
+const SHARE_KEY_FILE: &str = "share_key";
+
+/// Length of a generated signing key, in bytes.
+const KEY_LEN: usize = 32;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ShareError {
+    #[error("invalid share key")]
+    InvalidKey,
+    #[error("failed to read or write share key file")]
+    KeyFile(#[source] std::io::Error),
+}
+
+/// Query string of a share link.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Signature {
+    /// Unix timestamp in seconds after which the link stops working.
+    pub expires: u64,
+    /// Base64url HMAC-SHA256 of the job id and `expires`.
+    pub sig: String,
+}
+
+impl Signature {
+    /// Path of the share link for `id`.
+    pub fn path(&self, id: Uuid) -> String {
+        format!("/share/{}?expires={}&sig={}", id, self.expires, self.sig)
+    }
+
+    /// Full share link for `id` on the instance at `base_url`.
+    pub fn url(&self, base_url: &str, id: Uuid) -> String {
+        format!("{}{}", base_url.trim_end_matches('/'), self.path(id))
+    }
+}
+
+/// Signs links to kept files that work without access to the rest of the
+/// API, until they expire.
+pub struct Shares {
+    key: Vec<u8>,
+}
+
+impl Shares {
+    /// Uses `secret` if given, otherwise a key generated on first start and
+    /// kept in `data_dir` so links stay valid across restarts.
+    pub fn new(secret: Option<&str>, data_dir: &Path) -> Result<Self, ShareError> {
+        let key = match secret {
+            Some(secret) if !secret.is_empty() => secret.as_bytes().to_vec(),
+            Some(_) => return Err(ShareError::InvalidKey),
+            None => load_or_generate_key(&data_dir.join(SHARE_KEY_FILE))?,
+        };
+        Ok(Self { key })
+    }
+
+    /// Signs a link to `id`'s file, valid for `ttl`.
+    pub fn sign(&self, id: Uuid, ttl: Duration) -> Signature {
+        let expires = unix_now() + ttl.as_secs();
+        let sig = Base64UrlUnpadded::encode_string(&self.mac(id, expires).finalize().into_bytes());
+        Signature { expires, sig }
+    }
+
+    /// Whether `signature` was made for `id` by this server and hasn't
+    /// expired.
+    pub fn verify(&self, id: Uuid, signature: &Signature) -> bool {
+        if signature.expires <= unix_now() {
+            return false;
+        }
+        let Ok(sig) = Base64UrlUnpadded::decode_vec(&signature.sig) else {
+            return false;
+        };
+        self.mac(id, signature.expires).verify_slice(&sig).is_ok()
+    }
+
+    fn mac(&self, id: Uuid, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(id.as_bytes());
+        mac.update(&expires.to_be_bytes());
+        mac
+    }
+}
+
+/// A random one-time download token.
+pub fn new_token() -> String {
+    let token: [u8; KEY_LEN] = rand::random();
+    Base64UrlUnpadded::encode_string(&token)
+}
+
+fn load_or_generate_key(path: &Path) -> Result<Vec<u8>, ShareError> {
+    match std::fs::read_to_string(path) {
+        Ok(key) => Base64UrlUnpadded::decode_vec(key.trim()).map_err(|_| ShareError::InvalidKey),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            info!("Generating share key in {:?}", path);
+            let key: [u8; KEY_LEN] = rand::random();
+            write_private(path, Base64UrlUnpadded::encode_string(&key).as_bytes())
+                .map_err(ShareError::KeyFile)?;
+            Ok(key.to_vec())
+        }
+        Err(e) => Err(ShareError::KeyFile(e)),
+    }
+}
+
+/// Creates `path` readable and writable only by its owner, since anyone who
+/// can read the key can sign links.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
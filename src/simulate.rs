@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, instrument};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    download::{self, DownloadError, JobDir, PLAYLIST_LIST},
+    hooks,
+    jobs::JobOptions,
+    playlist, sites,
+    storage::sanitize_filename,
+    tee,
+    video::{DownloadedVideo, VideoInfo},
+};
+
+/// What downloading a URL would do, worked out without downloading it.
+#[derive(Debug, Serialize)]
+pub struct Simulation {
+    /// URL yt-dlp runs on, after rewrite rules and channel tabs.
+    pub url: String,
+    /// The download command, program first, with a throwaway job directory.
+    /// A share of `MAX_DOWNLOAD_RATE_KB` isn't included, since it depends on
+    /// the downloads running at the time.
+    pub command: Vec<String>,
+    /// Name the file would be delivered under. Transcode profiles, album
+    /// splits and sidecar bundles may still change its extension.
+    pub filename: String,
+    /// What yt-dlp picked for each video; a single one unless downloading a
+    /// playlist.
+    pub entries: Vec<Entry>,
+    /// Expected size of all entries, if known for each.
+    pub bytes: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Entry {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    /// yt-dlp's format code, e.g. `137+140` for merged video and audio.
+    pub format_id: Option<String>,
+    /// Description of the chosen formats.
+    pub format: Option<String>,
+    pub ext: Option<String>,
+    pub bytes: Option<u64>,
+    /// Whether `bytes` is yt-dlp's estimate from the bitrate rather than a
+    /// size reported by the site.
+    pub approximate: bool,
+}
+
+/// The fields of yt-dlp's `--dump-json` output describing the chosen format.
+#[derive(Deserialize)]
+struct DumpedEntry {
+    id: Option<String>,
+    title: Option<String>,
+    format_id: Option<String>,
+    format: Option<String>,
+    ext: Option<String>,
+    filesize: Option<f64>,
+    filesize_approx: Option<f64>,
+}
+
+impl From<DumpedEntry> for Entry {
+    fn from(entry: DumpedEntry) -> Self {
+        let (bytes, approximate) = match (entry.filesize, entry.filesize_approx) {
+            (Some(size), _) => (Some(size as u64), false),
+            (None, Some(size)) => (Some(size as u64), true),
+            (None, None) => (None, false),
+        };
+        Self {
+            id: entry.id,
+            title: entry.title,
+            format_id: entry.format_id,
+            format: entry.format,
+            ext: entry.ext,
+            bytes,
+            approximate,
+        }
+    }
+}
+
+/// Builds the command a download of `url` with `options` would run and has
+/// yt-dlp resolve it with `--dump-json`, which selects formats and names
+/// files without downloading anything. `streamed` is for a download
+/// streamed back from `/api/download`, which may use fragmented MP4.
+#[instrument(skip(state, options))]
+pub async fn simulate(
+    state: &AppState,
+    url: &str,
+    options: &JobOptions,
+    dest: Option<&str>,
+    streamed: bool,
+) -> Result<Simulation, DownloadError> {
+    let config = &state.config;
+    let storage = download::resolve_storage(state, dest)?;
+    let fragmented = streamed && storage.is_none() && tee::eligible(state, options, url);
+    let requested = url;
+    let url = hooks::rewrite_url(&config.hooks.url_rules, url).map_err(|reason| {
+        info!("Rejected URL {}: {}", requested, reason);
+        DownloadError::UrlRejected(reason)
+    })?;
+    download::resolve_profile(state, options)?;
+    download::check_options(&url, options)?;
+    let url = options
+        .tab
+        .and_then(|tab| playlist::channel_tab_url(&url, tab))
+        .unwrap_or(url);
+
+    let plugins = state.plugins.for_url(&url);
+    let mut extra_args = download::ytdlp_args(state, &plugins, options, &url, false, fragmented);
+    let job_dir = JobDir::create(&config.tmp_dir, Uuid::new_v4()).await?;
+    let dir = job_dir.path();
+    if options.is_playlist() {
+        extra_args.extend(download::playlist_args(
+            config,
+            options,
+            &dir.join(PLAYLIST_LIST),
+        ));
+    }
+    let limit_kb = sites::for_url(&config.sites, &url).and_then(|site| site.rate_limit_kb);
+    let output = dir.join(download::output_template(options));
+    let mut command = download::video_command(&url, &extra_args, &output, limit_kb, config, dir);
+    let std_command = command.as_std();
+    let command_line = std::iter::once(std_command.get_program())
+        .chain(std_command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    let probe = command.arg("--dump-json").kill_on_drop(true).output();
+    let (probe, title) = if options.is_playlist() {
+        (probe.await, None)
+    } else {
+        let restrict = download::restrict_filenames(state, options);
+        let (probe, title) = tokio::join!(probe, download::get_video_title(&url, restrict, config));
+        (probe, Some(title))
+    };
+    let probe = probe.map_err(DownloadError::VideoCommand)?;
+    match probe.status.code() {
+        Some(0) => {}
+        Some(code) => {
+            let stderr = String::from_utf8_lossy(&probe.stderr);
+            return Err(download::classify_failure(&stderr, code));
+        }
+        None => return Err(DownloadError::VideoExitNoCode),
+    }
+
+    let mut infos = Vec::new();
+    let mut entries = Vec::new();
+    let stdout = String::from_utf8_lossy(&probe.stdout);
+    for line in stdout.lines().filter(|line| !line.trim().is_empty()) {
+        let value: serde_json::Value =
+            serde_json::from_str(line).map_err(DownloadError::InfoJson)?;
+        infos.push(VideoInfo::deserialize(&value).map_err(DownloadError::InfoJson)?);
+        entries.push(Entry::from(
+            DumpedEntry::deserialize(&value).map_err(DownloadError::InfoJson)?,
+        ));
+    }
+    if entries.is_empty() {
+        return Err(DownloadError::EmptyPlaylist);
+    }
+
+    let (filename, info) = match title {
+        // Named like fetched playlists, after the playlist itself.
+        None => {
+            let first = &infos[0];
+            let name = sanitize_filename(first.playlist_title.as_deref().unwrap_or("playlist"));
+            let ext = if options.audiobook { "m4b" } else { "zip" };
+            let info = VideoInfo {
+                title: first.playlist_title.clone(),
+                uploader: first.uploader.clone(),
+                ..VideoInfo::default()
+            };
+            (format!("{}.{}", name, ext), info)
+        }
+        Some(title) => {
+            let mut filename = title.unwrap_or_else(|e| {
+                error!("Failed to get title, defaulting: {:?}", e);
+                "video".to_string()
+            });
+            // Merged audio tracks keep the container yt-dlp picked.
+            if !options.audio.is_empty()
+                && let Some(ext) = &entries[0].ext
+            {
+                filename = Path::new(&filename)
+                    .with_extension(ext)
+                    .to_string_lossy()
+                    .into_owned();
+            }
+            (filename, infos.swap_remove(0))
+        }
+    };
+    let video = DownloadedVideo {
+        path: PathBuf::new(),
+        filename,
+        info,
+        sidecars: Vec::new(),
+    };
+
+    Ok(Simulation {
+        url,
+        command: command_line,
+        filename: download::delivered_filename(state, options, &video),
+        bytes: entries.iter().map(|entry| entry.bytes).sum(),
+        entries,
+    })
+}
@@ -0,0 +1,77 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use serde::Deserialize;
+use url::Url;
+
+use crate::config::Config;
+
+/// Default yt-dlp format sort: best resolution, preferring MP4 with M4A audio.
+pub const DEFAULT_FORMAT_SORT: &str = "res,ext:mp4:m4a";
+
+/// yt-dlp settings for one site, from a `[sites."example.com"]` section of
+/// the config file. They apply to the domain and its subdomains.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SiteConfig {
+    /// yt-dlp `-S` format sort used instead of the default.
+    pub format_sort: Option<String>,
+    /// Download rate cap in KiB/s, on top of the server-wide limit.
+    pub rate_limit_kb: Option<u64>,
+    /// Netscape-format cookies file passed with `--cookies`.
+    pub cookies: Option<PathBuf>,
+    /// Further yt-dlp arguments, e.g. `["--extractor-args", "twitch:..."]`.
+    pub extra_args: Vec<String>,
+}
+
+impl SiteConfig {
+    /// Arguments for every yt-dlp run on this site, lookups included.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(cookies) = &self.cookies {
+            args.push("--cookies".to_string());
+            args.push(cookies.display().to_string());
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+
+    /// Checks what would otherwise only fail once a download runs.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if let Some(cookies) = &self.cookies
+            && !cookies.is_file()
+        {
+            return Err("cookies file does not exist");
+        }
+        if self.format_sort.as_deref().is_some_and(str::is_empty) {
+            return Err("format_sort is empty");
+        }
+        if self.rate_limit_kb == Some(0) {
+            return Err("rate_limit_kb must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// The `-S` format sort for downloading `url`.
+pub fn format_sort<'a>(config: &'a Config, url: &str) -> &'a str {
+    for_url(&config.sites, url)
+        .and_then(|site| site.format_sort.as_deref())
+        .unwrap_or(DEFAULT_FORMAT_SORT)
+}
+
+/// The settings for `url`'s host, if any. The most specific domain wins, so
+/// `clips.twitch.tv` can differ from `twitch.tv`.
+pub fn for_url<'a>(sites: &'a HashMap<String, SiteConfig>, url: &str) -> Option<&'a SiteConfig> {
+    let host = Url::parse(url).ok()?.host_str()?.to_ascii_lowercase();
+    sites
+        .iter()
+        .filter(|(domain, _)| {
+            let domain = domain.to_ascii_lowercase();
+            host == domain
+                || host
+                    .strip_suffix(&domain)
+                    .is_some_and(|rest| rest.ends_with('.'))
+        })
+        .max_by_key(|(domain, _)| domain.len())
+        .map(|(_, site)| site)
+}
@@ -0,0 +1,123 @@
+use std::{io, path::Path};
+
+use tokio::{fs::File, io::AsyncReadExt};
+
+/// Bytes read from the start of a file to tell its container, enough for a
+/// second MPEG-TS sync byte.
+const HEADER_LEN: usize = 256;
+
+/// MPEG-TS packets start with this byte, every 188 bytes.
+const TS_SYNC: u8 = 0x47;
+const TS_PACKET_LEN: usize = 188;
+
+/// Container formats told apart by their first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Mp4,
+    QuickTime,
+    WebM,
+    Matroska,
+    Ogg,
+    Mp3,
+    Flac,
+    Wav,
+    Avi,
+    Flv,
+    MpegTs,
+}
+
+impl Container {
+    /// The container a file starting with `header` is in, if it's one of
+    /// these.
+    pub fn sniff(header: &[u8]) -> Option<Self> {
+        if header.get(4..8) == Some(b"ftyp") {
+            return Some(match header.get(8..12) {
+                Some(b"qt  ") => Self::QuickTime,
+                _ => Self::Mp4,
+            });
+        }
+        if header.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+            // The EBML header names the document type early on.
+            let webm = header.windows(4).any(|window| window == b"webm");
+            return Some(if webm { Self::WebM } else { Self::Matroska });
+        }
+        if header.starts_with(b"RIFF") {
+            return match header.get(8..12) {
+                Some(b"WAVE") => Some(Self::Wav),
+                Some(b"AVI ") => Some(Self::Avi),
+                _ => None,
+            };
+        }
+        if header.starts_with(b"OggS") {
+            return Some(Self::Ogg);
+        }
+        if header.starts_with(b"fLaC") {
+            return Some(Self::Flac);
+        }
+        if header.starts_with(b"FLV") {
+            return Some(Self::Flv);
+        }
+        if header.starts_with(b"ID3") || is_mp3_frame(header) {
+            return Some(Self::Mp3);
+        }
+        if header.first() == Some(&TS_SYNC) && header.get(TS_PACKET_LEN) == Some(&TS_SYNC) {
+            return Some(Self::MpegTs);
+        }
+        None
+    }
+
+    /// Extension given to files in this container.
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::QuickTime => "mov",
+            Self::WebM => "webm",
+            Self::Matroska => "mkv",
+            Self::Ogg => "ogg",
+            Self::Mp3 => "mp3",
+            Self::Flac => "flac",
+            Self::Wav => "wav",
+            Self::Avi => "avi",
+            Self::Flv => "flv",
+            Self::MpegTs => "ts",
+        }
+    }
+
+    /// Whether `ext` suits a file in this container, like `m4a` for MP4.
+    pub fn fits(self, ext: &str) -> bool {
+        let fitting: &[&str] = match self {
+            Self::Mp4 => &["mp4", "m4a", "m4v", "m4b", "3gp", "3g2", "f4v", "mov"],
+            Self::QuickTime => &["mov"],
+            // WebM is a subset of Matroska.
+            Self::WebM => &["webm", "mkv", "mka"],
+            Self::Matroska => &["mkv", "mka", "mk3d"],
+            Self::Ogg => &["ogg", "oga", "ogv", "opus"],
+            Self::Mp3 => &["mp3"],
+            Self::Flac => &["flac"],
+            Self::Wav => &["wav"],
+            Self::Avi => &["avi"],
+            Self::Flv => &["flv"],
+            Self::MpegTs => &["ts", "m2ts", "mts"],
+        };
+        fitting.iter().any(|fit| fit.eq_ignore_ascii_case(ext))
+    }
+}
+
+/// Whether `header` starts with an MPEG audio layer III frame. ADTS AAC
+/// shares the sync bits but has layer 0.
+fn is_mp3_frame(header: &[u8]) -> bool {
+    match header {
+        [0xff, second, ..] => second & 0xe0 == 0xe0 && (second >> 1) & 0b11 == 0b01,
+        _ => false,
+    }
+}
+
+/// The container of the file at `path`, if it's one [`Container`] knows.
+pub async fn sniff_file(path: &Path) -> io::Result<Option<Container>> {
+    let file = File::open(path).await?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.take(HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .await?;
+    Ok(Container::sniff(&header))
+}
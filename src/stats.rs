@@ -0,0 +1,91 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use async_graphql::SimpleObject;
+use serde::Serialize;
+use url::Url;
+
+/// Number of domains listed in [`Stats::top_domains`].
+const TOP_DOMAINS: usize = 10;
+
+/// Usage summary served by `GET /api/admin/stats`.
+#[derive(Debug, Serialize, SimpleObject)]
+pub struct Stats {
+    #[serde(flatten)]
+    #[graphql(flatten)]
+    pub totals: StatsTotals,
+    /// Share of finished jobs that failed, between 0 and 1.
+    pub failure_rate: f64,
+    pub per_day: Vec<DayStats>,
+    pub top_domains: Vec<DomainStats>,
+    /// Size of yt-dlp's cache directory.
+    pub ytdlp_cache_bytes: u64,
+}
+
+#[derive(Debug, Serialize, SimpleObject)]
+pub struct StatsTotals {
+    pub jobs: u64,
+    pub completed: u64,
+    pub failed: u64,
+    /// Size of the files produced by yt-dlp.
+    pub bytes_downloaded: u64,
+    /// Bytes sent to clients, including partial transfers.
+    pub bytes_served: u64,
+    /// From submission to completion, so it includes time spent queued.
+    pub average_duration_secs: Option<f64>,
+}
+
+#[derive(Debug, Serialize, SimpleObject)]
+pub struct DayStats {
+    /// UTC date, `YYYY-MM-DD`.
+    pub date: String,
+    pub jobs: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub bytes_downloaded: u64,
+}
+
+#[derive(Debug, Serialize, SimpleObject)]
+pub struct DomainStats {
+    pub domain: String,
+    pub jobs: u64,
+}
+
+impl Stats {
+    pub fn new(totals: StatsTotals, per_day: Vec<DayStats>, urls: &[String]) -> Self {
+        let finished = totals.completed + totals.failed;
+        let failure_rate = match finished {
+            0 => 0.0,
+            _ => totals.failed as f64 / finished as f64,
+        };
+
+        Self {
+            totals,
+            failure_rate,
+            per_day,
+            top_domains: top_domains(urls),
+            ytdlp_cache_bytes: 0,
+        }
+    }
+}
+
+fn top_domains(urls: &[String]) -> Vec<DomainStats> {
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for url in urls {
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|u| u.host_str().map(String::from))
+        else {
+            continue;
+        };
+        let domain = host.strip_prefix("www.").unwrap_or(&host).to_string();
+        *counts.entry(domain).or_default() += 1;
+    }
+
+    let mut domains: Vec<DomainStats> = counts
+        .into_iter()
+        .map(|(domain, jobs)| DomainStats { domain, jobs })
+        .collect();
+    domains.sort_by_key(|d| (Reverse(d.jobs), d.domain.clone()));
+    domains.truncate(TOP_DOMAINS);
+    domains
+}
@@ -0,0 +1,294 @@
+mod ftp;
+mod library;
+mod rclone;
+mod s3;
+mod sftp;
+mod webdav;
+
+use std::{
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::{
+    fs::File,
+    io::{AsyncRead, ReadBuf},
+};
+use unicode_normalization::{UnicodeNormalization, char::is_combining_mark};
+use urlencoding::encode;
+
+use crate::{
+    jobs::{JobHandle, JobStage},
+    video::DownloadedVideo,
+};
+
+pub use ftp::{FtpConfig, FtpStorage};
+pub use library::{LibraryConfig, LibraryStorage};
+pub use rclone::{RcloneConfig, RcloneStorage};
+pub use s3::{S3Config, S3Storage};
+pub use sftp::{SftpConfig, SftpStorage};
+pub use webdav::{WebdavConfig, WebdavStorage};
+
+#[derive(thiserror::Error, Debug)]
+pub enum StorageError {
+    #[error("failed to open downloaded file")]
+    Open(#[source] io::Error),
+    #[error("S3 request failed")]
+    S3(#[from] ::s3::error::S3Error),
+    #[error("HTTP request failed")]
+    Http(#[from] reqwest::Error),
+    #[error("upload rejected with status code {0}")]
+    UploadStatus(u16),
+    #[error("failed to transfer file")]
+    Transfer(#[source] io::Error),
+    #[error("SSH connection failed")]
+    Ssh(#[from] russh::Error),
+    #[error("SFTP authentication failed")]
+    SftpAuth,
+    #[error("SFTP request failed")]
+    Sftp(#[from] russh_sftp::client::error::Error),
+    #[error("FTP request failed")]
+    Ftp(#[from] suppaftp::FtpError),
+    #[error("media server rejected library refresh with status code {0}")]
+    MediaServerStatus(u16),
+    #[error("failed to run rclone")]
+    RcloneCommand(#[source] io::Error),
+    #[error("rclone exited with no status code")]
+    RcloneExitNoCode,
+    #[error("rclone exited with status code {0}")]
+    RcloneExitErrorCode(i32),
+}
+
+/// A named destination from the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DestinationConfig {
+    S3(S3Config),
+    Webdav(WebdavConfig),
+    Rclone(RcloneConfig),
+    Sftp(SftpConfig),
+    Ftp(FtpConfig),
+    Library(LibraryConfig),
+}
+
+impl DestinationConfig {
+    pub fn build(&self) -> Result<Arc<dyn Storage>, StorageError> {
+        Ok(match self {
+            DestinationConfig::S3(config) => Arc::new(S3Storage::new(config)?),
+            DestinationConfig::Webdav(config) => Arc::new(WebdavStorage::new(config)?),
+            DestinationConfig::Rclone(config) => Arc::new(RcloneStorage::new(config)),
+            DestinationConfig::Sftp(config) => Arc::new(SftpStorage::new(config)),
+            DestinationConfig::Ftp(config) => Arc::new(FtpStorage::new(config)),
+            DestinationConfig::Library(config) => Arc::new(LibraryStorage::new(config)?),
+        })
+    }
+}
+
+/// Outcome of storing a finished download.
+#[derive(Debug)]
+pub enum Stored {
+    /// The client should be redirected here to fetch the file.
+    Presigned(String),
+    /// The file was pushed to a location the client already has access to.
+    Pushed(String),
+    /// The file was moved into a folder on this server.
+    Filed(PathBuf),
+}
+
+/// Where finished downloads are stored when they are not streamed straight
+/// from the job directory.
+#[async_trait]
+pub trait Storage: Send + Sync + Debug {
+    async fn store(&self, video: &DownloadedVideo, job: &JobHandle)
+    -> Result<Stored, StorageError>;
+
+    /// Whether files leave this server, which shows in the job as uploading
+    /// rather than storing.
+    fn remote(&self) -> bool {
+        true
+    }
+}
+
+/// Stores the finished download, moving the job to the uploading or storing
+/// stage first.
+pub async fn store(
+    storage: &dyn Storage,
+    video: &DownloadedVideo,
+    job: &JobHandle,
+) -> Result<Stored, StorageError> {
+    job.set_stage(if storage.remote() {
+        JobStage::Uploading
+    } else {
+        JobStage::Storing
+    });
+    storage.store(video, job).await
+}
+
+/// `Content-Disposition` value used when handing a file to the client.
+pub fn attachment_disposition(filename: &str) -> String {
+    format!("attachment; filename={}", encode(filename))
+}
+
+/// Makes a metadata value safe to use as a single path segment.
+pub fn sanitize_filename(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>()
+        .trim()
+        .trim_matches('.')
+        .to_string()
+}
+
+/// Normalizes a file name to Unicode NFC, so names from sources that use
+/// decomposed accents match what users type. `restrict` further reduces it
+/// to ASCII letters, digits, `-`, `_` and `.`, dropping accents and turning
+/// anything else into `_`, like yt-dlp's `--restrict-filenames`.
+pub fn normalize_filename(name: &str, restrict: bool) -> String {
+    if !restrict {
+        return name.nfc().collect();
+    }
+    let mut restricted = String::with_capacity(name.len());
+    for c in name.nfkd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_ascii_alphanumeric() || matches!(c, '-' | '.') {
+            restricted.push(c);
+        } else if !restricted.is_empty() && !restricted.ends_with('_') {
+            restricted.push('_');
+        }
+    }
+    let restricted = restricted.replace("_.", ".");
+    // Titles in other scripts can lose every character.
+    if restricted.is_empty() || restricted.starts_with('.') {
+        format!("video{}", restricted)
+    } else {
+        restricted
+    }
+}
+
+/// Longest accepted `filename` template, in characters.
+const MAX_FILENAME_CHARS: usize = 200;
+
+/// Placeholders a `filename` template may use.
+const FILENAME_TOKENS: &[&str] = &["title", "id", "uploader", "upload_date"];
+
+/// Checks a requested file name is a single path segment using only known
+/// placeholders. Returns the reason it isn't.
+pub fn check_filename_template(template: &str) -> Result<(), &'static str> {
+    if template.trim().trim_matches('.').is_empty() {
+        return Err("must not be empty");
+    }
+    if template.chars().count() > MAX_FILENAME_CHARS {
+        return Err("is too long");
+    }
+    if template.contains(['/', '\\']) || template.chars().any(char::is_control) {
+        return Err("must be a plain file name");
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            return Err("has an unclosed placeholder");
+        };
+        if !FILENAME_TOKENS.contains(&&rest[start + 1..start + end]) {
+            return Err("has an unknown placeholder");
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Fills in a `filename` template for `video`, appending the file's real
+/// extension unless the template already ends with it.
+pub fn render_filename(template: &str, video: &DownloadedVideo) -> String {
+    let info = &video.info;
+    let stem = Path::new(&video.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = template
+        .replace("{title}", info.title.as_deref().unwrap_or(&stem))
+        .replace("{id}", info.id.as_deref().unwrap_or(""))
+        .replace("{uploader}", info.uploader.as_deref().unwrap_or("Unknown"))
+        .replace("{upload_date}", &info.upload_date_iso().unwrap_or_default());
+    let mut name = sanitize_filename(&name);
+    if name.is_empty() {
+        name = stem;
+    }
+
+    let ext = video.ext();
+    let has_ext = name
+        .rsplit_once('.')
+        .is_some_and(|(_, name_ext)| name_ext.eq_ignore_ascii_case(ext));
+    if has_ext {
+        name
+    } else {
+        format!("{}.{}", name, ext)
+    }
+}
+
+/// How often, in bytes, upload progress is written to the job status.
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// File reader that reports how much has been read to the job's upload progress.
+struct ProgressReader {
+    inner: File,
+    job: JobHandle,
+    total: u64,
+    read: u64,
+    reported: u64,
+}
+
+impl ProgressReader {
+    async fn new(file: File, job: JobHandle) -> Result<Self, StorageError> {
+        let total = file.metadata().await.map_err(StorageError::Open)?.len();
+        job.set_upload_progress(0, total);
+
+        Ok(Self {
+            inner: file,
+            job,
+            total,
+            read: 0,
+            reported: 0,
+        })
+    }
+}
+
+impl AsyncRead for ProgressReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            let n = (buf.filled().len() - before) as u64;
+            self.read += n;
+            if n == 0 || self.read - self.reported >= PROGRESS_INTERVAL {
+                self.reported = self.read;
+                self.job.set_upload_progress(self.read, self.total);
+            }
+        }
+
+        poll
+    }
+}
+
+/// Moves a file, copying instead when the target is on another filesystem.
+pub(crate) async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to).await?;
+    tokio::fs::remove_file(from).await
+}
@@ -0,0 +1,79 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use suppaftp::{tokio::AsyncFtpStream, types::FileType};
+use tokio::fs::File;
+use tracing::{debug, instrument};
+
+use super::{ProgressReader, Storage, StorageError, Stored};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FtpConfig {
+    pub host: String,
+    #[serde(default = "FtpConfig::default_port")]
+    pub port: u16,
+    #[serde(default = "FtpConfig::default_username")]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+    /// Remote folder downloads are written into.
+    #[serde(default)]
+    pub path: String,
+}
+
+impl FtpConfig {
+    fn default_port() -> u16 {
+        21
+    }
+
+    fn default_username() -> String {
+        "anonymous".to_string()
+    }
+}
+
+/// Uploads finished downloads to a plain FTP server.
+#[derive(Debug)]
+pub struct FtpStorage {
+    config: FtpConfig,
+}
+
+impl FtpStorage {
+    pub fn new(config: &FtpConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for FtpStorage {
+    #[instrument(skip(self, video, job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        let filename = video.filename.as_str();
+        let mut ftp =
+            AsyncFtpStream::connect((self.config.host.as_str(), self.config.port)).await?;
+        ftp.login(self.config.username.as_str(), self.config.password.as_str())
+            .await?;
+        ftp.transfer_type(FileType::Binary).await?;
+        if !self.config.path.is_empty() {
+            ftp.cwd(&self.config.path).await?;
+        }
+
+        let reader = File::open(&video.path).await.map_err(StorageError::Open)?;
+        let mut reader = ProgressReader::new(reader, job.clone()).await?;
+        let written = ftp.put_file(filename, &mut reader).await?;
+        debug!("Uploaded {} bytes", written);
+        let _ = ftp.quit().await;
+
+        let folder = self.config.path.trim_matches('/');
+        let location = match folder {
+            "" => format!("ftp://{}/{}", self.config.host, filename),
+            _ => format!("ftp://{}/{}/{}", self.config.host, folder, filename),
+        };
+        Ok(Stored::Pushed(location))
+    }
+}
@@ -0,0 +1,233 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use tracing::{debug, error, instrument};
+
+use super::{Storage, StorageError, Stored, move_file, sanitize_filename};
+use crate::{
+    jobs::JobHandle,
+    video::{DownloadedVideo, VideoInfo},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LibraryConfig {
+    /// Library folder as mounted into this server.
+    pub path: PathBuf,
+    /// File name template relative to `path`. Supports `{title}`, `{uploader}`,
+    /// `{id}`, `{upload_date}`, `{year}` and `{ext}`; `/` creates folders.
+    #[serde(default = "LibraryConfig::default_naming")]
+    pub naming: String,
+    /// Write a Kodi-compatible `.nfo` next to each download.
+    #[serde(default = "LibraryConfig::default_nfo")]
+    pub nfo: bool,
+    /// Keep yt-dlp's `.info.json` next to each download.
+    #[serde(default)]
+    pub info_json: bool,
+    /// Media server to notify once a download lands in the library.
+    pub media_server: Option<MediaServerConfig>,
+}
+
+impl LibraryConfig {
+    fn default_naming() -> String {
+        "{uploader}/{title} [{id}].{ext}".to_string()
+    }
+
+    fn default_nfo() -> bool {
+        true
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MediaServerConfig {
+    Jellyfin {
+        url: String,
+        api_key: String,
+    },
+    Plex {
+        url: String,
+        token: String,
+        /// Library section id to refresh, as shown in the Plex web app URL.
+        section: u32,
+    },
+}
+
+/// Files downloads into a Jellyfin/Plex library folder with optional
+/// metadata sidecars, then asks the media server to rescan.
+#[derive(Debug)]
+pub struct LibraryStorage {
+    client: Client,
+    config: LibraryConfig,
+}
+
+impl LibraryStorage {
+    pub fn new(config: &LibraryConfig) -> Result<Self, StorageError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            config: config.clone(),
+        })
+    }
+
+    async fn refresh(&self, media_server: &MediaServerConfig) -> Result<(), StorageError> {
+        let request = match media_server {
+            MediaServerConfig::Jellyfin { url, api_key } => self
+                .client
+                .post(format!("{}/Library/Refresh", url.trim_end_matches('/')))
+                .header("X-Emby-Token", api_key),
+            MediaServerConfig::Plex {
+                url,
+                token,
+                section,
+            } => self
+                .client
+                .get(format!(
+                    "{}/library/sections/{}/refresh",
+                    url.trim_end_matches('/'),
+                    section
+                ))
+                .query(&[("X-Plex-Token", token)]),
+        };
+
+        let response = request.send().await?;
+        debug!("Library refresh status: {}", response.status());
+        if !response.status().is_success() {
+            return Err(StorageError::MediaServerStatus(response.status().as_u16()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for LibraryStorage {
+    #[instrument(skip(self, video, job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        let mut target = self
+            .config
+            .path
+            .join(render_name(&self.config.naming, video));
+        // A requested file name replaces the template's, but keeps its folders.
+        if job.options().filename.is_some() {
+            target.set_file_name(&video.filename);
+        }
+        if let Some(parent) = target.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(StorageError::Transfer)?;
+        }
+
+        move_file(&video.path, &target)
+            .await
+            .map_err(StorageError::Transfer)?;
+        if self.config.nfo {
+            tokio::fs::write(target.with_extension("nfo"), render_nfo(&video.info))
+                .await
+                .map_err(StorageError::Transfer)?;
+        }
+        for sidecar in &video.sidecars {
+            move_file(
+                &video.path.with_extension(sidecar),
+                &target.with_extension(sidecar),
+            )
+            .await
+            .map_err(StorageError::Transfer)?;
+        }
+        // Already moved as a sidecar if the job asked for it.
+        if self.config.info_json && !video.sidecars.iter().any(|s| s == "info.json") {
+            move_file(
+                &video.path.with_extension("info.json"),
+                &target.with_extension("info.json"),
+            )
+            .await
+            .map_err(StorageError::Transfer)?;
+        }
+        debug!("Added {:?} to library", target);
+
+        // The file is already in place, so a failed refresh shouldn't fail the
+        // job; the media server will pick it up on its next scheduled scan.
+        if let Some(media_server) = &self.config.media_server
+            && let Err(e) = self.refresh(media_server).await
+        {
+            error!("Failed to trigger library refresh: {:?}", e);
+        }
+
+        Ok(Stored::Filed(target))
+    }
+
+    fn remote(&self) -> bool {
+        false
+    }
+}
+
+fn render_name(template: &str, video: &DownloadedVideo) -> String {
+    let info = &video.info;
+    let title = info.title.as_deref().unwrap_or(video.filename.as_str());
+    let year = info
+        .upload_date
+        .as_deref()
+        .and_then(|date| date.get(0..4))
+        .unwrap_or("");
+
+    let name = template
+        .replace("{title}", &sanitize_filename(title))
+        .replace(
+            "{uploader}",
+            &sanitize_filename(info.uploader.as_deref().unwrap_or("Unknown")),
+        )
+        .replace("{id}", &sanitize_filename(info.id.as_deref().unwrap_or("")))
+        .replace("{upload_date}", &info.upload_date_iso().unwrap_or_default())
+        .replace("{year}", year)
+        .replace("{ext}", video.ext());
+
+    // Drop empty or dot-only segments so a template can never escape the library.
+    name.split('/')
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty() && segment.chars().any(|c| c != '.'))
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Kodi-style NFO, which both Jellyfin and Plex (with the XBMCnfo agent) read.
+fn render_nfo(info: &VideoInfo) -> String {
+    let mut nfo =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n<movie>\n");
+    let mut field = |name: &str, value: Option<&str>| {
+        if let Some(value) = value {
+            nfo.push_str(&format!("  <{0}>{1}</{0}>\n", name, escape_xml(value)));
+        }
+    };
+
+    field("title", info.title.as_deref());
+    field("plot", info.description.as_deref());
+    field("studio", info.uploader.as_deref());
+    field("premiered", info.upload_date_iso().as_deref());
+    field("aired", info.upload_date_iso().as_deref());
+    if let Some(id) = &info.id {
+        let kind = info
+            .extractor_key
+            .as_deref()
+            .unwrap_or("video")
+            .to_lowercase();
+        nfo.push_str(&format!(
+            "  <uniqueid type=\"{}\" default=\"true\">{}</uniqueid>\n",
+            escape_xml(&kind),
+            escape_xml(id)
+        ));
+    }
+
+    nfo.push_str("</movie>\n");
+    nfo
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
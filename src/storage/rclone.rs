@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use tokio::process::Command;
+use tracing::{debug, instrument};
+
+use super::{Storage, StorageError, Stored};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RcloneConfig {
+    /// Name of a remote from the rclone config, e.g. `gdrive`.
+    pub remote: String,
+    /// Folder on the remote downloads are copied into.
+    #[serde(default)]
+    pub path: String,
+    /// Extra flags passed to `rclone`, e.g. `["--config", "/etc/rclone.conf"]`.
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+/// Hands finished downloads to `rclone`, which gives access to every backend
+/// it supports without implementing them here.
+#[derive(Debug)]
+pub struct RcloneStorage {
+    config: RcloneConfig,
+}
+
+impl RcloneStorage {
+    pub fn new(config: &RcloneConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for RcloneStorage {
+    #[instrument(skip(self, video, job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        let filename = video.filename.as_str();
+        let folder = self.config.path.trim_end_matches('/');
+        let target = match folder {
+            "" => format!("{}:{}", self.config.remote, filename),
+            _ => format!("{}:{}/{}", self.config.remote, folder, filename),
+        };
+
+        // `copyto` rather than `copy` so the remote file gets the video title
+        // instead of the job directory's file name.
+        let cmd = Command::new("rclone")
+            .arg("copyto")
+            .arg("-v")
+            .args(&self.config.args)
+            .arg(&video.path)
+            .arg(&target)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(StorageError::RcloneCommand)?;
+
+        debug!("Command status: {}", cmd.status);
+        job.log_output(&String::from_utf8_lossy(&cmd.stdout));
+        job.log_output(&String::from_utf8_lossy(&cmd.stderr));
+
+        match cmd.status.code() {
+            Some(0) => Ok(Stored::Pushed(target)),
+            Some(code) => Err(StorageError::RcloneExitErrorCode(code)),
+            None => Err(StorageError::RcloneExitNoCode),
+        }
+    }
+}
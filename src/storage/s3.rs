@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use s3::{Bucket, Region, creds::Credentials, error::S3Error};
+use serde::Deserialize;
+use tokio::fs::File;
+use tracing::{debug, instrument};
+
+use super::{Storage, StorageError, Stored, attachment_disposition};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    #[serde(default = "S3Config::default_region")]
+    pub region: String,
+    /// Custom endpoint for S3-compatible services such as MinIO.
+    pub endpoint: Option<String>,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    #[serde(default)]
+    pub path_style: bool,
+    #[serde(default = "S3Config::default_presign_expiry_secs")]
+    pub presign_expiry_secs: u32,
+}
+
+impl S3Config {
+    pub fn default_region() -> String {
+        "us-east-1".to_string()
+    }
+
+    pub fn default_presign_expiry_secs() -> u32 {
+        3600
+    }
+}
+
+/// Uploads finished downloads to an S3 bucket and hands out presigned URLs.
+#[derive(Debug)]
+pub struct S3Storage {
+    bucket: Box<Bucket>,
+    presign_expiry_secs: u32,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config) -> Result<Self, StorageError> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.region.parse().map_err(S3Error::from)?,
+        };
+        let credentials = Credentials::new(
+            config.access_key_id.as_deref(),
+            config.secret_access_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .map_err(S3Error::from)?;
+
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+        if config.path_style {
+            bucket = bucket.with_path_style();
+        }
+
+        Ok(Self {
+            bucket,
+            presign_expiry_secs: config.presign_expiry_secs,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    #[instrument(skip(self, video, job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        // Audio, MKV and bundles are stored too, not just MP4.
+        let key = match video.path.extension() {
+            Some(ext) => format!("{}.{}", job.id(), ext.to_string_lossy()),
+            None => job.id().to_string(),
+        };
+        let content_type = mime_guess::from_path(&video.filename).first_or_octet_stream();
+        let mut reader = File::open(&video.path).await.map_err(StorageError::Open)?;
+        let response = self
+            .bucket
+            .put_object_stream_with_content_type(&mut reader, &key, content_type.as_ref())
+            .await?;
+        debug!("Upload status: {}", response.status_code());
+
+        let queries = HashMap::from([(
+            "response-content-disposition".to_string(),
+            attachment_disposition(&video.filename),
+        )]);
+        let url = self
+            .bucket
+            .presign_get(&key, self.presign_expiry_secs, Some(queries))
+            .await?;
+
+        Ok(Stored::Presigned(url))
+    }
+}
@@ -0,0 +1,160 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use russh::{
+    Disconnect, client,
+    keys::{HashAlg, PrivateKeyWithHashAlg, PublicKeyOrCertificate, load_secret_key},
+};
+use russh_sftp::client::SftpSession;
+use serde::Deserialize;
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::{debug, instrument, warn};
+
+use super::{ProgressReader, Storage, StorageError, Stored};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "SftpConfig::default_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: Option<String>,
+    /// Path to a private key, used instead of `password` when set.
+    pub private_key: Option<String>,
+    pub private_key_passphrase: Option<String>,
+    /// Remote folder downloads are written into.
+    #[serde(default)]
+    pub path: String,
+    /// Expected host key fingerprint, e.g. `SHA256:...` as printed by
+    /// `ssh-keygen -lf`. Any key is accepted when unset.
+    pub host_key: Option<String>,
+}
+
+impl SftpConfig {
+    fn default_port() -> u16 {
+        22
+    }
+}
+
+/// Writes finished downloads to a NAS or seedbox over SFTP.
+#[derive(Debug)]
+pub struct SftpStorage {
+    config: SftpConfig,
+}
+
+impl SftpStorage {
+    pub fn new(config: &SftpConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    async fn connect(&self) -> Result<client::Handle<HostKeyCheck>, StorageError> {
+        let handler = HostKeyCheck {
+            expected: self.config.host_key.clone(),
+        };
+        let ssh_config = Arc::new(client::Config::default());
+        let mut session = client::connect(
+            ssh_config,
+            (self.config.host.as_str(), self.config.port),
+            handler,
+        )
+        .await?;
+
+        let auth = match &self.config.private_key {
+            Some(path) => {
+                let key = load_secret_key(path, self.config.private_key_passphrase.as_deref())
+                    .map_err(russh::Error::from)?;
+                let hash_alg = session.best_supported_rsa_hash().await?.flatten();
+                session
+                    .authenticate_publickey(
+                        &self.config.username,
+                        PrivateKeyWithHashAlg::new(Arc::new(key), hash_alg),
+                    )
+                    .await?
+            }
+            None => {
+                session
+                    .authenticate_password(
+                        &self.config.username,
+                        self.config.password.clone().unwrap_or_default(),
+                    )
+                    .await?
+            }
+        };
+        if !auth.success() {
+            return Err(StorageError::SftpAuth);
+        }
+
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl Storage for SftpStorage {
+    #[instrument(skip(self, video, job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        let filename = video.filename.as_str();
+        let folder = self.config.path.trim_end_matches('/');
+        let remote_path = match folder {
+            "" => filename.to_string(),
+            _ => format!("{}/{}", folder, filename),
+        };
+
+        let session = self.connect().await?;
+        let channel = session.channel_open_session().await?;
+        channel.request_subsystem(true, "sftp").await?;
+        let sftp = SftpSession::new(channel.into_stream()).await?;
+
+        let reader = File::open(&video.path).await.map_err(StorageError::Open)?;
+        let mut reader = ProgressReader::new(reader, job.clone()).await?;
+        let mut remote = sftp.create(remote_path.as_str()).await?;
+        let written = tokio::io::copy(&mut reader, &mut remote)
+            .await
+            .map_err(StorageError::Transfer)?;
+        remote.shutdown().await.map_err(StorageError::Transfer)?;
+        debug!("Uploaded {} bytes to {}", written, remote_path);
+
+        let _ = sftp.close().await;
+        let _ = session
+            .disconnect(Disconnect::ByApplication, "", "English")
+            .await;
+
+        Ok(Stored::Pushed(format!(
+            "sftp://{}/{}",
+            self.config.host,
+            remote_path.trim_start_matches('/')
+        )))
+    }
+}
+
+struct HostKeyCheck {
+    expected: Option<String>,
+}
+
+impl client::Handler for HostKeyCheck {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &PublicKeyOrCertificate,
+    ) -> Result<bool, Self::Error> {
+        let fingerprint = server_public_key
+            .public_key()
+            .fingerprint(HashAlg::Sha256)
+            .to_string();
+
+        match &self.expected {
+            Some(expected) => Ok(*expected == fingerprint),
+            None => {
+                warn!("Accepting unverified SFTP host key {}", fingerprint);
+                Ok(true)
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use reqwest::{Body, Client, header};
+use serde::Deserialize;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+use tracing::{debug, instrument};
+use urlencoding::encode;
+
+use super::{Storage, StorageError, Stored};
+use crate::{jobs::JobHandle, video::DownloadedVideo};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebdavConfig {
+    /// Collection finished downloads are uploaded into, e.g.
+    /// `https://cloud.example.com/remote.php/dav/files/alice/Videos`.
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+/// Pushes finished downloads into a WebDAV folder (Nextcloud, ownCloud, ...).
+#[derive(Debug)]
+pub struct WebdavStorage {
+    client: Client,
+    config: WebdavConfig,
+}
+
+impl WebdavStorage {
+    pub fn new(config: &WebdavConfig) -> Result<Self, StorageError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            config: config.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for WebdavStorage {
+    #[instrument(skip(self, video, _job))]
+    async fn store(
+        &self,
+        video: &DownloadedVideo,
+        _job: &JobHandle,
+    ) -> Result<Stored, StorageError> {
+        let filename = video.filename.as_str();
+        let url = format!(
+            "{}/{}",
+            self.config.url.trim_end_matches('/'),
+            encode(filename)
+        );
+        let reader = File::open(&video.path).await.map_err(StorageError::Open)?;
+        let len = reader.metadata().await.map_err(StorageError::Open)?.len();
+
+        // Some servers reject chunked uploads, so send the length up front.
+        let mut request = self
+            .client
+            .put(&url)
+            .header(header::CONTENT_LENGTH, len)
+            .body(Body::wrap_stream(ReaderStream::new(reader)));
+        if let Some(username) = &self.config.username {
+            request = request.basic_auth(username, self.config.password.as_ref());
+        }
+
+        let response = request.send().await?;
+        debug!("Upload status: {}", response.status());
+        if !response.status().is_success() {
+            return Err(StorageError::UploadStatus(response.status().as_u16()));
+        }
+
+        Ok(Stored::Pushed(url))
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest accepted language code, e.g. `en-US` or `zh-Hans`.
+const MAX_LANG_LEN: usize = 20;
+
+/// Extensions yt-dlp writes subtitles with, before or after conversion.
+pub const SUBTITLE_EXTS: &[&str] = &[
+    "srt", "vtt", "ass", "lrc", "ttml", "srv1", "srv2", "srv3", "json3",
+];
+
+/// Format subtitles are converted to with yt-dlp's `--convert-subs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SubFormat {
+    Srt,
+    Vtt,
+    Ass,
+    Lrc,
+}
+
+impl SubFormat {
+    fn name(self) -> &'static str {
+        match self {
+            SubFormat::Srt => "srt",
+            SubFormat::Vtt => "vtt",
+            SubFormat::Ass => "ass",
+            SubFormat::Lrc => "lrc",
+        }
+    }
+}
+
+/// Checks requested languages are plain codes like `en` or `pt-BR`, so
+/// they can't smuggle yt-dlp's regex or exclusion syntax into
+/// `--sub-langs`.
+pub fn validate_langs(langs: &[String]) -> Result<(), String> {
+    match langs.iter().find(|lang| {
+        lang.len() > MAX_LANG_LEN
+            || !lang.starts_with(|c: char| c.is_ascii_alphanumeric())
+            || !lang
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    }) {
+        Some(lang) => Err(lang.clone()),
+        None => Ok(()),
+    }
+}
+
+/// yt-dlp options downloading `langs`, uploaded or automatic, converted to
+/// `format` if given. Uploaded subtitles win when a language has both.
+pub fn ytdlp_args(langs: &[String], format: Option<SubFormat>) -> Vec<String> {
+    let mut args = vec![
+        "--write-subs".to_string(),
+        "--write-auto-subs".to_string(),
+        "--sub-langs".to_string(),
+        langs.join(","),
+    ];
+    if let Some(format) = format {
+        args.push("--convert-subs".to_string());
+        args.push(format.name().to_string());
+    }
+    args
+}
+
+/// Subtitle files yt-dlp wrote next to `video` for `langs`, as sidecar
+/// extensions like `en.srt`.
+pub async fn find(video: &Path, langs: &[String]) -> Vec<String> {
+    let (Some(dir), Some(stem)) = (video.parent(), video.file_stem()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.", stem.to_string_lossy());
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+
+    let mut found = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some((lang, ext)) = name
+            .strip_prefix(&prefix)
+            .and_then(|rest| rest.rsplit_once('.'))
+        else {
+            continue;
+        };
+        if langs.iter().any(|l| l == lang) && SUBTITLE_EXTS.contains(&ext) {
+            found.push(format!("{}.{}", lang, ext));
+        }
+    }
+    found.sort();
+    found
+}
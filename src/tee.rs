@@ -0,0 +1,225 @@
+use std::{fs::Metadata, io, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use tokio::{fs::File, io::AsyncReadExt, sync::oneshot, task::AbortHandle};
+use tracing::{debug, error, instrument};
+
+use crate::{
+    AppState,
+    download::{self, DownloadError, JobDir, JobStream},
+    jobs::{Failure, FailureKind, JobHandle, JobOptions},
+    storage::sanitize_filename,
+    video::{DownloadedVideo, VideoInfo},
+};
+
+/// File yt-dlp's ffmpeg post-processors write before renaming it to the
+/// output template, `video.mp4`.
+const FFMPEG_OUTPUT: &str = "video.temp.mp4";
+
+/// How often the job directory and the followed file are checked for more data.
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Bytes read from the followed file at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Marks a response whose download is still running. The stream finishes
+/// the job once it catches up with the completed download.
+#[derive(Clone, Copy, Debug)]
+pub struct Following;
+
+/// How a teed download got going.
+pub enum Tee {
+    /// ffmpeg started writing the output, which is streamed as it grows.
+    Following { stream: JobStream, filename: String },
+    /// The download finished without writing through ffmpeg, e.g. a
+    /// single-file format, and is delivered as usual.
+    Finished(Box<DownloadedVideo>, JobDir),
+}
+
+type Outcome = (Result<DownloadedVideo, DownloadError>, JobDir);
+
+/// Whether a streamed job's file is final as soon as yt-dlp's ffmpeg
+/// writes it. Anything that rewrites the file afterwards (transcodes, tags,
+/// plugins, hooks) or delivers something else (bundles, playlists, albums)
+/// has to wait for the download instead.
+pub fn eligible(state: &AppState, options: &JobOptions, url: &str) -> bool {
+    state.config.tee_streaming
+        && state.config.hooks.post_download.is_none()
+        && state.config.hooks.scan.is_none()
+        && state.plugins.for_url(url).is_empty()
+        && options.profile.is_none()
+        && !options.normalize
+        && options.target_size.is_none()
+        && options.video_bitrate.is_none()
+        && options.hdr.is_none()
+        && options.widescreen.is_none()
+        && options.metadata.is_empty()
+        && !options.embed_chapters
+        && options.burn_subs.is_none()
+        && options.audio.is_empty()
+        && !options.album
+        && !options.is_playlist()
+        && !options.bundle()
+}
+
+/// Starts downloading `url` in the background and waits until ffmpeg starts
+/// writing the output, so it can be sent while yt-dlp is still merging or
+/// remuxing, or until the download finishes without doing so.
+#[instrument(skip(state, job))]
+pub async fn start(state: &AppState, job: &JobHandle, url: &str) -> Result<Tee, DownloadError> {
+    let job_dir = JobDir::create(&state.config.tmp_dir, job.id()).await?;
+    let dir = job_dir.path().to_path_buf();
+    let (done_tx, done_rx) = oneshot::channel::<Outcome>();
+    let task = {
+        let (state, job, url) = (state.clone(), job.clone(), url.to_string());
+        tokio::spawn(async move {
+            let result = download::fetch_fragmented(&state, &job, &url, &job_dir).await;
+            let _ = done_tx.send((result, job_dir));
+        })
+    };
+    let mut download = Download {
+        done: Some(done_rx),
+        task: task.abort_handle(),
+        job: job.clone(),
+    };
+
+    let file = loop {
+        if let Ok(file) = File::open(dir.join(FFMPEG_OUTPUT)).await {
+            break file;
+        }
+        let done = download.done.as_mut().expect("download is running");
+        tokio::select! {
+            outcome = done => {
+                download.done = None;
+                let (result, job_dir) = outcome.map_err(|_| DownloadError::MissingOutput)?;
+                return Ok(Tee::Finished(Box::new(result?), job_dir));
+            }
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+        }
+    };
+    debug!("Following {:?}", dir.join(FFMPEG_OUTPUT));
+    job.log_output("Streaming output while it is written");
+
+    // yt-dlp has written the info JSON by now, so the name can be worked out
+    // the same way as for a finished download.
+    let info = VideoInfo::read_for(&dir.join("video.mp4")).await;
+    let title = sanitize_filename(info.title.as_deref().unwrap_or("video"));
+    let filename = match &info.id {
+        Some(id) => format!("{} [{}].mp4", title, id),
+        None => format!("{}.mp4", title),
+    };
+    let provisional = DownloadedVideo {
+        path: dir.join("video.mp4"),
+        filename,
+        info,
+        sidecars: Vec::new(),
+    };
+    let filename = download::delivered_filename(state, &job.options(), &provisional);
+
+    let follower = Follower {
+        file,
+        download,
+        _dir: None,
+    };
+    let stream = futures_util::stream::unfold(follower, |mut follower| async move {
+        let chunk = follower.next_chunk().await?;
+        Some((chunk, follower))
+    });
+    Ok(Tee::Following {
+        stream: JobStream::from_stream(stream, job.clone(), None),
+        filename,
+    })
+}
+
+/// The background download task. Dropping it before the task reports back,
+/// as when the client goes away, aborts the download and fails the job.
+struct Download {
+    /// Taken once the task reports back.
+    done: Option<oneshot::Receiver<Outcome>>,
+    task: AbortHandle,
+    job: JobHandle,
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        if self.done.is_some() {
+            self.task.abort();
+            self.job
+                .cancel(&"client disconnected before the download finished");
+        }
+    }
+}
+
+/// Reads a file while the download task is still writing it.
+struct Follower {
+    // Declared before `_dir` so the file is closed before the directory is removed.
+    file: File,
+    download: Download,
+    /// Keeps the job directory until the file has been read to the end.
+    _dir: Option<JobDir>,
+}
+
+impl Follower {
+    async fn next_chunk(&mut self) -> Option<io::Result<Bytes>> {
+        let mut buf = BytesMut::with_capacity(CHUNK_SIZE);
+        loop {
+            match self.file.read_buf(&mut buf).await {
+                Ok(0) => {}
+                Ok(_) => return Some(Ok(buf.freeze())),
+                Err(e) => return Some(Err(e)),
+            }
+            // Caught up with the writer; the end only counts once it's done.
+            let done = self.download.done.as_mut()?;
+            tokio::select! {
+                outcome = done => {
+                    self.download.done = None;
+                    if let Err(e) = self.finish(outcome).await {
+                        return Some(Err(e));
+                    }
+                }
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Finishes the job with the download's outcome. Fails if the file that
+    /// was streamed isn't what the download ended up with.
+    async fn finish(
+        &mut self,
+        outcome: Result<Outcome, oneshot::error::RecvError>,
+    ) -> io::Result<()> {
+        let (result, job_dir) = outcome.map_err(io::Error::other)?;
+        self._dir = Some(job_dir);
+        let video = match result {
+            Ok(video) => video,
+            Err(e) => {
+                error!("Download failed: {:?}", e);
+                self.download.job.fail(&e);
+                return Err(io::Error::other(e));
+            }
+        };
+
+        let followed = self.file.metadata().await?;
+        let delivered = tokio::fs::metadata(&video.path).await?;
+        if !same_file(&followed, &delivered) {
+            let e = io::Error::other("output was rewritten after streaming started");
+            error!("Failed to stream {:?}: {}", video.path, e);
+            let failure = Failure::new(FailureKind::Internal, false);
+            self.download.job.fail_with(&e.to_string(), Some(failure));
+            return Err(e);
+        }
+        self.download.job.complete();
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    a.len() == b.len()
+}
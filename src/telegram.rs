@@ -0,0 +1,422 @@
+use std::{collections::HashMap, path::Path, sync::Arc, time::Duration};
+
+use reqwest::{
+    Body, Client,
+    multipart::{Form, Part},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::json;
+use tokio_util::io::ReaderStream;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    download::DownloadError,
+    hooks,
+    jobs::{Job, JobMode, JobOptions, JobStage, JobStatus},
+    quick::QuickToken,
+    quotas::Caller,
+};
+
+/// How long a `getUpdates` call waits for messages before returning empty.
+const POLL_TIMEOUT_SECS: u64 = 30;
+/// Pause after a failed `getUpdates` call before trying again.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+/// How often the progress message is refreshed; Telegram limits edits.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(3);
+
+#[derive(thiserror::Error, Debug)]
+pub enum TelegramError {
+    #[error("HTTP request failed")]
+    Http(#[source] reqwest::Error),
+    #[error("Telegram API error: {0}")]
+    Api(String),
+    #[error("failed to read file")]
+    File(#[source] std::io::Error),
+}
+
+impl From<reqwest::Error> for TelegramError {
+    /// Drops the request URL, which has the bot token in it.
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e.without_url())
+    }
+}
+
+/// A Telegram bot that downloads links sent to it, read from the
+/// `[telegram]` section of the config file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelegramConfig {
+    /// Token from @BotFather.
+    pub token: String,
+    /// Telegram user ids allowed to use the bot, and who each submits jobs
+    /// as. Everyone else is turned away.
+    pub users: HashMap<String, QuickToken>,
+    /// Public address of this instance, for share links to files too large
+    /// to upload to the chat.
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Bot API server, for a self-hosted one that takes larger files.
+    #[serde(default = "default_api_url")]
+    pub api_url: String,
+    /// Largest file uploaded to the chat; larger ones are linked instead.
+    #[serde(default = "default_max_upload_mb")]
+    pub max_upload_mb: u64,
+}
+
+fn default_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+fn default_max_upload_mb() -> u64 {
+    50
+}
+
+/// Reply to every Bot API method.
+#[derive(Deserialize)]
+struct ApiResponse<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    message_id: i64,
+    chat: Chat,
+    from: Option<User>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+#[derive(Deserialize)]
+struct User {
+    id: i64,
+}
+
+#[derive(Serialize)]
+struct SendMessage<'a> {
+    chat_id: i64,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_to_message_id: Option<i64>,
+}
+
+struct Bot {
+    client: Client,
+    /// `{api_url}/bot{token}`, followed by the method name.
+    base: String,
+}
+
+impl Bot {
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        body: &impl Serialize,
+    ) -> Result<T, TelegramError> {
+        let request = self
+            .client
+            .post(format!("{}/{}", self.base, method))
+            .json(body);
+        read_response(request.send().await?).await
+    }
+
+    async fn send_message(
+        &self,
+        chat_id: i64,
+        text: &str,
+        reply_to: Option<i64>,
+    ) -> Result<Message, TelegramError> {
+        let message = SendMessage {
+            chat_id,
+            text,
+            reply_to_message_id: reply_to,
+        };
+        self.call("sendMessage", &message).await
+    }
+
+    async fn edit_message(
+        &self,
+        chat_id: i64,
+        message_id: i64,
+        text: &str,
+    ) -> Result<(), TelegramError> {
+        let edit = json!({ "chat_id": chat_id, "message_id": message_id, "text": text });
+        self.call::<serde_json::Value>("editMessageText", &edit)
+            .await
+            .map(|_| ())
+    }
+
+    async fn send_document(
+        &self,
+        chat_id: i64,
+        path: &Path,
+        filename: &str,
+        caption: &str,
+    ) -> Result<(), TelegramError> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(TelegramError::File)?;
+        let len = file.metadata().await.map_err(TelegramError::File)?.len();
+        let part = Part::stream_with_length(Body::wrap_stream(ReaderStream::new(file)), len)
+            .file_name(filename.to_string());
+        let form = Form::new()
+            .text("chat_id", chat_id.to_string())
+            .text("caption", caption.to_string())
+            .part("document", part);
+        let request = self
+            .client
+            .post(format!("{}/sendDocument", self.base))
+            .multipart(form);
+        read_response::<serde_json::Value>(request.send().await?)
+            .await
+            .map(|_| ())
+    }
+}
+
+async fn read_response<T: DeserializeOwned>(
+    response: reqwest::Response,
+) -> Result<T, TelegramError> {
+    let response: ApiResponse<T> = response.json().await?;
+    match response.result {
+        Some(result) if response.ok => Ok(result),
+        _ => Err(TelegramError::Api(
+            response
+                .description
+                .unwrap_or_else(|| "no result".to_string()),
+        )),
+    }
+}
+
+/// Polls Telegram for messages and queues a job for each link sent by an
+/// allowed user.
+pub fn spawn(state: &AppState, config: &TelegramConfig) {
+    let bot = Arc::new(Bot {
+        client: Client::new(),
+        base: format!(
+            "{}/bot{}",
+            config.api_url.trim_end_matches('/'),
+            config.token
+        ),
+    });
+    let state = state.clone();
+    info!("Taking downloads from Telegram");
+    tokio::spawn(async move {
+        let mut offset = 0;
+        loop {
+            let poll = json!({
+                "offset": offset,
+                "timeout": POLL_TIMEOUT_SECS,
+                "allowed_updates": ["message"],
+            });
+            let updates: Vec<Update> = match bot.call("getUpdates", &poll).await {
+                Ok(updates) => updates,
+                Err(e) => {
+                    warn!("Failed to get Telegram updates: {:?}", e);
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    continue;
+                }
+            };
+            for update in updates {
+                offset = offset.max(update.update_id + 1);
+                if let Some(message) = update.message {
+                    let state = state.clone();
+                    let bot = bot.clone();
+                    tokio::spawn(async move {
+                        let chat_id = message.chat.id;
+                        if let Err(e) = handle(&state, &bot, message).await {
+                            error!("Failed to answer Telegram chat {}: {:?}", chat_id, e);
+                        }
+                    });
+                }
+            }
+        }
+    });
+}
+
+async fn handle(state: &AppState, bot: &Bot, message: Message) -> Result<(), TelegramError> {
+    let Some(config) = &state.config.telegram else {
+        return Ok(());
+    };
+    let chat_id = message.chat.id;
+    let reply_to = Some(message.message_id);
+    let Some(from) = message.from else {
+        return Ok(());
+    };
+    let Some(user) = config.users.get(&from.id.to_string()) else {
+        info!("Turned away Telegram user {}", from.id);
+        let text = format!(
+            "You're not allowed to use this bot. Your Telegram user id is {}.",
+            from.id
+        );
+        bot.send_message(chat_id, &text, reply_to).await?;
+        return Ok(());
+    };
+    let caller = Caller {
+        user: user.user.clone(),
+        role: user
+            .role
+            .clone()
+            .unwrap_or_else(|| state.config.quotas.default_role.clone()),
+    };
+    let url = message.text.as_deref().and_then(|text| {
+        text.split_whitespace()
+            .find(|word| word.starts_with("https://") || word.starts_with("http://"))
+    });
+    let Some(url) = url else {
+        bot.send_message(chat_id, "Send me a link to download it.", reply_to)
+            .await?;
+        return Ok(());
+    };
+
+    let rejected = match hooks::rewrite_url(&state.config.hooks.url_rules, url) {
+        Err(reason) => Some(DownloadError::UrlRejected(reason)),
+        Ok(_) => state
+            .config
+            .quotas
+            .check(&state.db, &caller)
+            .err()
+            .map(DownloadError::from),
+    };
+    if let Some(e) = rejected {
+        info!("Telegram download of {} rejected: {}", url, e);
+        bot.send_message(chat_id, &format!("Can't download that: {}", e), reply_to)
+            .await?;
+        return Ok(());
+    }
+
+    let job = state.jobs.create(
+        url,
+        None,
+        Some(&caller.user),
+        &[],
+        JobOptions::default(),
+        JobMode::Queued,
+    );
+    let id = job.id();
+    state.queue.push(job);
+    info!("Queued {} for {} via Telegram", id, caller.user);
+
+    let status = bot.send_message(chat_id, "Queued", reply_to).await?;
+    let job = follow(state, bot, chat_id, status.message_id, id).await?;
+    deliver(state, config, bot, chat_id, status.message_id, &job).await
+}
+
+/// Keeps the status message up to date until the job finishes.
+async fn follow(
+    state: &AppState,
+    bot: &Bot,
+    chat_id: i64,
+    message_id: i64,
+    id: Uuid,
+) -> Result<Job, TelegramError> {
+    let mut shown = "Queued".to_string();
+    let mut interval = tokio::time::interval(PROGRESS_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(job) = state.jobs.get(id) else {
+            return Err(TelegramError::Api(format!("job {} disappeared", id)));
+        };
+        if matches!(job.status, JobStatus::Completed | JobStatus::Failed) {
+            return Ok(job);
+        }
+        let text = progress_text(&job);
+        if text != shown {
+            // A missed update is made up for by the next one.
+            if let Err(e) = bot.edit_message(chat_id, message_id, &text).await {
+                warn!("Failed to update Telegram progress: {:?}", e);
+            }
+            shown = text;
+        }
+    }
+}
+
+fn progress_text(job: &Job) -> String {
+    let percent = job
+        .overall_percent
+        .or_else(|| job.progress.as_ref().and_then(|progress| progress.percent));
+    let stage = match job.stage {
+        JobStage::Queued => "Queued",
+        JobStage::Resolving => "Starting",
+        JobStage::Downloading => "Downloading",
+        JobStage::PostProcessing => "Processing",
+        JobStage::Uploading => "Uploading",
+        JobStage::Storing => "Saving",
+        JobStage::Paused => "Paused",
+        JobStage::Completed | JobStage::Failed | JobStage::Cancelled => "Finishing",
+    };
+    let mut text = match job.title.as_deref() {
+        Some(title) => format!("{}\n{}", title, stage),
+        None => stage.to_string(),
+    };
+    if let Some(percent) = percent {
+        text.push_str(&format!(" {:.0}%", percent));
+    }
+    text
+}
+
+/// Sends the finished file to the chat, or a link to it when it's too large
+/// or was uploaded elsewhere.
+async fn deliver(
+    state: &AppState,
+    config: &TelegramConfig,
+    bot: &Bot,
+    chat_id: i64,
+    message_id: i64,
+    job: &Job,
+) -> Result<(), TelegramError> {
+    let name = job
+        .title
+        .as_deref()
+        .or(job.filename.as_deref())
+        .unwrap_or(&job.url);
+    if job.status == JobStatus::Failed {
+        let error = job.error.as_deref().unwrap_or("unknown error");
+        let text = format!("Download failed: {}", error);
+        return bot.edit_message(chat_id, message_id, &text).await;
+    }
+
+    let kept = job.output.as_deref();
+    if let Some(path) = kept {
+        let len = tokio::fs::metadata(path)
+            .await
+            .map(|m| m.len())
+            .unwrap_or(u64::MAX);
+        if len <= config.max_upload_mb * 1024 * 1024 {
+            bot.edit_message(chat_id, message_id, &format!("{}\nSending", name))
+                .await?;
+            let filename = job.filename.as_deref().unwrap_or("video.mp4");
+            bot.send_document(chat_id, path, filename, name).await?;
+            return bot.edit_message(chat_id, message_id, name).await;
+        }
+    }
+
+    let link = match &job.location {
+        Some(location) if location.starts_with("https://") || location.starts_with("http://") => {
+            Some(location.clone())
+        }
+        _ => match (&config.base_url, kept) {
+            (Some(base_url), Some(_)) => {
+                let ttl = Duration::from_secs(state.config.share_ttl_hours * 60 * 60);
+                Some(state.shares.sign(job.id, ttl).url(base_url, job.id))
+            }
+            _ => None,
+        },
+    };
+    let text = match (link, &job.location) {
+        (Some(link), _) => format!("{}\n{}", name, link),
+        (None, Some(location)) if kept.is_none() => format!("{}\nSaved to {}", name, location),
+        (None, _) => format!("{}\nDownloaded, but too large to send here.", name),
+    };
+    bot.edit_message(chat_id, message_id, &text).await
+}
@@ -0,0 +1,850 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io,
+    path::Path,
+    process::{ExitStatus, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    process::Command,
+};
+use tracing::{debug, instrument, warn};
+
+use crate::{
+    hwaccel::HwEncoders,
+    jobs::{JobHandle, JobOptions},
+    progress::FfmpegProgress,
+    video::{DownloadedVideo, MetadataOverride},
+};
+
+/// An output profile clients pick with `profile=`, re-encoding the download
+/// with ffmpeg so it plays on a particular kind of device.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TranscodeProfile {
+    /// ffmpeg output options, e.g. `["-c:v", "libx264", "-crf", "23"]`.
+    pub ffmpeg_args: Vec<String>,
+}
+
+/// Video codec clients ask for with `vcodec=`. yt-dlp prefers formats in
+/// it, and re-encodes other than transcode profiles encode to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    /// yt-dlp `-S` field preferring formats in this codec.
+    pub fn format_sort(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "vcodec:h264",
+            VideoCodec::Hevc => "vcodec:hevc",
+            VideoCodec::Vp9 => "vcodec:vp9",
+            VideoCodec::Av1 => "vcodec:av01",
+        }
+    }
+
+    /// Whether a file with extension `ext` can hold this codec. WebM only
+    /// takes VP8, VP9 and AV1.
+    fn fits(self, ext: &str) -> bool {
+        ext != "webm" || matches!(self, VideoCodec::Vp9 | VideoCodec::Av1)
+    }
+
+    /// ffmpeg options selecting the encoder.
+    fn encoder_args(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["-c:v", "libx264", "-pix_fmt", "yuv420p"],
+            // Apple players only take HEVC in MP4 tagged `hvc1`.
+            VideoCodec::Hevc => &["-c:v", "libx265", "-pix_fmt", "yuv420p", "-tag:v", "hvc1"],
+            VideoCodec::Vp9 => &["-c:v", "libvpx-vp9", "-row-mt", "1"],
+            // libaom's default speed takes hours for a few minutes of video.
+            VideoCodec::Av1 => &["-c:v", "libaom-av1", "-cpu-used", "6", "-row-mt", "1"],
+        }
+    }
+
+    /// Constant quality options that keep about the source's quality.
+    fn quality_args(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["-crf", "20"],
+            VideoCodec::Hevc => &["-crf", "24"],
+            VideoCodec::Vp9 => &["-crf", "31", "-b:v", "0"],
+            VideoCodec::Av1 => &["-crf", "30", "-b:v", "0"],
+        }
+    }
+
+    /// Options for `pass` of a two-pass encode, logging to `passlog`.
+    /// x265 takes them in its own parameters rather than ffmpeg's.
+    fn pass_args(self, pass: u8, passlog: &Path) -> Vec<OsString> {
+        match self {
+            VideoCodec::Hevc => {
+                let mut params = OsString::from(format!("pass={}:stats=", pass));
+                params.push(passlog);
+                vec!["-x265-params".into(), params]
+            }
+            _ => vec![
+                "-pass".into(),
+                pass.to_string().into(),
+                "-passlogfile".into(),
+                passlog.into(),
+            ],
+        }
+    }
+}
+
+/// How a re-encode encodes video: in `codec` on the CPU, or on the GPU when
+/// one was found at startup that encodes it.
+#[derive(Clone, Copy)]
+struct Encoder<'a> {
+    codec: VideoCodec,
+    hw: Option<&'a HwEncoders>,
+}
+
+impl<'a> Encoder<'a> {
+    fn new(codec: VideoCodec, hw: Option<&'a HwEncoders>) -> Self {
+        Self {
+            codec,
+            hw: hw.filter(|hw| hw.supports(codec)),
+        }
+    }
+
+    /// Options opening the GPU, placed before the input.
+    fn input_args(self) -> Vec<String> {
+        self.hw.map(HwEncoders::input_args).unwrap_or_default()
+    }
+
+    /// `filter` with frames moved onto the GPU at the end, if the encoder
+    /// needs that.
+    fn filter(self, filter: &str) -> String {
+        match self.hw.and_then(HwEncoders::upload_filter) {
+            Some(upload) => format!("{},{}", filter, upload),
+            None => filter.to_string(),
+        }
+    }
+
+    /// `-vf` options for a re-encode without filters of its own.
+    fn upload_args(self) -> Vec<&'static str> {
+        match self.hw.and_then(HwEncoders::upload_filter) {
+            Some(upload) => vec!["-vf", upload],
+            None => Vec::new(),
+        }
+    }
+
+    fn encoder_args(self) -> Vec<&'static str> {
+        match self.hw {
+            Some(hw) => hw.encoder_args(self.codec),
+            None => self.codec.encoder_args().to_vec(),
+        }
+    }
+
+    fn quality_args(self) -> &'static [&'static str] {
+        match self.hw {
+            Some(hw) => hw.quality_args(self.codec),
+            None => self.codec.quality_args(),
+        }
+    }
+}
+
+/// Frame rate clients ask for with `fps=`. yt-dlp prefers formats closest
+/// to it without going over, and re-encodes drop frames above it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FrameRate {
+    #[serde(rename = "60")]
+    Fps60,
+    #[serde(rename = "30")]
+    Fps30,
+    /// Whatever the best format has, as without `fps`.
+    Source,
+}
+
+impl FrameRate {
+    /// yt-dlp `-S` field preferring formats at this frame rate.
+    pub fn format_sort(self) -> Option<&'static str> {
+        match self {
+            FrameRate::Fps60 => Some("fps:60"),
+            FrameRate::Fps30 => Some("fps:30"),
+            FrameRate::Source => None,
+        }
+    }
+
+    /// ffmpeg options capping the frame rate of a re-encode. Slower sources
+    /// keep theirs.
+    fn ffmpeg_args(self) -> &'static [&'static str] {
+        match self {
+            FrameRate::Fps60 => &["-fpsmax", "60"],
+            FrameRate::Fps30 => &["-fpsmax", "30"],
+            FrameRate::Source => &[],
+        }
+    }
+}
+
+/// What to do with HDR video, from `hdr=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hdr {
+    /// Prefer HDR formats and deliver them in MKV, which keeps their
+    /// metadata.
+    Preserve,
+    /// Tone map HDR downloads to SDR, for players that show them washed out.
+    Tonemap,
+}
+
+impl Hdr {
+    /// yt-dlp `-S` field for this mode, if it changes the choice of format.
+    pub fn format_sort(self) -> Option<&'static str> {
+        match self {
+            // Dolby Vision ranks above HDR12 in yt-dlp, and few players
+            // outside Apple's handle it.
+            Hdr::Preserve => Some("hdr:12"),
+            Hdr::Tonemap => None,
+        }
+    }
+}
+
+/// How `widescreen=` turns vertical videos into 16:9 ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Widescreen {
+    /// Fill the sides with a blurred, enlarged copy of the video.
+    Pad,
+    /// Keep a 16:9 band from the middle of the picture.
+    Crop,
+}
+
+impl Widescreen {
+    /// ffmpeg filter graph framing a `width` by `height` video as 16:9.
+    fn filter(self, width: u32, height: u32) -> String {
+        match self {
+            Widescreen::Pad => {
+                // As tall as the video is wide, so the picture is scaled down
+                // rather than the background blown up.
+                let out_height = width / 2 * 2;
+                let out_width = out_height * 16 / 9 / 2 * 2;
+                format!(
+                    "split[bg][fg];\
+                     [bg]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},\
+                     boxblur=20:5[bg];\
+                     [fg]scale=-2:{h}[fg];\
+                     [bg][fg]overlay=(W-w)/2:(H-h)/2,setsar=1",
+                    w = out_width,
+                    h = out_height
+                )
+            }
+            Widescreen::Crop => {
+                let out_height = (width * 9 / 16).min(height) / 2 * 2;
+                format!("crop={}:{},setsar=1", width / 2 * 2, out_height)
+            }
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum TranscodeError {
+    #[error("failed to run ffmpeg")]
+    Command(#[source] io::Error),
+    #[error("ffmpeg exited with no status code")]
+    ExitNoCode,
+    #[error("ffmpeg exited with status code {0}")]
+    ExitErrorCode(i32),
+    #[error("failed to replace download with transcoded file")]
+    Replace(#[source] io::Error),
+    #[error("subtitle file has no parent directory")]
+    SubtitlePath,
+    #[error("video duration is unknown, so its bitrate can't be worked out")]
+    UnknownDuration,
+    #[error("{0} bytes is too small for this video, which needs at least {1}")]
+    TargetTooSmall(u64, u64),
+}
+
+/// Audio bitrate when re-encoding to a target, in bits per second. Tight
+/// size targets get less.
+const TARGET_AUDIO_BITRATE: u64 = 128_000;
+/// Lowest video bitrate a size target may leave, below which the picture
+/// isn't worth watching.
+const MIN_VIDEO_BITRATE: u64 = 100_000;
+/// Share of a size target kept back for the container's own overhead.
+const CONTAINER_OVERHEAD: f64 = 0.03;
+
+/// What to re-encode to, from `target_size` or `video_bitrate`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateTarget {
+    /// Fit the whole file in this many bytes.
+    Size(u64),
+    /// Encode the video at this many bits per second.
+    VideoBitrate(u64),
+}
+
+impl RateTarget {
+    /// The target `options` ask for, if any, or why it's invalid.
+    pub fn from_options(options: &JobOptions) -> Result<Option<Self>, String> {
+        match (&options.target_size, &options.video_bitrate) {
+            (None, None) => Ok(None),
+            (Some(_), Some(_)) => {
+                Err("target_size and video_bitrate can't be combined".to_string())
+            }
+            (Some(size), None) => match parse_size(size) {
+                Some(bytes) if bytes > 0 => Ok(Some(RateTarget::Size(bytes))),
+                _ => Err(format!("invalid target_size {:?}, e.g. 200MB", size)),
+            },
+            (None, Some(rate)) => match parse_bitrate(rate) {
+                Some(bps) if bps > 0 => Ok(Some(RateTarget::VideoBitrate(bps))),
+                _ => Err(format!("invalid video_bitrate {:?}, e.g. 2M or 800k", rate)),
+            },
+        }
+    }
+
+    /// Video and audio bitrates in bits per second for a video lasting
+    /// `duration` seconds.
+    fn bitrates(self, duration: Option<f64>) -> Result<(u64, u64), TranscodeError> {
+        let bytes = match self {
+            RateTarget::VideoBitrate(bps) => return Ok((bps, TARGET_AUDIO_BITRATE)),
+            RateTarget::Size(bytes) => bytes,
+        };
+        let duration = duration
+            .filter(|duration| *duration > 0.0)
+            .ok_or(TranscodeError::UnknownDuration)?;
+        let total = (bytes as f64 * 8.0 * (1.0 - CONTAINER_OVERHEAD) / duration) as u64;
+        let audio = TARGET_AUDIO_BITRATE.min(total / 4);
+        let video = total - audio;
+        if video < MIN_VIDEO_BITRATE {
+            let min_total = MIN_VIDEO_BITRATE as f64 * 4.0 / 3.0;
+            let needed = (min_total * duration / 8.0 / (1.0 - CONTAINER_OVERHEAD)).ceil() as u64;
+            return Err(TranscodeError::TargetTooSmall(bytes, needed));
+        }
+        Ok((video, audio))
+    }
+}
+
+/// Sizes like `200MB`, `25MiB`, `1.5G` or `5000000`. `K`, `M` and `G`
+/// count in thousands, `KiB`, `MiB` and `GiB` in 1024s.
+fn parse_size(size: &str) -> Option<u64> {
+    let split = size
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let number: f64 = number.trim().parse().ok()?;
+    let unit = unit.trim().to_ascii_lowercase();
+    let multiplier = match unit.strip_suffix('b').unwrap_or(&unit) {
+        "" => 1.0,
+        "k" => 1e3,
+        "m" => 1e6,
+        "g" => 1e9,
+        "ki" => 1024.0,
+        "mi" => 1024.0 * 1024.0,
+        "gi" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    (number.is_finite() && number >= 0.0).then_some((number * multiplier) as u64)
+}
+
+/// Bitrates as ffmpeg takes them, e.g. `2M`, `800k` or `1500000`.
+fn parse_bitrate(rate: &str) -> Option<u64> {
+    let (number, multiplier) = match rate.strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1e3),
+        None => match rate.strip_suffix('M') {
+            Some(number) => (number, 1e6),
+            None => (rate, 1.0),
+        },
+    };
+    let number: f64 = number.parse().ok()?;
+    (number.is_finite() && number >= 0.0).then_some((number * multiplier) as u64)
+}
+
+/// Converts HDR (PQ or HLG) to BT.709 SDR: linearize, map the highlights
+/// into range with Hable's curve, then convert back to 8-bit 4:2:0.
+const TONEMAP_FILTER: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+     tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+/// EBU R128 targets for `normalize=true`: -16 LUFS integrated, as used by
+/// most podcast and streaming platforms, with -1.5 dBTP of headroom.
+const LOUDNORM_TARGET: &str = "I=-16:TP=-1.5:LRA=11";
+
+/// Loudness of the input as measured by loudnorm's first pass.
+#[derive(Debug, Deserialize)]
+struct LoudnormMeasurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Profiles available without configuration. The config file can override
+/// them or add more under `[profiles.<name>]`.
+pub fn builtin_profiles() -> HashMap<String, TranscodeProfile> {
+    // Written as one string for readability; no argument contains a space.
+    let profile = |args: &str| TranscodeProfile {
+        ffmpeg_args: args.split_whitespace().map(String::from).collect(),
+    };
+
+    HashMap::from([
+        // H.264 High@4.1 and AAC, which every iPhone and iPad plays.
+        (
+            "ios".to_string(),
+            profile(
+                "-c:v libx264 -profile:v high -level:v 4.1 -pix_fmt yuv420p \
+                 -vf scale='min(1920,iw)':-2 -c:a aac -b:a 160k -movflags +faststart",
+            ),
+        ),
+        // Conservative 1080p H.264 with stereo AAC for smart TVs and
+        // streaming sticks.
+        (
+            "tv".to_string(),
+            profile(
+                "-c:v libx264 -profile:v high -level:v 4.0 -pix_fmt yuv420p \
+                 -vf scale='min(1920,iw)':-2 -c:a aac -ac 2 -b:a 192k",
+            ),
+        ),
+        // Small 480p file for slow or metered connections.
+        (
+            "low-bandwidth".to_string(),
+            profile(
+                "-c:v libx264 -preset veryfast -crf 30 -pix_fmt yuv420p \
+                 -vf scale=-2:'min(480,ih)' -c:a aac -ac 2 -b:a 64k -movflags +faststart",
+            ),
+        ),
+    ])
+}
+
+/// Re-encodes the download in place with the profile's ffmpeg options,
+/// capturing ffmpeg's output into the job log. With a GPU, it is opened
+/// first, so profiles can name a hardware encoder.
+#[instrument(skip(profile, video, hw, job))]
+pub async fn transcode(
+    profile: &TranscodeProfile,
+    video: &DownloadedVideo,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    let output = video
+        .path
+        .with_extension(format!("transcoded.{}", video.ext()));
+    job.log_output(&format!(
+        "Transcoding with ffmpeg {}",
+        profile.ffmpeg_args.join(" ")
+    ));
+
+    let mut command = ffmpeg();
+    command
+        .args(hw.map(HwEncoders::input_args).unwrap_or_default())
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .args(&profile.ffmpeg_args)
+        .args(frame_rate_args(job))
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "transcode", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Re-encodes the download in place with `subtitles` rendered into the
+/// picture, for players and editors that can't show soft subtitles.
+///
+/// ffmpeg runs in the subtitle's directory so the filter gets a bare file
+/// name, sparing it the escaping rules for paths.
+#[instrument(skip(video, hw, job))]
+pub async fn burn_subtitles(
+    video: &DownloadedVideo,
+    subtitles: &Path,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    let (Some(dir), Some(name)) = (subtitles.parent(), subtitles.file_name()) else {
+        return Err(TranscodeError::SubtitlePath);
+    };
+    let output = video.path.with_extension(format!("burned.{}", video.ext()));
+    let encoder = Encoder::new(video_codec(video.ext(), job.options().vcodec), hw);
+    job.log_output(&format!(
+        "Burning in subtitles from {}",
+        name.to_string_lossy()
+    ));
+
+    let mut command = ffmpeg();
+    command
+        .current_dir(dir)
+        .args(encoder.input_args())
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .arg("-vf")
+        .arg(encoder.filter(&format!("subtitles={}", name.to_string_lossy())))
+        .args(encoder.encoder_args())
+        .args(encoder.quality_args())
+        .args(frame_rate_args(job))
+        .args(["-c:a", "copy"])
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "burn_subs", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Tone maps an HDR download to SDR in place. Downloads yt-dlp didn't report
+/// as HDR are left alone. Needs an ffmpeg built with zimg for `zscale`.
+#[instrument(skip(video, hw, job))]
+pub async fn tonemap(
+    video: &DownloadedVideo,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    match video.info.dynamic_range.as_deref() {
+        Some(range) if range != "SDR" => {
+            job.log_output(&format!("Tone mapping {} to SDR", range));
+        }
+        _ => {
+            job.log_output("Video isn't HDR, skipping tone mapping");
+            return Ok(());
+        }
+    }
+    let output = video.path.with_extension(format!("sdr.{}", video.ext()));
+    let encoder = Encoder::new(video_codec(video.ext(), job.options().vcodec), hw);
+
+    let mut command = ffmpeg();
+    command
+        .args(encoder.input_args())
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .arg("-vf")
+        .arg(encoder.filter(TONEMAP_FILTER))
+        .args(encoder.encoder_args())
+        .args(encoder.quality_args())
+        .args(frame_rate_args(job))
+        .args(["-c:a", "copy"])
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "tonemap", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Frames a vertical download as 16:9 in place. Videos that aren't taller
+/// than they are wide, or whose size yt-dlp didn't report, are left alone.
+#[instrument(skip(video, hw, job))]
+pub async fn widescreen(
+    video: &DownloadedVideo,
+    mode: Widescreen,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    let (width, height) = match (video.info.width, video.info.height) {
+        (Some(width), Some(height)) if height > width => (width, height),
+        _ => {
+            job.log_output("Video isn't vertical, leaving its framing alone");
+            return Ok(());
+        }
+    };
+    let filter = mode.filter(width, height);
+    job.log_output(&format!(
+        "Framing {}x{} video with {}",
+        width, height, filter
+    ));
+    let output = video.path.with_extension(format!("framed.{}", video.ext()));
+    let encoder = Encoder::new(video_codec(video.ext(), job.options().vcodec), hw);
+
+    let mut command = ffmpeg();
+    command
+        .args(encoder.input_args())
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .arg("-filter_complex")
+        .arg(encoder.filter(&filter))
+        .args(encoder.encoder_args())
+        .args(encoder.quality_args())
+        .args(frame_rate_args(job))
+        .args(["-c:a", "copy"])
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "widescreen", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Rewrites the download's tags in place. Streams are copied, so this only
+/// takes as long as copying the file.
+#[instrument(skip(video, job))]
+pub async fn tag(
+    video: &DownloadedVideo,
+    metadata: &MetadataOverride,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    let output = video.path.with_extension(format!("tagged.{}", video.ext()));
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-hide_banner")
+        .arg("-nostdin")
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .args(["-map", "0", "-c", "copy"]);
+    for (tag, value) in metadata.tags() {
+        job.log_output(&format!("Setting {} to {:?}", tag, value));
+        command.arg("-metadata").arg(format!("{}={}", tag, value));
+    }
+    let cmd = command
+        .arg(&output)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .map_err(TranscodeError::Command)?;
+
+    debug!("ffmpeg status: {}", cmd.status);
+    if !cmd.status.success() {
+        job.log_output(&String::from_utf8_lossy(&cmd.stderr));
+    }
+    check_status(&cmd.status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Normalizes the download's loudness in place with ffmpeg's loudnorm.
+///
+/// A first pass measures the file so the second can apply a linear gain,
+/// which keeps the dynamics intact. If the measurement can't be read, a
+/// single dynamic pass is used instead. Video is copied as-is.
+#[instrument(skip(video, job))]
+pub async fn normalize(video: &DownloadedVideo, job: &JobHandle) -> Result<(), TranscodeError> {
+    job.log_output("Measuring loudness");
+    let mut command = ffmpeg();
+    command
+        .arg("-i")
+        .arg(&video.path)
+        .arg("-af")
+        .arg(format!("loudnorm={}:print_format=json", LOUDNORM_TARGET))
+        .args(["-vn", "-f", "null", "-"]);
+    let (status, stderr) = run_pass(command, "measure_loudness", video, job).await?;
+    check_status(&status)?;
+
+    let filter = match parse_loudnorm(&stderr) {
+        Some(m) => format!(
+            "loudnorm={}:measured_I={}:measured_TP={}:measured_LRA={}:\
+             measured_thresh={}:offset={}:linear=true",
+            LOUDNORM_TARGET, m.input_i, m.input_tp, m.input_lra, m.input_thresh, m.target_offset
+        ),
+        None => {
+            warn!("Failed to read loudnorm measurement, normalizing in one pass");
+            job.log_output(&stderr);
+            format!("loudnorm={}", LOUDNORM_TARGET)
+        }
+    };
+
+    let output = video
+        .path
+        .with_extension(format!("normalized.{}", video.ext()));
+    job.log_output(&format!("Normalizing with ffmpeg -af {}", filter));
+    let mut command = ffmpeg();
+    command
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .arg("-af")
+        .arg(&filter)
+        // loudnorm works at 192 kHz internally; go back to a normal rate.
+        .args(["-ar", "48000", "-c:v", "copy", "-c:a"])
+        .arg(audio_encoder(video.ext()))
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "normalize", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// Re-encodes the download in place at the bitrate `target` calls for. Two
+/// passes let the encoder spend bits where the picture needs them, at the
+/// cost of decoding the file twice. Two passes run on the CPU, as GPU
+/// encoders don't take ffmpeg's pass options.
+#[instrument(skip(video, hw, job))]
+pub async fn encode_to_target(
+    video: &DownloadedVideo,
+    target: RateTarget,
+    two_pass: bool,
+    hw: Option<&HwEncoders>,
+    job: &JobHandle,
+) -> Result<(), TranscodeError> {
+    let (video_bitrate, audio_bitrate) = target
+        .bitrates(video.info.duration)
+        .inspect_err(|e| job.log_output(&format!("Can't re-encode: {}", e)))?;
+    job.log_output(&format!(
+        "Re-encoding at {} kb/s video and {} kb/s audio{}",
+        video_bitrate / 1000,
+        audio_bitrate / 1000,
+        if two_pass { " in two passes" } else { "" }
+    ));
+    let codec = video_codec(video.ext(), job.options().vcodec);
+    let encoder = Encoder::new(codec, hw.filter(|_| !two_pass));
+    let bitrate = ["-b:v".to_string(), video_bitrate.to_string()];
+    let passlog = video.path.with_extension("passlog");
+
+    if two_pass {
+        let mut command = ffmpeg();
+        command
+            .arg("-y")
+            .arg("-i")
+            .arg(&video.path)
+            .args(codec.encoder_args())
+            .args(&bitrate)
+            .args(frame_rate_args(job))
+            .args(codec.pass_args(1, &passlog))
+            .args(["-an", "-f", "null", "-"]);
+        let (status, stderr) = run_pass(command, "analyze_bitrate", video, job).await?;
+        if !status.success() {
+            job.log_output(&stderr);
+        }
+        check_status(&status)?;
+    }
+
+    let output = video
+        .path
+        .with_extension(format!("encoded.{}", video.ext()));
+    let mut command = ffmpeg();
+    command
+        .args(encoder.input_args())
+        .arg("-y")
+        .arg("-i")
+        .arg(&video.path)
+        .args(encoder.upload_args())
+        .args(encoder.encoder_args())
+        .args(&bitrate)
+        .args(frame_rate_args(job));
+    if two_pass {
+        command.args(codec.pass_args(2, &passlog));
+    } else {
+        // Without a first pass, capping the rate keeps the size in check.
+        command
+            .arg("-maxrate")
+            .arg(video_bitrate.to_string())
+            .arg("-bufsize")
+            .arg((2 * video_bitrate).to_string());
+    }
+    command
+        .arg("-c:a")
+        .arg(audio_encoder(video.ext()))
+        .arg("-b:a")
+        .arg(audio_bitrate.to_string())
+        .arg(&output);
+    let (status, stderr) = run_pass(command, "encode_bitrate", video, job).await?;
+    job.log_output(&stderr);
+    check_status(&status)?;
+
+    tokio::fs::rename(&output, &video.path)
+        .await
+        .map_err(TranscodeError::Replace)
+}
+
+/// ffmpeg writing machine-readable progress to stdout in place of its
+/// status line.
+fn ffmpeg() -> Command {
+    let mut command = Command::new("ffmpeg");
+    command
+        .args([
+            "-hide_banner",
+            "-nostdin",
+            "-nostats",
+            "-progress",
+            "pipe:1",
+        ])
+        .kill_on_drop(true);
+    command
+}
+
+/// Runs one ffmpeg pass over the download, reporting its progress on the
+/// job as `step`. Returns the exit status and stderr.
+async fn run_pass(
+    mut command: Command,
+    step: &str,
+    video: &DownloadedVideo,
+    job: &JobHandle,
+) -> Result<(ExitStatus, String), TranscodeError> {
+    job.start_pass(step);
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(TranscodeError::Command)?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+
+    let progress = async {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut progress = FfmpegProgress::default();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if progress.line(&line) {
+                let (percent, eta) = progress.estimate(video.info.duration);
+                job.set_pass_progress(percent, progress.speed, eta);
+            }
+        }
+    };
+    let mut output = Vec::new();
+    let ((), read, status) = tokio::join!(progress, stderr.read_to_end(&mut output), child.wait());
+    read.map_err(TranscodeError::Command)?;
+    let status = status.map_err(TranscodeError::Command)?;
+    debug!("ffmpeg status: {}", status);
+    Ok((status, String::from_utf8_lossy(&output).into_owned()))
+}
+
+/// Extracts the JSON block loudnorm prints at the end of its output.
+fn parse_loudnorm(stderr: &str) -> Option<LoudnormMeasurement> {
+    let start = stderr.rfind('{')?;
+    let end = stderr[start..].find('}')? + start;
+    serde_json::from_str(&stderr[start..=end]).ok()
+}
+
+/// ffmpeg options for the frame rate the job asked for, if any.
+fn frame_rate_args(job: &JobHandle) -> &'static [&'static str] {
+    job.options().fps.map_or(&[], |fps| fps.ffmpeg_args())
+}
+
+/// The codec to re-encode a file with extension `ext` in: the one asked
+/// for if the container takes it, otherwise the container's usual one.
+fn video_codec(ext: &str, requested: Option<VideoCodec>) -> VideoCodec {
+    match requested {
+        Some(codec) if codec.fits(ext) => codec,
+        _ if ext == "webm" => VideoCodec::Vp9,
+        _ => VideoCodec::H264,
+    }
+}
+
+/// Audio encoder that fits a file's container.
+fn audio_encoder(ext: &str) -> &'static str {
+    match ext {
+        "mp3" => "libmp3lame",
+        "opus" | "ogg" | "webm" | "mkv" => "libopus",
+        "flac" => "flac",
+        "wav" => "pcm_s16le",
+        _ => "aac",
+    }
+}
+
+fn check_status(status: &std::process::ExitStatus) -> Result<(), TranscodeError> {
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(TranscodeError::ExitErrorCode(code)),
+        None => Err(TranscodeError::ExitNoCode),
+    }
+}
@@ -0,0 +1,153 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{info, instrument, warn};
+use uuid::Uuid;
+
+use crate::{AppState, jobs::Job, retention};
+
+#[derive(thiserror::Error, Debug)]
+pub enum TrashError {
+    #[error("job has no kept file")]
+    NotKept,
+    #[error("file is not in the trash")]
+    NotTrashed,
+    #[error("another file is already at {0:?}")]
+    Occupied(PathBuf),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Where a job's trashed files are kept, under their own names.
+fn trash_dir(state: &AppState, id: Uuid) -> PathBuf {
+    state.config.data_dir.join("trash").join(id.to_string())
+}
+
+fn files_dir(state: &AppState, id: Uuid) -> PathBuf {
+    state.config.data_dir.join("files").join(id.to_string())
+}
+
+/// Moves a job's kept file and its sidecars to the trash, or deletes them
+/// right away when `TRASH_RETENTION_DAYS` is 0. Cached HLS segments are
+/// deleted either way. Returns the updated job.
+#[instrument(skip(state, job), fields(job = %job.id))]
+pub async fn trash(state: &AppState, job: &Job) -> Result<Job, TrashError> {
+    let output = job
+        .output
+        .as_ref()
+        .filter(|_| job.trashed_at.is_none())
+        .ok_or(TrashError::NotKept)?;
+    if !tokio::fs::try_exists(output).await? {
+        return Err(TrashError::NotKept);
+    }
+    if state.config.trash_retention_days == 0 {
+        retention::remove(state, job).await;
+        return state.jobs.get(job.id).ok_or(TrashError::NotKept);
+    }
+
+    let trash = trash_dir(state, job.id);
+    let files = files_dir(state, job.id);
+    if let Some(parent) = trash.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if output.starts_with(&files) {
+        tokio::fs::rename(&files, &trash).await?;
+    } else {
+        tokio::fs::create_dir_all(&trash).await?;
+        let sidecars = retention::sidecars(output, &job.options.subs);
+        for file in std::iter::once(output.clone()).chain(sidecars) {
+            let Some(name) = file.file_name() else {
+                continue;
+            };
+            if tokio::fs::try_exists(&file).await? {
+                move_file(&file, &trash.join(name)).await?;
+            }
+        }
+    }
+    state.hls.remove(job.id).await;
+    info!("Moved kept file of job {} to the trash", job.id);
+
+    state
+        .jobs
+        .set_trashed(job.id, true)
+        .ok_or(TrashError::NotKept)
+}
+
+/// Moves a job's trashed files back where they were. Returns the updated
+/// job.
+#[instrument(skip(state, job), fields(job = %job.id))]
+pub async fn restore(state: &AppState, job: &Job) -> Result<Job, TrashError> {
+    let output = job
+        .output
+        .as_ref()
+        .filter(|_| job.trashed_at.is_some())
+        .ok_or(TrashError::NotTrashed)?;
+    if tokio::fs::try_exists(output).await? {
+        return Err(TrashError::Occupied(output.clone()));
+    }
+
+    let trash = trash_dir(state, job.id);
+    let files = files_dir(state, job.id);
+    if output.starts_with(&files) {
+        if let Some(parent) = files.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::rename(&trash, &files).await?;
+    } else {
+        let Some(dir) = output.parent() else {
+            return Err(TrashError::NotTrashed);
+        };
+        tokio::fs::create_dir_all(dir).await?;
+        let mut entries = tokio::fs::read_dir(&trash).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            move_file(&entry.path(), &dir.join(entry.file_name())).await?;
+        }
+        tokio::fs::remove_dir(&trash).await?;
+    }
+    info!("Restored kept file of job {} from the trash", job.id);
+
+    state
+        .jobs
+        .set_trashed(job.id, false)
+        .ok_or(TrashError::NotTrashed)
+}
+
+/// Deletes files that have been in the trash longer than
+/// `TRASH_RETENTION_DAYS`.
+pub async fn purge(state: &AppState) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let cutoff = now.saturating_sub(state.config.trash_retention_days * 24 * 60 * 60);
+    for job in state.jobs.trashed() {
+        if job.trashed_at.is_none_or(|at| at >= cutoff) {
+            continue;
+        }
+        let trash = trash_dir(state, job.id);
+        match tokio::fs::remove_dir_all(&trash).await {
+            Ok(()) => info!("Purged kept file of job {} from the trash", job.id),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                warn!("Failed to delete {:?}: {:?}", trash, e);
+                continue;
+            }
+        }
+        state.jobs.clear_output(job.id);
+    }
+}
+
+/// Renames `from` to `to`, copying instead when they are on different
+/// disks, as a library folder and `DATA_DIR` may be.
+pub(crate) async fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            tokio::fs::copy(from, to).await?;
+            tokio::fs::remove_file(from).await
+        }
+        result => result,
+    }
+}
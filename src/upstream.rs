@@ -0,0 +1,81 @@
+use std::io;
+
+use axum::{
+    body::Body,
+    http::{Response, StatusCode, header},
+};
+use futures_util::TryStreamExt;
+use reqwest::Client;
+use tracing::info;
+use url::Url;
+
+use crate::{download::JobStream, jobs::JobHandle};
+
+/// Header marking requests proxied from another instance, so two instances
+/// falling back to each other can't loop.
+pub const PROXIED_HEADER: &str = "x-ytdlp-web-proxied";
+
+/// Response headers passed on from the upstream instance.
+const FORWARDED_HEADERS: &[header::HeaderName] = &[
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_DISPOSITION,
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum UpstreamError {
+    #[error("HTTP request failed")]
+    Http(#[from] reqwest::Error),
+    #[error("upstream instance responded with status code {0}")]
+    Status(u16),
+}
+
+/// Another yt-dlp-web instance that streaming downloads fall back to when
+/// yt-dlp fails here, e.g. because this server's IP is blocked.
+pub struct Upstream {
+    client: Client,
+    base: Url,
+    authorization: Option<String>,
+}
+
+impl Upstream {
+    pub fn new(base: Url, authorization: Option<String>) -> Result<Self, UpstreamError> {
+        Ok(Self {
+            client: Client::builder().build()?,
+            base,
+            authorization,
+        })
+    }
+
+    /// Requests `GET /api/download` with the client's original query string
+    /// and streams the file back, counting it towards `job`'s served bytes.
+    pub async fn download(
+        &self,
+        query: &str,
+        job: JobHandle,
+    ) -> Result<Response<Body>, UpstreamError> {
+        let mut url = self.base.join("api/download").expect("path is valid");
+        url.set_query(Some(query));
+        info!("Proxying job {} to {}", job.id(), self.base);
+
+        let mut request = self.client.get(url).header(PROXIED_HEADER, "1");
+        if let Some(authorization) = &self.authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let upstream = request.send().await?;
+        if !upstream.status().is_success() {
+            return Err(UpstreamError::Status(upstream.status().as_u16()));
+        }
+
+        let mut response = Response::builder().status(StatusCode::OK);
+        for name in FORWARDED_HEADERS {
+            if let Some(value) = upstream.headers().get(name) {
+                response = response.header(name, value);
+            }
+        }
+        let stream = upstream.bytes_stream().map_err(io::Error::other);
+        Ok(response
+            .body(Body::from_stream(JobStream::from_stream(stream, job, None)))
+            .unwrap())
+    }
+}
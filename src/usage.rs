@@ -0,0 +1,101 @@
+use std::{collections::BTreeMap, io, path::Path};
+
+use serde::Serialize;
+
+use crate::{config::Config, storage::DestinationConfig};
+
+/// Disk usage served by `GET /api/storage`. Server paths are left out, as
+/// elsewhere in the API.
+#[derive(Debug, Serialize)]
+pub struct StorageUsage {
+    /// Per-job scratch directories of running downloads.
+    pub tmp: DirUsage,
+    /// Files kept for `GET /api/jobs/{id}/file`.
+    pub kept_files: DirUsage,
+    /// Cached HLS segments.
+    pub hls: DirUsage,
+    /// Deleted kept files that can still be restored.
+    pub trash: DirUsage,
+    pub ytdlp_cache: DirUsage,
+    /// `library` destinations, by name.
+    pub libraries: BTreeMap<String, DirUsage>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirUsage {
+    /// Size of the files in the directory.
+    pub used_bytes: u64,
+    /// Space left on the disk holding the directory.
+    pub free_bytes: u64,
+    /// Size of the disk holding the directory.
+    pub total_bytes: u64,
+}
+
+/// Measures every directory the server writes to.
+pub async fn collect(config: &Config) -> io::Result<StorageUsage> {
+    let mut libraries = BTreeMap::new();
+    for (name, destination) in &config.destinations {
+        if let DestinationConfig::Library(library) = destination {
+            libraries.insert(name.clone(), measure(&library.path).await?);
+        }
+    }
+
+    Ok(StorageUsage {
+        tmp: measure(&config.tmp_dir).await?,
+        kept_files: measure(&config.data_dir.join("files")).await?,
+        hls: measure(&config.data_dir.join("hls")).await?,
+        trash: measure(&config.data_dir.join("trash")).await?,
+        ytdlp_cache: measure(&config.ytdlp_cache_dir).await?,
+        libraries,
+    })
+}
+
+async fn measure(path: &Path) -> io::Result<DirUsage> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        // A directory that hasn't been created yet is on its parent's disk.
+        let disk = path
+            .ancestors()
+            .find(|dir| dir.exists())
+            .unwrap_or(Path::new("."));
+        Ok(DirUsage {
+            used_bytes: dir_size(&path)?,
+            free_bytes: fs4::available_space(disk)?,
+            total_bytes: fs4::total_space(disk)?,
+        })
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Total size of the files under `dir`, without following symlinks; 0 if it
+/// doesn't exist.
+pub async fn size(dir: &Path) -> io::Result<u64> {
+    let dir = dir.to_path_buf();
+    tokio::task::spawn_blocking(move || dir_size(&dir))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Blocking version of [`size`].
+pub fn dir_size(dir: &Path) -> io::Result<u64> {
+    let mut total = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                total += metadata.len();
+            }
+        }
+    }
+    Ok(total)
+}
@@ -0,0 +1,155 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Metadata written by yt-dlp's `--write-info-json`. Only the fields used by
+/// the server are read; extractors leave many of them out.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VideoInfo {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub uploader: Option<String>,
+    /// `YYYYMMDD`
+    pub upload_date: Option<String>,
+    pub extractor_key: Option<String>,
+    /// Set on entries downloaded from a playlist.
+    pub playlist_title: Option<String>,
+    /// Set by music sites and some YouTube music uploads.
+    pub artist: Option<String>,
+    /// Length in seconds.
+    pub duration: Option<f64>,
+    /// `SDR`, `HDR10`, `HLG`, `DV` etc., for the chosen format.
+    pub dynamic_range: Option<String>,
+    /// Picture size of the chosen format, in pixels.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// `null` when the video has none.
+    pub chapters: Option<Vec<Chapter>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Chapter {
+    pub start_time: f64,
+    pub end_time: f64,
+    pub title: Option<String>,
+}
+
+impl VideoInfo {
+    /// Reads the info JSON yt-dlp wrote next to `video`, falling back to empty
+    /// metadata if it is missing or malformed.
+    pub async fn read_for(video: &Path) -> Self {
+        let path = video.with_extension("info.json");
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) => {
+                warn!("Failed to read info JSON {:?}: {:?}", path, e);
+                return Self::default();
+            }
+        };
+
+        serde_json::from_slice(&contents).unwrap_or_else(|e| {
+            warn!("Failed to parse info JSON {:?}: {:?}", path, e);
+            Self::default()
+        })
+    }
+
+    /// Upload date as `YYYY-MM-DD`.
+    pub fn upload_date_iso(&self) -> Option<String> {
+        let date = self.upload_date.as_deref()?;
+        if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(format!("{}-{}-{}", &date[0..4], &date[4..6], &date[6..8]))
+    }
+}
+
+/// Tags a client asked to set on the download, replacing what the uploader
+/// provided.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetadataOverride {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+}
+
+impl MetadataOverride {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// The overrides as ffmpeg tag names and values.
+    pub fn tags(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("title", &self.title),
+            ("artist", &self.artist),
+            ("album", &self.album),
+            ("genre", &self.genre),
+        ]
+        .into_iter()
+        .filter_map(|(tag, value)| Some((tag, value.as_deref()?)))
+        .collect()
+    }
+
+    /// Updates the metadata used for naming and NFO files to match.
+    pub fn apply(&self, info: &mut VideoInfo) {
+        if let Some(title) = &self.title {
+            info.title = Some(title.clone());
+        }
+        if let Some(artist) = &self.artist {
+            info.artist = Some(artist.clone());
+        }
+    }
+}
+
+/// A finished download waiting in its job directory.
+#[derive(Debug)]
+pub struct DownloadedVideo {
+    pub path: PathBuf,
+    /// File name presented to the user, as printed by yt-dlp.
+    pub filename: String,
+    pub info: VideoInfo,
+    /// Extra files kept alongside the video, as the extension that replaces
+    /// the video's own, e.g. `chat.json` for `video.chat.json`.
+    pub sidecars: Vec<String>,
+}
+
+impl DownloadedVideo {
+    /// Extension of the downloaded file, without the dot.
+    pub fn ext(&self) -> &str {
+        self.path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("mp4")
+    }
+
+    /// Moves the comments yt-dlp put in the info JSON (`--write-comments`)
+    /// into a `comments.json` sidecar. Returns `false` if there were none.
+    pub async fn split_comments(&mut self) -> io::Result<bool> {
+        let path = self.path.with_extension("info.json");
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let mut info: serde_json::Value =
+            serde_json::from_slice(&contents).map_err(io::Error::other)?;
+        let Some(comments) = info.get_mut("comments").map(serde_json::Value::take) else {
+            return Ok(false);
+        };
+        if comments.is_null() {
+            return Ok(false);
+        }
+
+        let comments = serde_json::to_vec(&comments).map_err(io::Error::other)?;
+        tokio::fs::write(self.path.with_extension("comments.json"), comments).await?;
+        self.sidecars.push("comments.json".to_string());
+        Ok(true)
+    }
+}
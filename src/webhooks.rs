@@ -0,0 +1,218 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, warn};
+
+use crate::{
+    hooks::HooksConfig,
+    jobs::{FailureKind, Job, JobStatus, Jobs},
+};
+
+/// How long a webhook endpoint gets to answer.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An endpoint sent a JSON `POST` when jobs finish.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    /// Events to send; all of them when empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+    /// Extra request headers, e.g. `Authorization`.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// A Slack incoming webhook, posted a message when jobs finish.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SlackWebhook {
+    pub url: String,
+    /// Events to post about; all of them when empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+/// A Matrix room sent a message when jobs finish, through the client-server
+/// API as the user `access_token` belongs to, who must have joined it.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixRoom {
+    /// e.g. `https://matrix.example.org`.
+    pub homeserver: String,
+    pub access_token: String,
+    /// e.g. `!abcdef:example.org`.
+    pub room_id: String,
+    /// Events to post about; all of them when empty.
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "job.completed")]
+    JobCompleted,
+    #[serde(rename = "job.failed")]
+    JobFailed,
+}
+
+/// Body of a webhook request.
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: WebhookEvent,
+    job: &'a Job,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    failure: Option<FailureDetail<'a>>,
+}
+
+/// What went wrong, in one place for `job.failed`.
+#[derive(Serialize)]
+struct FailureDetail<'a> {
+    error: Option<&'a str>,
+    /// `None` for jobs that failed before failures were classified.
+    kind: Option<FailureKind>,
+    retryable: bool,
+    exit_code: Option<i32>,
+    /// Last lines yt-dlp wrote to stderr.
+    stderr_tail: &'a [String],
+}
+
+impl<'a> Payload<'a> {
+    fn new(job: &'a Job) -> Self {
+        match job.status {
+            JobStatus::Completed => Self {
+                event: WebhookEvent::JobCompleted,
+                job,
+                failure: None,
+            },
+            _ => Self {
+                event: WebhookEvent::JobFailed,
+                job,
+                failure: Some(FailureDetail {
+                    error: job.error.as_deref(),
+                    kind: job.failure.as_ref().map(|failure| failure.kind),
+                    retryable: job.failure.as_ref().is_some_and(|f| f.retryable),
+                    exit_code: job.failure.as_ref().and_then(|f| f.exit_code),
+                    stderr_tail: &job.stderr_tail,
+                }),
+            },
+        }
+    }
+}
+
+/// Sends each finished job, streamed or queued, to the webhooks, Slack
+/// channels and Matrix rooms in `hooks` that want its event. Deliveries
+/// aren't retried; failures are only logged.
+pub fn spawn_notifier(hooks: &HooksConfig, jobs: &Jobs) {
+    if hooks.webhooks.is_empty() && hooks.slack.is_empty() && hooks.matrix.is_empty() {
+        return;
+    }
+    let hooks = Arc::new(hooks.clone());
+    let client = Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .expect("TLS backend is available");
+    let mut finished = jobs.subscribe_finished();
+    tokio::spawn(async move {
+        loop {
+            match finished.recv().await {
+                Ok(job) => {
+                    let client = client.clone();
+                    let hooks = hooks.clone();
+                    tokio::spawn(async move { notify(&client, &hooks, &job).await });
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Skipped webhooks for {} jobs", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+fn wants(events: &[WebhookEvent], event: WebhookEvent) -> bool {
+    events.is_empty() || events.contains(&event)
+}
+
+async fn notify(client: &Client, hooks: &HooksConfig, job: &Job) {
+    let payload = Payload::new(job);
+    let text = chat_message(job);
+    let mut requests = Vec::new();
+    for webhook in &hooks.webhooks {
+        if wants(&webhook.events, payload.event) {
+            let mut request = client.post(&webhook.url).json(&payload);
+            for (name, value) in &webhook.headers {
+                request = request.header(name, value);
+            }
+            requests.push((format!("Webhook {}", webhook.url), request));
+        }
+    }
+    for slack in &hooks.slack {
+        if wants(&slack.events, payload.event) {
+            // The URL is the secret, so it stays out of the logs.
+            let request = client.post(&slack.url).json(&json!({ "text": text }));
+            requests.push(("Slack webhook".to_string(), request));
+        }
+    }
+    for matrix in &hooks.matrix {
+        if wants(&matrix.events, payload.event) {
+            // The job id doubles as the transaction id, so a repeated request
+            // isn't posted twice.
+            let url = format!(
+                "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+                matrix.homeserver.trim_end_matches('/'),
+                urlencoding::encode(&matrix.room_id),
+                job.id
+            );
+            let request = client
+                .put(url)
+                .bearer_auth(&matrix.access_token)
+                .json(&json!({ "msgtype": "m.text", "body": text }));
+            requests.push((format!("Matrix room {}", matrix.room_id), request));
+        }
+    }
+    let sends = requests
+        .into_iter()
+        .map(|(name, request)| send(name, request, job));
+    futures_util::future::join_all(sends).await;
+}
+
+async fn send(name: String, request: RequestBuilder, job: &Job) {
+    match request.send().await {
+        Ok(response) if response.status().is_success() => {
+            debug!("{} was sent job {}", name, job.id);
+        }
+        Ok(response) => warn!(
+            "{} rejected job {} with status code {}",
+            name,
+            job.id,
+            response.status()
+        ),
+        Err(e) => warn!("{} failed: {:?}", name, e),
+    }
+}
+
+/// What Slack and Matrix are told about `job`.
+fn chat_message(job: &Job) -> String {
+    let name = job
+        .title
+        .as_deref()
+        .or(job.filename.as_deref())
+        .unwrap_or(&job.url);
+    match job.status {
+        JobStatus::Completed => match job.location.as_deref() {
+            Some(location)
+                if location.starts_with("https://") || location.starts_with("http://") =>
+            {
+                format!("Downloaded {}\n{}", name, location)
+            }
+            _ => format!("Downloaded {}", name),
+        },
+        _ => format!(
+            "Download of {} failed: {}",
+            name,
+            job.error.as_deref().unwrap_or("unknown error")
+        ),
+    }
+}
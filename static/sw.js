@@ -0,0 +1,17 @@
+// Shows the notifications sent by the server when a queued job finishes.
+self.addEventListener("push", (event) => {
+    const data = event.data ? event.data.json() : {};
+    event.waitUntil(
+        self.registration.showNotification(data.title || "yt-dlp webui", {
+            body: data.body,
+            tag: data.job_id,
+            data,
+        }),
+    );
+});
+
+self.addEventListener("notificationclick", (event) => {
+    event.notification.close();
+    const { location } = event.notification.data || {};
+    event.waitUntil(self.clients.openWindow(location || "/"));
+});
@@ -0,0 +1,37 @@
+//! Chapter markers embedded with `embed_chapters`.
+
+mod common;
+
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+
+async fn command(app: &TestApp, query: &str) -> Vec<String> {
+    let response = app
+        .get(&format!("/api/simulate?url=https://mock.test/ok{}", query))
+        .await;
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    serde_json::from_value(simulation["command"].clone()).unwrap()
+}
+
+#[tokio::test]
+async fn embeds_chapters_alongside_the_recode() {
+    let app = TestApp::new();
+    let args = command(&app, "&embed_chapters=true").await;
+    assert!(
+        args.iter().any(|arg| arg == "--embed-chapters"),
+        "{:?}",
+        args
+    );
+    assert!(
+        args.windows(2).any(|w| w == ["--recode", "mp4"]),
+        "{:?}",
+        args
+    );
+
+    let args = command(&app, "").await;
+    assert!(
+        !args.iter().any(|arg| arg == "--embed-chapters"),
+        "{:?}",
+        args
+    );
+}
@@ -0,0 +1,61 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use yt_dlp_web::jobs::JobStatus;
+
+async fn verify(app: &TestApp, uri: &str) -> Value {
+    let request = Request::post(uri).body(Body::empty()).unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    serde_json::from_slice(&body_bytes(response).await).unwrap()
+}
+
+#[tokio::test]
+async fn records_and_serves_checksum() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    let response = app.get(&format!("/api/jobs/{}/file", id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let header = response.headers()["x-checksum"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = body_bytes(response).await;
+    let expected: String = Sha256::digest(&body)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(job.sha256.as_deref(), Some(expected.as_str()));
+    assert_eq!(header, format!("sha256={}", expected));
+
+    let verification = verify(&app, &format!("/api/library/{}/verify", id)).await;
+    assert_eq!(verification["sha256"], expected);
+    assert_eq!(verification["intact"], true);
+}
+
+#[tokio::test]
+async fn detects_changed_file() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    std::fs::write(job.output.unwrap(), b"truncated").unwrap();
+    let verification = verify(&app, &format!("/api/library/{}/verify", id)).await;
+    assert_eq!(verification["intact"], false);
+    assert_eq!(verification["expected"], job.sha256.unwrap());
+
+    let all = verify(&app, "/api/library/verify").await;
+    assert_eq!(all.as_array().unwrap().len(), 1);
+    assert_eq!(all[0]["id"], id.to_string());
+    assert_eq!(all[0]["intact"], false);
+}
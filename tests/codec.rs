@@ -0,0 +1,110 @@
+//! Jobs preferring a video codec with `vcodec` or a frame rate with `fps`.
+
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+use yt_dlp_web::jobs::{Job, JobStatus};
+
+#[tokio::test]
+async fn prefers_codec_and_keeps_it_when_remuxing() {
+    let app = TestApp::with_config(|config| config.quotas.admin_roles = vec!["admins".to_string()]);
+    let body = serde_json::json!({ "url": "https://mock.test/ok", "vcodec": "vp9", "debug": true });
+    let request = Request::post("/api/jobs")
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", "admins")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let job: Job = serde_json::from_slice(&body_bytes(app.request(request).await).await).unwrap();
+    assert_eq!(app.finished(job.id).await.status, JobStatus::Completed);
+
+    let request = Request::get(format!("/api/jobs/{}/debug", job.id))
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", "admins")
+        .body(Body::empty())
+        .unwrap();
+    let debug = String::from_utf8(body_bytes(app.request(request).await).await.to_vec()).unwrap();
+    assert!(debug.contains("-S vcodec:vp9"), "{}", debug);
+    assert!(debug.contains("--remux-video mp4"), "{}", debug);
+    assert!(!debug.contains("--recode"), "{}", debug);
+}
+
+#[tokio::test]
+async fn re_encodes_with_requested_codec() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "video_bitrate": "1M",
+            "two_pass": true,
+            "vcodec": "hevc",
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("-c:v libx265"), "{}", log);
+    assert!(log.contains("-x265-params pass=2:stats="), "{}", log);
+}
+
+#[tokio::test]
+async fn falls_back_to_h264_by_default() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({ "url": "https://mock.test/ok", "video_bitrate": "1M" }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("-c:v libx264"), "{}", log);
+}
+
+#[tokio::test]
+async fn sorts_by_codec_then_frame_rate() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/simulate?url=https://mock.test/ok&vcodec=av1&fps=60")
+        .await;
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command = simulation["command"].as_array().unwrap();
+    assert!(
+        command
+            .windows(2)
+            .any(|w| w == ["-S", "vcodec:av01,fps:60"]),
+        "{:?}",
+        command
+    );
+
+    let response = app
+        .get("/api/simulate?url=https://mock.test/ok&fps=source")
+        .await;
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let sorts = simulation["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|arg| *arg == "-S")
+        .count();
+    assert_eq!(sorts, 1);
+}
+
+#[tokio::test]
+async fn caps_frame_rate_of_re_encodes() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "video_bitrate": "1M",
+            "fps": "30",
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("-fpsmax 30"), "{}", log);
+}
@@ -0,0 +1,136 @@
+// Each test binary uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Once,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    http::{Request, Response},
+};
+use tempfile::TempDir;
+use tower::ServiceExt;
+use uuid::Uuid;
+use yt_dlp_web::{
+    AppState, api,
+    config::Config,
+    jobs::{Job, JobStatus},
+};
+
+/// How long a test waits for a job before giving up.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// The server with its data in a temporary directory, running the fake
+/// `yt-dlp` in `tests/fixtures`.
+pub struct TestApp {
+    pub app: Router,
+    pub state: AppState,
+    pub dir: TempDir,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with_config(|_| {})
+    }
+
+    /// Like [`new`](Self::new), with settings changed by `configure`.
+    pub fn with_config(configure: impl FnOnce(&mut Config)) -> Self {
+        use_mock_ytdlp();
+        let dir = tempfile::tempdir().expect("temp dir");
+        let mut config = Config::load().expect("default configuration is valid");
+        config.data_dir = dir.path().join("data");
+        config.tmp_dir = dir.path().join("tmp");
+        config.ytdlp_cache_dir = dir.path().join("ytdlp-cache");
+        config.ytdlp_config = None;
+        configure(&mut config);
+        config.check_tmp_dir().expect("temp dir is usable");
+        let state = AppState::new(config).expect("server starts");
+        state.start();
+        Self {
+            app: api::app(&state),
+            state,
+            dir,
+        }
+    }
+
+    pub async fn request(&self, request: Request<Body>) -> Response<Body> {
+        self.app.clone().oneshot(request).await.unwrap()
+    }
+
+    pub async fn get(&self, uri: &str) -> Response<Body> {
+        self.request(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+    }
+
+    /// Queues `url` through `POST /api/jobs` and returns the job id.
+    pub async fn submit(&self, url: &str) -> Uuid {
+        self.submit_json(serde_json::json!({ "url": url })).await
+    }
+
+    /// Like [`submit`](Self::submit), with the whole request body.
+    pub async fn submit_json(&self, body: serde_json::Value) -> Uuid {
+        let body = body.to_string();
+        let request = Request::post("/api/jobs")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        let response = self.request(request).await;
+        assert!(response.status().is_success(), "{}", response.status());
+        let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+        job.id
+    }
+
+    pub fn job(&self, id: Uuid) -> Job {
+        self.state.jobs.get(id).expect("job exists")
+    }
+
+    /// Waits until the job completes or fails.
+    pub async fn finished(&self, id: Uuid) -> Job {
+        wait_for(|| {
+            let job = self.job(id);
+            matches!(job.status, JobStatus::Completed | JobStatus::Failed).then_some(job)
+        })
+        .await
+    }
+}
+
+pub async fn body_bytes(response: Response<Body>) -> Bytes {
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap()
+}
+
+/// Polls `check` until it returns something, panicking after [`TIMEOUT`].
+pub async fn wait_for<T>(mut check: impl FnMut() -> Option<T>) -> T {
+    let start = Instant::now();
+    loop {
+        if let Some(value) = check() {
+            return value;
+        }
+        assert!(start.elapsed() < TIMEOUT, "timed out");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Puts the fake `yt-dlp` and `ffmpeg` first on `PATH`, once for the whole test binary.
+fn use_mock_ytdlp() {
+    static ONCE: Once = Once::new();
+    ONCE.call_once(|| {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        let mut paths = vec![fixtures()];
+        paths.extend(std::env::split_paths(&path));
+        let path = std::env::join_paths(paths).unwrap();
+        // SAFETY: set before any test starts a command, and never changed
+        // again; tests waiting on `ONCE` don't read the environment until it
+        // returns.
+        unsafe { std::env::set_var("PATH", path) };
+    });
+}
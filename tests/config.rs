@@ -0,0 +1,31 @@
+//! Settings read from the environment. This is the only test in its binary,
+//! since it changes variables every other test's `Config::load` would read.
+
+use yt_dlp_web::config::{Config, ConfigError};
+
+fn load_with_max_resolution(value: &str) -> Result<Config, ConfigError> {
+    // SAFETY: no other test runs in this binary, and nothing here starts a
+    // thread that reads the environment.
+    unsafe { std::env::set_var("MAX_RESOLUTION", value) };
+    Config::load()
+}
+
+#[test]
+fn rejects_an_invalid_max_resolution() {
+    for value in ["1080p", "1080 ", "-1", "high"] {
+        let result = load_with_max_resolution(value);
+        assert!(
+            matches!(result, Err(ConfigError::InvalidMaxResolution(ref v)) if v == value),
+            "{:?}: {:?}",
+            value,
+            result.map(|config| config.max_resolution)
+        );
+    }
+
+    assert_eq!(
+        load_with_max_resolution("1080").unwrap().max_resolution,
+        Some(1080)
+    );
+    assert_eq!(load_with_max_resolution("0").unwrap().max_resolution, None);
+    assert_eq!(load_with_max_resolution("").unwrap().max_resolution, None);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use axum::http::{StatusCode, header};
+use common::TestApp;
+use yt_dlp_web::jobs::{FailureKind, JobStatus};
+
+#[tokio::test]
+async fn renames_output_to_its_real_container() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/mislabeled").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    assert!(
+        job.filename.as_deref().unwrap().ends_with(".webm"),
+        "{:?}",
+        job.filename
+    );
+    assert_eq!(job.output.unwrap().extension().unwrap(), "webm");
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Output is WebM, not .mp4; renamed to .webm"),
+        "{}",
+        log
+    );
+
+    let response = app.get(&format!("/api/jobs/{}/file", id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "video/webm");
+}
+
+#[tokio::test]
+async fn leaves_unrecognized_files_alone() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    assert!(
+        job.filename.as_deref().unwrap().ends_with(".mp4"),
+        "{:?}",
+        job.filename
+    );
+    let response = app.get(&format!("/api/jobs/{}/file", id)).await;
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "video/mp4");
+}
+
+#[tokio::test]
+async fn remuxes_to_mkv_when_recoding_fails() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/unrecodable").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    assert!(
+        job.filename.as_deref().unwrap().ends_with(".mkv"),
+        "{:?}",
+        job.filename
+    );
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Recoding to MP4 failed, remuxing to MKV instead"),
+        "{}",
+        log
+    );
+
+    let response = app.get(&format!("/api/jobs/{}/file", id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "video/x-matroska");
+}
+
+#[tokio::test]
+async fn fails_a_streamed_download_whose_recode_fails() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/download?url=https://mock.test/unrecodable")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id: uuid::Uuid = response.headers()["x-job-id"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    // Part of the MP4 went out already, so there's no switching to MKV.
+    assert!(
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .is_err()
+    );
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Failed);
+    assert_eq!(job.failure.unwrap().kind, FailureKind::PostProcessing);
+    assert!(
+        !job.log.iter().any(|line| line.contains("remuxing to MKV")),
+        "{:?}",
+        job.log
+    );
+}
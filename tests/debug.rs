@@ -0,0 +1,102 @@
+//! Jobs run in debug mode, which is limited to admin roles.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use uuid::Uuid;
+use yt_dlp_web::{
+    jobs::{Job, JobStatus},
+    quotas::RoleQuota,
+};
+
+fn app() -> TestApp {
+    TestApp::with_config(|config| config.quotas.admin_roles = vec!["admins".to_string()])
+}
+
+fn as_user(request: axum::http::request::Builder, groups: &str) -> axum::http::request::Builder {
+    request
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", groups)
+}
+
+async fn submit_debug(app: &TestApp, groups: &str) -> axum::http::Response<Body> {
+    let body = serde_json::json!({ "url": "https://mock.test/ok", "debug": true });
+    let request = as_user(Request::post("/api/jobs"), groups)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    app.request(request).await
+}
+
+async fn get_debug(app: &TestApp, id: Uuid, groups: &str) -> axum::http::Response<Body> {
+    let request = as_user(Request::get(format!("/api/jobs/{}/debug", id)), groups)
+        .body(Body::empty())
+        .unwrap();
+    app.request(request).await
+}
+
+#[tokio::test]
+async fn keeps_verbose_output_apart_from_the_log() {
+    let app = app();
+    let response = submit_debug(&app, "admins").await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(app.finished(job.id).await.status, JobStatus::Completed);
+
+    let response = get_debug(&app, job.id, "admins").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let debug = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(debug.contains("[debug] yt-dlp version mock"), "{}", debug);
+    assert!(debug.contains("[download] 100%"), "{}", debug);
+
+    let log = body_bytes(app.get(&format!("/api/jobs/{}/log", job.id)).await).await;
+    let log = String::from_utf8_lossy(&log);
+    assert!(log.contains("[download] 100%"), "{}", log);
+    assert!(!log.contains("[debug]"), "{}", log);
+}
+
+#[tokio::test]
+async fn refuses_debug_mode_to_other_roles() {
+    let app = app();
+    let response = submit_debug(&app, "users").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app
+        .get("/api/download?url=https://mock.test/ok&debug=true")
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(app.state.jobs.list().is_empty());
+
+    let id = app.submit("https://mock.test/ok").await;
+    app.finished(id).await;
+    assert_eq!(
+        get_debug(&app, id, "users").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        get_debug(&app, id, "admins").await.status(),
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn recognizes_admins_whatever_their_group_order() {
+    let app = TestApp::with_config(|config| {
+        config.quotas.admin_roles = vec!["admins".to_string()];
+        config
+            .quotas
+            .roles
+            .insert("users".to_string(), RoleQuota::default());
+    });
+    let response = submit_debug(&app, "users, admins").await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    app.finished(job.id).await;
+    assert_eq!(
+        get_debug(&app, job.id, "users,admins").await.status(),
+        StatusCode::OK
+    );
+}
@@ -0,0 +1,230 @@
+//! Runs downloads end to end against the fake `yt-dlp` and `ffmpeg` in
+//! `tests/fixtures`.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode, header},
+};
+use common::{TestApp, body_bytes, wait_for};
+use uuid::Uuid;
+use yt_dlp_web::jobs::{Job, JobStage, JobStatus};
+
+fn job_id(response: &axum::http::Response<axum::body::Body>) -> Uuid {
+    response.headers()["x-job-id"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn streams_a_successful_download() {
+    let app = TestApp::new();
+    let response = app.get("/api/download?url=https://mock.test/ok").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id = job_id(&response);
+    let disposition = response.headers()[header::CONTENT_DISPOSITION]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(
+        disposition.contains("filename=Mock%20Video%20%5Bmock%5D.mp4"),
+        "{}",
+        disposition
+    );
+    assert_eq!(body_bytes(response).await, "mock video data\n");
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed);
+    assert_eq!(job.bytes, Some(16));
+}
+
+#[tokio::test]
+async fn keeps_a_queued_download() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let stages: Vec<JobStage> = job.stages.iter().map(|change| change.stage).collect();
+    assert_eq!(
+        stages,
+        [
+            JobStage::Queued,
+            JobStage::Resolving,
+            JobStage::Downloading,
+            JobStage::PostProcessing,
+            JobStage::Storing,
+            JobStage::Completed,
+        ]
+    );
+    assert!(job.stages.is_sorted_by_key(|change| change.at_ms));
+
+    let response = app.get(&format!("/api/jobs/{}/file", id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_bytes(response).await, "mock video data\n");
+}
+
+#[tokio::test]
+async fn fails_on_nonzero_exit() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/download?url=https://mock.test/fail&details=true")
+        .await;
+    assert!(response.status().is_server_error(), "{}", response.status());
+    let id = job_id(&response);
+    let body = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert!(body.contains("This video is broken"), "{}", body);
+
+    let job = app.job(id);
+    assert_eq!(job.status, JobStatus::Failed);
+    assert!(
+        job.stderr_tail
+            .iter()
+            .any(|line| line.contains("ERROR: [mock] fail: This video is broken")),
+        "{:?}",
+        job.stderr_tail
+    );
+}
+
+#[tokio::test]
+async fn fails_a_queued_job_on_nonzero_exit() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/fail").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Failed);
+    assert!(job.error.is_some());
+    assert!(!job.stderr_tail.is_empty());
+}
+
+#[tokio::test]
+async fn tolerates_malformed_utf8_output() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/download?url=https://mock.test/bad-utf8")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let id = job_id(&response);
+    assert_eq!(body_bytes(response).await, "mock video data\n");
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed);
+    assert!(
+        job.log
+            .iter()
+            .any(|line| line.contains("\u{FFFD}") && line.contains("invalid output")),
+        "{:?}",
+        job.log
+    );
+}
+
+#[tokio::test]
+async fn logs_slow_output_as_it_arrives() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/slow").await;
+    let seen_running = wait_for(|| {
+        let job = app.job(id);
+        job.log
+            .iter()
+            .any(|line| line.contains("20.0%"))
+            .then_some(job.status)
+    })
+    .await;
+    assert_eq!(seen_running, JobStatus::Running);
+    let progress = app.job(id).progress.expect("progress is parsed");
+    assert!(progress.estimated);
+    assert_eq!(progress.total_bytes, Some(1024 * 1024));
+    assert_eq!(progress.speed, Some(512 * 1024));
+    assert!(progress.eta.is_some_and(|eta| eta <= 3), "{:?}", progress);
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed);
+    for percent in ["20.0%", "40.0%", "60.0%", "80.0%"] {
+        assert!(job.log.iter().any(|line| line.contains(percent)));
+    }
+}
+
+#[tokio::test]
+async fn reports_post_processing_progress() {
+    let app = TestApp::new();
+    let body = serde_json::json!({ "url": "https://mock.test/ok", "profile": "ios" });
+    let request = Request::post("/api/jobs")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+
+    let running = wait_for(|| {
+        let job = app.job(job.id);
+        job.post_processing
+            .as_ref()
+            .is_some_and(|post| post.percent.is_some())
+            .then_some(job)
+    })
+    .await;
+    let post = running.post_processing.unwrap();
+    assert_eq!(post.step, "transcode");
+    assert_eq!((post.pass, post.passes), (1, 1));
+    assert_eq!(post.percent, Some(50.0));
+    assert_eq!(post.speed, Some(2.5));
+    assert_eq!(post.eta, Some(2));
+    // The download and the one pass count equally.
+    assert_eq!(running.overall_percent, Some(75.0));
+
+    let job = app.finished(job.id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    assert_eq!(job.overall_percent, Some(100.0));
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn kills_yt_dlp_when_the_client_disconnects() {
+    let app = TestApp::new();
+    let pid_file = app.dir.path().join("pid");
+    let uri = format!(
+        "/api/download?url={}",
+        urlencoding::encode(&format!(
+            "https://mock.test/hang?pid={}",
+            pid_file.display()
+        ))
+    );
+    let request = tokio::spawn({
+        let app = app.app.clone();
+        async move {
+            use tower::ServiceExt;
+            let request = axum::http::Request::get(uri)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            app.oneshot(request).await
+        }
+    });
+
+    let pid: u32 = wait_for(|| std::fs::read_to_string(&pid_file).ok()?.trim().parse().ok()).await;
+    assert!(running(pid));
+    let id = app.state.jobs.list()[0].id;
+    request.abort();
+
+    wait_for(|| (!running(pid)).then_some(())).await;
+    let job = wait_for(|| {
+        let job = app.job(id);
+        (job.status == JobStatus::Failed).then_some(job)
+    })
+    .await;
+    assert!(job.error.is_some());
+    assert_eq!(job.stage, JobStage::Cancelled);
+}
+
+/// Whether process `pid` is alive. Killed children may linger as zombies
+/// until reaped, which counts as stopped.
+#[cfg(target_os = "linux")]
+fn running(pid: u32) -> bool {
+    match std::fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        Ok(stat) => stat
+            .rsplit_once(") ")
+            .is_some_and(|(_, rest)| !rest.starts_with('Z')),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,88 @@
+//! Submitting a video that is already being downloaded.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use yt_dlp_web::jobs::{Job, JobStatus};
+
+async fn post(app: &TestApp, body: Value) -> (StatusCode, Uuid) {
+    let request = Request::post("/api/jobs")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.request(request).await;
+    let status = response.status();
+    let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    (status, job.id)
+}
+
+#[tokio::test]
+async fn answers_with_the_running_job() {
+    let app = TestApp::new();
+    let (status, id) = post(&app, json!({ "url": "https://mock.test/slow" })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(app.job(id).video_key.as_deref(), Some("Mock-slow"));
+
+    let (status, same) = post(&app, json!({ "url": "https://mock.test/slow?si=shared" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(same, id);
+
+    let (status, other) = post(
+        &app,
+        json!({ "url": "https://mock.test/slow", "description": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_ne!(other, id);
+
+    let (status, forced) = post(
+        &app,
+        json!({ "url": "https://mock.test/slow", "force": true }),
+    )
+    .await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_ne!(forced, id);
+}
+
+#[tokio::test]
+async fn answers_with_a_recently_completed_job() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    assert_eq!(app.finished(id).await.status, JobStatus::Completed);
+
+    let (status, same) = post(&app, json!({ "url": "https://mock.test/ok" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(same, id);
+}
+
+#[tokio::test]
+async fn retries_a_failed_job() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/fail").await;
+    assert_eq!(app.finished(id).await.status, JobStatus::Failed);
+
+    let (status, retry) = post(&app, json!({ "url": "https://mock.test/fail" })).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_ne!(retry, id);
+}
+
+#[tokio::test]
+async fn rejects_invalid_requests_for_downloaded_videos() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    assert_eq!(app.finished(id).await.status, JobStatus::Completed);
+
+    let body = json!({ "url": "https://mock.test/ok", "tags": ["bell\u{7}"] });
+    let request = Request::post("/api/jobs")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
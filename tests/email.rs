@@ -0,0 +1,111 @@
+mod common;
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+use yt_dlp_web::email::EmailConfig;
+
+use common::TestApp;
+
+/// Accepts mail like an SMTP server, returning its address and the messages
+/// it gets.
+async fn smtp_server() -> (String, mpsc::UnboundedReceiver<String>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("smtp://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let (reader, mut writer) = stream.into_split();
+                let mut lines = BufReader::new(reader).lines();
+                writer.write_all(b"220 localhost\r\n").await.unwrap();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let reply: &[u8] = match line.split(' ').next().unwrap_or_default() {
+                        "DATA" => {
+                            writer.write_all(b"354 go ahead\r\n").await.unwrap();
+                            let mut message = String::new();
+                            while let Ok(Some(line)) = lines.next_line().await {
+                                if line == "." {
+                                    break;
+                                }
+                                message.push_str(&line);
+                                message.push('\n');
+                            }
+                            let _ = tx.send(message);
+                            b"250 queued\r\n"
+                        }
+                        "QUIT" => {
+                            let _ = writer.write_all(b"221 bye\r\n").await;
+                            break;
+                        }
+                        _ => b"250 ok\r\n",
+                    };
+                    writer.write_all(reply).await.unwrap();
+                }
+            });
+        }
+    });
+    (url, rx)
+}
+
+fn email_config(smtp_url: String) -> EmailConfig {
+    EmailConfig {
+        smtp_url,
+        from: "yt-dlp-web <ytdlp@example.com>".to_string(),
+        to: vec!["me@example.com".to_string()],
+        base_url: Some("https://dl.example.com/".to_string()),
+        tags: Vec::new(),
+        completed: true,
+        failed: true,
+        subject: "Download {status}: {title}".to_string(),
+        body: "{title}\n{url}\n\n{link}{error}\n".to_string(),
+    }
+}
+
+#[tokio::test]
+async fn completed_job_emails_share_link() {
+    let (url, mut messages) = smtp_server().await;
+    let app = TestApp::with_config(|config| config.email = Some(email_config(url)));
+
+    let id = app.submit("https://example.com/ok").await;
+    app.finished(id).await;
+
+    let message = messages.recv().await.unwrap();
+    assert!(message.contains("To: me@example.com"), "{}", message);
+    assert!(
+        message.contains("Subject: Download completed: Mock Video"),
+        "{}",
+        message
+    );
+    let link = format!("https://dl.example.com/share/{}?expires=", id);
+    assert!(message.contains(&link), "{}", message);
+}
+
+#[tokio::test]
+async fn emails_only_tagged_jobs() {
+    let (url, mut messages) = smtp_server().await;
+    let app = TestApp::with_config(|config| {
+        let mut email = email_config(url);
+        email.tags = vec!["archive".to_string()];
+        email.subject = "{status} {id}".to_string();
+        config.email = Some(email);
+    });
+
+    let untagged = app.submit("https://example.com/ok").await;
+    app.finished(untagged).await;
+    let tagged = app
+        .submit_json(serde_json::json!({
+            "url": "https://example.com/fail",
+            "tags": ["archive"],
+        }))
+        .await;
+    app.finished(tagged).await;
+
+    let message = messages.recv().await.unwrap();
+    let subject = format!("Subject: failed {}", tagged);
+    assert!(message.contains(&subject), "{}", message);
+    assert!(message.contains("exited with status code 1"), "{}", message);
+}
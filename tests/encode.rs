@@ -0,0 +1,81 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes, wait_for};
+use yt_dlp_web::jobs::JobStatus;
+
+#[tokio::test]
+async fn encodes_to_fit_target_size_in_two_passes() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "target_size": "5MB",
+            "two_pass": true,
+        }))
+        .await;
+
+    let running = wait_for(|| {
+        let job = app.job(id);
+        job.post_processing
+            .as_ref()
+            .is_some_and(|post| post.step == "encode_bitrate")
+            .then_some(job)
+    })
+    .await;
+    assert_eq!(running.post_processing.unwrap().passes, 2);
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    // 5 MB over the fake video's 10 seconds, less 3% overhead and the audio.
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Re-encoding at 3752 kb/s video and 128 kb/s audio in two passes"),
+        "{}",
+        log
+    );
+}
+
+#[tokio::test]
+async fn fails_when_target_size_is_too_small() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "target_size": "10KB",
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Failed);
+    let log = job.log.join("\n");
+    assert!(log.contains("is too small for this video"), "{}", log);
+}
+
+#[tokio::test]
+async fn rejects_invalid_targets() {
+    let app = TestApp::new();
+    for options in [
+        serde_json::json!({ "target_size": "big" }),
+        serde_json::json!({ "video_bitrate": "fast" }),
+        serde_json::json!({ "target_size": "200MB", "video_bitrate": "2M" }),
+    ] {
+        let mut body = options.clone();
+        body["url"] = "https://mock.test/ok".into();
+        let request = Request::post("/api/jobs")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.request(request).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", options);
+        let message = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+        assert!(
+            message.starts_with("Invalid encoding target"),
+            "{}",
+            message
+        );
+    }
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+
+#[tokio::test]
+async fn passes_allowed_args_grouped_by_extractor() {
+    let app = TestApp::new();
+    let response = app
+        .get(
+            "/api/simulate?url=https://mock.test/ok\
+             &extractor_args=youtube:player_client=android,web;youtube:lang=en;youtubetab:skip=webpage",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command: Vec<&str> = simulation["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arg| arg.as_str().unwrap())
+        .collect();
+    let args: Vec<&str> = command
+        .windows(2)
+        .filter(|w| w[0] == "--extractor-args")
+        .map(|w| w[1])
+        .collect();
+    assert_eq!(
+        args,
+        [
+            "youtube:lang=en;player_client=android,web",
+            "youtubetab:skip=webpage"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rejects_args_outside_allowlist() {
+    let app = TestApp::new();
+    for extractor_args in [
+        serde_json::json!({ "generic:impersonate": "chrome" }),
+        serde_json::json!({ "youtube:player_client": "web;po_token=x" }),
+        serde_json::json!({ "youtube:lang": "" }),
+    ] {
+        let body = serde_json::json!({
+            "url": "https://mock.test/ok",
+            "extractor_args": extractor_args,
+        });
+        let request = Request::post("/api/jobs")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.request(request).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::BAD_REQUEST,
+            "{}",
+            extractor_args
+        );
+        let message = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+        assert!(message.starts_with("Invalid extractor args"), "{}", message);
+    }
+}
@@ -0,0 +1,106 @@
+//! Telling yt-dlp failures apart by the `ERROR:` lines they print.
+
+use yt_dlp_web::{
+    download::{DownloadError, classify_failure},
+    jobs::FailureKind,
+};
+
+#[test]
+fn classifies_error_lines() {
+    let cases = [
+        (
+            "ERROR: [youtube] abc: Private video. Sign in if you've been granted access",
+            FailureKind::Private,
+        ),
+        (
+            "ERROR: [vimeo] 123: This video is private",
+            FailureKind::Private,
+        ),
+        (
+            "ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users.",
+            FailureKind::AgeRestricted,
+        ),
+        (
+            "ERROR: [youtube] abc: The uploader has not made this video available in your country",
+            FailureKind::GeoBlocked,
+        ),
+        (
+            "ERROR: [bbc] abc: This video is geo-restricted",
+            FailureKind::GeoBlocked,
+        ),
+        (
+            "ERROR: Unsupported URL: https://example.com/",
+            FailureKind::UnsupportedUrl,
+        ),
+        (
+            "ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader",
+            FailureKind::Unavailable,
+        ),
+        (
+            "ERROR: unable to download video data: HTTP Error 404: Not Found",
+            FailureKind::Unavailable,
+        ),
+        (
+            "ERROR: Postprocessing: Conversion failed!",
+            FailureKind::PostProcessing,
+        ),
+        (
+            "ERROR: unable to download video data: HTTP Error 429: Too Many Requests",
+            FailureKind::Transient,
+        ),
+        (
+            "ERROR: [download] Got error: The read operation timed out",
+            FailureKind::Transient,
+        ),
+        (
+            "ERROR: fragment 3 not found, unable to continue",
+            FailureKind::Transient,
+        ),
+        (
+            "ERROR: unable to download video data: HTTP Error 503: Service Unavailable",
+            FailureKind::Transient,
+        ),
+        (
+            "ERROR: [generic] abc: Something nobody expected",
+            FailureKind::Ytdlp,
+        ),
+    ];
+    for (stderr, kind) in cases {
+        assert_eq!(
+            classify_failure(stderr, 1).failure_kind(),
+            kind,
+            "{}",
+            stderr
+        );
+    }
+}
+
+#[test]
+fn only_reads_error_lines() {
+    let stderr = "WARNING: [youtube] Private video playlists are skipped\n\
+                  ERROR: [youtube] abc: Video unavailable";
+    assert_eq!(
+        classify_failure(stderr, 1).failure_kind(),
+        FailureKind::Unavailable
+    );
+    assert_eq!(
+        classify_failure("[download] fragment 1 of 3 retried", 1).failure_kind(),
+        FailureKind::Ytdlp
+    );
+}
+
+#[test]
+fn keeps_the_exit_code_and_transient_reason() {
+    assert!(matches!(
+        classify_failure("ERROR: Postprocessing: Conversion failed!", 2),
+        DownloadError::VideoPostprocessing(2)
+    ));
+    assert!(matches!(
+        classify_failure("ERROR: [generic] abc: Something nobody expected", 3),
+        DownloadError::VideoExitErrorCode(3)
+    ));
+    assert!(matches!(
+        classify_failure("ERROR: HTTP Error 429: Too Many Requests", 1),
+        DownloadError::VideoTransient("rate limited")
+    ));
+}
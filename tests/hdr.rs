@@ -0,0 +1,63 @@
+//! HDR handling with `hdr=preserve` and `hdr=tonemap`.
+
+mod common;
+
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+use yt_dlp_web::jobs::JobStatus;
+
+#[tokio::test]
+async fn preserves_hdr_in_mkv() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/simulate?url=https://mock.test/hdr&hdr=preserve&vcodec=av1")
+        .await;
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command = simulation["command"].as_array().unwrap();
+    assert!(
+        command
+            .windows(2)
+            .any(|w| w == ["-S", "hdr:12,vcodec:av01"]),
+        "{:?}",
+        command
+    );
+    assert!(
+        command.windows(2).any(|w| w == ["--remux-video", "mkv"]),
+        "{:?}",
+        command
+    );
+
+    let id = app
+        .submit_json(serde_json::json!({ "url": "https://mock.test/hdr", "hdr": "preserve" }))
+        .await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    assert_eq!(job.filename.as_deref(), Some("Mock Video [mock].mkv"));
+}
+
+#[tokio::test]
+async fn tone_maps_hdr_downloads() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({ "url": "https://mock.test/hdr", "hdr": "tonemap" }))
+        .await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("Tone mapping HDR10 to SDR"), "{}", log);
+    assert!(log.contains("tonemap=tonemap=hable"), "{}", log);
+    assert_eq!(job.filename.as_deref(), Some("Mock Video [mock].mp4"));
+}
+
+#[tokio::test]
+async fn leaves_sdr_downloads_alone() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({ "url": "https://mock.test/ok", "hdr": "tonemap" }))
+        .await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("isn't HDR"), "{}", log);
+    assert!(!log.contains("zscale"), "{}", log);
+}
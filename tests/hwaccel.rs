@@ -0,0 +1,124 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+use yt_dlp_web::{hwaccel::HwAccel, jobs::JobStatus};
+
+fn hwaccel_app(accel: HwAccel) -> TestApp {
+    TestApp::with_config(|config| config.hwaccel = Some(accel))
+}
+
+#[tokio::test]
+async fn re_encodes_on_the_gpu() {
+    let app = hwaccel_app(HwAccel::Nvenc);
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "target_size": "5MB",
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("-c:v h264_nvenc -pix_fmt yuv420p"), "{}", log);
+    assert!(!log.contains("libx264"), "{}", log);
+}
+
+#[tokio::test]
+async fn uploads_frames_for_vaapi() {
+    let app = hwaccel_app(HwAccel::Vaapi);
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "target_size": "5MB",
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("-vaapi_device /dev/dri/renderD128 -y -i"),
+        "{}",
+        log
+    );
+    assert!(
+        log.contains("-vf format=nv12,hwupload -c:v h264_vaapi"),
+        "{}",
+        log
+    );
+}
+
+#[tokio::test]
+async fn two_passes_stay_on_the_cpu() {
+    let app = hwaccel_app(HwAccel::Nvenc);
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://mock.test/ok",
+            "target_size": "5MB",
+            "two_pass": true,
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(log.contains("-c:v libx264"), "{}", log);
+    assert!(!log.contains("h264_nvenc"), "{}", log);
+}
+
+#[tokio::test]
+async fn recodes_on_the_gpu() {
+    let app = hwaccel_app(HwAccel::Qsv);
+    let response = app.get("/api/simulate?url=https://mock.test/ok").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command: Vec<&str> = simulation["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arg| arg.as_str().unwrap())
+        .collect();
+    assert!(
+        command.windows(2).any(|w| w == ["--recode", "mp4"]),
+        "{:?}",
+        command
+    );
+    let args: Vec<&str> = command
+        .windows(2)
+        .filter(|w| w[0] == "--postprocessor-args")
+        .map(|w| w[1])
+        .collect();
+    assert_eq!(
+        args,
+        [
+            "ffmpeg_o:-movflags +frag_keyframe+empty_moov+default_base_moof",
+            "VideoConvertor+ffmpeg_i1:-init_hw_device qsv=hw -filter_hw_device hw",
+            // Streamed while written, so the recode keeps fragmenting.
+            "VideoConvertor+ffmpeg_o:-c:v h264_qsv -pix_fmt nv12 -global_quality 22 \
+             -movflags +frag_keyframe+empty_moov+default_base_moof",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn falls_back_to_mkv_when_a_gpu_recode_fails() {
+    let app = hwaccel_app(HwAccel::Nvenc);
+    let id = app.submit("https://mock.test/unrecodable").await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Recoding to MP4 failed, remuxing to MKV instead"),
+        "{}",
+        log
+    );
+    assert!(
+        job.filename.as_deref().unwrap().ends_with(".mkv"),
+        "{:?}",
+        job.filename
+    );
+}
@@ -0,0 +1,74 @@
+//! Retrying job submissions with an `Idempotency-Key`.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, Response, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use yt_dlp_web::jobs::Job;
+
+async fn submit(app: &TestApp, user: &str, key: &str, body: Value) -> Response<Body> {
+    let request = Request::post("/api/jobs")
+        .header("content-type", "application/json")
+        .header("Remote-User", user)
+        .header("Idempotency-Key", key)
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    app.request(request).await
+}
+
+async fn job_id(response: Response<Body>) -> Uuid {
+    let job: Job = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    job.id
+}
+
+#[tokio::test]
+async fn replays_the_first_job_for_a_retried_request() {
+    let app = TestApp::new();
+    // Forced, so only the key keeps the retry from queueing a second job.
+    let body = json!({ "url": "https://mock.test/slow", "force": true });
+
+    let first = submit(&app, "alice", "retry-1", body.clone()).await;
+    assert_eq!(first.status(), StatusCode::ACCEPTED);
+    assert!(!first.headers().contains_key("idempotent-replayed"));
+    let id = job_id(first).await;
+
+    let retry = submit(&app, "alice", "retry-1", body.clone()).await;
+    assert_eq!(retry.status(), StatusCode::ACCEPTED);
+    assert_eq!(retry.headers()["idempotent-replayed"], "true");
+    assert_eq!(job_id(retry).await, id);
+
+    let other_user = submit(&app, "bob", "retry-1", body.clone()).await;
+    assert_eq!(other_user.status(), StatusCode::ACCEPTED);
+    assert_ne!(job_id(other_user).await, id);
+
+    let other_key = submit(&app, "alice", "retry-2", body).await;
+    assert_ne!(job_id(other_key).await, id);
+}
+
+#[tokio::test]
+async fn rejects_a_key_reused_for_another_request() {
+    let app = TestApp::new();
+    let first = submit(
+        &app,
+        "alice",
+        "key",
+        json!({ "url": "https://mock.test/ok" }),
+    )
+    .await;
+    assert_eq!(first.status(), StatusCode::ACCEPTED);
+
+    let reused = submit(
+        &app,
+        "alice",
+        "key",
+        json!({ "url": "https://mock.test/slow" }),
+    )
+    .await;
+    assert_eq!(reused.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(app.state.jobs.list().len(), 1);
+}
@@ -0,0 +1,78 @@
+//! Pausing and resuming queued jobs.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes, wait_for};
+use uuid::Uuid;
+use yt_dlp_web::jobs::{Job, JobStage, JobStatus};
+
+async fn post(app: &TestApp, id: Uuid, action: &str) -> (StatusCode, Option<Job>) {
+    let request = Request::post(format!("/api/jobs/{}/{}", id, action))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    let status = response.status();
+    (
+        status,
+        serde_json::from_slice(&body_bytes(response).await).ok(),
+    )
+}
+
+#[tokio::test]
+async fn resumes_a_stopped_download_where_it_left_off() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/slow").await;
+    wait_for(|| {
+        let job = app.job(id);
+        job.log
+            .iter()
+            .any(|line| line.contains("20.0%"))
+            .then_some(())
+    })
+    .await;
+
+    let (status, _) = post(&app, id, "pause").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let job = wait_for(|| Some(app.job(id)).filter(|job| job.status == JobStatus::Paused)).await;
+    assert_eq!(job.stage, JobStage::Paused);
+    let dir = app.dir.path().join("tmp").join(id.to_string());
+    assert!(dir.exists(), "partial files are kept");
+
+    let (status, job) = post(&app, id, "resume").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(job.unwrap().status, JobStatus::Queued);
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    assert!(
+        job.log
+            .iter()
+            .any(|line| line == "Resuming interrupted download")
+    );
+    assert!(!dir.exists());
+}
+
+#[tokio::test]
+async fn holds_a_queued_job_until_resumed() {
+    let app = TestApp::with_config(|config| config.max_concurrent_jobs = 1);
+    let busy = app.submit("https://mock.test/slow").await;
+    let id = app.submit("https://mock.test/ok").await;
+
+    let (status, job) = post(&app, id, "pause").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(job.unwrap().status, JobStatus::Paused);
+    assert_eq!(app.finished(busy).await.status, JobStatus::Completed);
+    assert_eq!(app.job(id).status, JobStatus::Paused);
+
+    let (status, _) = post(&app, id, "resume").await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(app.finished(id).await.status, JobStatus::Completed);
+
+    let (status, _) = post(&app, id, "pause").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = post(&app, id, "resume").await;
+    assert_eq!(status, StatusCode::CONFLICT);
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use axum::http::{StatusCode, header};
+use common::{TestApp, body_bytes};
+use yt_dlp_web::jobs::JobStatus;
+
+#[tokio::test]
+async fn serves_playlist_of_kept_entries_in_order() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({ "url": "https://mock.test/playlist", "items": "1-2" }))
+        .await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    let response = app.get(&format!("/api/library/{}/playlist.m3u8", id)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let playlist = String::from_utf8(body_bytes(response).await.to_vec()).unwrap();
+    assert_eq!(
+        playlist,
+        "#EXTM3U\n\
+         #EXTINF:10,Mock Video 1\n\
+         entries/001%20-%20Mock%20Video%201%20%5Bmock1%5D.mp4\n\
+         #EXTINF:20,Mock Video 2\n\
+         entries/002%20-%20Mock%20Video%202%20%5Bmock2%5D.mp4\n"
+    );
+
+    let response = app
+        .get(&format!(
+            "/api/library/{}/entries/002%20-%20Mock%20Video%202%20%5Bmock2%5D.mp4",
+            id
+        ))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "video/mp4");
+    assert_eq!(&body_bytes(response).await[..], b"mock video 2 data\n");
+
+    let response = app
+        .get(&format!("/api/library/{}/entries/missing.mp4", id))
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn has_no_playlist_for_single_videos() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    assert_eq!(app.finished(id).await.status, JobStatus::Completed);
+
+    let response = app.get(&format!("/api/library/{}/playlist.m3u8", id)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,74 @@
+//! Reordering the jobs waiting in the queue.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes, wait_for};
+use serde_json::{Value, json};
+use uuid::Uuid;
+use yt_dlp_web::jobs::{Job, JobStage, JobStatus};
+
+async fn reorder(app: &TestApp, body: Value) -> (StatusCode, Vec<Uuid>) {
+    let request = Request::post("/api/queue/reorder")
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.request(request).await;
+    let status = response.status();
+    let jobs: Vec<Job> = serde_json::from_slice(&body_bytes(response).await).unwrap_or_default();
+    (status, jobs.into_iter().map(|job| job.id).collect())
+}
+
+/// When the job was picked up by a worker.
+fn started_at(job: &Job) -> u64 {
+    job.stages
+        .iter()
+        .find(|change| change.stage == JobStage::Resolving)
+        .expect("job ran")
+        .at_ms
+}
+
+#[tokio::test]
+async fn runs_bumped_jobs_first() {
+    let app = TestApp::with_config(|config| config.max_concurrent_jobs = 1);
+    let busy = app.submit("https://mock.test/slow").await;
+    wait_for(|| (app.job(busy).status == JobStatus::Running).then_some(())).await;
+    let submit = || app.submit_json(json!({ "url": "https://mock.test/ok", "force": true }));
+    let a = submit().await;
+    let b = submit().await;
+    let c = submit().await;
+
+    let (status, order) = reorder(&app, json!({ "top": c })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(order, [c, a, b]);
+    let (status, order) = reorder(&app, json!({ "ids": [c, b] })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(order, [c, b, a]);
+
+    let finished = [
+        app.finished(c).await,
+        app.finished(b).await,
+        app.finished(a).await,
+    ];
+    assert!(
+        finished
+            .iter()
+            .all(|job| job.status == JobStatus::Completed)
+    );
+    assert!(finished.is_sorted_by_key(started_at));
+}
+
+#[tokio::test]
+async fn rejects_jobs_that_are_not_waiting() {
+    let app = TestApp::new();
+    let id = app.submit("https://mock.test/ok").await;
+    app.finished(id).await;
+
+    let (status, _) = reorder(&app, json!({ "top": id })).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = reorder(&app, json!({})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
@@ -0,0 +1,191 @@
+//! Per-role download limits for users identified by the proxy headers.
+
+mod common;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::json;
+use uuid::Uuid;
+use yt_dlp_web::{
+    jobs::{JobMode, JobOptions},
+    quotas::RoleQuota,
+};
+
+const MIB: u64 = 1024 * 1024;
+
+fn app(roles: Vec<(&str, RoleQuota)>) -> TestApp {
+    TestApp::with_config(|config| {
+        for (name, quota) in roles {
+            config.quotas.roles.insert(name.to_string(), quota);
+        }
+    })
+}
+
+/// Submits a job as alice, in `groups` if given, returning the status and
+/// body.
+async fn submit(app: &TestApp, groups: Option<&str>) -> (StatusCode, String) {
+    let body = json!({ "url": "https://mock.test/ok", "force": true });
+    let mut request = Request::post("/api/jobs")
+        .header("content-type", "application/json")
+        .header("Remote-User", "alice");
+    if let Some(groups) = groups {
+        request = request.header("Remote-Groups", groups);
+    }
+    let response = app
+        .request(request.body(Body::from(body.to_string())).unwrap())
+        .await;
+    let status = response.status();
+    let body = String::from_utf8_lossy(&body_bytes(response).await).into_owned();
+    (status, body)
+}
+
+/// Records a download of `bytes` by alice `days_ago` days ago.
+fn downloaded(app: &TestApp, days_ago: u64, bytes: u64) {
+    let handle = app.state.jobs.create(
+        "https://mock.test/ok",
+        None,
+        Some("template"),
+        &[],
+        JobOptions::default(),
+        JobMode::Queued,
+    );
+    let mut job = app.state.jobs.get(handle.id()).unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    job.id = Uuid::new_v4();
+    job.user = Some("alice".to_string());
+    job.created_at = now - days_ago * 24 * 60 * 60;
+    job.bytes = Some(bytes);
+    assert!(app.state.jobs.import(&job));
+}
+
+#[tokio::test]
+async fn limits_daily_jobs() {
+    let app = app(vec![(
+        "user",
+        RoleQuota {
+            daily_jobs: Some(2),
+            ..RoleQuota::default()
+        },
+    )]);
+    assert_eq!(submit(&app, None).await.0, StatusCode::ACCEPTED);
+    assert_eq!(submit(&app, None).await.0, StatusCode::ACCEPTED);
+
+    let (status, body) = submit(&app, None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("2 downloads per day"), "{}", body);
+}
+
+#[tokio::test]
+async fn limits_monthly_jobs_from_the_start_of_the_month() {
+    let app = app(vec![(
+        "user",
+        RoleQuota {
+            monthly_jobs: Some(1),
+            ..RoleQuota::default()
+        },
+    )]);
+    // Always before the start of this month.
+    downloaded(&app, 40, 0);
+    assert_eq!(submit(&app, None).await.0, StatusCode::ACCEPTED);
+
+    let (status, body) = submit(&app, None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("1 downloads per month"), "{}", body);
+}
+
+#[tokio::test]
+async fn limits_daily_megabytes() {
+    let app = app(vec![(
+        "user",
+        RoleQuota {
+            daily_mb: Some(1),
+            ..RoleQuota::default()
+        },
+    )]);
+    downloaded(&app, 0, MIB / 2);
+    assert_eq!(submit(&app, None).await.0, StatusCode::ACCEPTED);
+
+    downloaded(&app, 0, MIB);
+    let (status, body) = submit(&app, None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("1 MiB per day"), "{}", body);
+}
+
+#[tokio::test]
+async fn limits_monthly_megabytes() {
+    let app = app(vec![(
+        "user",
+        RoleQuota {
+            monthly_mb: Some(10),
+            ..RoleQuota::default()
+        },
+    )]);
+    downloaded(&app, 40, 20 * MIB);
+    assert_eq!(submit(&app, None).await.0, StatusCode::ACCEPTED);
+
+    downloaded(&app, 0, 10 * MIB);
+    let (status, body) = submit(&app, None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(body.contains("10 MiB per month"), "{}", body);
+}
+
+#[tokio::test]
+async fn falls_back_to_the_default_role() {
+    let app = app(vec![(
+        "user",
+        RoleQuota {
+            daily_jobs: Some(0),
+            ..RoleQuota::default()
+        },
+    )]);
+    assert_eq!(submit(&app, None).await.0, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        submit(&app, Some("unconfigured")).await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // Without the user header, nobody is limited.
+    let id = app.submit("https://mock.test/ok").await;
+    app.finished(id).await;
+}
+
+#[tokio::test]
+async fn picks_the_most_generous_of_several_roles() {
+    let app = app(vec![
+        (
+            "free",
+            RoleQuota {
+                daily_jobs: Some(0),
+                monthly_mb: Some(100),
+                ..RoleQuota::default()
+            },
+        ),
+        (
+            "premium",
+            RoleQuota {
+                monthly_mb: Some(10_000),
+                ..RoleQuota::default()
+            },
+        ),
+    ]);
+    assert_eq!(
+        submit(&app, Some("free")).await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(
+        submit(&app, Some("free, premium")).await.0,
+        StatusCode::ACCEPTED
+    );
+    assert_eq!(
+        submit(&app, Some("premium,free")).await.0,
+        StatusCode::ACCEPTED
+    );
+}
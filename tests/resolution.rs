@@ -0,0 +1,45 @@
+//! The server-wide `MAX_RESOLUTION` cap on format selection.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+
+/// The `-f` format selection a download with `query` would use.
+async fn format(app: &TestApp, query: &str) -> Option<String> {
+    let response = app
+        .get(&format!("/api/simulate?url=https://mock.test/ok{}", query))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command = simulation["command"].as_array().unwrap();
+    command
+        .windows(2)
+        .rev()
+        .find(|w| w[0] == "-f")
+        .map(|w| w[1].as_str().unwrap().to_string())
+}
+
+#[tokio::test]
+async fn caps_every_format_selection() {
+    let app = TestApp::with_config(|config| config.max_resolution = Some(1080));
+    assert_eq!(
+        format(&app, "").await.as_deref(),
+        Some("bv*[height<=?1080]+ba/b[height<=?1080]")
+    );
+    assert_eq!(
+        format(&app, "&audio=all").await.as_deref(),
+        Some("bv*[height<=?1080]+mergeall[vcodec=none]/bv*[height<=?1080]+ba/b[height<=?1080]")
+    );
+    assert_eq!(
+        format(&app, "&audiobook=true").await.as_deref(),
+        Some("bestaudio/best[height<=?1080]")
+    );
+}
+
+#[tokio::test]
+async fn leaves_format_selection_alone_without_a_cap() {
+    let app = TestApp::new();
+    assert_eq!(format(&app, "").await, None);
+}
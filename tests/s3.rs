@@ -0,0 +1,75 @@
+//! Uploads to an S3 bucket, against a fake S3 server.
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use axum::{
+    Router,
+    extract::{Path, State},
+    http::{HeaderMap, header},
+    response::IntoResponse,
+    routing::put,
+};
+use common::TestApp;
+use yt_dlp_web::{jobs::JobStatus, storage::S3Config};
+
+/// Key and content type of each object uploaded.
+type Uploads = Arc<Mutex<Vec<(String, String)>>>;
+
+async fn put_object(
+    State(uploads): State<Uploads>,
+    Path((_, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    uploads.lock().unwrap().push((key, content_type));
+    [(header::ETAG, "\"etag\"")]
+}
+
+async fn start() -> (TestApp, Uploads) {
+    let uploads = Uploads::default();
+    let s3 = Router::new()
+        .route("/{bucket}/{key}", put(put_object))
+        .with_state(uploads.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, s3).await.unwrap() });
+
+    let app = TestApp::with_config(|config| {
+        config.s3 = Some(S3Config {
+            bucket: "videos".to_string(),
+            region: S3Config::default_region(),
+            endpoint: Some(endpoint),
+            access_key_id: Some("key".to_string()),
+            secret_access_key: Some("secret".to_string()),
+            path_style: true,
+            presign_expiry_secs: S3Config::default_presign_expiry_secs(),
+        });
+    });
+    (app, uploads)
+}
+
+#[tokio::test]
+async fn keys_and_types_objects_after_the_file() {
+    let (app, uploads) = start().await;
+
+    let mp4 = app.submit("https://mock.test/ok").await;
+    let job = app.finished(mp4).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let mkv = app.submit("https://mock.test/unrecodable").await;
+    let job = app.finished(mkv).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    assert_eq!(
+        *uploads.lock().unwrap(),
+        [
+            (format!("{}.mp4", mp4), "video/mp4".to_string()),
+            (format!("{}.mkv", mkv), "video/x-matroska".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use common::TestApp;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use yt_dlp_web::{
+    jobs::{FailureKind, JobStatus},
+    scan::ScanConfig,
+};
+
+/// Answers one `INSTREAM` scan with `reply` and returns the bytes it was
+/// sent.
+async fn fake_clamd(reply: &'static str) -> (String, tokio::task::JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let task = tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut command = [0; 10];
+        socket.read_exact(&mut command).await.unwrap();
+        assert_eq!(&command, b"zINSTREAM\0");
+        let mut data = Vec::new();
+        loop {
+            let len = socket.read_u32().await.unwrap() as usize;
+            if len == 0 {
+                break;
+            }
+            let mut chunk = vec![0; len];
+            socket.read_exact(&mut chunk).await.unwrap();
+            data.extend(chunk);
+        }
+        socket.write_all(reply.as_bytes()).await.unwrap();
+        data
+    });
+    (address, task)
+}
+
+#[tokio::test]
+async fn delivers_files_clamd_passes() {
+    let (address, clamd) = fake_clamd("stream: OK\0").await;
+    let app = TestApp::with_config(|config| config.hooks.scan = Some(ScanConfig::Clamd(address)));
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+
+    let scanned = clamd.await.unwrap();
+    assert_eq!(scanned, std::fs::read(job.output.unwrap()).unwrap());
+}
+
+#[tokio::test]
+async fn quarantines_files_clamd_flags() {
+    let (address, _clamd) = fake_clamd("stream: Eicar-Test-Signature FOUND\0").await;
+    let app = TestApp::with_config(|config| config.hooks.scan = Some(ScanConfig::Clamd(address)));
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+
+    assert_eq!(job.status, JobStatus::Failed);
+    assert_eq!(
+        job.error.as_deref(),
+        Some("file flagged by content scan: Eicar-Test-Signature")
+    );
+    assert_eq!(job.failure.unwrap().kind, FailureKind::Quarantined);
+    assert_eq!(job.output, None);
+    let quarantine = app.dir.path().join("data/quarantine").join(id.to_string());
+    assert_eq!(std::fs::read_dir(quarantine).unwrap().count(), 1);
+}
+
+#[tokio::test]
+async fn fails_when_scan_command_flags_file() {
+    let app = TestApp::with_config(|config| {
+        config.hooks.scan = Some(ScanConfig::Command(vec!["false".to_string()]))
+    });
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+
+    assert_eq!(job.status, JobStatus::Failed);
+    assert_eq!(
+        job.error.as_deref(),
+        Some("file flagged by content scan: scanner exited with status code 1")
+    );
+}
@@ -0,0 +1,68 @@
+//! Tailing the server's own log through `GET /api/admin/logs/stream`, which
+//! is limited to admin roles.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, Response, StatusCode},
+};
+use common::TestApp;
+use futures_util::StreamExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use yt_dlp_web::server_log;
+
+fn app() -> TestApp {
+    TestApp::with_config(|config| config.quotas.admin_roles = vec!["admins".to_string()])
+}
+
+async fn stream(app: &TestApp, query: &str, groups: &str) -> Response<Body> {
+    let request = Request::get(format!("/api/admin/logs/stream{}", query))
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", groups)
+        .body(Body::empty())
+        .unwrap();
+    app.request(request).await
+}
+
+#[tokio::test]
+async fn streams_events_at_the_requested_level() {
+    tracing_subscriber::registry()
+        .with(server_log::layer())
+        .init();
+    let app = app();
+    let response = stream(&app, "?level=warn", "admins").await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    tracing::info!("below the level");
+    tracing::warn!(job = 7, "at the level");
+    let mut body = response.into_body().into_data_stream();
+    let chunk = body.next().await.unwrap().unwrap();
+    let event = String::from_utf8_lossy(&chunk);
+    assert!(event.starts_with("data: "), "{}", event);
+    assert!(
+        event.contains(" WARN server_log: at the level job=7"),
+        "{}",
+        event
+    );
+}
+
+#[tokio::test]
+async fn rejects_unknown_levels() {
+    let app = app();
+    let response = stream(&app, "?level=loud", "admins").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn is_limited_to_admins() {
+    let app = app();
+    assert_eq!(
+        stream(&app, "", "users").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(
+        app.get("/api/admin/logs/stream").await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
@@ -0,0 +1,99 @@
+//! Signing and checking share links.
+
+use std::{path::Path, time::Duration};
+
+use uuid::Uuid;
+use yt_dlp_web::share::{ShareError, Shares, Signature, unix_now};
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+
+fn shares() -> Shares {
+    Shares::new(Some("secret"), Path::new("/nonexistent")).unwrap()
+}
+
+#[test]
+fn accepts_a_signed_link() {
+    let shares = shares();
+    let id = Uuid::new_v4();
+    let signature = shares.sign(id, HOUR);
+    assert!(signature.expires > unix_now());
+    assert!(shares.verify(id, &signature));
+}
+
+#[test]
+fn rejects_an_expired_link() {
+    let shares = shares();
+    let id = Uuid::new_v4();
+    assert!(!shares.verify(id, &shares.sign(id, Duration::ZERO)));
+}
+
+#[test]
+fn rejects_a_link_for_another_job_or_expiry() {
+    let shares = shares();
+    let id = Uuid::new_v4();
+    let signature = shares.sign(id, HOUR);
+    assert!(!shares.verify(Uuid::new_v4(), &signature));
+
+    let extended = Signature {
+        expires: signature.expires + 1,
+        sig: signature.sig.clone(),
+    };
+    assert!(!shares.verify(id, &extended));
+}
+
+#[test]
+fn rejects_a_link_signed_with_another_key() {
+    let id = Uuid::new_v4();
+    let other = Shares::new(Some("other"), Path::new("/nonexistent")).unwrap();
+    assert!(!shares().verify(id, &other.sign(id, HOUR)));
+}
+
+#[test]
+fn rejects_a_malformed_signature() {
+    let shares = shares();
+    let id = Uuid::new_v4();
+    let signature = shares.sign(id, HOUR);
+    for sig in ["not base64!", "", &signature.sig[1..]] {
+        let signature = Signature {
+            expires: signature.expires,
+            sig: sig.to_string(),
+        };
+        assert!(!shares.verify(id, &signature), "{:?}", sig);
+    }
+}
+
+#[test]
+fn rejects_an_empty_secret() {
+    assert!(matches!(
+        Shares::new(Some(""), Path::new("/nonexistent")),
+        Err(ShareError::InvalidKey)
+    ));
+}
+
+#[test]
+fn keeps_a_generated_key_across_restarts() {
+    let dir = tempfile::tempdir().unwrap();
+    let id = Uuid::new_v4();
+    let signature = Shares::new(None, dir.path()).unwrap().sign(id, HOUR);
+
+    let restarted = Shares::new(None, dir.path()).unwrap();
+    assert!(restarted.verify(id, &signature));
+    assert!(!shares().verify(id, &signature));
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = std::fs::metadata(dir.path().join("share_key")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+    }
+}
+
+#[test]
+fn rejects_a_corrupt_key_file() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("share_key"), "not base64!").unwrap();
+    assert!(matches!(
+        Shares::new(None, dir.path()),
+        Err(ShareError::InvalidKey)
+    ));
+}
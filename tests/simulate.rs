@@ -0,0 +1,45 @@
+//! Dry runs through `GET /api/simulate` against the fake `yt-dlp`.
+
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+
+#[tokio::test]
+async fn reports_the_command_format_and_filename() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/simulate?url=https://mock.test/ok&fragments=2")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+
+    let command: Vec<&str> = simulation["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arg| arg.as_str().unwrap())
+        .collect();
+    assert_eq!(command[0], "yt-dlp");
+    assert_eq!(command.last(), Some(&"https://mock.test/ok"));
+    assert!(
+        command
+            .windows(2)
+            .any(|w| w == ["--concurrent-fragments", "2"])
+    );
+    assert!(!command.contains(&"--dump-json"));
+
+    assert_eq!(simulation["filename"], "Mock Video [mock].mp4");
+    assert_eq!(simulation["entries"][0]["format_id"], "137+140");
+    assert_eq!(simulation["entries"][0]["approximate"], true);
+    assert_eq!(simulation["bytes"], 1048576);
+    assert!(app.state.jobs.list().is_empty());
+}
+
+#[tokio::test]
+async fn reports_yt_dlp_errors() {
+    let app = TestApp::new();
+    let response = app.get("/api/simulate?url=https://mock.test/fail").await;
+    assert!(response.status().is_server_error(), "{}", response.status());
+}
@@ -0,0 +1,62 @@
+//! Usage stats, which are limited to admin roles.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::{Value, json};
+
+fn app() -> TestApp {
+    TestApp::with_config(|config| config.quotas.admin_roles = vec!["admins".to_string()])
+}
+
+fn as_user(request: axum::http::request::Builder, groups: &str) -> axum::http::request::Builder {
+    request
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", groups)
+}
+
+async fn graphql_stats(app: &TestApp, groups: &str) -> Value {
+    let body = json!({ "query": "{ stats { ytdlpCacheBytes } }" });
+    let request = as_user(Request::post("/api/graphql"), groups)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    serde_json::from_slice(&body_bytes(app.request(request).await).await).unwrap()
+}
+
+#[tokio::test]
+async fn serves_stats_to_admins() {
+    let app = app();
+    let request = as_user(Request::get("/api/admin/stats"), "admins")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let stats: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert!(stats["ytdlp_cache_bytes"].is_u64(), "{}", stats);
+
+    let response = graphql_stats(&app, "admins").await;
+    assert!(response.get("errors").is_none(), "{}", response);
+    assert!(response["data"]["stats"]["ytdlpCacheBytes"].is_number());
+}
+
+#[tokio::test]
+async fn keeps_stats_from_everyone_else() {
+    let app = app();
+    let request = as_user(Request::get("/api/admin/stats"), "users")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.request(request).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        app.get("/api/admin/stats").await.status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let response = graphql_stats(&app, "users").await;
+    assert!(response["data"]["stats"].is_null(), "{}", response);
+    assert!(response["errors"][0]["message"].is_string(), "{}", response);
+}
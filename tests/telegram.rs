@@ -0,0 +1,141 @@
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use axum::{
+    Json, Router,
+    body::Bytes,
+    extract::{Path, State},
+    routing::post,
+};
+use serde_json::{Value, json};
+use tokio::sync::mpsc;
+use yt_dlp_web::{
+    quick::QuickToken,
+    telegram::{TelegramConfig, TelegramError},
+};
+
+use common::TestApp;
+
+/// A stand-in for the Bot API that hands out `updates` once and records
+/// every other call.
+struct FakeTelegram {
+    updates: Mutex<Vec<Value>>,
+    calls: mpsc::UnboundedSender<(String, Bytes)>,
+}
+
+async fn bot_api(
+    State(fake): State<Arc<FakeTelegram>>,
+    Path((_, method)): Path<(String, String)>,
+    body: Bytes,
+) -> Json<Value> {
+    if method == "getUpdates" {
+        let updates: Vec<Value> = fake.updates.lock().unwrap().drain(..).collect();
+        if updates.is_empty() {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        return Json(json!({ "ok": true, "result": updates }));
+    }
+    let _ = fake.calls.send((method, body));
+    Json(json!({ "ok": true, "result": { "message_id": 99, "chat": { "id": 1 } } }))
+}
+
+/// Starts the fake Bot API with a message from user `from`, and the server
+/// talking to it.
+async fn start(from: i64, text: &str) -> (TestApp, mpsc::UnboundedReceiver<(String, Bytes)>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let fake = Arc::new(FakeTelegram {
+        updates: Mutex::new(vec![json!({
+            "update_id": 1,
+            "message": {
+                "message_id": 5,
+                "chat": { "id": 1 },
+                "from": { "id": from },
+                "text": text,
+            },
+        })]),
+        calls: tx,
+    });
+    let api = Router::new()
+        .route("/{bot}/{method}", post(bot_api))
+        .with_state(fake);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let api_url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, api).await.unwrap() });
+
+    let app = TestApp::with_config(|config| {
+        config.telegram = Some(TelegramConfig {
+            token: "123:abc".to_string(),
+            users: HashMap::from([(
+                "42".to_string(),
+                QuickToken {
+                    user: "alice".to_string(),
+                    role: None,
+                },
+            )]),
+            base_url: None,
+            api_url,
+            max_upload_mb: 50,
+        });
+    });
+    (app, rx)
+}
+
+async fn next_call(calls: &mut mpsc::UnboundedReceiver<(String, Bytes)>) -> (String, Bytes) {
+    tokio::time::timeout(Duration::from_secs(20), calls.recv())
+        .await
+        .expect("bot called the API")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn sends_finished_file_to_chat() {
+    let (app, mut calls) = start(42, "get this https://example.com/ok please").await;
+
+    let (method, body) = next_call(&mut calls).await;
+    assert_eq!(method, "sendMessage");
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["text"], "Queued");
+    assert_eq!(body["reply_to_message_id"], 5);
+
+    let body = loop {
+        let (method, body) = next_call(&mut calls).await;
+        if method == "sendDocument" {
+            break String::from_utf8_lossy(&body).into_owned();
+        }
+        assert_eq!(method, "editMessageText");
+    };
+    assert!(body.contains("name=\"document\""), "{}", body);
+    assert!(body.contains("filename="), "{}", body);
+
+    let jobs = app.state.jobs.all().unwrap();
+    assert_eq!(jobs.len(), 1);
+    assert_eq!(jobs[0].url, "https://example.com/ok");
+    assert_eq!(jobs[0].user.as_deref(), Some("alice"));
+}
+
+#[tokio::test]
+async fn turns_away_unknown_users() {
+    let (app, mut calls) = start(7, "https://example.com/ok").await;
+
+    let (method, body) = next_call(&mut calls).await;
+    assert_eq!(method, "sendMessage");
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    let text = body["text"].as_str().unwrap();
+    assert!(text.contains("not allowed"), "{}", text);
+    assert!(text.contains('7'), "{}", text);
+    assert!(app.state.jobs.all().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn keeps_the_token_out_of_http_errors() {
+    let e = reqwest::get("http://127.0.0.1:1/bot123:SECRET/getUpdates")
+        .await
+        .unwrap_err();
+    let e = TelegramError::from(e);
+    assert!(!format!("{:?}", e).contains("SECRET"), "{:?}", e);
+}
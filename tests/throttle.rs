@@ -0,0 +1,39 @@
+mod common;
+
+use common::TestApp;
+use yt_dlp_web::jobs::JobStatus;
+
+fn throttle_app() -> TestApp {
+    TestApp::with_config(|config| {
+        config.throttle_min_speed_kb = 100;
+        config.throttle_grace_secs = 1;
+        config.throttle_fallback_clients = vec!["tv".to_string(), "ios".to_string()];
+    })
+}
+
+#[tokio::test]
+async fn retries_throttled_youtube_download_with_another_client() {
+    let app = throttle_app();
+    let id = app.submit("https://www.youtube.com/throttled").await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Download throttled to 50 KB/s, retrying with the tv player client"),
+        "{}",
+        log
+    );
+    assert!(!log.contains("ios player client"), "{}", log);
+}
+
+#[tokio::test]
+async fn leaves_other_sites_alone() {
+    let app = throttle_app();
+    let id = app.submit("https://mock.test/throttled").await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(!log.contains("throttled"), "{}", log);
+}
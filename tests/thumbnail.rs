@@ -0,0 +1,32 @@
+//! Standalone thumbnails from `GET /api/thumbnail/download`.
+
+mod common;
+
+use axum::http::{StatusCode, header};
+use common::{TestApp, body_bytes};
+
+#[tokio::test]
+async fn returns_converted_thumbnail() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/thumbnail/download?url=https://mock.test/ok&format=png")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    assert_eq!(&body_bytes(response).await[..], b"mock png thumbnail\n");
+
+    let response = app
+        .get("/api/thumbnail/download?url=https://mock.test/ok")
+        .await;
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/jpeg");
+    assert_eq!(&body_bytes(response).await[..], b"mock jpg thumbnail\n");
+}
+
+#[tokio::test]
+async fn reports_missing_thumbnail() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/thumbnail/download?url=https://mock.test/vertical")
+        .await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use uuid::Uuid;
+use yt_dlp_web::jobs::{Job, JobStatus};
+
+async fn kept_job(app: &TestApp) -> Uuid {
+    let id = app.submit("https://mock.test/ok").await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    id
+}
+
+async fn send(app: &TestApp, method: &str, uri: &str) -> (StatusCode, Option<Job>) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.request(request).await;
+    let status = response.status();
+    let job = serde_json::from_slice(&body_bytes(response).await).ok();
+    (status, job)
+}
+
+#[tokio::test]
+async fn restores_deleted_file_from_trash() {
+    let app = TestApp::new();
+    let id = kept_job(&app).await;
+
+    let (status, job) = send(&app, "DELETE", &format!("/api/library/{}", id)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(job.unwrap().trashed_at.is_some());
+    let file = format!("/api/jobs/{}/file", id);
+    assert_eq!(app.get(&file).await.status(), StatusCode::NOT_FOUND);
+    assert!(
+        app.dir
+            .path()
+            .join("data/trash")
+            .join(id.to_string())
+            .is_dir()
+    );
+
+    let response = app.get("/api/library/trash").await;
+    let trashed: Vec<Job> = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(trashed.len(), 1);
+    assert_eq!(trashed[0].id, id);
+
+    let restore = format!("/api/library/{}/restore", id);
+    let (status, job) = send(&app, "POST", &restore).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(job.unwrap().trashed_at, None);
+    assert_eq!(app.get(&file).await.status(), StatusCode::OK);
+
+    let (status, _) = send(&app, "POST", &restore).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deletes_right_away_without_trash() {
+    let app = TestApp::with_config(|config| config.trash_retention_days = 0);
+    let id = kept_job(&app).await;
+
+    let (status, job) = send(&app, "DELETE", &format!("/api/library/{}", id)).await;
+    assert_eq!(status, StatusCode::OK);
+    let job = job.unwrap();
+    assert_eq!(job.trashed_at, None);
+    assert_eq!(job.location, None);
+
+    let (status, _) = send(&app, "POST", &format!("/api/library/{}/restore", id)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "DELETE", &format!("/api/library/{}", id)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
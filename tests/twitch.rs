@@ -0,0 +1,64 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+use yt_dlp_web::jobs::JobStatus;
+
+#[tokio::test]
+async fn caps_quality_and_embeds_chapters() {
+    let app = TestApp::new();
+    let response = app
+        .get("/api/simulate?url=https://www.twitch.tv/videos/123")
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let simulation: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    let command: Vec<&str> = simulation["command"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|arg| arg.as_str().unwrap())
+        .collect();
+    assert!(command.contains(&"--embed-chapters"), "{:?}", command);
+    assert!(
+        command.windows(2).any(|w| w == ["-S", "res:720,fps:30"]),
+        "{:?}",
+        command
+    );
+}
+
+#[tokio::test]
+async fn logs_game_chapters_and_keeps_chat() {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({
+            "url": "https://www.twitch.tv/videos/123",
+            "chat": true,
+        }))
+        .await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Chapters: 0:00:00 Just Chatting, 0:00:05 Elden Ring"),
+        "{}",
+        log
+    );
+    let bundle = std::fs::File::open(job.output.unwrap()).unwrap();
+    let mut bundle = zip::ZipArchive::new(bundle).unwrap();
+    let chat = bundle.by_name("Mock Video [mock].chat.json").unwrap();
+    let chat: Value = serde_json::from_reader(chat).unwrap();
+    assert_eq!(chat["video"]["id"], "123");
+}
+
+#[tokio::test]
+async fn skips_chat_unless_asked() {
+    let app = TestApp::new();
+    let id = app.submit("https://www.twitch.tv/videos/123").await;
+
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    assert!(!job.log.join("\n").contains("chat replay"));
+    assert_eq!(job.output.unwrap().extension().unwrap(), "mp4");
+}
@@ -0,0 +1,160 @@
+mod common;
+
+use std::collections::HashMap;
+
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, Method, Uri},
+};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use yt_dlp_web::{
+    jobs::FailureKind,
+    webhooks::{MatrixRoom, SlackWebhook, Webhook, WebhookEvent},
+};
+
+use common::TestApp;
+
+/// A request the receiver got.
+struct Received {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Value,
+}
+
+/// Serves webhook endpoints on any path, returning the base URL and the
+/// requests it gets.
+async fn receiver() -> (String, mpsc::UnboundedReceiver<Received>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let app = Router::new()
+        .fallback(
+            |State(tx): State<mpsc::UnboundedSender<Received>>,
+             method: Method,
+             uri: Uri,
+             headers: HeaderMap,
+             Json(body): Json<Value>| async move {
+                let _ = tx.send(Received {
+                    method,
+                    uri,
+                    headers,
+                    body,
+                });
+            },
+        )
+        .with_state(tx);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (format!("http://{}", addr), rx)
+}
+
+fn webhook(url: String) -> Webhook {
+    Webhook {
+        url,
+        events: Vec::new(),
+        headers: HashMap::new(),
+    }
+}
+
+#[tokio::test]
+async fn failed_job_sends_failure_detail() {
+    let (url, mut requests) = receiver().await;
+    let app = TestApp::with_config(|config| {
+        config.hooks.webhooks = vec![webhook(format!("{}/hook", url))];
+    });
+
+    let id = app.submit("https://example.com/fail").await;
+    let job = app.finished(id).await;
+    let failure = job.failure.expect("failure is classified");
+    assert_eq!(failure.kind, FailureKind::Ytdlp);
+    assert_eq!(failure.exit_code, Some(1));
+
+    let body = requests.recv().await.unwrap().body;
+    assert_eq!(body["event"], "job.failed");
+    assert_eq!(body["job"]["id"], id.to_string());
+    let failure = &body["failure"];
+    assert_eq!(failure["kind"], "ytdlp");
+    assert_eq!(failure["retryable"], true);
+    assert_eq!(failure["exit_code"], 1);
+    assert!(failure["error"].is_string());
+    let stderr = failure["stderr_tail"].as_array().unwrap();
+    assert!(
+        stderr
+            .iter()
+            .any(|line| line.as_str().unwrap().contains("This video is broken")),
+        "{:?}",
+        stderr
+    );
+}
+
+#[tokio::test]
+async fn webhook_gets_only_its_events() {
+    let (url, mut requests) = receiver().await;
+    let app = TestApp::with_config(|config| {
+        config.hooks.webhooks = vec![Webhook {
+            url: format!("{}/hook", url),
+            events: vec![WebhookEvent::JobFailed],
+            headers: HashMap::from([("authorization".to_string(), "Bearer hook".to_string())]),
+        }];
+    });
+
+    let ok = app.submit("https://example.com/ok").await;
+    app.finished(ok).await;
+    let failed = app.submit("https://example.com/fail").await;
+    app.finished(failed).await;
+
+    let request = requests.recv().await.unwrap();
+    assert_eq!(request.headers["authorization"], "Bearer hook");
+    assert_eq!(request.body["event"], "job.failed");
+    assert_eq!(request.body["job"]["id"], failed.to_string());
+}
+
+#[tokio::test]
+async fn posts_to_slack_and_matrix() {
+    let (url, mut requests) = receiver().await;
+    let app = TestApp::with_config(|config| {
+        config.hooks.slack = vec![SlackWebhook {
+            url: format!("{}/slack", url),
+            events: vec![WebhookEvent::JobFailed],
+        }];
+        config.hooks.matrix = vec![MatrixRoom {
+            homeserver: url.clone(),
+            access_token: "matrix-token".to_string(),
+            room_id: "!room:example.org".to_string(),
+            events: Vec::new(),
+        }];
+    });
+
+    let id = app.submit("https://example.com/ok").await;
+    app.finished(id).await;
+    let request = requests.recv().await.unwrap();
+    assert_eq!(request.method, Method::PUT);
+    assert_eq!(
+        request.uri.path(),
+        format!(
+            "/_matrix/client/v3/rooms/%21room%3Aexample.org/send/m.room.message/{}",
+            id
+        )
+    );
+    assert_eq!(request.headers["authorization"], "Bearer matrix-token");
+    assert_eq!(request.body["msgtype"], "m.text");
+    assert_eq!(request.body["body"], "Downloaded Mock Video");
+
+    let id = app.submit("https://example.com/fail").await;
+    app.finished(id).await;
+    let mut paths = Vec::new();
+    for _ in 0..2 {
+        let request = requests.recv().await.unwrap();
+        let text = request.body["text"]
+            .as_str()
+            .or(request.body["body"].as_str())
+            .unwrap()
+            .to_string();
+        assert!(text.starts_with("Download of "), "{}", text);
+        paths.push(request.uri.path().to_string());
+    }
+    paths.sort();
+    assert_eq!(paths[1], "/slack");
+}
@@ -0,0 +1,44 @@
+//! Framing vertical videos as 16:9 with `widescreen`.
+
+mod common;
+
+use common::TestApp;
+use yt_dlp_web::jobs::{Job, JobStatus};
+
+async fn run(url: &str, mode: &str) -> Job {
+    let app = TestApp::new();
+    let id = app
+        .submit_json(serde_json::json!({ "url": url, "widescreen": mode }))
+        .await;
+    let job = app.finished(id).await;
+    assert_eq!(job.status, JobStatus::Completed, "{:?}", job.error);
+    job
+}
+
+#[tokio::test]
+async fn pads_vertical_video_with_blurred_background() {
+    let job = run("https://mock.test/vertical", "pad").await;
+    let log = job.log.join("\n");
+    assert!(
+        log.contains("Framing 1080x1920 video with split"),
+        "{}",
+        log
+    );
+    assert!(log.contains("crop=1920:1080,boxblur"), "{}", log);
+    assert!(log.contains("[fg]scale=-2:1080[fg]"), "{}", log);
+}
+
+#[tokio::test]
+async fn crops_vertical_video() {
+    let job = run("https://mock.test/vertical", "crop").await;
+    let log = job.log.join("\n");
+    assert!(log.contains("-filter_complex crop=1080:606"), "{}", log);
+}
+
+#[tokio::test]
+async fn leaves_other_videos_alone() {
+    let job = run("https://mock.test/ok", "pad").await;
+    let log = job.log.join("\n");
+    assert!(log.contains("isn't vertical"), "{}", log);
+    assert!(!log.contains("-filter_complex"), "{}", log);
+}
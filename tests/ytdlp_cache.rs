@@ -0,0 +1,36 @@
+//! Clearing yt-dlp's cache, which is limited to admin roles.
+
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{TestApp, body_bytes};
+use serde_json::Value;
+
+async fn clear(app: &TestApp, groups: &str) -> axum::http::Response<Body> {
+    let request = Request::post("/api/admin/cache/clear")
+        .header("Remote-User", "alice")
+        .header("Remote-Groups", groups)
+        .body(Body::empty())
+        .unwrap();
+    app.request(request).await
+}
+
+#[tokio::test]
+async fn lets_admins_clear_the_cache() {
+    let app = TestApp::with_config(|config| config.quotas.admin_roles = vec!["admins".to_string()]);
+    let cached = app.state.config.ytdlp_cache_dir.join("youtube-sigfuncs");
+    std::fs::create_dir_all(&cached).unwrap();
+    std::fs::write(cached.join("js_abc.json"), "0123456789").unwrap();
+
+    assert_eq!(clear(&app, "users").await.status(), StatusCode::FORBIDDEN);
+    assert!(cached.join("js_abc.json").exists());
+
+    let response = clear(&app, "admins").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let cleared: Value = serde_json::from_slice(&body_bytes(response).await).unwrap();
+    assert_eq!(cleared["bytes_freed"], 10);
+    assert!(!cached.join("js_abc.json").exists());
+}